@@ -474,6 +474,14 @@ impl<'a, D: Digest, H: HmacImpl<D>> AttestationManager<D, H> {
         self.extend_msmt_register(TcgPcrIndex::TvmPage, bytes, Some(address))
     }
 
+    /// Extend the TVM pages measurement with the permissions a page
+    /// was mapped with.
+    /// The permissions are measured as a little-endian u64, along
+    /// with the page address.
+    pub fn extend_tvm_page_perms(&self, perms: u64, address: u64) -> Result<()> {
+        self.extend_msmt_register(TcgPcrIndex::TvmPage, &perms.to_le_bytes(), Some(address))
+    }
+
     /// Extend the TVM configuration measurement.
     /// This is a extend_msmt_register wrapper, where the address is not
     /// optional, and the measurement register is fixed to TvmPage.
@@ -609,3 +617,46 @@ impl<'a, D: Digest, H: HmacImpl<D>> AttestationManager<D, H> {
         Ok(caps)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type TestAttestationManager = AttestationManager<sha2::Sha384>;
+    type TestDigest = GenericArray<u8, <sha2::Sha384 as OutputSizeUser>::OutputSize>;
+
+    const PAGE_ADDR: u64 = 0x8020_0000;
+
+    // Returns the TVM pages measurement of a page filled with `fill` at `PAGE_ADDR`, measured
+    // along with `perms` if given.
+    fn tvm_page_measurement(fill: u8, perms: Option<u64>) -> TestDigest {
+        let mgr = TestAttestationManager::new(
+            b"TESTATTESTATIONCDI",
+            b"TESTSEALINGCDI",
+            1,
+            const_oid::db::rfc5912::ID_SHA_384,
+        )
+        .unwrap();
+        mgr.extend_tvm_page(&[fill; 4096], PAGE_ADDR).unwrap();
+        if let Some(perms) = perms {
+            mgr.extend_tvm_page_perms(perms, PAGE_ADDR).unwrap();
+        }
+        mgr.read_msmt_register(TcgPcrIndex::TvmPage).unwrap()
+    }
+
+    #[test]
+    fn tvm_page_perms_measurement() {
+        let unrestricted = tvm_page_measurement(0xa5, None);
+        assert_eq!(tvm_page_measurement(0xa5, None), unrestricted);
+        assert_ne!(tvm_page_measurement(0x5a, None), unrestricted);
+
+        // The same page measures differently once permissions are included, and differently for
+        // each set of permissions.
+        let read_only = tvm_page_measurement(0xa5, Some(0x2));
+        let read_exec = tvm_page_measurement(0xa5, Some(0xa));
+        assert_ne!(read_only, unrestricted);
+        assert_ne!(read_exec, unrestricted);
+        assert_ne!(read_only, read_exec);
+        assert_eq!(tvm_page_measurement(0xa5, Some(0x2)), read_only);
+    }
+}
@@ -5,6 +5,10 @@
 use arrayvec::{ArrayString, ArrayVec};
 use core::fmt;
 use device_tree::{DeviceTree, DeviceTreeNode, DeviceTreeResult};
+use riscv_page_tables::{tlb, GuestStagePagingMode, Sv39x4, Sv48x4, Sv57x4};
+#[cfg(all(target_arch = "riscv64", target_os = "none"))]
+use riscv_regs::{hgatp, ReadWriteable, Readable, Writeable, CSR};
+use sbi::api::base;
 use spin::Once;
 
 const MAX_ISA_STRING_LEN: usize = 256;
@@ -38,8 +42,15 @@ pub struct CpuInfo {
     has_sscofpmf: bool,
     // True if the vector extension is supported
     has_vector: bool,
+    // Bitmap of the guest-stage paging modes supported in HGATP, indexed by their HGATP encoding.
+    guest_paging_modes: u64,
     // CPU timer frequency.
     timer_frequency: u32,
+    // Machine vendor, architecture and implementation IDs as reported by firmware. All CPUs are
+    // expected to report the same IDs.
+    mvendorid: u64,
+    marchid: u64,
+    mimpid: u64,
     // ISA string as reported in the device-tree. All CPUs are expected to have the same ISA.
     isa_string: ArrayString<MAX_ISA_STRING_LEN>,
     // Mapping of logical CPU index to hart IDs.
@@ -89,9 +100,43 @@ fn isa_string_has_base_extension(isa_string: &str, extension: char) -> bool {
         .is_some()
 }
 
+// Probes HGATP for support of the guest-stage paging mode `T`. Writes of unsupported modes to
+// HGATP have no effect. Must only be called while HGATP is 0, i.e. before any VM has run.
+#[cfg(all(target_arch = "riscv64", target_os = "none"))]
+fn probe_guest_paging_mode<T: GuestStagePagingMode>() -> bool {
+    CSR.hgatp.modify(hgatp::mode.val(T::HGATP_VALUE));
+    let supported = CSR.hgatp.read(hgatp::mode) == T::HGATP_VALUE;
+    CSR.hgatp.set(0);
+    supported
+}
+
+// Assume all guest-stage paging modes are supported in testing environments.
+#[cfg(not(any(target_arch = "riscv64", target_os = "none")))]
+fn probe_guest_paging_mode<T: GuestStagePagingMode>() -> bool {
+    true
+}
+
+// Returns the bitmap of supported guest-stage paging modes, indexed by their HGATP encoding.
+fn get_guest_paging_modes() -> u64 {
+    let mut modes = 0;
+    if probe_guest_paging_mode::<Sv39x4>() {
+        modes |= 1 << Sv39x4::HGATP_VALUE;
+    }
+    if probe_guest_paging_mode::<Sv48x4>() {
+        modes |= 1 << Sv48x4::HGATP_VALUE;
+    }
+    if probe_guest_paging_mode::<Sv57x4>() {
+        modes |= 1 << Sv57x4::HGATP_VALUE;
+    }
+    // Flush any translations that may have been cached while probing.
+    tlb::hfence_gvma(None, None);
+    modes
+}
+
 impl CpuInfo {
-    /// Initializes the global `CpuInfo` state from the a device-tree. Must be called first before
-    /// get(). Panics if the device-tree is malformed (missing CPU nodes or expected properties).
+    /// Initializes the global `CpuInfo` state from the a device-tree and the machine IDs reported by
+    /// firmware. Must be called first before get(). Panics if the device-tree is malformed (missing
+    /// CPU nodes or expected properties). Probes HGATP, so must be called before any VM is run.
     pub fn parse_from(dt: &DeviceTree) {
         // Locate the /cpus node in the device-tree.
         let mut iter = dt.iter();
@@ -158,12 +203,22 @@ impl CpuInfo {
             intc_phandles.push(intc_phandle_from_cpu_node(dt, cpu));
         }
 
+        // The machine IDs aren't in the device-tree, so ask the firmware for them. 0 is a legal
+        // value meaning "not implemented", so use that if the firmware can't tell us.
+        let mvendorid = base::get_machine_vendor_id().unwrap_or(0);
+        let marchid = base::get_machine_architecture_id().unwrap_or(0);
+        let mimpid = base::get_machine_implementation_id().unwrap_or(0);
+
         let cpu_info = CpuInfo {
             has_sstc: isa_string_has_extension(isa_string, "sstc"),
             has_sscofpmf: isa_string_has_extension(isa_string, "sscofpmf"),
             has_vector: isa_string_has_base_extension(isa_string, 'v'),
+            guest_paging_modes: get_guest_paging_modes(),
             isa_string: ArrayString::from(isa_string).unwrap(),
             timer_frequency,
+            mvendorid,
+            marchid,
+            mimpid,
             hart_ids,
             intc_phandles,
         };
@@ -191,6 +246,26 @@ impl CpuInfo {
         self.has_vector
     }
 
+    /// Returns true if the guest-stage paging mode `T` is supported.
+    pub fn has_guest_paging_mode<T: GuestStagePagingMode>(&self) -> bool {
+        self.guest_paging_modes & (1 << T::HGATP_VALUE) != 0
+    }
+
+    /// Returns the vendor ID of the machine (`mvendorid`).
+    pub fn mvendorid(&self) -> u64 {
+        self.mvendorid
+    }
+
+    /// Returns the architecture ID of the machine (`marchid`).
+    pub fn marchid(&self) -> u64 {
+        self.marchid
+    }
+
+    /// Returns the implementation ID of the machine (`mimpid`).
+    pub fn mimpid(&self) -> u64 {
+        self.mimpid
+    }
+
     /// Returns the total number of CPUs.
     pub fn num_cpus(&self) -> usize {
         self.hart_ids.len()
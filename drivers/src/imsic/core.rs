@@ -7,9 +7,7 @@ use core::{fmt, marker::PhantomData};
 use device_tree::{DeviceTree, DeviceTreeResult};
 use page_tracking::HwMemMap;
 use riscv_pages::*;
-#[cfg(all(target_arch = "riscv64", target_os = "none"))]
-use riscv_regs::Readable;
-use riscv_regs::{sie, stopei, RiscvCsrInterface, Writeable, CSR};
+use riscv_regs::{sie, stopei, Readable, RiscvCsrInterface, Writeable, CSR};
 use spin::{Mutex, Once};
 
 use super::error::{Error, Result};
@@ -18,12 +16,15 @@ use crate::{CpuId, CpuInfo, MAX_CPUS};
 
 const MAX_GUEST_FILES: usize = 7;
 const MAX_MMIO_REGIONS: usize = 8;
+// Enough EIE registers to cover the architectural maximum of 2047 interrupt IDs.
+const MAX_EIE_REGISTERS: usize = 32;
 
 /// IMSIC indirect CSRs.
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 enum ImsicRegister {
     Eidelivery,
     Eithreshold,
+    // Indexed by EIE register number, with each register covering 64 interrupt IDs.
     Eie(u64),
 }
 
@@ -33,7 +34,8 @@ impl ImsicRegister {
         match self {
             ImsicRegister::Eidelivery => 0x70,
             ImsicRegister::Eithreshold => 0x72,
-            ImsicRegister::Eie(i) => 0xc0 + i / 64,
+            // Only the even-numbered EIE registers exist on RV64.
+            ImsicRegister::Eie(i) => 0xc0 + i * 2,
         }
     }
 }
@@ -171,6 +173,14 @@ struct ImsicPerCpu {
     taken: bool,
 }
 
+/// The saved state of a CPU's supervisor interrupt file. See `Imsic::save_this_cpu()`.
+#[derive(Clone, Debug, Default)]
+pub struct ImsicSavedState {
+    eidelivery: u64,
+    eithreshold: u64,
+    eie: [u64; MAX_EIE_REGISTERS],
+}
+
 // Holds the per-CPU IMSIC state for all CPUs.
 struct ImsicCpuState {
     // Indexed by `CpuId`.
@@ -225,6 +235,11 @@ fn get_guests_per_hart(guest_index_bits: u32) -> usize {
     (1 << guest_index_bits) - 1
 }
 
+fn indirect_csr_read(reg: ImsicRegister) -> u64 {
+    CSR.siselect.set(reg.to_raw());
+    CSR.sireg.get()
+}
+
 fn indirect_csr_write(reg: ImsicRegister, val: u64) {
     CSR.siselect.set(reg.to_raw());
     CSR.sireg.set(val);
@@ -457,6 +472,39 @@ impl Imsic {
         indirect_csr_set_bits(id.eie_register(), 1 << id.eie_bit());
     }
 
+    /// Saves the state of the supervisor interrupt file on this CPU. Used to preserve the IMSIC
+    /// configuration across platform suspend states which lose interrupt file state.
+    pub fn save_this_cpu(&self) -> ImsicSavedState {
+        let mut state = ImsicSavedState {
+            eidelivery: indirect_csr_read(ImsicRegister::Eidelivery),
+            eithreshold: indirect_csr_read(ImsicRegister::Eithreshold),
+            ..Default::default()
+        };
+        let num_regs = self.num_eie_registers();
+        for (i, eie) in state.eie.iter_mut().take(num_regs).enumerate() {
+            *eie = indirect_csr_read(ImsicRegister::Eie(i as u64));
+        }
+        state
+    }
+
+    /// Restores the state of the supervisor interrupt file on this CPU from `state`, which must
+    /// have been returned by a prior call to `save_this_cpu()`.
+    pub fn restore_this_cpu(&self, state: &ImsicSavedState) {
+        // Keep interrupt delivery disabled until all the enable bits have been restored.
+        indirect_csr_write(ImsicRegister::Eidelivery, 0);
+        let num_regs = self.num_eie_registers();
+        for (i, &eie) in state.eie.iter().take(num_regs).enumerate() {
+            indirect_csr_write(ImsicRegister::Eie(i as u64), eie);
+        }
+        indirect_csr_write(ImsicRegister::Eithreshold, state.eithreshold);
+        indirect_csr_write(ImsicRegister::Eidelivery, state.eidelivery);
+    }
+
+    // Returns the number of EIE registers needed to cover all the implemented interrupt IDs.
+    fn num_eie_registers(&self) -> usize {
+        (self.interrupt_ids as usize / 64 + 1).min(MAX_EIE_REGISTERS)
+    }
+
     /// Returns a reference to the global IMSIC state.
     pub fn get() -> &'static Self {
         IMSIC.get().unwrap()
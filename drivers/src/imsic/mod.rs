@@ -6,7 +6,9 @@ mod core;
 mod error;
 mod geometry;
 
-pub use self::core::{Imsic, ImsicGuestPage, ImsicGuestPageIter, ImsicInterruptId};
+pub use self::core::{
+    Imsic, ImsicGuestPage, ImsicGuestPageIter, ImsicInterruptId, ImsicSavedState,
+};
 pub use error::Error as ImsicError;
 pub use error::Result as ImsicResult;
 pub use geometry::*;
@@ -39,6 +39,14 @@ pub struct Iommu {
     gscids: Mutex<[Option<GscIdState>; MAX_GSCIDS]>,
 }
 
+/// IOMMU register state saved across a platform suspend. See `Iommu::save_state()`.
+#[derive(Clone, Copy, Debug)]
+pub struct IommuSavedState {
+    fctrl: u32,
+    cqb: u64,
+    ddtp: u64,
+}
+
 // The global IOMMU singleton.
 static IOMMU: Once<Iommu> = Once::new();
 
@@ -241,6 +249,50 @@ impl Iommu {
         self.submit_commands_sync(&commands).unwrap();
     }
 
+    /// Saves the IOMMU's register state prior to entering a platform suspend state in which the
+    /// IOMMU may lose its state. The CQ is always idle between calls since commands are submitted
+    /// synchronously, so the queue pointers themselves need not be saved.
+    pub fn save_state(&self) -> IommuSavedState {
+        IommuSavedState {
+            fctrl: self.registers.fctrl.get(),
+            cqb: self.registers.cqb.get(),
+            ddtp: self.registers.ddtp.get(),
+        }
+    }
+
+    /// Restores the IOMMU's register state from `state` upon resume from a platform suspend.
+    pub fn restore_state(&self, state: &IommuSavedState) -> Result<()> {
+        {
+            let mut cq = self.command_queue.lock();
+            // The CQ base can only be written while the CQ is off.
+            self.registers.cqcsr.write(CqControl::Enable.val(0));
+            while self.registers.cqcsr.is_set(CqControl::On) {
+                pause();
+            }
+            self.registers.fctrl.set(state.fctrl);
+            self.registers.cqb.set(state.cqb);
+            // The hardware head pointer is reset when the CQ is enabled, so start over with an
+            // empty queue.
+            cq.reset();
+            self.registers.cqt.set(0);
+            self.registers.cqcsr.write(CqControl::Enable.val(1));
+            while !self.registers.cqcsr.is_set(CqControl::On) {
+                pause();
+            }
+        }
+        // Ensure the CQ is up before we point the IOMMU back at the DDT.
+        mmio_wmb();
+        self.registers.ddtp.set(state.ddtp);
+
+        // Drop anything the IOMMU may have cached from before the suspend.
+        let commands = [
+            Command::iodir_inval_ddt(None),
+            Command::iotinval_gvma(None, None),
+            Command::iofence(),
+        ];
+        self.submit_commands_sync(&commands)
+    }
+
     // Posts the commands in `commands` to the CQ, synchronously waiting for their completion.
     fn submit_commands_sync(&self, commands: &[Command]) -> Result<()> {
         let mut cq = self.command_queue.lock();
@@ -9,7 +9,7 @@ mod msi_page_table;
 mod queue;
 mod registers;
 
-pub use self::core::Iommu;
+pub use self::core::{Iommu, IommuSavedState};
 pub use device_directory::{DeviceId, GscId};
 pub use error::Error as IommuError;
 pub use error::Result as IommuResult;
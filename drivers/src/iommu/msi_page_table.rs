@@ -96,7 +96,7 @@ impl Drop for MsiPageTableInner {
         .unwrap();
         for p in pages {
             // Unwrap ok, the page must've been assigned to us to begin with.
-            self.page_tracker.release_page(p).unwrap();
+            self.page_tracker.release_page(p.clean()).unwrap();
         }
     }
 }
@@ -160,6 +160,7 @@ impl MsiPageTable {
             .ok_or(Error::InvalidImsicLocation(dest))?;
         if !inner.page_tracker.is_mapped_page(
             dest_addr,
+            PageSize::Size4k,
             inner.owner,
             MemType::Mmio(DeviceMemType::Imsic),
         ) {
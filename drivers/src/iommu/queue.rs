@@ -82,6 +82,13 @@ impl<T: DataInit, Q> Queue<T, Q> {
     pub fn tail(&self) -> usize {
         self.tail
     }
+
+    /// Resets the queue to empty. Expected to be used when the IOMMU's queue pointers are
+    /// reinitialized, e.g. when the queue is re-enabled after the IOMMU lost its state.
+    pub fn reset(&mut self) {
+        self.head = 0;
+        self.tail = 0;
+    }
 }
 
 impl<T: DataInit> Queue<T, Producer> {
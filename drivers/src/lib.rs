@@ -30,6 +30,8 @@ pub mod iommu;
 pub mod pci;
 /// Caches information about platform hardware and firmware PMU counters.
 pub mod pmu;
+/// Provides a driver for resetting and powering off the platform via a system controller.
+pub mod reset;
 /// Provides a simple UART driver for console output.
 pub mod uart;
 
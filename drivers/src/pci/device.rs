@@ -748,8 +748,10 @@ fn bar_range_is_owned(
     page_tracker: &PageTracker,
     guest_id: PageOwnerId,
 ) -> Result<()> {
+    let page_size = range.page_size();
+    let mem_type = MemType::Mmio(DeviceMemType::PciBar);
     for p in range {
-        if !page_tracker.is_mapped_page(p, guest_id, MemType::Mmio(DeviceMemType::PciBar)) {
+        if !page_tracker.is_mapped_page(p, page_size, guest_id, mem_type) {
             return Err(Error::UnownedBarPage(p));
         }
     }
@@ -1027,7 +1029,7 @@ impl PciDevice {
         // Disable bus mastering to prevent any further DMAs.
         self.common_registers()
             .command
-            .modify(Command::BusMasterEnable.val(1));
+            .modify(Command::BusMasterEnable.val(0));
         self.common_mut().iommu_attached = false;
     }
 
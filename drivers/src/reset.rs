@@ -0,0 +1,185 @@
+// Copyright (c) 2022 by Rivos Inc.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use core::ptr::NonNull;
+use device_tree::{DeviceTree, DeviceTreeNode};
+use page_tracking::{HwMemMap, HwMemRegionType};
+use riscv_pages::{DeviceMemType, RawAddr};
+use spin::Once;
+
+/// Errors that can be returned by the reset driver.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// No `syscon-poweroff` or `syscon-reboot` node found in the device tree.
+    ResetDeviceNotFound,
+    /// Missing or invalid `regmap` property in a reset node.
+    MissingRegmap,
+    /// No `syscon` node with the phandle referenced by the `regmap` property.
+    SysconNotFound,
+    /// Missing `value` property in a reset node.
+    MissingValue,
+    /// Missing `reg` property or cells in the syscon node.
+    MissingRegisters,
+    /// The `offset` of the reset register lies outside of, or is misaligned within, the syscon
+    /// register set.
+    InvalidRegisterLocation,
+    /// Failed to add an MMIO region to the system memory map.
+    AddingMmioRegion(page_tracking::MemMapError),
+}
+
+/// Holds the result of a reset driver operation.
+pub type Result<T> = core::result::Result<T, Error>;
+
+static RESET_DRIVER: Once<ResetDriver> = Once::new();
+
+// A register in a system controller which triggers a reset when `value` is written to it.
+struct SysconRegister {
+    addr: NonNull<u32>,
+    value: u32,
+}
+
+impl SysconRegister {
+    // Parses the reset register from the `syscon-poweroff` or `syscon-reboot` node `node`, adding
+    // the system controller it references to `mem_map` if it isn't already present.
+    fn from_node(dt: &DeviceTree, node: &DeviceTreeNode, mem_map: &mut HwMemMap) -> Result<Self> {
+        let regmap = node
+            .props()
+            .find(|p| p.name() == "regmap")
+            .and_then(|p| p.value_u32().next())
+            .ok_or(Error::MissingRegmap)?;
+        let offset = node
+            .props()
+            .find(|p| p.name() == "offset")
+            .and_then(|p| p.value_u32().next())
+            .unwrap_or(0) as u64;
+        // Older bindings only specify a `mask`, which doubles as the value to be written.
+        let value = node
+            .props()
+            .find(|p| p.name() == "value")
+            .or_else(|| node.props().find(|p| p.name() == "mask"))
+            .and_then(|p| p.value_u32().next())
+            .ok_or(Error::MissingValue)?;
+
+        let syscon = dt
+            .iter()
+            .find(|n| {
+                n.compatible(["syscon"])
+                    && n.props()
+                        .find(|p| p.name() == "phandle")
+                        .and_then(|p| p.value_u32().next())
+                        == Some(regmap)
+            })
+            .ok_or(Error::SysconNotFound)?;
+        let mut regs = syscon
+            .props()
+            .find(|p| p.name() == "reg")
+            .ok_or(Error::MissingRegisters)?
+            .value_u64();
+        let base_address = regs.next().ok_or(Error::MissingRegisters)?;
+        let len = regs.next().ok_or(Error::MissingRegisters)?;
+        if base_address == 0
+            || offset % (core::mem::size_of::<u32>() as u64) != 0
+            || offset + core::mem::size_of::<u32>() as u64 > len
+        {
+            return Err(Error::InvalidRegisterLocation);
+        }
+
+        // The poweroff and reboot registers are commonly in the same system controller, so only
+        // add it to the memory map the first time we see it.
+        let already_mapped = mem_map.regions().any(|r| {
+            r.base().bits() <= base_address
+                && base_address < r.end().bits()
+                && r.region_type() == HwMemRegionType::Mmio(DeviceMemType::Syscon)
+        });
+        if !already_mapped {
+            // Safety: We trust that the device tree accurately described the location of the
+            // system controller.
+            unsafe {
+                mem_map
+                    .add_mmio_region(
+                        DeviceMemType::Syscon,
+                        RawAddr::supervisor(base_address),
+                        len,
+                    )
+                    .map_err(Error::AddingMmioRegion)
+            }?;
+        }
+
+        // Unwrap ok, we've already verified that base_address is non-NULL.
+        let addr = NonNull::new((base_address + offset) as *mut u32).unwrap();
+        Ok(Self { addr, value })
+    }
+
+    // Writes the reset value to the register.
+    fn trigger(&self) {
+        // Safety: `from_node()` verified that the register lies within the MMIO region of a
+        // system controller described by the device tree, which we then took ownership of.
+        unsafe { core::ptr::write_volatile(self.addr.as_ptr(), self.value) };
+    }
+}
+
+/// Driver for resetting or powering off the platform through the `syscon-reboot` and
+/// `syscon-poweroff` registers of a system controller.
+pub struct ResetDriver {
+    poweroff: Option<SysconRegister>,
+    reboot: Option<SysconRegister>,
+}
+
+impl ResetDriver {
+    /// Probes for `syscon-poweroff` and `syscon-reboot` devices from `dt`, adding the MMIO
+    /// registers of the system controllers they reference to `mem_map`.
+    pub fn probe_from(dt: &DeviceTree, mem_map: &mut HwMemMap) -> Result<()> {
+        let poweroff = dt
+            .iter()
+            .find(|n| n.compatible(["syscon-poweroff"]) && !n.disabled())
+            .map(|n| SysconRegister::from_node(dt, n, mem_map))
+            .transpose()?;
+        let reboot = dt
+            .iter()
+            .find(|n| n.compatible(["syscon-reboot"]) && !n.disabled())
+            .map(|n| SysconRegister::from_node(dt, n, mem_map))
+            .transpose()?;
+        if poweroff.is_none() && reboot.is_none() {
+            return Err(Error::ResetDeviceNotFound);
+        }
+        RESET_DRIVER.call_once(|| ResetDriver { poweroff, reboot });
+        Ok(())
+    }
+
+    /// Returns a reference to the reset driver, if one was found.
+    pub fn get() -> Option<&'static Self> {
+        RESET_DRIVER.get()
+    }
+
+    /// Returns if the platform can be powered off.
+    pub fn can_poweroff(&self) -> bool {
+        self.poweroff.is_some()
+    }
+
+    /// Returns if the platform can be rebooted.
+    pub fn can_reboot(&self) -> bool {
+        self.reboot.is_some()
+    }
+
+    /// Powers off the platform. Only returns if no poweroff register is present or if the write
+    /// failed to take effect.
+    pub fn poweroff(&self) {
+        if let Some(r) = self.poweroff.as_ref() {
+            r.trigger();
+        }
+    }
+
+    /// Reboots the platform. Only returns if no reboot register is present or if the write
+    /// failed to take effect.
+    pub fn reboot(&self) {
+        if let Some(r) = self.reboot.as_ref() {
+            r.trigger();
+        }
+    }
+}
+
+// Safety: The reset registers are only ever written, each with a single value, and writing them
+// from any CPU is equivalent.
+unsafe impl Send for ResetDriver {}
+unsafe impl Sync for ResetDriver {}
@@ -27,6 +27,12 @@ pub type Result<T> = core::result::Result<T, Error>;
 
 // Standard 16500a register set length.
 const UART_REGISTERS_LEN: u64 = 8;
+// Offset of the receive buffer register.
+const UART_RBR_OFFSET: usize = 0;
+// Offset of the line status register.
+const UART_LSR_OFFSET: usize = 5;
+// Set in the line status register if there is data in the receive buffer.
+const UART_LSR_DATA_READY: u8 = 1 << 0;
 
 static UART_DRIVER: Once<UartDriver> = Once::new();
 
@@ -68,6 +74,31 @@ impl UartDriver {
         Console::set_writer(UART_DRIVER.get().unwrap());
         Ok(())
     }
+
+    /// Returns a reference to the UART driver, if one was found.
+    pub fn get() -> Option<&'static Self> {
+        UART_DRIVER.get()
+    }
+
+    /// Reads any bytes pending in this UART's receive buffer into `bytes` without blocking.
+    /// Returns the number of bytes read.
+    pub fn read_bytes(&self, bytes: &mut [u8]) -> usize {
+        let base_address = self.base_address.lock();
+        let mut count = 0;
+        for b in bytes.iter_mut() {
+            // Safety: the caller of ::new() had to guarantee that the given address belongs to an
+            // actual UART and that nobody else is using it, thereby making this defined behavior.
+            let lsr =
+                unsafe { core::ptr::read_volatile(base_address.as_ptr().add(UART_LSR_OFFSET)) };
+            if lsr & UART_LSR_DATA_READY == 0 {
+                break;
+            }
+            // Safety: As above.
+            *b = unsafe { core::ptr::read_volatile(base_address.as_ptr().add(UART_RBR_OFFSET)) };
+            count += 1;
+        }
+        count
+    }
 }
 
 impl ConsoleWriter for UartDriver {
@@ -8,7 +8,9 @@ use core::ops::Deref;
 use core::ptr::NonNull;
 use core::sync::atomic::{AtomicUsize, Ordering};
 
-use riscv_pages::{InternalClean, InternalDirty, Page, PageAddr, PageSize, PhysPage, RawAddr};
+use riscv_pages::{
+    CleanablePhysPage, InternalClean, InternalDirty, Page, PageAddr, PageSize, PhysPage, RawAddr,
+};
 
 use crate::collections::PageBox;
 use crate::PageTracker;
@@ -134,7 +136,7 @@ impl<T> Drop for PageArc<T> {
         };
 
         // Unwrap ok: we have unique ownership of the page so we must be able to release it.
-        page_tracker.release_page(page).unwrap();
+        page_tracker.release_page(page.clean()).unwrap();
     }
 }
 
@@ -215,6 +217,7 @@ mod tests {
         assert!(page_tracker
             .get_converted_page::<Page<ConvertedDirty>>(
                 addr,
+                PageSize::Size4k,
                 PageOwnerId::hypervisor(),
                 TlbVersion::new()
             )
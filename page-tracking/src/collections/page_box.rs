@@ -7,7 +7,8 @@ use core::ops::{Deref, DerefMut};
 use core::ptr::NonNull;
 use core::{borrow, cmp};
 use riscv_pages::{
-    InternalClean, InternalDirty, Page, PageAddr, PageSize, PhysPage, RawAddr, SequentialPages,
+    CleanablePhysPage, InternalClean, InternalDirty, Page, PageAddr, PageSize, PhysPage, RawAddr,
+    SequentialPages,
 };
 
 use crate::PageTracker;
@@ -180,7 +181,7 @@ impl<T> Drop for PageBox<T> {
         let pages = unsafe { self.take_pages() };
         for p in pages.into_iter() {
             // Unwrap ok: we have unique ownership of the page so we must be able to release it.
-            self.page_tracker.release_page(p).unwrap();
+            self.page_tracker.release_page(p.clean()).unwrap();
         }
     }
 }
@@ -338,6 +339,7 @@ mod tests {
             assert!(page_tracker
                 .get_converted_page::<Page<ConvertedDirty>>(
                     addr,
+                    page_size,
                     PageOwnerId::hypervisor(),
                     TlbVersion::new()
                 )
@@ -7,7 +7,9 @@ use alloc::vec::Vec;
 use core::mem::{self, ManuallyDrop};
 use core::ops::{Deref, DerefMut, Index, IndexMut};
 use core::slice::SliceIndex;
-use riscv_pages::{InternalClean, InternalDirty, PageAddr, PageSize, RawAddr, SequentialPages};
+use riscv_pages::{
+    CleanablePhysPage, InternalClean, InternalDirty, PageAddr, PageSize, RawAddr, SequentialPages,
+};
 
 use crate::PageTracker;
 
@@ -218,7 +220,7 @@ impl<T> Drop for PageVec<T> {
         let pages = unsafe { self.0.take_pages() };
         for p in pages.into_iter() {
             // Unwrap ok: we have unique ownership of the page so we must be able to release it.
-            self.1.release_page(p).unwrap();
+            self.1.release_page(p.clean()).unwrap();
         }
     }
 }
@@ -281,6 +283,7 @@ mod tests {
         assert!(page_tracker
             .get_converted_page::<Page<ConvertedDirty>>(
                 first_page_addr,
+                PageSize::Size4k,
                 PageOwnerId::hypervisor(),
                 TlbVersion::new()
             )
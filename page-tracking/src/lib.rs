@@ -14,6 +14,7 @@
 //! - `PageTracker` - Contains information about active VMs (page owners), manages allocation of
 //! unique owner IDs, and per-page state such as current and previous owner. This is system-wide
 //! state updated whenever a page owner changes or a VM starts or stops.
+//! - `VmRegionList` - Tracks the purpose of each region of a VM's guest physical address space.
 //!
 //! ## Initialization
 //!
@@ -34,8 +35,10 @@ mod page_info;
 pub mod page_list;
 /// Handles tracking the owner and state of each page.
 pub mod page_tracker;
-/// Implements a `TlbVersion` type, used for tracking the progress of TLB shootdowns.
+/// Implements a `TlbVersion` type, used for tracking the progress of TLB shootdowns, and the
+/// `InvalidatedRanges` fenced by them.
 pub mod tlb_version;
+mod vm_region_list;
 
 pub use hw_mem_map::Error as MemMapError;
 pub use hw_mem_map::Result as MemMapResult;
@@ -45,7 +48,10 @@ pub use page_list::{LockedPageList, PageList};
 pub use page_tracker::Error as PageTrackingError;
 pub use page_tracker::Result as PageTrackingResult;
 pub use page_tracker::{HypPageAlloc, PageTracker};
-pub use tlb_version::TlbVersion;
+pub use tlb_version::{InvalidatedRanges, TlbVersion, MAX_RANGED_FENCE_PAGES};
+pub use vm_region_list::Error as VmRegionError;
+pub use vm_region_list::Result as VmRegionResult;
+pub use vm_region_list::{VmRegionList, VmRegionType};
 
 #[cfg(test)]
 #[macro_use]
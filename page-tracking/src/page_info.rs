@@ -40,14 +40,21 @@ pub enum PageState {
     /// Page has been invalidated and started the conversion operation at the given TLB version.
     Converting(TlbVersion),
 
+    /// Page has been invalidated at the given TLB version so that it can be removed by the parent
+    /// of the current owner, e.g. because the current owner is sharing it. Unlike a Converting page
+    /// a Blocked page can't be reclaimed by the current owner, and it never completes conversion:
+    /// it remains Blocked until it is removed and released.
+    Blocked(TlbVersion),
+
     /// Page has completed the conversion operation and is eligible for assignment or to be reclaimed.
     /// The page must be locked exclusively before it can be assigned or reclaimed.
     Converted,
 }
 
-/// The maximum length for an ownership chain. Enough for the host VM to assign to a guest VM
-/// without further nesting. An empty owners vector indicates that the page is hypervisor-owned.
-pub const MAX_PAGE_OWNERS: usize = 2;
+/// The maximum length for an ownership chain. Enough for the host VM to assign to a guest VM, which
+/// may in turn assign to its own guest VMs with up to two levels of nesting. An empty owners vector
+/// indicates that the page is hypervisor-owned.
+pub const MAX_PAGE_OWNERS: usize = 4;
 
 /// Holds ownership and typing details about a particular page in the system memory map.
 #[derive(Clone, Debug)]
@@ -110,7 +117,7 @@ impl PageInfo {
     pub fn owner(&self) -> Option<PageOwnerId> {
         use PageState::*;
         match self.state {
-            Converting(_) | Converted | Mapped | VmState | Shared(_) => {
+            Converting(_) | Blocked(_) | Converted | Mapped | VmState | Shared(_) => {
                 if !self.owners.is_empty() {
                     Some(self.owners[self.owners.len() - 1])
                 } else {
@@ -137,6 +144,11 @@ impl PageInfo {
         matches!(self.state, PageState::Reserved)
     }
 
+    /// Returns if the page is exclusively locked.
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+
     /// Returns the page type.
     pub fn mem_type(&self) -> MemType {
         self.mem_type
@@ -146,7 +158,7 @@ impl PageInfo {
     pub fn release(&mut self) -> PageTrackingResult<()> {
         use PageState::*;
         match self.state {
-            Mapped | VmState | Converted | Converting(_) => {
+            Mapped | VmState | Converted | Converting(_) | Blocked(_) => {
                 // Locked pages cannot be released
                 if self.locked {
                     return Err(PageTrackingError::PageLocked);
@@ -226,13 +238,28 @@ impl PageInfo {
         }
     }
 
+    /// Transitions the page to Blocked at `tlb_version` if it is currently Mapped.
+    pub fn begin_blocking(&mut self, tlb_version: TlbVersion) -> PageTrackingResult<()> {
+        use PageState::*;
+        match self.state {
+            Mapped => {
+                self.state = Blocked(tlb_version);
+                Ok(())
+            }
+            _ => Err(PageTrackingError::PageNotConvertible),
+        }
+    }
+
     /// Transitions the page to Converted if it is Converting with a TLB version older than
     /// `tlb_version`. After the page is converted it may be assigned to child VMs.
     pub fn complete_conversion(&mut self, tlb_version: TlbVersion) -> PageTrackingResult<()> {
         use PageState::*;
         match self.state {
             Converting(version) => {
-                if version < tlb_version {
+                // Converting pages are only locked while they're being reclaimed.
+                if self.locked {
+                    Err(PageTrackingError::PageLocked)
+                } else if version.is_older_than(tlb_version) {
                     self.state = Converted;
                     Ok(())
                 } else {
@@ -247,7 +274,18 @@ impl PageInfo {
     pub fn is_convertible(&self, tlb_version: TlbVersion) -> bool {
         use PageState::*;
         match self.state {
-            Converting(version) => version < tlb_version,
+            Converting(version) => version.is_older_than(tlb_version),
+            _ => false,
+        }
+    }
+
+    /// Returns if the page can be removed from its current owner at the given `tlb_version`: it is
+    /// either Converted, or was Converting or Blocked at an older TLB version.
+    pub fn is_removable(&self, tlb_version: TlbVersion) -> bool {
+        use PageState::*;
+        match self.state {
+            Converted => true,
+            Converting(version) | Blocked(version) => version.is_older_than(tlb_version),
             _ => false,
         }
     }
@@ -285,6 +323,23 @@ impl PageInfo {
         }
     }
 
+    /// Obtains an exclusive reference on a page that is Converted, or still Converting, in
+    /// preparation for reclaim.
+    pub fn lock_for_reclaim(&mut self) -> PageTrackingResult<()> {
+        use PageState::*;
+        match self.state {
+            Converted | Converting(_) => {
+                if !self.locked {
+                    self.locked = true;
+                    Ok(())
+                } else {
+                    Err(PageTrackingError::PageLocked)
+                }
+            }
+            _ => Err(PageTrackingError::PageNotReclaimable),
+        }
+    }
+
     /// Drops the exclusive lock on a page.
     pub fn unlock(&mut self) -> PageTrackingResult<()> {
         if self.locked {
@@ -319,16 +374,17 @@ impl PageInfo {
     }
 
     /// Reclaims the converted and locked, but unassigned, page as a Mapped page for the current owner.
-    /// The lock is dropped on success.
+    /// A locked page that is still Converting has its conversion cancelled, which is always safe as
+    /// a page can't be assigned to a child VM until its conversion completes. The lock is dropped
+    /// on success.
     pub fn reclaim(&mut self) -> PageTrackingResult<()> {
         use PageState::*;
         match self.state {
-            Converted => {
+            Converted | Converting(_) => {
                 self.unlock()?;
                 self.state = Mapped;
                 Ok(())
             }
-            // TODO: Reclaim pages that are converting but not yet fully converted?
             _ => Err(PageTrackingError::PageNotReclaimable),
         }
     }
@@ -590,6 +646,21 @@ mod tests {
         RawPageVec::from(SequentialPages::from(page))
     }
 
+    // Like `stub_page_vec()`, but with room for `num_pages` pages of `PageInfo`s, for maps too large
+    // to track in one page now that each `PageInfo` holds a nested ownership chain. The pages are
+    // carved out of `backing_mem`, which the caller must keep alive for as long as the vector.
+    fn stub_page_vec_in(backing_mem: &mut [u8], num_pages: u64) -> RawPageVec<PageInfo> {
+        assert!(backing_mem.len() >= (num_pages as usize + 1) * 4096);
+        let offset = backing_mem.as_ptr().align_offset(4096);
+        let aligned_pointer = backing_mem[offset..].as_mut_ptr();
+        let addr = PageAddr::new(RawAddr::supervisor(aligned_pointer as u64)).unwrap();
+        let pages = unsafe {
+            // Test-only: safe because the caller keeps `backing_mem` alive while the pages are used.
+            SequentialPages::from_mem_range(addr, PageSize::Size4k, num_pages).unwrap()
+        };
+        RawPageVec::from(pages)
+    }
+
     #[test]
     fn indexing() {
         let pages = stub_page_vec();
@@ -668,7 +739,8 @@ mod tests {
 
     #[test]
     fn sparse_map() {
-        let pages = stub_page_vec();
+        let mut backing_mem = vec![0u8; 3 * 4096];
+        let pages = stub_page_vec_in(&mut backing_mem, 2);
         const TOTAL_SIZE: u64 = 0x4_0000;
         let mem_map = unsafe {
             // Not safe - just a test.
@@ -726,4 +798,218 @@ mod tests {
             .assign(PageOwnerId::hypervisor(), PageState::Converted)
             .is_err());
     }
+
+    #[test]
+    fn nested_page_ownership() {
+        let mut page = PageInfo::new();
+        assert!(page
+            .assign(PageOwnerId::hypervisor(), PageState::Converted)
+            .is_ok());
+        assert!(page.lock_for_assignment().is_ok());
+        assert!(page.assign(PageOwnerId::host(), PageState::Mapped).is_ok());
+
+        // Convert the page and assign it to the next guest down the chain until the chain is full.
+        let mut version = TlbVersion::new();
+        let mut owners = vec![PageOwnerId::host()];
+        for id in 2..=MAX_PAGE_OWNERS as u64 {
+            assert!(page.begin_conversion(version).is_ok());
+            version = version.increment();
+            assert!(page.complete_conversion(version).is_ok());
+            assert!(page.lock_for_assignment().is_ok());
+            let guest_id = PageOwnerId::new(id).unwrap();
+            assert!(page.assign(guest_id, PageState::Mapped).is_ok());
+            assert_eq!(page.owner().unwrap(), guest_id);
+            owners.push(guest_id);
+        }
+        assert!(page.begin_conversion(version).is_ok());
+        version = version.increment();
+        assert!(page.complete_conversion(version).is_ok());
+        assert!(page.lock_for_assignment().is_ok());
+        let guest_id = PageOwnerId::new(MAX_PAGE_OWNERS as u64 + 1).unwrap();
+        assert_eq!(
+            page.assign(guest_id, PageState::Mapped),
+            Err(PageTrackingError::OwnerOverflow)
+        );
+        assert!(page.unlock().is_ok());
+
+        // Releasing the page returns it to each of the previous owners in turn.
+        owners.pop();
+        while let Some(owner) = owners.pop() {
+            assert!(page.release().is_ok());
+            assert_eq!(page.state(), PageState::Converted);
+            assert_eq!(page.owner().unwrap(), owner);
+        }
+        assert!(page.lock_for_assignment().is_ok());
+        assert!(page.reclaim().is_ok());
+        assert_eq!(page.owner().unwrap(), PageOwnerId::host());
+        assert_eq!(page.state(), PageState::Mapped);
+    }
+
+    #[test]
+    fn conversion_fence_cycles() {
+        const NUM_PAGES: usize = 4;
+        const NUM_CYCLES: u64 = 4096;
+        let mut pages = [(); NUM_PAGES].map(|_| {
+            let mut page = PageInfo::new();
+            page.assign(PageOwnerId::host(), PageState::Mapped).unwrap();
+            page
+        });
+
+        // Start close enough to the end of the version space that the counter wraps midway.
+        let mut version = TlbVersion::from_raw(u64::MAX - NUM_CYCLES / 2);
+        for cycle in 0..NUM_CYCLES {
+            // Stagger the pages so that some begin conversion one version later than the others.
+            let (early, late) = pages.split_at_mut(NUM_PAGES / 2);
+            for page in early.iter_mut() {
+                assert!(page.begin_conversion(version).is_ok());
+            }
+            let prev = version;
+            version = version.increment();
+            for page in late.iter_mut() {
+                assert!(page.begin_conversion(version).is_ok());
+            }
+
+            // Pages can't complete conversion until the version at which they began conversion has
+            // been retired, including when checked against an older version.
+            for page in early.iter() {
+                assert!(page.is_convertible(version));
+                assert!(!page.is_convertible(prev));
+            }
+            for page in late.iter_mut() {
+                assert!(!page.is_convertible(version));
+                assert!(!page.is_convertible(prev));
+                assert_eq!(
+                    page.complete_conversion(version),
+                    Err(PageTrackingError::PageNotConvertible)
+                );
+            }
+            version = version.increment();
+            for page in pages.iter_mut() {
+                assert!(page.is_convertible(version), "cycle {cycle}");
+                assert!(page.complete_conversion(version).is_ok());
+                assert!(page.lock_for_assignment().is_ok());
+                assert!(page.reclaim().is_ok());
+                assert_eq!(page.state(), PageState::Mapped);
+            }
+        }
+    }
+
+    #[test]
+    fn cancel_conversion() {
+        let mut page = PageInfo::new();
+        assert!(page.assign(PageOwnerId::host(), PageState::Mapped).is_ok());
+        // Only converted or converting pages can be reclaimed.
+        assert_eq!(
+            page.lock_for_reclaim(),
+            Err(PageTrackingError::PageNotReclaimable)
+        );
+        assert_eq!(page.reclaim(), Err(PageTrackingError::PageNotReclaimable));
+
+        let version = TlbVersion::new();
+        assert!(page.begin_conversion(version).is_ok());
+        // A converting page can't be locked for assignment, so it can't be assigned to a child.
+        assert_eq!(
+            page.lock_for_assignment(),
+            Err(PageTrackingError::PageNotAssignable)
+        );
+        assert_eq!(
+            page.assign(PageOwnerId::new(2).unwrap(), PageState::Mapped),
+            Err(PageTrackingError::PageNotAssignable)
+        );
+        // Reclaim requires the page to be locked first.
+        assert_eq!(page.reclaim(), Err(PageTrackingError::PageNotLocked));
+        assert!(page.lock_for_reclaim().is_ok());
+        assert_eq!(page.lock_for_reclaim(), Err(PageTrackingError::PageLocked));
+        // Conversion can't complete while the page is being reclaimed, even once it's been fenced.
+        let fenced = version.increment();
+        assert!(page.is_convertible(fenced));
+        assert_eq!(
+            page.complete_conversion(fenced),
+            Err(PageTrackingError::PageLocked)
+        );
+        assert_eq!(page.state(), PageState::Converting(version));
+
+        // Cancel the conversion before it's been fenced.
+        assert!(page.reclaim().is_ok());
+        assert_eq!(page.state(), PageState::Mapped);
+        assert_eq!(page.owner().unwrap(), PageOwnerId::host());
+        assert!(!page.is_convertible(fenced));
+        assert_eq!(
+            page.complete_conversion(fenced),
+            Err(PageTrackingError::PageNotConvertible)
+        );
+
+        // Dropping the lock leaves a converting page to complete its conversion as normal.
+        let version = fenced;
+        assert!(page.begin_conversion(version).is_ok());
+        assert!(page.lock_for_reclaim().is_ok());
+        assert!(page.unlock().is_ok());
+        let fenced = version.increment();
+        assert!(page.complete_conversion(fenced).is_ok());
+        assert_eq!(page.state(), PageState::Converted);
+
+        // Converted pages may be reclaimed after being locked for either assignment or reclaim.
+        assert!(page.lock_for_reclaim().is_ok());
+        assert!(page.reclaim().is_ok());
+        assert_eq!(page.state(), PageState::Mapped);
+        assert!(page.begin_conversion(fenced).is_ok());
+        assert!(page.complete_conversion(fenced.increment()).is_ok());
+        assert!(page.lock_for_assignment().is_ok());
+        assert!(page.reclaim().is_ok());
+        assert_eq!(page.state(), PageState::Mapped);
+    }
+
+    #[test]
+    fn blocked_page() {
+        let mut page = PageInfo::new();
+        assert!(page
+            .assign(PageOwnerId::hypervisor(), PageState::Converted)
+            .is_ok());
+        assert!(page.lock_for_assignment().is_ok());
+        assert!(page.assign(PageOwnerId::host(), PageState::Mapped).is_ok());
+        let version = TlbVersion::new();
+        assert!(page.begin_conversion(version).is_ok());
+        let version = version.increment();
+        assert!(page.complete_conversion(version).is_ok());
+        assert!(page.lock_for_assignment().is_ok());
+        let guest_id = PageOwnerId::new(2).unwrap();
+        assert!(page.assign(guest_id, PageState::Mapped).is_ok());
+
+        // Only mapped pages can be blocked.
+        assert!(page.begin_blocking(version).is_ok());
+        assert_eq!(page.state(), PageState::Blocked(version));
+        assert_eq!(
+            page.begin_blocking(version),
+            Err(PageTrackingError::PageNotConvertible)
+        );
+        assert_eq!(
+            page.begin_conversion(version),
+            Err(PageTrackingError::PageNotConvertible)
+        );
+        assert_eq!(page.owner().unwrap(), guest_id);
+
+        // The owner can't reclaim or assign a blocked page, and it never completes conversion.
+        let fenced = version.increment();
+        assert_eq!(
+            page.lock_for_reclaim(),
+            Err(PageTrackingError::PageNotReclaimable)
+        );
+        assert_eq!(
+            page.lock_for_assignment(),
+            Err(PageTrackingError::PageNotAssignable)
+        );
+        assert!(!page.is_convertible(fenced));
+        assert_eq!(
+            page.complete_conversion(fenced),
+            Err(PageTrackingError::PageNotConvertible)
+        );
+        assert_eq!(page.state(), PageState::Blocked(version));
+
+        // It can be removed once fenced, returning it to the previous owner.
+        assert!(!page.is_removable(version));
+        assert!(page.is_removable(fenced));
+        assert!(page.release().is_ok());
+        assert_eq!(page.owner().unwrap(), PageOwnerId::host());
+        assert_eq!(page.state(), PageState::Converted);
+    }
 }
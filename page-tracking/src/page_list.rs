@@ -4,19 +4,21 @@
 
 use core::marker::PhantomData;
 use core::ops::{Deref, DerefMut};
-use riscv_pages::{PhysPage, SupervisorPageAddr};
+use riscv_pages::{PageSize, PhysPage, SupervisorPageAddr};
 
-use crate::{PageTracker, PageTrackingResult};
+use crate::{PageTracker, PageTrackingError, PageTrackingResult};
 
 /// A linked list of exclusively-owned `PhysPages` created using links in the array of `PageInfo`
 /// structs. This list can be used to pass around a list of non-contiguous pages without having
 /// to allocate storage (e.g. in a `Vec<>`). Pages are unlinked from the list by calling `pop()`.
-/// Any pages remaining on the list when the list is dropped are unlinked.
+/// Any pages remaining on the list when the list is dropped are unlinked. All pages in the list
+/// must be of the same size.
 pub struct PageList<P: PhysPage> {
     page_tracker: PageTracker,
     head: Option<SupervisorPageAddr>,
     tail: Option<SupervisorPageAddr>,
     len: usize,
+    page_size: PageSize,
     page_state: PhantomData<P>,
 }
 
@@ -28,6 +30,7 @@ impl<P: PhysPage> PageList<P> {
             head: None,
             tail: None,
             len: 0,
+            page_size: PageSize::Size4k,
             page_state: PhantomData,
         }
     }
@@ -37,7 +40,7 @@ impl<P: PhysPage> PageList<P> {
     /// # Safety
     ///
     /// The caller must guarantee that all pages in the list (`head` and the pages linked from it)
-    /// are uniquely owned and are of type `P`.
+    /// are uniquely owned 4kB pages of type `P`.
     pub(crate) unsafe fn from_raw_parts(
         page_tracker: PageTracker,
         head: SupervisorPageAddr,
@@ -54,18 +57,24 @@ impl<P: PhysPage> PageList<P> {
             head: Some(head),
             tail: Some(tail),
             len,
+            page_size: PageSize::Size4k,
             page_state: PhantomData,
         }
     }
 
-    /// Appends `page` to the end of the list. Returns an error if `page` is already linked.
+    /// Appends `page` to the end of the list. Returns an error if `page` is already linked or is
+    /// not the same size as the other pages in the list.
     pub fn push(&mut self, page: P) -> PageTrackingResult<()> {
         if let Some(tail_addr) = self.tail {
+            if page.size() != self.page_size {
+                return Err(PageTrackingError::PageSizeMismatch);
+            }
             self.page_tracker.link_pages(tail_addr, page.addr())?;
             self.tail = Some(page.addr());
         } else {
             self.head = Some(page.addr());
             self.tail = Some(page.addr());
+            self.page_size = page.size();
         }
         self.len += 1;
         Ok(())
@@ -81,7 +90,7 @@ impl<P: PhysPage> PageList<P> {
         }
         self.len -= 1;
         // Safety: This list has unique ownership of the page ever sicne it was pushed.
-        Some(unsafe { P::new(addr) })
+        Some(unsafe { P::new_with_size(addr, self.page_size) })
     }
 
     /// Returns if the list is empty.
@@ -94,6 +103,11 @@ impl<P: PhysPage> PageList<P> {
         self.len
     }
 
+    /// Returns the size of the pages in the list.
+    pub fn page_size(&self) -> PageSize {
+        self.page_size
+    }
+
     /// Returns the address of the page at the head of the list.
     pub fn peek(&self) -> Option<SupervisorPageAddr> {
         self.head
@@ -106,8 +120,8 @@ impl<P: PhysPage> PageList<P> {
         }
         let mut prev = self.head.unwrap();
         while let Some(addr) = self.page_tracker.linked_page(prev) {
-            if let Some(next) = prev.checked_add_pages(1) && addr == next {
-                prev = next;
+            if prev.checked_add_pages_with_size(1, self.page_size) == Some(addr) {
+                prev = addr;
             } else {
                 return false;
             }
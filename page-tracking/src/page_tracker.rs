@@ -50,6 +50,8 @@ pub enum Error {
     PageLocked,
     /// Attempt to create a link from a page that is already linked.
     PageAlreadyLinked,
+    /// Attempt to add a page to a list of pages of a different size.
+    PageSizeMismatch,
     /// The ref count was at u64::MAX.
     RefCountOverflow,
     /// The ref count was already 0.
@@ -74,6 +76,39 @@ impl PageTrackerInner {
     fn get(&mut self, addr: SupervisorPageAddr) -> Result<&PageInfo> {
         self.pages.get(addr).ok_or(Error::InvalidPage(addr))
     }
+
+    /// Applies `f` to the `PageInfo` of each 4kB page making up the `page_size` page at `addr`.
+    /// Either all of the constituent pages are updated, or none are if `f` fails for any of them.
+    fn update_pages<F>(&mut self, addr: SupervisorPageAddr, page_size: PageSize, f: F) -> Result<()>
+    where
+        F: Fn(&mut PageInfo) -> Result<()>,
+    {
+        let num_pages = page_size as u64 / PageSize::Size4k as u64;
+        if num_pages > 1 {
+            // Try the update on a copy of each page's state first so that a failure part way
+            // through doesn't leave the huge page partially updated.
+            for a in addr.iter_from().take(num_pages as usize) {
+                let mut info = self.get(a)?.clone();
+                f(&mut info)?;
+            }
+        }
+        for a in addr.iter_from().take(num_pages as usize) {
+            f(self.get_mut(a)?)?;
+        }
+        Ok(())
+    }
+
+    /// Returns true if and only if `pred` holds for the `PageInfo` of each 4kB page making up the
+    /// `page_size` page at `addr`.
+    fn all_pages<F>(&mut self, addr: SupervisorPageAddr, page_size: PageSize, pred: F) -> bool
+    where
+        F: Fn(&PageInfo) -> bool,
+    {
+        let num_pages = page_size as u64 / PageSize::Size4k as u64;
+        addr.iter_from()
+            .take(num_pages as usize)
+            .all(|a| self.get(a).map_or(false, &pred))
+    }
 }
 
 /// This struct wraps the list of all memory pages and active guests. It can be cloned and passed to
@@ -198,8 +233,9 @@ impl PageTracker {
         M: MeasureRequirement,
     {
         let mut page_tracker = self.inner.lock();
-        let info = page_tracker.get_mut(page.addr()).unwrap();
-        info.assign(owner, PageState::Mapped)?;
+        page_tracker.update_pages(page.addr(), page.size(), |info| {
+            info.assign(owner, PageState::Mapped)
+        })?;
         // Safe since we own the page and have updated its state.
         Ok(unsafe { P::MappablePage::new_with_size(page.addr(), page.size()) })
     }
@@ -228,8 +264,9 @@ impl PageTracker {
         owner: PageOwnerId,
     ) -> Result<Page<InternalClean>> {
         let mut page_tracker = self.inner.lock();
-        let info = page_tracker.get_mut(page.addr()).unwrap();
-        info.assign(owner, PageState::VmState)?;
+        page_tracker.update_pages(page.addr(), page.size(), |info| {
+            info.assign(owner, PageState::VmState)
+        })?;
         // Safe since we own the page and have updated its state.
         Ok(unsafe { Page::new_with_size(page.addr(), page.size()) })
     }
@@ -237,22 +274,25 @@ impl PageTracker {
     /// Relases `page` back to its previous owner.
     pub fn release_page<P: PhysPage>(&self, page: P) -> Result<()> {
         let mut page_tracker = self.inner.lock();
-        let info = page_tracker.get_mut(page.addr()).unwrap();
-        info.release()?;
-        Ok(())
+        page_tracker.update_pages(page.addr(), page.size(), |info| info.release())
     }
 
-    /// Releases the page at `addr` back to its previous owner if it's currently owned by `owner`
-    /// and is in a releasable state.
-    pub fn release_page_by_addr(&self, addr: SupervisorPageAddr, owner: PageOwnerId) -> Result<()> {
+    /// Releases the `page_size` page at `addr` back to its previous owner if it's currently owned
+    /// by `owner` and is in a releasable state.
+    pub fn release_page_by_addr(
+        &self,
+        addr: SupervisorPageAddr,
+        page_size: PageSize,
+        owner: PageOwnerId,
+    ) -> Result<()> {
         let mut page_tracker = self.inner.lock();
-        let info = page_tracker.get_mut(addr)?;
-        // Shared pages might be owned by the parent
-        if info.owner() != Some(owner) && !info.is_shared() {
-            return Err(Error::OwnerMismatch);
-        }
-        info.release()?;
-        Ok(())
+        page_tracker.update_pages(addr, page_size, |info| {
+            // Shared pages might be owned by the parent
+            if info.owner() != Some(owner) && !info.is_shared() {
+                return Err(Error::OwnerMismatch);
+            }
+            info.release()
+        })
     }
 
     /// Marks the invalidated page as having started conversion at `tlb_version`.
@@ -262,51 +302,89 @@ impl PageTracker {
         tlb_version: TlbVersion,
     ) -> Result<()> {
         let mut page_tracker = self.inner.lock();
-        let info = page_tracker.get_mut(page.addr()).unwrap();
-        info.begin_conversion(tlb_version)
+        page_tracker.update_pages(page.addr(), page.size(), |info| {
+            info.begin_conversion(tlb_version)
+        })
+    }
+
+    /// Marks the invalidated page as blocked at `tlb_version`, pending its removal by the parent of
+    /// its current owner.
+    pub fn block_page<P: InvalidatedPhysPage>(
+        &self,
+        page: P,
+        tlb_version: TlbVersion,
+    ) -> Result<()> {
+        let mut page_tracker = self.inner.lock();
+        page_tracker.update_pages(page.addr(), page.size(), |info| {
+            info.begin_blocking(tlb_version)
+        })
     }
 
     /// Reclaims the converted, but unassigned, `page` back to a mapped page for the current owner.
     /// Returns a page that can then be mapped in a page table.
     pub fn reclaim_page<P: ReclaimablePhysPage>(&self, page: P) -> Result<P::MappablePage> {
         let mut page_tracker = self.inner.lock();
-        let info = page_tracker.get_mut(page.addr()).unwrap();
-        info.reclaim()?;
+        page_tracker.update_pages(page.addr(), page.size(), |info| info.reclaim())?;
         // Safe since we own the page and have verified that it can be reclaimed.
         Ok(unsafe { P::MappablePage::new_with_size(page.addr(), page.size()) })
     }
 
-    /// Acquires an exclusive reference to the Converted page at `addr` if it's unassigned and owned
-    /// by `owner`. Completes conversion if the page was Converting at a TLB version older than
-    /// `tlb_version`.
+    /// Acquires an exclusive reference to the Converted `page_size` page at `addr` if it's
+    /// unassigned and owned by `owner`. Completes conversion if the page was Converting at a TLB
+    /// version older than `tlb_version`.
     pub fn get_converted_page<P: ConvertedPhysPage>(
         &self,
         addr: SupervisorPageAddr,
+        page_size: PageSize,
         owner: PageOwnerId,
         tlb_version: TlbVersion,
     ) -> Result<P::DirtyPage> {
-        let mut page_tracker = self.inner.lock();
-        let info = page_tracker.get_mut(addr)?;
-        if info.owner() != Some(owner)
-            || info.mem_type() != P::mem_type()
-            || (info.state() != PageState::Converted
-                && info.complete_conversion(tlb_version).is_err())
-        {
-            return Err(Error::PageNotConvertible);
+        if !addr.is_aligned(page_size) {
+            return Err(Error::InvalidPage(addr));
         }
-        info.lock_for_assignment()?;
+        let mut page_tracker = self.inner.lock();
+        page_tracker.update_pages(addr, page_size, |info| {
+            if info.owner() != Some(owner)
+                || info.mem_type() != P::mem_type()
+                || (info.state() != PageState::Converted
+                    && info.complete_conversion(tlb_version).is_err())
+            {
+                return Err(Error::PageNotConvertible);
+            }
+            info.lock_for_assignment()
+        })?;
         // Safe since we've taken exclusive ownership of the page, verified its typing, and that it is
         // converted as of `tlb_version`.
-        //
-        // TODO: Page size
-        Ok(unsafe { P::DirtyPage::new(addr) })
+        Ok(unsafe { P::DirtyPage::new_with_size(addr, page_size) })
+    }
+
+    /// Acquires an exclusive reference to the `page_size` page at `addr` if it's owned by `owner`
+    /// and is either Converted and unassigned, or still Converting, so that it can be reclaimed.
+    pub fn get_reclaimable_page<P: ConvertedPhysPage>(
+        &self,
+        addr: SupervisorPageAddr,
+        page_size: PageSize,
+        owner: PageOwnerId,
+    ) -> Result<P::DirtyPage> {
+        if !addr.is_aligned(page_size) {
+            return Err(Error::InvalidPage(addr));
+        }
+        let mut page_tracker = self.inner.lock();
+        page_tracker.update_pages(addr, page_size, |info| {
+            if info.owner() != Some(owner) || info.mem_type() != P::mem_type() {
+                return Err(Error::PageNotReclaimable);
+            }
+            info.lock_for_reclaim()
+        })?;
+        // Safe since we've taken exclusive ownership of the page and verified its typing. The page
+        // can't have been assigned to anyone else while Converting.
+        Ok(unsafe { P::DirtyPage::new_with_size(addr, page_size) })
     }
 
     /// Releases an exclusive reference to a locked page
     pub fn unlock_page<P: PhysPage>(&self, page: P) -> Result<()> {
         let mut page_tracker = self.inner.lock();
-        let info = page_tracker.get_mut(page.addr())?;
-        info.unlock()
+        page_tracker.update_pages(page.addr(), page.size(), |info| info.unlock())
     }
 
     /// Returns true if and only if `addr` is a page owned by `owner`.
@@ -319,21 +397,49 @@ impl PageTracker {
         }
     }
 
-    /// Returns true if and only if `addr` is a "Mapped" page owned by `owner` with type `mem_type`.
+    /// Returns true if and only if `addr` is a `page_size` page of RAM owned by `owner` that isn't
+    /// shared with any other VM.
+    pub fn is_private_ram_page(
+        &self,
+        addr: SupervisorPageAddr,
+        page_size: PageSize,
+        owner: PageOwnerId,
+    ) -> bool {
+        let mut page_tracker = self.inner.lock();
+        page_tracker.all_pages(addr, page_size, |info| {
+            info.owner() == Some(owner) && info.mem_type() == MemType::Ram && !info.is_shared()
+        })
+    }
+
+    /// Returns true if and only if `addr` is a "Mapped" `page_size` page owned by `owner` with type
+    /// `mem_type`.
     pub fn is_mapped_page(
         &self,
         addr: SupervisorPageAddr,
+        page_size: PageSize,
         owner: PageOwnerId,
         mem_type: MemType,
     ) -> bool {
         let mut page_tracker = self.inner.lock();
-        if let Ok(info) = page_tracker.get(addr) {
+        page_tracker.all_pages(addr, page_size, |info| {
             info.owner() == Some(owner)
                 && info.mem_type() == mem_type
                 && info.state() == PageState::Mapped
-        } else {
-            false
-        }
+        })
+    }
+
+    /// Returns true if and only if `addr` is a `page_size` page of type `mem_type` that is
+    /// currently shared.
+    pub fn is_shared_page(
+        &self,
+        addr: SupervisorPageAddr,
+        page_size: PageSize,
+        mem_type: MemType,
+    ) -> bool {
+        let mut page_tracker = self.inner.lock();
+        page_tracker.all_pages(addr, page_size, |info| {
+            info.mem_type() == mem_type && info.is_shared()
+        })
     }
 
     /// Acquires an exclusive reference to the shareable page at `addr` if it's owned by owner,
@@ -355,23 +461,67 @@ impl PageTracker {
         Ok(unsafe { P::new(addr) })
     }
 
-    /// Returns true if and only if `addr` is a page owned by `owner` with type `mem_type` and
-    /// was converted at a TLB version older than `tlb_version`.
+    /// Returns true if and only if `addr` is a `page_size` page owned by `owner` with type
+    /// `mem_type` and was converted at a TLB version older than `tlb_version`. Pages that are
+    /// currently locked, e.g. because they're being reclaimed, aren't considered to be converted.
     pub fn is_converted_page(
         &self,
         addr: SupervisorPageAddr,
+        page_size: PageSize,
         owner: PageOwnerId,
         mem_type: MemType,
         tlb_version: TlbVersion,
     ) -> bool {
         let mut page_tracker = self.inner.lock();
-        if let Ok(info) = page_tracker.get(addr) {
+        page_tracker.all_pages(addr, page_size, |info| {
             info.owner() == Some(owner)
                 && info.mem_type() == mem_type
+                && !info.is_locked()
                 && (info.state() == PageState::Converted || info.is_convertible(tlb_version))
-        } else {
-            false
-        }
+        })
+    }
+
+    /// Returns true if and only if `addr` is a `page_size` page owned by `owner` with type
+    /// `mem_type` and can be removed from `owner` at `tlb_version`, i.e. it's either converted or
+    /// was converting or blocked at an older TLB version. Pages that are currently locked aren't
+    /// considered to be removable.
+    pub fn is_removable_page(
+        &self,
+        addr: SupervisorPageAddr,
+        page_size: PageSize,
+        owner: PageOwnerId,
+        mem_type: MemType,
+        tlb_version: TlbVersion,
+    ) -> bool {
+        let mut page_tracker = self.inner.lock();
+        page_tracker.all_pages(addr, page_size, |info| {
+            info.owner() == Some(owner)
+                && info.mem_type() == mem_type
+                && !info.is_locked()
+                && info.is_removable(tlb_version)
+        })
+    }
+
+    /// Returns true if and only if `addr` is a `page_size` page owned by `owner` with type
+    /// `mem_type` that has started, but not yet completed, conversion, or that has been blocked.
+    /// Pages that are currently locked aren't considered to be converting.
+    pub fn is_converting_page(
+        &self,
+        addr: SupervisorPageAddr,
+        page_size: PageSize,
+        owner: PageOwnerId,
+        mem_type: MemType,
+    ) -> bool {
+        let mut page_tracker = self.inner.lock();
+        page_tracker.all_pages(addr, page_size, |info| {
+            info.owner() == Some(owner)
+                && info.mem_type() == mem_type
+                && !info.is_locked()
+                && matches!(
+                    info.state(),
+                    PageState::Converting(_) | PageState::Blocked(_)
+                )
+        })
     }
 
     /// Returns true if and only if `addr` is a `VmState` page owned by `owner`.
@@ -657,4 +807,55 @@ mod tests {
 
         assert_eq!(page_tracker.inner.lock().active_guests.len(), 1);
     }
+
+    #[test]
+    fn huge_page_conversion() {
+        let (page_tracker, host_pages) = stub_page_tracker();
+        let mut host_pages = host_pages.skip_while(|p| !p.addr().is_aligned(PageSize::Size2M));
+        let base = host_pages.next().unwrap().addr();
+        let last = host_pages.nth(510).unwrap().addr();
+        assert_eq!(
+            last.bits(),
+            base.bits() + PageSize::Size2M as u64 - PageSize::Size4k as u64
+        );
+
+        let id = PageOwnerId::host();
+        let huge: Page<ConvertedClean> = unsafe {
+            // Not safe - just a test
+            Page::new_with_size(base, PageSize::Size2M)
+        };
+        let mapped = page_tracker.assign_page_for_mapping(huge, id).unwrap();
+        assert!(page_tracker.is_mapped_page(base, PageSize::Size2M, id, MemType::Ram));
+        assert!(page_tracker.is_mapped_page(last, PageSize::Size4k, id, MemType::Ram));
+
+        let version = TlbVersion::new();
+        let invalidated: Page<Invalidated> =
+            unsafe { Page::new_with_size(mapped.addr(), mapped.size()) };
+        page_tracker.convert_page(invalidated, version).unwrap();
+        assert!(!page_tracker.is_converted_page(base, PageSize::Size2M, id, MemType::Ram, version));
+        assert!(page_tracker.is_converting_page(base, PageSize::Size2M, id, MemType::Ram));
+        let version = version.increment();
+        assert!(page_tracker.is_converted_page(base, PageSize::Size2M, id, MemType::Ram, version));
+        let converted = page_tracker
+            .get_converted_page::<Page<ConvertedDirty>>(base, PageSize::Size2M, id, version)
+            .unwrap();
+        assert_eq!(converted.size(), PageSize::Size2M);
+        assert!(!page_tracker.is_converting_page(base, PageSize::Size2M, id, MemType::Ram));
+        let reclaimed = page_tracker.reclaim_page(converted.clean()).unwrap();
+        assert!(page_tracker.is_mapped_page(base, PageSize::Size2M, id, MemType::Ram));
+
+        // Conversion must fail without modifying any page if one of the 4kB pages making up the
+        // huge page can't be converted.
+        page_tracker
+            .release_page_by_addr(last, PageSize::Size4k, id)
+            .unwrap();
+        let invalidated: Page<Invalidated> =
+            unsafe { Page::new_with_size(reclaimed.addr(), reclaimed.size()) };
+        assert_eq!(
+            page_tracker.convert_page(invalidated, version),
+            Err(Error::PageNotConvertible)
+        );
+        assert!(page_tracker.is_mapped_page(base, PageSize::Size4k, id, MemType::Ram));
+        assert!(!page_tracker.is_mapped_page(base, PageSize::Size2M, id, MemType::Ram));
+    }
 }
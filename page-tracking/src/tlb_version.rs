@@ -2,16 +2,21 @@
 // Licensed under the Apache License, Version 2.0, see LICENSE for details.
 // SPDX-License-Identifier: Apache-2.0
 
+use arrayvec::ArrayVec;
+use riscv_pages::{GuestPageAddr, PageSize};
+
 /// A TLB version number.
 ///
 /// We use TLB versions to track the progress of a coordinated TLB shootdown across multiple CPUs.
 /// A shootdown is considered completed if the TLB version at which the shootdown was initiated is
-/// older (less than) the current TLB version.
+/// older than the current TLB version.
 ///
-/// TODO: Overflow. A simple '!=' check against the current version should be sufficient since a
-/// page cannot have been invalidated at a future version. Let's do the more defensive thing for now
-/// though given how long it'll take a 64-bit counter to overflow.
-#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, PartialOrd, Ord)]
+/// The version counter wraps on overflow. Versions are compared using serial number arithmetic:
+/// the wrapping distance between two versions is interpreted as a signed quantity, so ordering is
+/// preserved across wraparound as long as the versions being compared are less than 2^63
+/// increments apart. Comparisons are deliberately not exposed through `PartialOrd` as the ordering
+/// isn't transitive over the entire range of versions.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
 pub struct TlbVersion(u64);
 
 impl TlbVersion {
@@ -20,8 +25,238 @@ impl TlbVersion {
         TlbVersion(0)
     }
 
-    /// Increments this TLB version number.
+    /// Creates a TLB version number with the raw counter value `version`.
+    #[cfg(test)]
+    pub(crate) fn from_raw(version: u64) -> Self {
+        TlbVersion(version)
+    }
+
+    /// Increments this TLB version number, wrapping on overflow.
     pub fn increment(self) -> Self {
-        TlbVersion(self.0 + 1)
+        TlbVersion(self.0.wrapping_add(1))
+    }
+
+    /// Returns true if `self` is an older version than `other`.
+    pub fn is_older_than(self, other: TlbVersion) -> bool {
+        (other.0.wrapping_sub(self.0) as i64) > 0
+    }
+}
+
+/// The maximum number of invalidated guest physical address ranges tracked for fine-grained TLB
+/// invalidation.
+const MAX_INVALIDATED_RANGES: usize = 16;
+
+/// The maximum number of pages to fence individually. Fences covering more pages than this flush
+/// the entire address space instead.
+pub const MAX_RANGED_FENCE_PAGES: u64 = 64;
+
+/// A range of guest physical address space whose mappings were invalidated at a given TLB version.
+#[derive(Clone, Copy, Debug)]
+struct InvalidatedRange {
+    version: TlbVersion,
+    addr: GuestPageAddr,
+    page_size: PageSize,
+    num_pages: u64,
+}
+
+/// Tracks the most recently invalidated ranges of a guest physical address space so that a TLB
+/// shootdown can fence just the invalidated pages rather than the entire address space.
+#[derive(Default)]
+pub struct InvalidatedRanges {
+    // The most recently invalidated ranges, oldest first.
+    ranges: ArrayVec<InvalidatedRange, MAX_INVALIDATED_RANGES>,
+    // The newest TLB version of any invalidated range that has been dropped from `ranges`.
+    evicted: Option<TlbVersion>,
+}
+
+impl InvalidatedRanges {
+    /// Creates an empty set of invalidated ranges.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records that the mappings for `num_pages` of size `page_size` starting at `addr` were
+    /// invalidated at `version`. Ranges must be recorded in version order.
+    pub fn record(
+        &mut self,
+        version: TlbVersion,
+        addr: GuestPageAddr,
+        page_size: PageSize,
+        num_pages: u64,
+    ) {
+        if self.ranges.is_full() {
+            // Drop the oldest range. Anyone that needs to fence it will flush everything instead.
+            // Ranges are recorded in version order, so this is the newest version evicted so far.
+            let oldest = self.ranges.remove(0);
+            self.evicted = Some(oldest.version);
+        }
+        self.ranges.push(InvalidatedRange {
+            version,
+            addr,
+            page_size,
+            num_pages,
+        });
+    }
+
+    /// Calls `fence_page` with the address of each page invalidated at TLB versions from `from` up
+    /// to, but not including, `to`. Returns false without calling `fence_page` if those pages are
+    /// no longer all tracked, or are too numerous to be fenced individually, in which case the
+    /// caller must fence the entire address space instead.
+    pub fn fence_pages<F>(&self, from: TlbVersion, to: TlbVersion, mut fence_page: F) -> bool
+    where
+        F: FnMut(GuestPageAddr),
+    {
+        // Versions too far apart to be ordered reliably are handled by fencing everything.
+        if !from.is_older_than(to) || self.evicted.map_or(false, |v| !v.is_older_than(from)) {
+            return false;
+        }
+        let ranges = self
+            .ranges
+            .iter()
+            .filter(|r| !r.version.is_older_than(from) && r.version.is_older_than(to));
+        let num_pages: u64 = ranges.clone().map(|r| r.num_pages).sum();
+        if num_pages > MAX_RANGED_FENCE_PAGES {
+            return false;
+        }
+        for r in ranges {
+            // Unwrap ok since the range must have been `page_size`-aligned to be invalidated.
+            r.addr
+                .iter_from_with_size(r.page_size)
+                .unwrap()
+                .take(r.num_pages as usize)
+                .for_each(&mut fence_page);
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use riscv_pages::{PageAddr, PageOwnerId, RawAddr};
+    use std::vec::Vec;
+
+    fn guest_addr(gpa: u64) -> GuestPageAddr {
+        PageAddr::new(RawAddr::guest(gpa, PageOwnerId::host())).unwrap()
+    }
+
+    // Returns the pages fenced between `from` and `to`, or None if a full fence is required.
+    fn fenced_pages(
+        ranges: &InvalidatedRanges,
+        from: TlbVersion,
+        to: TlbVersion,
+    ) -> Option<Vec<u64>> {
+        let mut pages = Vec::new();
+        ranges
+            .fence_pages(from, to, |addr| pages.push(addr.bits()))
+            .then_some(pages)
+    }
+
+    #[test]
+    fn version_ordering() {
+        let v = TlbVersion::new();
+        let next = v.increment();
+        assert!(v.is_older_than(next));
+        assert!(!next.is_older_than(v));
+        assert!(!v.is_older_than(v));
+    }
+
+    #[test]
+    fn version_wraparound() {
+        let last = TlbVersion::from_raw(u64::MAX);
+        let first = last.increment();
+        assert_eq!(first, TlbVersion::new());
+        assert!(last.is_older_than(first));
+        assert!(!first.is_older_than(last));
+
+        // Ordering is maintained for every version in a window straddling the wraparound point.
+        let mut prev = TlbVersion::from_raw(u64::MAX - 1000);
+        for _ in 0..2000 {
+            let next = prev.increment();
+            assert!(prev.is_older_than(next));
+            assert!(!next.is_older_than(prev));
+            prev = next;
+        }
+    }
+
+    #[test]
+    fn fence_invalidated_ranges() {
+        let mut ranges = InvalidatedRanges::new();
+        let v0 = TlbVersion::new();
+        let v1 = v0.increment();
+        let v2 = v1.increment();
+        let v3 = v2.increment();
+        ranges.record(v0, guest_addr(0x1000), PageSize::Size4k, 1);
+        ranges.record(v1, guest_addr(0x2000), PageSize::Size4k, 2);
+        ranges.record(v1, guest_addr(0x20_0000), PageSize::Size2M, 1);
+        ranges.record(v2, guest_addr(0x8000), PageSize::Size4k, 1);
+
+        // Only ranges invalidated at versions in [from, to) are fenced.
+        assert_eq!(fenced_pages(&ranges, v0, v1), Some(vec![0x1000]));
+        assert_eq!(
+            fenced_pages(&ranges, v1, v2),
+            Some(vec![0x2000, 0x3000, 0x20_0000])
+        );
+        assert_eq!(fenced_pages(&ranges, v2, v3), Some(vec![0x8000]));
+        assert_eq!(
+            fenced_pages(&ranges, v0, v3),
+            Some(vec![0x1000, 0x2000, 0x3000, 0x20_0000, 0x8000])
+        );
+        // Nothing was invalidated at or after v3.
+        assert_eq!(fenced_pages(&ranges, v3, v3.increment()), Some(vec![]));
+        // An empty or inverted window can't be fenced in ranges.
+        assert_eq!(fenced_pages(&ranges, v1, v1), None);
+        assert_eq!(fenced_pages(&ranges, v2, v1), None);
+    }
+
+    #[test]
+    fn fence_too_many_pages() {
+        let mut ranges = InvalidatedRanges::new();
+        let v0 = TlbVersion::new();
+        let v1 = v0.increment();
+        ranges.record(
+            v0,
+            guest_addr(0x10_0000),
+            PageSize::Size4k,
+            MAX_RANGED_FENCE_PAGES,
+        );
+        let pages = fenced_pages(&ranges, v0, v1).unwrap();
+        assert_eq!(pages.len(), MAX_RANGED_FENCE_PAGES as usize);
+
+        // One more page takes the total over the limit, requiring a full fence.
+        ranges.record(v0, guest_addr(0x1000), PageSize::Size4k, 1);
+        assert_eq!(fenced_pages(&ranges, v0, v1), None);
+        // Pages outside the window don't count towards the limit.
+        ranges.record(v1, guest_addr(0x2000), PageSize::Size4k, 1);
+        assert_eq!(
+            fenced_pages(&ranges, v1, v1.increment()),
+            Some(vec![0x2000])
+        );
+    }
+
+    #[test]
+    fn fence_evicted_ranges() {
+        let mut ranges = InvalidatedRanges::new();
+        let mut version = TlbVersion::new();
+        let first = version;
+        for i in 0..MAX_INVALIDATED_RANGES as u64 {
+            ranges.record(version, guest_addr(0x1000 * (i + 1)), PageSize::Size4k, 1);
+            version = version.increment();
+        }
+        let pages = fenced_pages(&ranges, first, version).unwrap();
+        assert_eq!(pages.len(), MAX_INVALIDATED_RANGES);
+
+        // Recording one more range evicts the oldest, so fences that include its version must flush
+        // everything.
+        ranges.record(version, guest_addr(0x100_0000), PageSize::Size4k, 1);
+        let last = version.increment();
+        assert_eq!(fenced_pages(&ranges, first, last), None);
+        assert_eq!(fenced_pages(&ranges, first, first.increment()), None);
+        // Fences of only the versions still tracked can be done in ranges.
+        let second = first.increment();
+        let pages = fenced_pages(&ranges, second, last).unwrap();
+        assert_eq!(pages.len(), MAX_INVALIDATED_RANGES);
+        assert_eq!(pages[0], 0x2000);
+        assert_eq!(pages[MAX_INVALIDATED_RANGES - 1], 0x100_0000);
     }
 }
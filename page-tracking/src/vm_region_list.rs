@@ -0,0 +1,401 @@
+// Copyright (c) 2022 by Rivos Inc.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use core::result;
+use riscv_pages::{GuestPageAddr, GuestPhysAddr, InternalClean, SequentialPages};
+use spin::Mutex;
+
+use crate::collections::PageVec;
+use crate::PageTracker;
+
+/// Errors that can be raised while manipulating a `VmRegionList`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The region overlaps with an existing region.
+    OverlappingRegion,
+
+    /// No more entries available in the region list.
+    InsufficientSpace,
+
+    /// The range isn't contained within a single region of the expected type.
+    InvalidRegion,
+}
+/// Holds the result of region list operations.
+pub type Result<T> = result::Result<T, Error>;
+
+/// Types of regions in a VM's guest physical address space.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum VmRegionType {
+    /// Memory that is private to this VM.
+    Confidential,
+    /// Memory that is shared with the parent
+    Shared,
+    /// Emulated MMIO region; accesses always cause a fault that is forwarded to the VM's host.
+    Mmio,
+    /// IMSIC interrupt file pages.
+    Imsic,
+    /// PCI BAR pages.
+    Pci,
+}
+
+/// A contiguous region of guest physical address space.
+struct VmRegion {
+    start: GuestPageAddr,
+    end: GuestPageAddr,
+    region_type: VmRegionType,
+}
+
+/// The regions of guest physical address space for a VM. Used to track which parts of the address
+/// space are designated for a particular purpose. The region list is created during VM initialization
+/// and remains static after the VM is finalized, except for conversions between confidential and
+/// shared memory. Pages may only be inserted into a VM's address space if the mapping falls within a
+/// region of the proper type.
+pub struct VmRegionList {
+    regions: Mutex<PageVec<VmRegion>>,
+}
+
+impl VmRegionList {
+    /// Creates a new `VmRegionList` using `pages` as the backing store.
+    pub fn new(pages: SequentialPages<InternalClean>, page_tracker: PageTracker) -> Self {
+        Self {
+            regions: Mutex::new(PageVec::new(pages, page_tracker)),
+        }
+    }
+
+    /// Inserts a region at [`start`, `end`) of type `region_type`.
+    pub fn add(
+        &self,
+        start: GuestPageAddr,
+        end: GuestPageAddr,
+        region_type: VmRegionType,
+    ) -> Result<()> {
+        let mut regions = self.regions.lock();
+        // Keep the list sorted, inserting the region in the requested spot as long as it doesn't
+        // overlap with anything else.
+        let mut index = 0;
+        for other in regions.iter() {
+            if other.start > start {
+                if other.start < end {
+                    return Err(Error::OverlappingRegion);
+                }
+                break;
+            } else if other.end > start {
+                return Err(Error::OverlappingRegion);
+            }
+            index += 1;
+        }
+        let region = VmRegion {
+            start,
+            end,
+            region_type,
+        };
+        regions
+            .try_reserve(1)
+            .map_err(|_| Error::InsufficientSpace)?;
+        regions.insert(index, region);
+
+        // Coalesce with same-typed regions before and after this one, if possible.
+        Self::coalesce(&mut regions, index);
+
+        Ok(())
+    }
+
+    /// Changes the type of the range [`start`, `end`) from `from` to `to`. The range must be fully
+    /// contained within a single region of type `from`. `f` is called with the list locked before
+    /// the type is changed, and the type is left unchanged if `f` fails.
+    pub fn change_type<E, F>(
+        &self,
+        start: GuestPageAddr,
+        end: GuestPageAddr,
+        from: VmRegionType,
+        to: VmRegionType,
+        f: F,
+    ) -> result::Result<(), E>
+    where
+        E: From<Error>,
+        F: FnOnce() -> result::Result<(), E>,
+    {
+        let mut regions = self.regions.lock();
+        let mut index = regions
+            .iter()
+            .position(|r| r.start <= start && r.end >= end && r.region_type == from)
+            .ok_or(Error::InvalidRegion)?;
+        // Splitting the region may require up to two more entries.
+        regions
+            .try_reserve(2)
+            .map_err(|_| Error::InsufficientSpace)?;
+        f()?;
+
+        let (region_start, region_end) = (regions[index].start, regions[index].end);
+        regions[index].start = start;
+        regions[index].end = end;
+        regions[index].region_type = to;
+        if region_end > end {
+            let after = VmRegion {
+                start: end,
+                end: region_end,
+                region_type: from,
+            };
+            regions.insert(index + 1, after);
+        }
+        if region_start < start {
+            let before = VmRegion {
+                start: region_start,
+                end: start,
+                region_type: from,
+            };
+            regions.insert(index, before);
+            index += 1;
+        }
+        Self::coalesce(&mut regions, index);
+
+        Ok(())
+    }
+
+    // Coalesces the region at `index` with the same-typed regions before and after it, if possible.
+    fn coalesce(regions: &mut PageVec<VmRegion>, mut index: usize) {
+        let (mut start, end, region_type) = match regions.get(index) {
+            Some(r) => (r.start, r.end, r.region_type),
+            None => return,
+        };
+
+        // Avoid potential underflows and overflows on index.
+        if let Some(ref mut before) = index.checked_sub(1).and_then(|i| regions.get_mut(i)) {
+            if before.end == start && before.region_type == region_type {
+                before.end = end;
+                start = before.start;
+                regions.remove(index);
+                index -= 1;
+            }
+        }
+
+        if let Some(ref mut after) = index.checked_add(1).and_then(|i| regions.get_mut(i)) {
+            if after.start == end && after.region_type == region_type {
+                after.start = start;
+                regions.remove(index);
+            }
+        }
+    }
+
+    /// Returns if the range [`start`, `end`) is fully contained within a region of type `region_type`.
+    pub fn contains(
+        &self,
+        start: GuestPageAddr,
+        end: GuestPageAddr,
+        region_type: VmRegionType,
+    ) -> bool {
+        let regions = self.regions.lock();
+        regions
+            .iter()
+            .any(|r| r.start <= start && r.end >= end && r.region_type == region_type)
+    }
+
+    /// Calls `f` with the start, end and type of each region, in order of increasing address,
+    /// stopping at the first error.
+    pub fn try_for_each<E, F>(&self, mut f: F) -> result::Result<(), E>
+    where
+        F: FnMut(GuestPageAddr, GuestPageAddr, VmRegionType) -> result::Result<(), E>,
+    {
+        let regions = self.regions.lock();
+        regions
+            .iter()
+            .try_for_each(|r| f(r.start, r.end, r.region_type))
+    }
+
+    /// Returns the type of the region that `addr` resides in, or `None` if it's not in any region.
+    pub fn find(&self, addr: GuestPhysAddr) -> Option<VmRegionType> {
+        let regions = self.regions.lock();
+        regions
+            .iter()
+            .find(|r| r.start.bits() <= addr.bits() && r.end.bits() > addr.bits())
+            .map(|r| r.region_type)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+    use riscv_pages::{PageAddr, PageOwnerId, RawAddr};
+    use VmRegionType::*;
+
+    fn stub_region_list() -> VmRegionList {
+        let (page_tracker, mut pages) = PageTracker::new_in_test();
+        let region_pages = SequentialPages::from_pages(pages.by_ref().take(1).map(|p| {
+            page_tracker
+                .assign_page_for_internal_state(p, PageOwnerId::host())
+                .unwrap()
+        }))
+        .unwrap();
+        VmRegionList::new(region_pages, page_tracker)
+    }
+
+    fn guest_addr(addr: u64) -> GuestPageAddr {
+        PageAddr::new(RawAddr::guest(addr, PageOwnerId::host())).unwrap()
+    }
+
+    // Returns the start, end and type of each region in `list`.
+    fn regions(list: &VmRegionList) -> Vec<(u64, u64, VmRegionType)> {
+        let mut regions = Vec::new();
+        list.try_for_each::<(), _>(|start, end, region_type| {
+            regions.push((start.bits(), end.bits(), region_type));
+            Ok(())
+        })
+        .unwrap();
+        regions
+    }
+
+    fn add(list: &VmRegionList, start: u64, end: u64, region_type: VmRegionType) -> Result<()> {
+        list.add(guest_addr(start), guest_addr(end), region_type)
+    }
+
+    fn change_type(
+        list: &VmRegionList,
+        start: u64,
+        end: u64,
+        from: VmRegionType,
+        to: VmRegionType,
+    ) -> Result<()> {
+        list.change_type(guest_addr(start), guest_addr(end), from, to, || Ok(()))
+    }
+
+    #[test]
+    fn add_and_coalesce() {
+        let list = stub_region_list();
+        add(&list, 0x3000, 0x4000, Confidential).unwrap();
+        add(&list, 0x1000, 0x2000, Confidential).unwrap();
+        add(&list, 0x4000, 0x5000, Shared).unwrap();
+        assert_eq!(
+            regions(&list),
+            [
+                (0x1000, 0x2000, Confidential),
+                (0x3000, 0x4000, Confidential),
+                (0x4000, 0x5000, Shared)
+            ]
+        );
+        assert_eq!(
+            add(&list, 0x1000, 0x3000, Confidential),
+            Err(Error::OverlappingRegion)
+        );
+        assert_eq!(
+            add(&list, 0x2000, 0x4000, Confidential),
+            Err(Error::OverlappingRegion)
+        );
+
+        // Filling the gap merges the same-typed regions on either side.
+        add(&list, 0x2000, 0x3000, Confidential).unwrap();
+        assert_eq!(
+            regions(&list),
+            [(0x1000, 0x4000, Confidential), (0x4000, 0x5000, Shared)]
+        );
+        assert_eq!(list.find(guest_addr(0x3000).into()), Some(Confidential));
+        assert_eq!(list.find(guest_addr(0x4000).into()), Some(Shared));
+        assert_eq!(list.find(guest_addr(0x5000).into()), None);
+    }
+
+    #[test]
+    fn contains_range() {
+        let list = stub_region_list();
+        add(&list, 0x1000, 0x3000, Confidential).unwrap();
+        add(&list, 0x3000, 0x5000, Shared).unwrap();
+        let contains = |start, end, region_type| {
+            list.contains(guest_addr(start), guest_addr(end), region_type)
+        };
+        assert!(contains(0x1000, 0x3000, Confidential));
+        assert!(contains(0x2000, 0x3000, Confidential));
+        assert!(contains(0x3000, 0x4000, Shared));
+        // The range must lie within a single region of the given type.
+        assert!(!contains(0x3000, 0x4000, Confidential));
+        assert!(!contains(0x2000, 0x4000, Confidential));
+        assert!(!contains(0x1000, 0x5000, Shared));
+        assert!(!contains(0x5000, 0x6000, Confidential));
+    }
+
+    #[test]
+    fn change_type_within_region() {
+        let list = stub_region_list();
+        add(&list, 0x1000, 0x5000, Confidential).unwrap();
+
+        // Head of the region.
+        change_type(&list, 0x1000, 0x2000, Confidential, Shared).unwrap();
+        assert_eq!(
+            regions(&list),
+            [(0x1000, 0x2000, Shared), (0x2000, 0x5000, Confidential)]
+        );
+
+        // Tail of the region.
+        change_type(&list, 0x4000, 0x5000, Confidential, Shared).unwrap();
+        assert_eq!(
+            regions(&list),
+            [
+                (0x1000, 0x2000, Shared),
+                (0x2000, 0x4000, Confidential),
+                (0x4000, 0x5000, Shared)
+            ]
+        );
+
+        // Middle of a region, which is coalesced back into the region it was split from.
+        change_type(&list, 0x4000, 0x5000, Shared, Confidential).unwrap();
+        change_type(&list, 0x1000, 0x2000, Shared, Confidential).unwrap();
+        assert_eq!(regions(&list), [(0x1000, 0x5000, Confidential)]);
+        change_type(&list, 0x2000, 0x3000, Confidential, Shared).unwrap();
+        assert_eq!(
+            regions(&list),
+            [
+                (0x1000, 0x2000, Confidential),
+                (0x2000, 0x3000, Shared),
+                (0x3000, 0x5000, Confidential)
+            ]
+        );
+
+        // Changing a whole region coalesces it with its neighbors.
+        change_type(&list, 0x2000, 0x3000, Shared, Confidential).unwrap();
+        assert_eq!(regions(&list), [(0x1000, 0x5000, Confidential)]);
+    }
+
+    #[test]
+    fn change_type_failures() {
+        let list = stub_region_list();
+        add(&list, 0x1000, 0x3000, Confidential).unwrap();
+        add(&list, 0x3000, 0x5000, Shared).unwrap();
+        let before = regions(&list);
+
+        // The range must be contained within a single region of the right type.
+        assert_eq!(
+            change_type(&list, 0x2000, 0x4000, Confidential, Shared),
+            Err(Error::InvalidRegion)
+        );
+        assert_eq!(
+            change_type(&list, 0x1000, 0x5000, Confidential, Shared),
+            Err(Error::InvalidRegion)
+        );
+        assert_eq!(
+            change_type(&list, 0x1000, 0x2000, Shared, Confidential),
+            Err(Error::InvalidRegion)
+        );
+        assert_eq!(
+            change_type(&list, 0x5000, 0x6000, Shared, Confidential),
+            Err(Error::InvalidRegion)
+        );
+        assert_eq!(regions(&list), before);
+
+        // The list is left unchanged if the closure fails.
+        let mut called = false;
+        assert_eq!(
+            list.change_type(
+                guest_addr(0x1000),
+                guest_addr(0x2000),
+                Confidential,
+                Shared,
+                || {
+                    called = true;
+                    Err(Error::InsufficientSpace)
+                }
+            ),
+            Err(Error::InsufficientSpace)
+        );
+        assert!(called);
+        assert_eq!(regions(&list), before);
+    }
+}
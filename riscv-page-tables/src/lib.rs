@@ -13,8 +13,8 @@
 //! - `GuestStagePageTable` is a top-level page table structures used to manipulate address translation
 //! and protection.
 //! - `PageTable` provides a generic implementation of a single level of multi-level translation.
-//! - `Sv48x4`, `Sv48`, etc. define standard RISC-V translation modes for 1st or 2nd-stage translation
-//! tables.
+//! - `Sv39x4`, `Sv48x4`, `Sv57x4`, `Sv48`, etc. define standard RISC-V translation modes for 1st or
+//! 2nd-stage translation tables.
 //!
 //! ## Safety
 //!
@@ -33,10 +33,14 @@ extern crate alloc;
 mod page_table;
 /// Provides access to the fields of a riscv PTE.
 mod pte;
+/// Interfaces to build and manage sv39x4 page tables for VMs.
+pub mod sv39x4;
 /// Interfaces to build and manage sv48 page tables for S and U mode access.
 mod sv48;
 /// Interfaces to build and manage sv48x4 page tables for VMs.
 pub mod sv48x4;
+/// Interfaces to build and manage sv57x4 page tables for VMs.
+pub mod sv57x4;
 /// Provides low-level TLB management functions such as fencing.
 pub mod tlb;
 
@@ -47,8 +51,10 @@ pub use page_table::{
     GuestStagePagingMode, PagingMode,
 };
 pub use pte::{PteFieldBits, PteLeafPerms};
+pub use sv39x4::Sv39x4;
 pub use sv48::Sv48;
 pub use sv48x4::Sv48x4;
+pub use sv57x4::Sv57x4;
 
 #[cfg(test)]
 #[macro_use]
@@ -62,8 +68,10 @@ mod tests {
     use std::{mem, slice};
 
     use super::page_table::*;
+    use super::sv39x4::Sv39x4;
     use super::sv48::Sv48;
     use super::sv48x4::Sv48x4;
+    use super::sv57x4::Sv57x4;
     use super::*;
 
     struct StubState {
@@ -95,7 +103,7 @@ mod tests {
         let mut hyp_mem = HypPageAlloc::new(hw_map);
         let root_pages =
             hyp_mem.take_pages_for_host_state_with_alignment(4, Sv48x4::TOP_LEVEL_ALIGN);
-        let pte_pages = hyp_mem.take_pages_for_host_state(3);
+        let pte_pages = hyp_mem.take_pages_for_host_state(5);
         let (page_tracker, host_pages) = PageTracker::from(hyp_mem, Sv48x4::TOP_LEVEL_ALIGN);
         // Leak the backing ram so it doesn't get freed
         std::mem::forget(backing_mem);
@@ -107,6 +115,91 @@ mod tests {
         }
     }
 
+    // A host-owned guest-stage page table along with the pages needed to populate it.
+    struct StubGuestPageTable<T: GuestStagePagingMode> {
+        page_table: GuestStagePageTable<T>,
+        page_tracker: PageTracker,
+        host_pages: PageList<Page<ConvertedClean>>,
+        pte_pages: SeqPageIter<InternalClean>,
+        id: PageOwnerId,
+    }
+
+    impl<T: GuestStagePagingMode> StubGuestPageTable<T> {
+        fn new() -> Self {
+            let state = stub_sys_memory();
+            let id = PageOwnerId::host();
+            let page_table =
+                GuestStagePageTable::new(state.root_pages, id, state.page_tracker.clone())
+                    .expect("creating page table");
+            Self {
+                page_table,
+                page_tracker: state.page_tracker,
+                host_pages: state.host_pages,
+                pte_pages: state.pte_pages.into_iter(),
+                id,
+            }
+        }
+
+        // Returns the page-aligned guest physical address `gpa`.
+        fn guest_addr(&self, gpa: u64) -> GuestPageAddr {
+            PageAddr::new(RawAddr::guest(gpa, self.id)).unwrap()
+        }
+
+        // Maps a host page at each of the consecutive 4kB pages starting at `gpa`, with the
+        // permissions given for that page in `perms`. Returns the addresses of the mapped pages.
+        fn map_pages_with_perms(
+            &mut self,
+            gpa: GuestPageAddr,
+            perms: &[PteLeafPerms],
+        ) -> Vec<SupervisorPageAddr> {
+            let mapper = self
+                .page_table
+                .map_range(gpa, PageSize::Size4k, perms.len() as u64, &mut || {
+                    self.pte_pages.next()
+                })
+                .unwrap();
+            let mut page_addrs = Vec::new();
+            for (gpa, perms) in gpa.iter_from().zip(perms) {
+                let page = self.host_pages.next().unwrap();
+                page_addrs.push(page.addr());
+                let mappable = self
+                    .page_tracker
+                    .assign_page_for_mapping(page, self.id)
+                    .unwrap();
+                assert!(mapper.map_page_with_perms(gpa, mappable, *perms).is_ok());
+            }
+            page_addrs
+        }
+
+        // Same as `map_pages_with_perms()`, mapping `num_pages` pages with full permissions.
+        fn map_pages(&mut self, gpa: GuestPageAddr, num_pages: usize) -> Vec<SupervisorPageAddr> {
+            self.map_pages_with_perms(gpa, &vec![PteLeafPerms::RWX; num_pages])
+        }
+
+        // Invalidates the `num_pages` 4kB pages starting at `gpa` and starts their conversion at
+        // `version`.
+        fn convert_pages(&self, gpa: GuestPageAddr, num_pages: u64, version: TlbVersion) {
+            self.page_table
+                .invalidate_range::<Page<Invalidated>>(gpa, PageSize::Size4k, num_pages)
+                .unwrap()
+                .for_each(|invalidated| {
+                    self.page_tracker
+                        .convert_page(invalidated, version)
+                        .unwrap()
+                });
+        }
+
+        // Same as `convert_pages()`, but blocks the pages for removal instead of converting them.
+        fn block_pages(&self, gpa: GuestPageAddr, num_pages: u64, version: TlbVersion) {
+            self.page_table
+                .invalidate_range::<Page<Invalidated>>(gpa, PageSize::Size4k, num_pages)
+                .unwrap()
+                .for_each(|invalidated| {
+                    self.page_tracker.block_page(invalidated, version).unwrap()
+                });
+        }
+    }
+
     #[test]
     fn ownership_root_pages() {
         let state = stub_sys_memory();
@@ -170,6 +263,507 @@ mod tests {
         page_tracker.unlock_page(clean_page).unwrap();
     }
 
+    #[test]
+    fn count_mapped_4k_pages() {
+        let mut stub = StubGuestPageTable::<Sv48x4>::new();
+        assert_eq!(stub.page_table.num_mapped_4k_pages(), 0);
+        let gpa_base = stub.guest_addr(0x8000_0000);
+        stub.map_pages(gpa_base, 3);
+        assert_eq!(stub.page_table.num_mapped_4k_pages(), 3);
+
+        // Invalidated pages are no longer counted as mapped.
+        let version = TlbVersion::new();
+        stub.convert_pages(gpa_base, 2, version);
+        assert_eq!(stub.page_table.num_mapped_4k_pages(), 1);
+        let gpa = gpa_base.checked_add_pages(2).unwrap();
+        stub.convert_pages(gpa, 1, version);
+        assert_eq!(stub.page_table.num_mapped_4k_pages(), 0);
+    }
+
+    // Maps a 4kB page at `gpa` in a guest page table of paging mode `T`, then converts it back.
+    fn map_and_unmap_4k<T: GuestStagePagingMode>(gpa: u64) {
+        let mut stub = StubGuestPageTable::<T>::new();
+        let gpa = stub.guest_addr(gpa);
+        let page_addrs = stub.map_pages(gpa, 1);
+        assert_eq!(stub.page_table.num_mapped_4k_pages(), 1);
+
+        let version = TlbVersion::new();
+        stub.convert_pages(gpa, 1, version);
+        let version = version.increment();
+        let mut converted_pages = stub
+            .page_table
+            .get_converted_range::<Page<ConvertedDirty>>(gpa, PageSize::Size4k, 1, version)
+            .unwrap();
+        let converted = converted_pages.next().unwrap();
+        assert_eq!(converted.addr(), page_addrs[0]);
+        stub.page_tracker.unlock_page(converted).unwrap();
+    }
+
+    #[test]
+    fn map_and_unmap_sv39x4() {
+        // Use an address that requires the full 41-bit guest physical address space.
+        map_and_unmap_4k::<Sv39x4>(0x100_0000_0000);
+    }
+
+    #[test]
+    fn map_and_unmap_sv57x4() {
+        // Use an address that requires the full 59-bit guest physical address space.
+        map_and_unmap_4k::<Sv57x4>(0x400_0000_0000_0000);
+    }
+
+    #[test]
+    fn map_and_unmap_huge_sv48x4() {
+        let state = stub_sys_memory();
+
+        let page_tracker = state.page_tracker;
+        let id = PageOwnerId::host();
+        let host_page_table: GuestStagePageTable<Sv48x4> =
+            GuestStagePageTable::new(state.root_pages, id, page_tracker.clone())
+                .expect("creating sv48x4");
+
+        // Map a 2MB-aligned, contiguous chunk of host memory with 4kB pages.
+        let mut host_pages = state
+            .host_pages
+            .skip_while(|p| !p.addr().is_aligned(PageSize::Size2M));
+        let pages_to_map: Vec<Page<ConvertedClean>> = host_pages.by_ref().take(512).collect();
+        let huge_addr = pages_to_map[0].addr();
+        assert_eq!(
+            pages_to_map[511].addr().bits(),
+            huge_addr.bits() + PageSize::Size2M as u64 - PageSize::Size4k as u64
+        );
+        let mut pte_pages = state.pte_pages.into_iter();
+        let gpa_base = PageAddr::new(RawAddr::guest(0x8000_0000, id)).unwrap();
+        let mapper = host_page_table
+            .map_range(gpa_base, PageSize::Size4k, 512, &mut || pte_pages.next())
+            .unwrap();
+        for (page, gpa) in pages_to_map.into_iter().zip(gpa_base.iter_from()) {
+            unsafe {
+                // Not safe - just a test
+                *(page.addr().bits() as *mut u64) = 0xdeadbeef;
+            }
+            let mappable = page_tracker.assign_page_for_mapping(page, id).unwrap();
+            assert!(mapper.map_page(gpa, mappable).is_ok());
+        }
+
+        // Convert the 4kB mappings as a single 2MB page.
+        let version = TlbVersion::new();
+        let mut invalidated = host_page_table
+            .invalidate_range::<Page<Invalidated>>(gpa_base, PageSize::Size2M, 1)
+            .unwrap();
+        let huge_page = invalidated.next().unwrap();
+        assert_eq!(huge_page.addr(), huge_addr);
+        assert_eq!(huge_page.size(), PageSize::Size2M);
+        page_tracker.convert_page(huge_page, version).unwrap();
+        let version = version.increment();
+        let dirty_page = host_page_table
+            .get_converted_range::<Page<ConvertedDirty>>(gpa_base, PageSize::Size2M, 1, version)
+            .unwrap()
+            .next()
+            .unwrap();
+        assert_eq!(dirty_page.addr(), huge_addr);
+        assert_eq!(dirty_page.size(), PageSize::Size2M);
+        assert_eq!(dirty_page.get_u64(0).unwrap(), 0xdeadbeef);
+
+        // Now map it into a guest using a 2MB leaf PTE.
+        let guest_id = page_tracker.add_active_guest().unwrap();
+        let guest_root_pages = SequentialPages::from_pages(
+            host_pages
+                .by_ref()
+                .skip_while(|p| p.addr().bits() & (Sv48x4::TOP_LEVEL_ALIGN - 1) != 0)
+                .take(4)
+                .map(|p| {
+                    page_tracker
+                        .assign_page_for_internal_state(p, guest_id)
+                        .unwrap()
+                }),
+        )
+        .unwrap();
+        let guest_page_table: GuestStagePageTable<Sv48x4> =
+            GuestStagePageTable::new(guest_root_pages, guest_id, page_tracker.clone())
+                .expect("creating sv48x4");
+        let guest_gpa = PageAddr::new(RawAddr::guest(0x8020_0000, guest_id)).unwrap();
+        let mapper = guest_page_table
+            .map_range(guest_gpa, PageSize::Size2M, 1, &mut || pte_pages.next())
+            .unwrap();
+        let mappable = page_tracker
+            .assign_page_for_mapping(dirty_page.clean(), guest_id)
+            .unwrap();
+        assert!(mapper.map_page(guest_gpa, mappable).is_ok());
+        drop(mapper);
+
+        // The huge page can't be converted in 4kB pieces, but can as a whole.
+        assert!(guest_page_table
+            .invalidate_range::<Page<Invalidated>>(guest_gpa, PageSize::Size4k, 1)
+            .is_err());
+        let huge_page = guest_page_table
+            .invalidate_range::<Page<Invalidated>>(guest_gpa, PageSize::Size2M, 1)
+            .unwrap()
+            .next()
+            .unwrap();
+        assert_eq!(huge_page.addr(), huge_addr);
+        assert_eq!(huge_page.size(), PageSize::Size2M);
+    }
+
+    #[test]
+    fn drop_guest_page_table() {
+        let state = stub_sys_memory();
+
+        let page_tracker = state.page_tracker;
+        let guest_id = page_tracker.add_active_guest().unwrap();
+        let mut host_pages = state
+            .host_pages
+            .skip_while(|p| p.addr().bits() & (Sv48x4::TOP_LEVEL_ALIGN - 1) != 0);
+        let guest_state_pages: Vec<Page<InternalClean>> = host_pages
+            .by_ref()
+            .take(7)
+            .map(|p| {
+                page_tracker
+                    .assign_page_for_internal_state(p, guest_id)
+                    .unwrap()
+            })
+            .collect();
+        let mut guest_state_pages = guest_state_pages.into_iter();
+        let guest_root_pages =
+            SequentialPages::from_pages(guest_state_pages.by_ref().take(4)).unwrap();
+        let root_addr = guest_root_pages.base();
+        let guest_page_table: GuestStagePageTable<Sv48x4> =
+            GuestStagePageTable::new(guest_root_pages, guest_id, page_tracker.clone())
+                .expect("creating sv48x4");
+
+        let page = host_pages.next().unwrap();
+        let page_addr = page.addr();
+        unsafe {
+            // Not safe - just a test
+            *(page_addr.bits() as *mut u64) = 0xdeadbeef;
+        }
+        let gpa = PageAddr::new(RawAddr::guest(0x8000_0000, guest_id)).unwrap();
+        let mapper = guest_page_table
+            .map_range(gpa, PageSize::Size4k, 1, &mut || guest_state_pages.next())
+            .unwrap();
+        let mappable = page_tracker
+            .assign_page_for_mapping(page, guest_id)
+            .unwrap();
+        assert!(mapper.map_page(gpa, mappable).is_ok());
+        drop(mapper);
+
+        // Dropping the page table should wipe the guest's pages and return them to their previous
+        // owner, which for the stub pages is the hypervisor.
+        drop(guest_page_table);
+        let version = TlbVersion::new();
+        for addr in [page_addr, root_addr] {
+            assert!(page_tracker.is_converted_page(
+                addr,
+                PageSize::Size4k,
+                PageOwnerId::hypervisor(),
+                MemType::Ram,
+                version
+            ));
+            assert_eq!(unsafe { *(addr.bits() as *const u64) }, 0);
+        }
+    }
+
+    #[test]
+    fn remove_converted_pages() {
+        let mut stub = StubGuestPageTable::<Sv48x4>::new();
+        let gpa = stub.guest_addr(0x8000_0000);
+        let page_addr = stub.map_pages(gpa, 1)[0];
+        unsafe {
+            // Not safe - just a test
+            *(page_addr.bits() as *mut u64) = 0xdeadbeef;
+        }
+        let guest_page_table = &stub.page_table;
+
+        // Mapped pages can't be removed.
+        let version = TlbVersion::new();
+        assert!(guest_page_table
+            .remove_converted_range::<Page<ConvertedDirty>>(gpa, PageSize::Size4k, 1, version)
+            .is_err());
+        stub.convert_pages(gpa, 1, version);
+        // Nor can pages that haven't been fenced.
+        assert!(guest_page_table
+            .remove_converted_range::<Page<ConvertedDirty>>(gpa, PageSize::Size4k, 1, version)
+            .is_err());
+
+        let version = version.increment();
+        let dirty_page = guest_page_table
+            .remove_converted_range::<Page<ConvertedDirty>>(gpa, PageSize::Size4k, 1, version)
+            .unwrap()
+            .next()
+            .unwrap();
+        assert_eq!(dirty_page.addr(), page_addr);
+        assert_eq!(dirty_page.get_u64(0).unwrap(), 0xdeadbeef);
+        // The PTE is now unused so a new page can be mapped in its place.
+        assert!(guest_page_table
+            .get_converted_range::<Page<ConvertedDirty>>(gpa, PageSize::Size4k, 1, version)
+            .is_err());
+        stub.map_pages(gpa, 1);
+        stub.page_tracker.release_page(dirty_page).unwrap();
+    }
+
+    #[test]
+    fn reclaim_converting_pages() {
+        let mut stub = StubGuestPageTable::<Sv48x4>::new();
+        let gpa = stub.guest_addr(0x8000_0000);
+        let page_addr = stub.map_pages(gpa, 1)[0];
+        let guest_page_table = &stub.page_table;
+        let page_tracker = &stub.page_tracker;
+
+        // Mapped pages can't be reclaimed.
+        assert!(guest_page_table
+            .get_reclaimable_range::<Page<ConvertedDirty>>(gpa, PageSize::Size4k, 1)
+            .is_err());
+        let version = TlbVersion::new();
+        stub.convert_pages(gpa, 1, version);
+
+        // Pages can be reclaimed before the conversion has been fenced, and can't be claimed as
+        // converted pages while they're being reclaimed.
+        let mut reclaimable = guest_page_table
+            .get_reclaimable_range::<Page<ConvertedDirty>>(gpa, PageSize::Size4k, 1)
+            .unwrap();
+        let fenced = version.increment();
+        assert!(guest_page_table
+            .get_converted_range::<Page<ConvertedDirty>>(gpa, PageSize::Size4k, 1, fenced)
+            .is_err());
+        let dirty_page = reclaimable.next().unwrap();
+        assert_eq!(dirty_page.addr(), page_addr);
+        let mappable = page_tracker.reclaim_page(dirty_page.clean()).unwrap();
+        let mapper = guest_page_table
+            .map_range(gpa, PageSize::Size4k, 1, &mut || stub.pte_pages.next())
+            .unwrap();
+        assert!(mapper.map_page(gpa, mappable).is_ok());
+        drop(mapper);
+
+        // The conversion was cancelled, so the page is no longer converted once fenced.
+        assert!(guest_page_table
+            .get_converted_range::<Page<ConvertedDirty>>(gpa, PageSize::Size4k, 1, fenced)
+            .is_err());
+        assert!(guest_page_table
+            .get_reclaimable_range::<Page<ConvertedDirty>>(gpa, PageSize::Size4k, 1)
+            .is_err());
+    }
+
+    #[test]
+    fn remove_blocked_pages() {
+        let mut stub = StubGuestPageTable::<Sv48x4>::new();
+        let gpa = stub.guest_addr(0x8000_0000);
+        let page_addr = stub.map_pages(gpa, 1)[0];
+        let version = TlbVersion::new();
+        stub.block_pages(gpa, 1, version);
+        let guest_page_table = &stub.page_table;
+
+        // Blocked pages can't be reclaimed or claimed as converted pages, even once fenced.
+        assert!(
+            guest_page_table.is_converting_range::<Page<ConvertedDirty>>(gpa, PageSize::Size4k, 1)
+        );
+        assert!(guest_page_table
+            .get_reclaimable_range::<Page<ConvertedDirty>>(gpa, PageSize::Size4k, 1)
+            .is_err());
+        let fenced = version.increment();
+        assert!(guest_page_table
+            .get_converted_range::<Page<ConvertedDirty>>(gpa, PageSize::Size4k, 1, fenced)
+            .is_err());
+        assert!(guest_page_table
+            .get_reclaimable_range::<Page<ConvertedDirty>>(gpa, PageSize::Size4k, 1)
+            .is_err());
+
+        // They can only be removed, once fenced.
+        assert!(guest_page_table
+            .remove_converted_range::<Page<ConvertedDirty>>(gpa, PageSize::Size4k, 1, version)
+            .is_err());
+        let dirty_page = guest_page_table
+            .remove_converted_range::<Page<ConvertedDirty>>(gpa, PageSize::Size4k, 1, fenced)
+            .unwrap()
+            .next()
+            .unwrap();
+        assert_eq!(dirty_page.addr(), page_addr);
+        assert!(
+            !guest_page_table.is_converting_range::<Page<ConvertedDirty>>(gpa, PageSize::Size4k, 1)
+        );
+        stub.page_tracker.release_page(dirty_page).unwrap();
+    }
+
+    #[test]
+    fn restrict_page_perms() {
+        let mut stub = StubGuestPageTable::<Sv48x4>::new();
+        let gpa_base = stub.guest_addr(0x8000_0000);
+        stub.map_pages_with_perms(gpa_base, &[PteLeafPerms::RWX, PteLeafPerms::RX]);
+        let guest_page_table = &stub.page_table;
+        let rx_gpa = gpa_base.checked_add_pages(1).unwrap();
+        assert_eq!(
+            guest_page_table.get_mapped_perms(gpa_base.into()),
+            Some(PteLeafPerms::RWX)
+        );
+        assert_eq!(
+            guest_page_table.get_mapped_perms(rx_gpa.into()),
+            Some(PteLeafPerms::RX)
+        );
+
+        // Permissions can't be extended, and failures leave the whole range untouched.
+        assert!(matches!(
+            guest_page_table.restrict_range_perms(gpa_base, 2, PteLeafPerms::RW),
+            Err(Error::PermissionEscalation)
+        ));
+        assert_eq!(
+            guest_page_table.get_mapped_perms(gpa_base.into()),
+            Some(PteLeafPerms::RWX)
+        );
+        // Nor can unmapped pages be restricted.
+        assert!(matches!(
+            guest_page_table.restrict_range_perms(gpa_base, 3, PteLeafPerms::R),
+            Err(Error::PageNotMapped)
+        ));
+        assert_eq!(
+            guest_page_table.get_mapped_perms(rx_gpa.into()),
+            Some(PteLeafPerms::RX)
+        );
+
+        assert!(guest_page_table
+            .restrict_range_perms(gpa_base, 2, PteLeafPerms::R)
+            .is_ok());
+        for gpa in [gpa_base, rx_gpa] {
+            assert_eq!(
+                guest_page_table.get_mapped_perms(gpa.into()),
+                Some(PteLeafPerms::R)
+            );
+        }
+        assert!(guest_page_table
+            .restrict_range_perms(gpa_base, 1, PteLeafPerms::RX)
+            .is_err());
+        let unmapped_gpa = rx_gpa.checked_add_pages(1).unwrap();
+        assert_eq!(guest_page_table.get_mapped_perms(unmapped_gpa.into()), None);
+    }
+
+    #[test]
+    fn permission_faults() {
+        let mut stub = StubGuestPageTable::<Sv48x4>::new();
+        let gpa_base = stub.guest_addr(0x8000_0000);
+        stub.map_pages_with_perms(gpa_base, &[PteLeafPerms::RWX, PteLeafPerms::RX]);
+        let guest_page_table = &stub.page_table;
+        let rx_gpa = gpa_base.checked_add_pages(1).unwrap();
+        let unmapped_gpa = rx_gpa.checked_add_pages(1).unwrap();
+        use PteLeafPerms::*;
+        for access in [R, RW, X] {
+            assert!(!guest_page_table.is_permission_fault(gpa_base.into(), access));
+            // Faults on unmapped pages are never permission faults.
+            assert!(!guest_page_table.is_permission_fault(unmapped_gpa.into(), access));
+        }
+        assert!(!guest_page_table.is_permission_fault(rx_gpa.into(), R));
+        assert!(guest_page_table.is_permission_fault(rx_gpa.into(), RW));
+        assert!(!guest_page_table.is_permission_fault(rx_gpa.into(), X));
+
+        // Accesses the restricted permissions don't allow now fault.
+        assert!(guest_page_table
+            .restrict_range_perms(gpa_base, 2, R)
+            .is_ok());
+        for gpa in [gpa_base, rx_gpa] {
+            assert!(!guest_page_table.is_permission_fault(gpa.into(), R));
+            assert!(guest_page_table.is_permission_fault(gpa.into(), RW));
+            assert!(guest_page_table.is_permission_fault(gpa.into(), X));
+        }
+    }
+
+    #[test]
+    fn unshare_pages() {
+        let mut stub = StubGuestPageTable::<Sv48x4>::new();
+        let gpa = stub.guest_addr(0x8000_0000);
+        let page_tracker = stub.page_tracker.clone();
+        let id = stub.id;
+
+        let shared_addr = {
+            let page = stub.host_pages.next().unwrap();
+            let addr = page.addr();
+            page_tracker.assign_page_for_mapping(page, id).unwrap();
+            addr
+        };
+        let shareable = page_tracker
+            .get_shareable_page::<Page<Shareable>>(shared_addr, id)
+            .unwrap();
+        let shared = page_tracker.share_page(shareable, id).unwrap();
+        let mapper = stub
+            .page_table
+            .map_range(gpa, PageSize::Size4k, 1, &mut || stub.pte_pages.next())
+            .unwrap();
+        assert!(mapper.map_page(gpa, shared).is_ok());
+        drop(mapper);
+        let private_gpa = gpa.checked_add_pages(1).unwrap();
+        stub.map_pages(private_gpa, 1);
+        let guest_page_table = &stub.page_table;
+
+        // Only shared pages can be unshared, and only after they've been invalidated.
+        assert!(guest_page_table.invalidate_shared_range(gpa, 2).is_err());
+        assert!(!guest_page_table.is_invalidated_shared_range(gpa, 1));
+        assert!(guest_page_table
+            .remove_invalidated_shared_range(gpa, 1)
+            .is_err());
+        guest_page_table.invalidate_shared_range(gpa, 1).unwrap();
+        assert!(guest_page_table.is_invalidated_shared_range(gpa, 1));
+        assert!(!guest_page_table.is_invalidated_shared_range(gpa, 2));
+
+        // Removing the page drops the shared reference, leaving it mapped only by its owner.
+        guest_page_table
+            .remove_invalidated_shared_range(gpa, 1)
+            .unwrap();
+        assert!(!guest_page_table.is_invalidated_shared_range(gpa, 1));
+        assert!(page_tracker.is_mapped_page(shared_addr, PageSize::Size4k, id, MemType::Ram));
+    }
+
+    #[test]
+    fn invalidate_populated_pages() {
+        let mut stub = StubGuestPageTable::<Sv48x4>::new();
+        let gpa = stub.guest_addr(0x8000_0000);
+        let populated_gpa = gpa.checked_add_pages(2).unwrap();
+        let page_addrs = [
+            stub.map_pages(gpa, 1)[0],
+            stub.map_pages(populated_gpa, 1)[0],
+        ];
+        let guest_page_table = &stub.page_table;
+
+        // Unpopulated pages are skipped, but pages that have already been invalidated aren't.
+        let invalidated: Vec<SupervisorPageAddr> = guest_page_table
+            .invalidate_populated_range::<Page<Invalidated>>(gpa, 4)
+            .unwrap()
+            .map(|p| p.addr())
+            .collect();
+        assert_eq!(invalidated, page_addrs);
+        assert_eq!(guest_page_table.num_mapped_4k_pages(), 0);
+        assert!(guest_page_table
+            .invalidate_populated_range::<Page<Invalidated>>(gpa, 4)
+            .is_err());
+        assert!(guest_page_table
+            .invalidate_populated_range::<Page<Invalidated>>(gpa.checked_add_pages(3).unwrap(), 1)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn map_unused_pages() {
+        let mut stub = StubGuestPageTable::<Sv48x4>::new();
+        let gpa = stub.guest_addr(0x8000_0000);
+        let mapper = stub
+            .page_table
+            .map_unused_range(gpa, PageSize::Size4k, 1, &mut || stub.pte_pages.next())
+            .unwrap();
+        let mappable = stub
+            .page_tracker
+            .assign_page_for_mapping(stub.host_pages.next().unwrap(), stub.id)
+            .unwrap();
+        assert!(mapper.map_page(gpa, mappable).is_ok());
+        drop(mapper);
+
+        // Neither mapped nor invalidated pages may be replaced.
+        let guest_page_table = &stub.page_table;
+        assert!(guest_page_table
+            .map_unused_range(gpa, PageSize::Size4k, 1, &mut || stub.pte_pages.next())
+            .is_err());
+        stub.convert_pages(gpa, 1, TlbVersion::new());
+        assert!(guest_page_table
+            .map_unused_range(gpa, PageSize::Size4k, 1, &mut || stub.pte_pages.next())
+            .is_err());
+        assert!(guest_page_table
+            .map_range(gpa, PageSize::Size4k, 1, &mut || stub.pte_pages.next())
+            .is_ok());
+    }
+
     #[test]
     fn map_and_unmap_sv48() {
         let state = stub_sys_memory();
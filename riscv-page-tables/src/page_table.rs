@@ -4,7 +4,7 @@
 
 use crate::pte::{Pte, PteFieldBits, PteLeafPerms};
 use core::marker::PhantomData;
-use page_tracking::{LockedPageList, PageList, PageTracker, TlbVersion};
+use page_tracking::{LockedPageList, PageList, PageTracker, PageTrackingResult, TlbVersion};
 use riscv_pages::*;
 use spin::Mutex;
 
@@ -23,6 +23,10 @@ pub enum Error {
     MisalignedPages(SequentialPages<InternalClean>),
     /// The requested page size isn't (yet) handled by the hypervisor.
     PageSizeNotSupported(PageSize),
+    /// The address isn't aligned to the requested page size.
+    MisalignedAddress,
+    /// The pages backing the requested range aren't physically contiguous.
+    PageNotContiguous,
     /// Attempt to create a mapping over an existing one.
     MappingExists,
     /// The requested range isn't mapped.
@@ -41,6 +45,10 @@ pub enum Error {
     OutOfMapRange,
     /// The page cannot be shared
     PageNotShareable,
+    /// Attempt to access a non-shared page as shared.
+    PageNotShared,
+    /// Attempt to grant a mapping access that its current permissions don't allow.
+    PermissionEscalation,
 }
 /// Hold the result of page table operations.
 pub type Result<T> = core::result::Result<T, Error>;
@@ -117,6 +125,18 @@ impl<'a, T: PagingMode> TableEntryType<'a, T> {
             Leaf(LeafPte::new(pte, level))
         }
     }
+
+    /// Returns the `PageTableLevel` this entry is at.
+    fn level(&self) -> T::Level {
+        use TableEntryType::*;
+        match self {
+            Unused(u) => u.level(),
+            Invalidated(i) => i.level(),
+            Locked(l) => l.level(),
+            Leaf(l) => l.level(),
+            Table(t) => t.level(),
+        }
+    }
 }
 
 /// A mutable reference to a page table entry of a particular type.
@@ -175,6 +195,12 @@ impl<'a, T: PagingMode> InvalidatedPte<'a, T> {
         self.pte.lock();
         LockedPte::new(self.pte, self.level)
     }
+
+    /// Clears this PTE, returning it as an unused entry.
+    fn clear(self) -> UnusedPte<'a, T> {
+        self.pte.clear();
+        UnusedPte::new(self.pte, self.level)
+    }
 }
 
 impl<'a, T: PagingMode> LockedPte<'a, T> {
@@ -210,6 +236,17 @@ impl<'a, T: PagingMode> LeafPte<'a, T> {
         PageAddr::from_pfn(self.pte.pfn(), self.level.leaf_page_size()).unwrap()
     }
 
+    /// Returns the permissions this PTE grants.
+    fn perms(&self) -> PteLeafPerms {
+        // Unwrap ok since leaf PTEs are only ever created with valid permissions.
+        self.pte.leaf_perms().unwrap()
+    }
+
+    /// Replaces the permissions this PTE grants with `perms`.
+    fn set_perms(&mut self, perms: PteLeafPerms) {
+        self.pte.set_leaf_perms(perms);
+    }
+
     /// Inavlidates this PTE, returning it as an invalid entry.
     fn invalidate(self) -> InvalidatedPte<'a, T> {
         self.pte.invalidate();
@@ -332,6 +369,22 @@ impl<'a, T: PagingMode> PageTable<'a, T> {
         Ok(table_pte.table())
     }
 
+    /// Returns the number of 4kB pages currently mapped by valid leaf entries in this page table,
+    /// recursing through the paging hierarchy if any next-level table pointers are encountered.
+    fn count_mapped_4k_pages(&mut self) -> u64 {
+        let iter = PageTableIndexIter::new(self.level);
+        let mut count = 0;
+        for index in iter {
+            use TableEntryType::*;
+            match self.entry_for_index_mut(index) {
+                Table(t) => count += t.table().count_mapped_4k_pages(),
+                Leaf(l) => count += l.level().leaf_page_size() as u64 / PageSize::Size4k as u64,
+                _ => (),
+            }
+        }
+        count
+    }
+
     /// Releases the pages mapped by this page table, recursing through the paging hierarchy if any
     /// next-level table pointers are encountered.
     fn release_pages(&mut self, page_tracker: PageTracker, owner: PageOwnerId) {
@@ -348,21 +401,19 @@ impl<'a, T: PagingMode> PageTable<'a, T> {
                     // Safe since we must uniquely own the page if we're using it as a page-table page.
                     let table_page: Page<InternalDirty> = unsafe { Page::new(table_addr) };
                     // Unwrap ok since the page must have been assigned to us.
-                    page_tracker.release_page(table_page).unwrap();
+                    page_tracker.release_page(table_page.clean()).unwrap();
                 }
                 Leaf(l) => {
                     // Unwrap ok since by virtue of being mapped into this page table, we must
                     // uniquely own the page and it must be in a releasable state.
-                    page_tracker
-                        .release_page_by_addr(l.page_addr(), owner)
-                        .unwrap();
+                    let page_size = l.level().leaf_page_size();
+                    wipe_and_release_page(&page_tracker, l.page_addr(), page_size, owner).unwrap();
                 }
                 Invalidated(i) => {
                     // Unwrap ok since the only usage of invalid PTEs we currently have is for
                     // converted pages.
-                    page_tracker
-                        .release_page_by_addr(i.page_addr(), owner)
-                        .unwrap();
+                    let page_size = i.level().leaf_page_size();
+                    wipe_and_release_page(&page_tracker, i.page_addr(), page_size, owner).unwrap();
                 }
                 _ => (),
             }
@@ -370,6 +421,23 @@ impl<'a, T: PagingMode> PageTable<'a, T> {
     }
 }
 
+/// Releases the `page_size` page at `addr` from `owner` back to its previous owner. The page is
+/// zeroed first if it's private to `owner` so that none of its contents are leaked to the previous
+/// owner. Shared pages are left untouched.
+fn wipe_and_release_page(
+    page_tracker: &PageTracker,
+    addr: SupervisorPageAddr,
+    page_size: PageSize,
+    owner: PageOwnerId,
+) -> PageTrackingResult<()> {
+    if page_tracker.is_private_ram_page(addr, page_size, owner) {
+        // Safe since the page is RAM that's owned exclusively by `owner`, which is being torn down.
+        let page: Page<InternalDirty> = unsafe { Page::new_with_size(addr, page_size) };
+        page.clean();
+    }
+    page_tracker.release_page_by_addr(addr, page_size, owner)
+}
+
 /// An index to an entry in a page table.
 trait PteIndex {
     /// Returns the offset in bytes of the index
@@ -456,6 +524,12 @@ pub trait PagingMode {
     /// Calculates the number of PTE pages that are needed to map all pages for `num_pages` mapped
     /// pages for this type of page table.
     fn max_pte_pages(num_pages: u64) -> u64;
+
+    /// Returns the number of address bits translated by this type of page table.
+    fn addr_bits() -> u64 {
+        let root = Self::root_level();
+        root.addr_shift() + root.addr_width()
+    }
 }
 
 /// A page table for a S or U mode. It's enabled by storing its root address in `satp`.
@@ -508,107 +582,243 @@ impl<T: PagingMode> PageTableInner<T> {
         entry
     }
 
-    /// Creates a translation for `vaddr` to `paddr` with the given permissions.
+    /// Calls `f` on each of the PTEs covering the `page_size` page at `vaddr`, in order of
+    /// increasing address, along with the offset into the page at which the PTE's translation
+    /// starts. The range may be covered by PTEs at a finer granularity than `page_size`, but not
+    /// by a PTE mapping a larger page.
+    fn for_each_entry_in<F>(
+        &mut self,
+        vaddr: PageAddr<T::MappedAddressSpace>,
+        page_size: PageSize,
+        mut f: F,
+    ) -> Result<()>
+    where
+        F: FnMut(TableEntryType<T>, u64) -> Result<()>,
+    {
+        let mut offset = 0;
+        while offset < page_size as u64 {
+            // Unwrap ok since `vaddr` is `page_size`-aligned so the whole page is addressable.
+            let entry = self.walk(RawAddr::from(vaddr).checked_increment(offset).unwrap());
+            let entry_size = entry.level().leaf_page_size();
+            use TableEntryType::*;
+            if entry_size > page_size && !matches!(entry, Unused(_)) {
+                // TODO: Support breaking up huge pages.
+                return Err(Error::PageSizeNotSupported(entry_size));
+            }
+            f(entry, offset)?;
+            offset += entry_size as u64;
+        }
+        Ok(())
+    }
+
+    /// Calls `f` on each of the valid leaf PTEs translating the range of addresses from `start` to
+    /// `end`, in order of increasing address. The range must be fully covered by valid leaf PTEs,
+    /// none of which may map a page extending beyond the range.
+    fn for_each_leaf_in<F>(
+        &mut self,
+        start: PageAddr<T::MappedAddressSpace>,
+        end: PageAddr<T::MappedAddressSpace>,
+        mut f: F,
+    ) -> Result<()>
+    where
+        F: FnMut(LeafPte<T>) -> Result<()>,
+    {
+        let mut addr = start;
+        while addr < end {
+            use TableEntryType::*;
+            let leaf = match self.walk(RawAddr::from(addr)) {
+                Leaf(l) => l,
+                _ => return Err(Error::PageNotMapped),
+            };
+            let leaf_size = leaf.level().leaf_page_size();
+            let next = addr
+                .checked_add_pages_with_size(1, leaf_size)
+                .filter(|next| addr.is_aligned(leaf_size) && *next <= end)
+                // TODO: Support breaking up huge pages.
+                .ok_or(Error::PageSizeNotSupported(leaf_size))?;
+            f(leaf)?;
+            addr = next;
+        }
+        Ok(())
+    }
+
+    /// Returns the base of the physical range translated by the PTEs covering the `page_size` page
+    /// at `vaddr`, with `entry_addr` used to fetch the address from each PTE. The physical range
+    /// must be contiguous and aligned to `page_size`.
+    fn get_contiguous_paddr<F>(
+        &mut self,
+        vaddr: PageAddr<T::MappedAddressSpace>,
+        page_size: PageSize,
+        entry_addr: F,
+    ) -> Result<SupervisorPageAddr>
+    where
+        F: Fn(TableEntryType<T>) -> Result<SupervisorPageAddr>,
+    {
+        let mut base = None;
+        self.for_each_entry_in(vaddr, page_size, |entry, offset| {
+            let paddr = entry_addr(entry)?;
+            match base {
+                None if paddr.is_aligned(page_size) => base = Some(paddr),
+                Some(b) if paddr.bits() == b.bits() + offset => (),
+                _ => return Err(Error::PageNotContiguous),
+            }
+            Ok(())
+        })?;
+        // Unwrap ok since there's always at least one PTE covering the page.
+        Ok(base.unwrap())
+    }
+
+    /// Creates a translation for the `page_size` page at `vaddr` to `paddr` with the given
+    /// permissions, filling in the PTEs locked by `lock_leaf_for_mapping()`.
     ///
     /// # Safety
     ///
     /// The caller must guarantee that `paddr` references a page uniquely owned by the root
     /// `GuestStagePageTable`.
-    unsafe fn map_4k_leaf(
+    unsafe fn map_leaf(
         &mut self,
         vaddr: PageAddr<T::MappedAddressSpace>,
         paddr: SupervisorPageAddr,
+        page_size: PageSize,
         perms: PteFieldBits,
     ) -> Result<()> {
-        let entry = self.walk(RawAddr::from(vaddr));
+        if !paddr.is_aligned(page_size) {
+            return Err(Error::MisalignedAddress);
+        }
         use TableEntryType::*;
-        match entry {
-            Locked(l) => {
-                if !l.level().is_leaf() {
-                    return Err(Error::PageSizeNotSupported(l.level().leaf_page_size()));
-                }
-                l.map_leaf(paddr, perms);
-                Ok(())
-            }
+        // Make sure the whole page is locked before we start creating any translations.
+        self.for_each_entry_in(vaddr, page_size, |entry, _| match entry {
+            Locked(_) => Ok(()),
             Unused(_) | Invalidated(_) => Err(Error::PteNotLocked),
             Leaf(_) => Err(Error::MappingExists),
             Table(_) => unreachable!(),
-        }
+        })?;
+        self.for_each_entry_in(vaddr, page_size, |entry, offset| {
+            if let Locked(l) = entry {
+                // Unwrap ok since `paddr` is `page_size`-aligned and the offset is within the page.
+                let entry_paddr = paddr
+                    .checked_add_pages(offset / PageSize::Size4k as u64)
+                    .unwrap();
+                l.map_leaf(entry_paddr, perms);
+            }
+            Ok(())
+        })
     }
 
-    /// Locks the invalid leaf PTE mapping `vaddr`, filling in any missing intermediate page tables
-    /// using `get_pte_page`.
-    fn lock_4k_leaf_for_mapping(
+    /// Locks the invalid PTEs covering the `page_size` page at `vaddr`, filling in any missing
+    /// intermediate page tables down to the level mapping `page_size` pages using `get_pte_page`.
+    fn lock_leaf_for_mapping(
         &mut self,
         vaddr: PageAddr<T::MappedAddressSpace>,
+        page_size: PageSize,
         get_pte_page: &mut dyn FnMut() -> Option<Page<InternalClean>>,
     ) -> Result<()> {
-        let mut table = PageTable::from_root(self);
-        while !table.level().is_leaf() {
-            table = table.next_level_or_fill_fn(RawAddr::from(vaddr), get_pte_page)?;
-        }
-        let entry = table.entry_for_addr_mut(RawAddr::from(vaddr));
-        use TableEntryType::*;
-        match entry {
-            Invalidated(i) => {
-                i.lock();
-                Ok(())
+        {
+            let mut table = PageTable::from_root(self);
+            while table.level().leaf_page_size() > page_size {
+                table = table.next_level_or_fill_fn(RawAddr::from(vaddr), get_pte_page)?;
             }
-            Unused(u) => {
-                u.lock();
-                Ok(())
+            if table.level().leaf_page_size() != page_size {
+                return Err(Error::PageSizeNotSupported(page_size));
             }
+        }
+
+        use TableEntryType::*;
+        // The page may already be covered by smaller PTEs, all of which must be lockable.
+        self.for_each_entry_in(vaddr, page_size, |entry, _| match entry {
+            Unused(_) | Invalidated(_) => Ok(()),
             Locked(_) => Err(Error::PteLocked),
             Leaf(_) => Err(Error::MappingExists),
             Table(_) => unreachable!(),
-        }
+        })?;
+        self.for_each_entry_in(vaddr, page_size, |entry, _| {
+            match entry {
+                Invalidated(i) => {
+                    i.lock();
+                }
+                Unused(u) => {
+                    u.lock();
+                }
+                _ => unreachable!(),
+            }
+            Ok(())
+        })
     }
 
-    /// Unlocks the leaf PTE mapping `vaddr`.
-    fn unlock_4k_leaf(&mut self, vaddr: PageAddr<T::MappedAddressSpace>) -> Result<()> {
-        let entry = self.walk(RawAddr::from(vaddr));
+    /// Unlocks the PTEs covering the `page_size` page at `vaddr`.
+    fn unlock_leaf(
+        &mut self,
+        vaddr: PageAddr<T::MappedAddressSpace>,
+        page_size: PageSize,
+    ) -> Result<()> {
         use TableEntryType::*;
-        match entry {
+        self.for_each_entry_in(vaddr, page_size, |entry, _| match entry {
             Locked(l) => {
                 l.unlock();
                 Ok(())
             }
             _ => Err(Error::PteNotLocked),
-        }
+        })
     }
 
-    /// Returns the valid 4kB leaf PTE mapping `vaddr` if it exists.
-    fn get_mapped_4k_leaf(&mut self, vaddr: PageAddr<T::MappedAddressSpace>) -> Result<LeafPte<T>> {
-        let entry = self.walk(RawAddr::from(vaddr));
+    /// Returns the physical address of the `page_size` page mapped at `vaddr` if the page is fully
+    /// covered by valid leaf PTEs.
+    fn get_mapped_leaf(
+        &mut self,
+        vaddr: PageAddr<T::MappedAddressSpace>,
+        page_size: PageSize,
+    ) -> Result<SupervisorPageAddr> {
         use TableEntryType::*;
-        match entry {
+        self.get_contiguous_paddr(vaddr, page_size, |entry| match entry {
+            Leaf(l) => Ok(l.page_addr()),
+            _ => Err(Error::PageNotMapped),
+        })
+    }
+
+    /// Invalidates the valid leaf PTEs covering the `page_size` page at `vaddr`.
+    fn invalidate_leaf(
+        &mut self,
+        vaddr: PageAddr<T::MappedAddressSpace>,
+        page_size: PageSize,
+    ) -> Result<()> {
+        use TableEntryType::*;
+        self.for_each_entry_in(vaddr, page_size, |entry, _| match entry {
             Leaf(l) => {
-                if !l.level().is_leaf() {
-                    return Err(Error::PageSizeNotSupported(l.level().leaf_page_size()));
-                }
-                Ok(l)
+                l.invalidate();
+                Ok(())
             }
             _ => Err(Error::PageNotMapped),
-        }
+        })
+    }
+
+    /// Returns the physical address of the `page_size` page that was mapped at `vaddr` if the page
+    /// is fully covered by invalid PTEs that reference pages that were invalidated.
+    fn get_invalidated_leaf(
+        &mut self,
+        vaddr: PageAddr<T::MappedAddressSpace>,
+        page_size: PageSize,
+    ) -> Result<SupervisorPageAddr> {
+        use TableEntryType::*;
+        self.get_contiguous_paddr(vaddr, page_size, |entry| match entry {
+            Invalidated(i) => Ok(i.page_addr()),
+            _ => Err(Error::PageNotConverted),
+        })
     }
 
-    /// Returns the invalid 4kB leaf PTE mapping `vaddr` if the PFN the PTE references is a
-    /// page that was invalidated.
-    fn get_invalidated_4k_leaf(
+    /// Clears the invalid PTEs covering the `page_size` page at `vaddr`.
+    fn clear_invalidated_leaf(
         &mut self,
         vaddr: PageAddr<T::MappedAddressSpace>,
-    ) -> Result<InvalidatedPte<T>> {
-        let entry = self.walk(RawAddr::from(vaddr));
+        page_size: PageSize,
+    ) -> Result<()> {
         use TableEntryType::*;
-        match entry {
+        self.for_each_entry_in(vaddr, page_size, |entry, _| match entry {
             Invalidated(i) => {
-                if !i.level().is_leaf() {
-                    return Err(Error::PageSizeNotSupported(i.level().leaf_page_size()));
-                }
-                Ok(i)
+                i.clear();
+                Ok(())
             }
             _ => Err(Error::PageNotConverted),
-        }
+        })
     }
 }
 
@@ -654,7 +864,7 @@ impl<T: FirstStagePagingMode> FirstStagePageTable<T> {
 
         let mut inner = self.inner.lock();
         for a in addr.iter_from().take(num_pages as usize) {
-            inner.lock_4k_leaf_for_mapping(a, get_pte_page)?;
+            inner.lock_leaf_for_mapping(a, page_size, get_pte_page)?;
         }
 
         Ok(FirstStageMapper::new(self, addr, num_pages))
@@ -702,13 +912,11 @@ impl<'a, T: FirstStagePagingMode> FirstStageMapper<'a, T> {
         }
 
         let mut inner = self.owner.inner.lock();
-        inner.map_4k_leaf(vaddr, paddr, pte_perms)
+        inner.map_leaf(vaddr, paddr, PageSize::Size4k, pte_perms)
     }
 }
 
 /// A paging hierarchy for a given addressing type.
-///
-/// TODO: Support non-4k page sizes.
 pub struct GuestStagePageTable<T: PagingMode> {
     inner: Mutex<PageTableInner<T>>,
     page_tracker: PageTracker,
@@ -755,12 +963,76 @@ impl<T: PagingMode> GuestStagePageTable<T> {
         self.inner.lock().root.base()
     }
 
+    /// Returns the number of 4kB pages currently mapped in this page table. Huge pages are counted
+    /// as the number of 4kB pages they span. Invalidated or locked entries are not counted.
+    pub fn num_mapped_4k_pages(&self) -> u64 {
+        let mut inner = self.inner.lock();
+        PageTable::from_root(&mut inner).count_mapped_4k_pages()
+    }
+
     /// Handles a fault from the owner of this page table.
     pub fn do_fault(&self, _addr: RawAddr<T::MappedAddressSpace>) -> bool {
         // At the moment we have no reason to take a page fault.
         false
     }
 
+    /// Returns the permissions of the page mapped at `addr`, or `None` if `addr` isn't translated
+    /// by a valid leaf PTE.
+    pub fn get_mapped_perms(&self, addr: RawAddr<T::MappedAddressSpace>) -> Option<PteLeafPerms> {
+        let mut inner = self.inner.lock();
+        match inner.walk(addr) {
+            TableEntryType::Leaf(l) => Some(l.perms()),
+            _ => None,
+        }
+    }
+
+    /// Returns if an access to `addr` that requires the permissions in `access` is denied by the
+    /// permissions of the page mapped there. Stores require `RW` since write-only mappings aren't
+    /// allowed. Returns false if `addr` isn't translated by a valid leaf PTE.
+    pub fn is_permission_fault(
+        &self,
+        addr: RawAddr<T::MappedAddressSpace>,
+        access: PteLeafPerms,
+    ) -> bool {
+        self.get_mapped_perms(addr)
+            .filter(|perms| !perms.contains(access))
+            .is_some()
+    }
+
+    /// Restricts the permissions of the pages mapped in the `num_pages` 4kB pages starting at
+    /// `addr` to `perms`. The range must be fully covered by mapped pages which lie entirely within
+    /// it, and `perms` must not grant any access that the existing mappings don't already allow.
+    /// Either the permissions of the full range are restricted, or none of them are. The caller is
+    /// responsible for fencing any stale translations.
+    pub fn restrict_range_perms(
+        &self,
+        addr: PageAddr<T::MappedAddressSpace>,
+        num_pages: u64,
+        perms: PteLeafPerms,
+    ) -> Result<()> {
+        let end = addr
+            .checked_add_pages(num_pages)
+            .ok_or(Error::PageNotMapped)?;
+
+        let mut inner = self.inner.lock();
+        // First make sure the entire range can be restricted before we start changing things.
+        inner.for_each_leaf_in(addr, end, |l| {
+            if l.perms().contains(perms) {
+                Ok(())
+            } else {
+                Err(Error::PermissionEscalation)
+            }
+        })?;
+        // Unwrap ok since we verified above that the full range can be restricted.
+        inner
+            .for_each_leaf_in(addr, end, |mut l| {
+                l.set_perms(perms);
+                Ok(())
+            })
+            .unwrap();
+        Ok(())
+    }
+
     /// Prepares for mapping `num_pages` pages of size `page_size` starting at `addr` in the mapped
     /// address space by locking the target PTEs and populating any intermediate page tables using
     /// `get_pte_page`. Upon success, returns a `GuestStageMapper` that is guaranteed to be able to
@@ -772,61 +1044,126 @@ impl<T: PagingMode> GuestStagePageTable<T> {
         num_pages: u64,
         get_pte_page: &mut dyn FnMut() -> Option<Page<InternalClean>>,
     ) -> Result<GuestStageMapper<T>> {
-        if page_size.is_huge() {
-            return Err(Error::PageSizeNotSupported(page_size));
+        let addrs = addr
+            .iter_from_with_size(page_size)
+            .ok_or(Error::MisalignedAddress)?;
+        let mut mapper = GuestStageMapper::new(self, addr, page_size, 0);
+        let mut inner = self.inner.lock();
+        for a in addrs.take(num_pages as usize) {
+            inner.lock_leaf_for_mapping(a, page_size, get_pte_page)?;
+            mapper.num_pages += 1;
         }
 
-        let mut mapper = GuestStageMapper::new(self, addr, 0);
+        Ok(mapper)
+    }
+
+    /// Same as `map_range()`, but fails with `MappingExists` if any of the pages in the range are
+    /// currently mapped or invalidated, rather than allowing invalidated pages to be replaced.
+    pub fn map_unused_range(
+        &self,
+        addr: PageAddr<T::MappedAddressSpace>,
+        page_size: PageSize,
+        num_pages: u64,
+        get_pte_page: &mut dyn FnMut() -> Option<Page<InternalClean>>,
+    ) -> Result<GuestStageMapper<T>> {
+        let addrs = addr
+            .iter_from_with_size(page_size)
+            .ok_or(Error::MisalignedAddress)?;
+        let mut mapper = GuestStageMapper::new(self, addr, page_size, 0);
         let mut inner = self.inner.lock();
-        for a in addr.iter_from().take(num_pages as usize) {
-            inner.lock_4k_leaf_for_mapping(a, get_pte_page)?;
+        for a in addrs.take(num_pages as usize) {
+            inner.for_each_entry_in(a, page_size, |entry, _| match entry {
+                TableEntryType::Invalidated(_) => Err(Error::MappingExists),
+                _ => Ok(()),
+            })?;
+            inner.lock_leaf_for_mapping(a, page_size, get_pte_page)?;
             mapper.num_pages += 1;
         }
 
         Ok(mapper)
     }
 
-    /// Returns a list of invalidated pages for the given range.
+    /// Returns a list of invalidated pages of size `page_size` for the given range.
     pub fn invalidate_range<P: InvalidatedPhysPage>(
         &self,
         addr: PageAddr<T::MappedAddressSpace>,
         page_size: PageSize,
         num_pages: u64,
     ) -> Result<PageList<P>> {
-        if page_size.is_huge() {
-            return Err(Error::PageSizeNotSupported(page_size));
+        if !addr.is_aligned(page_size) {
+            return Err(Error::MisalignedAddress);
         }
 
         let mut inner = self.inner.lock();
         // First make sure the entire range can be unmapped before we start invalidating things.
-        if !addr.iter_from().take(num_pages as usize).all(|a| {
-            Self::get_mapped_owned_4k_leaf(
+        // Unwraps ok since we've checked the alignment of `addr` above.
+        let addrs = addr.iter_from_with_size(page_size).unwrap();
+        for a in addrs.take(num_pages as usize) {
+            Self::get_mapped_owned_leaf(
                 &mut inner,
                 a,
+                page_size,
                 self.page_tracker.clone(),
                 self.owner,
                 P::mem_type(),
             )
-            .is_ok()
-        }) {
-            return Err(Error::PageNotUnmappable);
+            .map_err(|_| Error::PageNotUnmappable)?;
         }
 
         let mut pages = PageList::new(self.page_tracker.clone());
-        for a in addr.iter_from().take(num_pages as usize) {
+        let addrs = addr.iter_from_with_size(page_size).unwrap();
+        for a in addrs.take(num_pages as usize) {
             // We verified above that we can safely unwrap here.
-            let entry = Self::get_mapped_owned_4k_leaf(
+            let paddr = inner.get_mapped_leaf(a, page_size).unwrap();
+            inner.invalidate_leaf(a, page_size).unwrap();
+            let page = unsafe {
+                // Safe since we've verified the typing of the page.
+                P::new_with_size(paddr, page_size)
+            };
+            // Unwrap ok, a just-invalidated page can't be on any other PageList.
+            pages.push(page).unwrap();
+        }
+
+        Ok(pages)
+    }
+
+    /// Same as `invalidate_range()` for `num_pages` 4kB pages starting at `addr`, except that pages
+    /// in the range that aren't populated are skipped. Either all of the populated pages in the
+    /// range are invalidated, or none of them are.
+    pub fn invalidate_populated_range<P: InvalidatedPhysPage>(
+        &self,
+        addr: PageAddr<T::MappedAddressSpace>,
+        num_pages: u64,
+    ) -> Result<PageList<P>> {
+        let mut inner = self.inner.lock();
+        // First make sure all the populated pages can be unmapped before we start invalidating
+        // things.
+        for a in addr.iter_from().take(num_pages as usize) {
+            if matches!(inner.walk(RawAddr::from(a)), TableEntryType::Unused(_)) {
+                continue;
+            }
+            Self::get_mapped_owned_leaf(
                 &mut inner,
                 a,
+                PageSize::Size4k,
                 self.page_tracker.clone(),
                 self.owner,
                 P::mem_type(),
             )
-            .unwrap();
-            let invalidated = entry.invalidate();
+            .map_err(|_| Error::PageNotUnmappable)?;
+        }
+
+        let mut pages = PageList::new(self.page_tracker.clone());
+        for a in addr.iter_from().take(num_pages as usize) {
+            if matches!(inner.walk(RawAddr::from(a)), TableEntryType::Unused(_)) {
+                continue;
+            }
+            // We verified above that we can safely unwrap here.
+            let paddr = inner.get_mapped_leaf(a, PageSize::Size4k).unwrap();
+            inner.invalidate_leaf(a, PageSize::Size4k).unwrap();
             let page = unsafe {
                 // Safe since we've verified the typing of the page.
-                P::new(invalidated.page_addr())
+                P::new(paddr)
             };
             // Unwrap ok, a just-invalidated page can't be on any other PageList.
             pages.push(page).unwrap();
@@ -835,9 +1172,9 @@ impl<T: PagingMode> GuestStagePageTable<T> {
         Ok(pages)
     }
 
-    /// Returns a list of converted pages that were previously mapped in this page table if they were
-    /// invalidated a TLB version older than `tlb_version`. Guarantees that the full range of pages
-    /// are converted pages.
+    /// Returns a list of converted pages of size `page_size` that were previously mapped in this
+    /// page table if they were invalidated a TLB version older than `tlb_version`. Guarantees that
+    /// the full range of pages are converted pages.
     pub fn get_converted_range<P: ConvertedPhysPage>(
         &self,
         addr: PageAddr<T::MappedAddressSpace>,
@@ -845,26 +1182,26 @@ impl<T: PagingMode> GuestStagePageTable<T> {
         num_pages: u64,
         tlb_version: TlbVersion,
     ) -> Result<LockedPageList<P::DirtyPage>> {
-        if page_size.is_huge() {
-            return Err(Error::PageSizeNotSupported(page_size));
-        }
+        let addrs = addr
+            .iter_from_with_size(page_size)
+            .ok_or(Error::MisalignedAddress)?;
 
         let mut inner = self.inner.lock();
         let page_tracker = self.page_tracker.clone();
         let mut pages = LockedPageList::new(self.page_tracker.clone());
-        for a in addr.iter_from().take(num_pages as usize) {
-            let paddr = Self::get_converted_4k_leaf(
+        for a in addrs.take(num_pages as usize) {
+            let paddr = Self::get_converted_leaf(
                 &mut inner,
                 a,
+                page_size,
                 self.page_tracker.clone(),
                 self.owner,
                 P::mem_type(),
                 tlb_version,
-            )?
-            .page_addr();
+            )?;
             // Unwrap ok since we've already verified that this page is owned and converted.
             let page = page_tracker
-                .get_converted_page::<P>(paddr, self.owner, tlb_version)
+                .get_converted_page::<P>(paddr, page_size, self.owner, tlb_version)
                 .unwrap();
             // Unwrap ok since we have unique ownership of the page and therefore it can't be on
             // any other list.
@@ -874,6 +1211,115 @@ impl<T: PagingMode> GuestStagePageTable<T> {
         Ok(pages)
     }
 
+    /// Returns a list of pages of size `page_size` that were previously mapped in this page table
+    /// and have since been converted, regardless of whether or not the conversion has been fenced.
+    /// Guarantees that the full range of pages are either converted or converting pages, and that
+    /// none of them have been assigned elsewhere.
+    pub fn get_reclaimable_range<P: ConvertedPhysPage>(
+        &self,
+        addr: PageAddr<T::MappedAddressSpace>,
+        page_size: PageSize,
+        num_pages: u64,
+    ) -> Result<LockedPageList<P::DirtyPage>> {
+        let addrs = addr
+            .iter_from_with_size(page_size)
+            .ok_or(Error::MisalignedAddress)?;
+
+        let mut inner = self.inner.lock();
+        let mut pages = LockedPageList::new(self.page_tracker.clone());
+        for a in addrs.take(num_pages as usize) {
+            let paddr = inner.get_invalidated_leaf(a, page_size)?;
+            let page = self
+                .page_tracker
+                .get_reclaimable_page::<P>(paddr, page_size, self.owner)
+                .map_err(|_| Error::PageNotUnmappable)?;
+            // Unwrap ok since we have unique ownership of the page and therefore it can't be on
+            // any other list.
+            pages.push(page).unwrap();
+        }
+
+        Ok(pages)
+    }
+
+    /// Removes the converted or blocked pages of size `page_size` in the given range from this page
+    /// table if they were invalidated at a TLB version older than `tlb_version`, returning them as a
+    /// list of dirty pages that are still owned by this page table's owner. Either the full range of
+    /// pages is removed, or none of it is.
+    pub fn remove_converted_range<P: ConvertedPhysPage>(
+        &self,
+        addr: PageAddr<T::MappedAddressSpace>,
+        page_size: PageSize,
+        num_pages: u64,
+        tlb_version: TlbVersion,
+    ) -> Result<PageList<P::DirtyPage>> {
+        if !addr.is_aligned(page_size) {
+            return Err(Error::MisalignedAddress);
+        }
+
+        let mut inner = self.inner.lock();
+        // First make sure the entire range can be removed before we start clearing things.
+        // Unwraps ok since we've checked the alignment of `addr` above.
+        let addrs = addr.iter_from_with_size(page_size).unwrap();
+        for a in addrs.take(num_pages as usize) {
+            let paddr = inner.get_invalidated_leaf(a, page_size)?;
+            if !self.page_tracker.is_removable_page(
+                paddr,
+                page_size,
+                self.owner,
+                P::mem_type(),
+                tlb_version,
+            ) {
+                return Err(Error::PageNotUnmappable);
+            }
+        }
+
+        let mut pages = PageList::new(self.page_tracker.clone());
+        let addrs = addr.iter_from_with_size(page_size).unwrap();
+        for a in addrs.take(num_pages as usize) {
+            // We verified above that we can safely unwrap here.
+            let paddr = inner.get_invalidated_leaf(a, page_size).unwrap();
+            inner.clear_invalidated_leaf(a, page_size).unwrap();
+            let page = unsafe {
+                // Safe since we've verified the typing of the page and it's no longer referenced
+                // by this page table.
+                P::DirtyPage::new_with_size(paddr, page_size)
+            };
+            // Unwrap ok, a just-removed page can't be on any other PageList.
+            pages.push(page).unwrap();
+        }
+
+        Ok(pages)
+    }
+
+    /// Returns true if and only if the `num_pages` pages of size `page_size` starting at `addr` are
+    /// all invalidated pages that have started, but not yet completed, conversion, or that have
+    /// been blocked.
+    pub fn is_converting_range<P: ConvertedPhysPage>(
+        &self,
+        addr: PageAddr<T::MappedAddressSpace>,
+        page_size: PageSize,
+        num_pages: u64,
+    ) -> bool {
+        let addrs = match addr.iter_from_with_size(page_size) {
+            Some(addrs) => addrs,
+            None => return false,
+        };
+
+        let mut inner = self.inner.lock();
+        addrs.take(num_pages as usize).all(|a| {
+            inner
+                .get_invalidated_leaf(a, page_size)
+                .map_or(false, |paddr| {
+                    self.page_tracker.is_converting_page(
+                        paddr,
+                        page_size,
+                        self.owner,
+                        P::mem_type(),
+                    )
+                })
+        })
+    }
+
     /// Returns a list of shareable pages for the given range. Gurantees that the full range of pages
     /// are shareable
     pub fn get_shareable_range<P: ShareablePhysPage>(
@@ -889,15 +1335,14 @@ impl<T: PagingMode> GuestStagePageTable<T> {
         let mut inner = self.inner.lock();
         let mut pages = LockedPageList::new(self.page_tracker.clone());
         for a in addr.iter_from().take(num_pages as usize) {
-            let entry = Self::get_mapped_owned_4k_leaf(
+            let paddr = Self::get_mapped_owned_leaf(
                 &mut inner,
                 a,
+                page_size,
                 self.page_tracker.clone(),
                 self.owner,
                 P::mem_type(),
             )?;
-            // Unwrap ok, PFN must have been properly aligned in order to have been mapped.
-            let paddr = entry.page_addr();
             let page = self
                 .page_tracker
                 .get_shareable_page::<P>(paddr, self.owner)
@@ -910,39 +1355,135 @@ impl<T: PagingMode> GuestStagePageTable<T> {
         Ok(pages)
     }
 
-    fn get_mapped_owned_4k_leaf(
+    /// Invalidates the mappings of `num_pages` shared 4kB pages starting at `addr`. The pages
+    /// remain referenced by this page table until they are removed with
+    /// `remove_invalidated_shared_range()`. Either the full range of pages is invalidated, or none
+    /// of it is.
+    pub fn invalidate_shared_range(
+        &self,
+        addr: PageAddr<T::MappedAddressSpace>,
+        num_pages: u64,
+    ) -> Result<()> {
+        let mut inner = self.inner.lock();
+        // First make sure the entire range is mapped to shared pages before invalidating anything.
+        for a in addr.iter_from().take(num_pages as usize) {
+            let paddr = inner.get_mapped_leaf(a, PageSize::Size4k)?;
+            if !self
+                .page_tracker
+                .is_shared_page(paddr, PageSize::Size4k, MemType::Ram)
+            {
+                return Err(Error::PageNotShared);
+            }
+        }
+
+        for a in addr.iter_from().take(num_pages as usize) {
+            // We verified above that we can safely unwrap here.
+            inner.invalidate_leaf(a, PageSize::Size4k).unwrap();
+        }
+
+        Ok(())
+    }
+
+    /// Returns true if and only if the `num_pages` 4kB pages starting at `addr` are all shared
+    /// pages that were invalidated with `invalidate_shared_range()`.
+    pub fn is_invalidated_shared_range(
+        &self,
+        addr: PageAddr<T::MappedAddressSpace>,
+        num_pages: u64,
+    ) -> bool {
+        let mut inner = self.inner.lock();
+        addr.iter_from()
+            .take(num_pages as usize)
+            .all(|a| Self::get_invalidated_shared_leaf(&mut inner, a, &self.page_tracker).is_ok())
+    }
+
+    /// Removes the `num_pages` invalidated shared 4kB pages starting at `addr` from this page
+    /// table, dropping this page table's reference to each of them. Either the full range of pages
+    /// is removed, or none of it is.
+    ///
+    /// The caller must ensure that any stale translations for the pages have been fenced.
+    pub fn remove_invalidated_shared_range(
+        &self,
+        addr: PageAddr<T::MappedAddressSpace>,
+        num_pages: u64,
+    ) -> Result<()> {
+        let mut inner = self.inner.lock();
+        // First make sure the entire range can be removed before we start clearing things.
+        for a in addr.iter_from().take(num_pages as usize) {
+            Self::get_invalidated_shared_leaf(&mut inner, a, &self.page_tracker)?;
+        }
+
+        for a in addr.iter_from().take(num_pages as usize) {
+            // We verified above that we can safely unwrap here.
+            let paddr = inner.get_invalidated_leaf(a, PageSize::Size4k).unwrap();
+            inner.clear_invalidated_leaf(a, PageSize::Size4k).unwrap();
+            // Unwrap ok since the page is shared and therefore releasable.
+            self.page_tracker
+                .release_page_by_addr(paddr, PageSize::Size4k, self.owner)
+                .unwrap();
+        }
+
+        Ok(())
+    }
+
+    // Returns the physical address of the `page_size` page mapped at `vaddr` if it's a page owned
+    // by `owner` in the "Mapped" state.
+    fn get_mapped_owned_leaf(
         inner: &mut PageTableInner<T>,
         vaddr: PageAddr<T::MappedAddressSpace>,
+        page_size: PageSize,
         page_tracker: PageTracker,
         owner: PageOwnerId,
         mem_type: MemType,
-    ) -> Result<LeafPte<T>> {
-        inner.get_mapped_4k_leaf(vaddr).and_then(|l| {
-            if !page_tracker.is_mapped_page(l.page_addr(), owner, mem_type) {
+    ) -> Result<SupervisorPageAddr> {
+        inner.get_mapped_leaf(vaddr, page_size).and_then(|paddr| {
+            if !page_tracker.is_mapped_page(paddr, page_size, owner, mem_type) {
                 Err(Error::PageNotUnmappable)
             } else {
-                Ok(l)
+                Ok(paddr)
             }
         })
     }
 
-    // Returns the invalid 4kB leaf PTE mapping `vaddr` if the PFN the PTE references is a
+    // Returns the physical address of the 4kB page that was mapped at `vaddr` if it's a shared page
+    // whose mapping was invalidated.
+    fn get_invalidated_shared_leaf(
+        inner: &mut PageTableInner<T>,
+        vaddr: PageAddr<T::MappedAddressSpace>,
+        page_tracker: &PageTracker,
+    ) -> Result<SupervisorPageAddr> {
+        inner
+            .get_invalidated_leaf(vaddr, PageSize::Size4k)
+            .map_err(|_| Error::PageNotShared)
+            .and_then(|paddr| {
+                if !page_tracker.is_shared_page(paddr, PageSize::Size4k, MemType::Ram) {
+                    Err(Error::PageNotShared)
+                } else {
+                    Ok(paddr)
+                }
+            })
+    }
+
+    // Returns the physical address of the `page_size` page that was mapped at `vaddr` if it's a
     // page that was converted at a TLB version older than `tlb_version`.
-    fn get_converted_4k_leaf(
+    fn get_converted_leaf(
         inner: &mut PageTableInner<T>,
         vaddr: PageAddr<T::MappedAddressSpace>,
+        page_size: PageSize,
         page_tracker: PageTracker,
         owner: PageOwnerId,
         mem_type: MemType,
         tlb_version: TlbVersion,
-    ) -> Result<InvalidatedPte<T>> {
-        inner.get_invalidated_4k_leaf(vaddr).and_then(|l| {
-            if !page_tracker.is_converted_page(l.page_addr(), owner, mem_type, tlb_version) {
-                Err(Error::PageNotUnmappable)
-            } else {
-                Ok(l)
-            }
-        })
+    ) -> Result<SupervisorPageAddr> {
+        inner
+            .get_invalidated_leaf(vaddr, page_size)
+            .and_then(|paddr| {
+                if !page_tracker.is_converted_page(paddr, page_size, owner, mem_type, tlb_version) {
+                    Err(Error::PageNotUnmappable)
+                } else {
+                    Ok(paddr)
+                }
+            })
     }
 }
 
@@ -961,7 +1502,7 @@ impl<T: PagingMode> Drop for GuestStagePageTable<T> {
         .unwrap();
         for p in root_pages {
             // Unwrap ok, the page must've been assigned to us to begin with.
-            self.page_tracker.release_page(p).unwrap();
+            self.page_tracker.release_page(p.clean()).unwrap();
         }
     }
 }
@@ -972,44 +1513,62 @@ impl<T: PagingMode> Drop for GuestStagePageTable<T> {
 pub struct GuestStageMapper<'a, T: PagingMode> {
     owner: &'a GuestStagePageTable<T>,
     vaddr: PageAddr<T::MappedAddressSpace>,
+    page_size: PageSize,
     num_pages: u64,
 }
 
 impl<'a, T: PagingMode> GuestStageMapper<'a, T> {
-    /// Creates a new `GuestStageMapper` for `num_pages` starting at `vaddr`.
+    /// Creates a new `GuestStageMapper` for `num_pages` of size `page_size` starting at `vaddr`.
     fn new(
         owner: &'a GuestStagePageTable<T>,
         vaddr: PageAddr<T::MappedAddressSpace>,
+        page_size: PageSize,
         num_pages: u64,
     ) -> Self {
         Self {
             owner,
             vaddr,
+            page_size,
             num_pages,
         }
     }
 
-    /// Maps `vaddr` to `page_to_map`, consuming `page_to_map`.
-    ///
-    /// TODO: Page permissions.
+    /// Maps `vaddr` to `page_to_map` with full read, write and execute permissions, consuming
+    /// `page_to_map`. The page must be of the size this `GuestStageMapper` was created for.
     pub fn map_page<P: MappablePhysPage<M>, M: MeasureRequirement>(
         &self,
         vaddr: PageAddr<T::MappedAddressSpace>,
         page_to_map: P,
     ) -> Result<()> {
-        if page_to_map.size().is_huge() {
+        self.map_page_with_perms(vaddr, page_to_map, PteLeafPerms::RWX)
+    }
+
+    /// Same as `map_page()`, but maps the page with the permissions in `perms`.
+    pub fn map_page_with_perms<P: MappablePhysPage<M>, M: MeasureRequirement>(
+        &self,
+        vaddr: PageAddr<T::MappedAddressSpace>,
+        page_to_map: P,
+        perms: PteLeafPerms,
+    ) -> Result<()> {
+        if page_to_map.size() != self.page_size {
             return Err(Error::PageSizeNotSupported(page_to_map.size()));
         }
-        let end_vaddr = self.vaddr.checked_add_pages(self.num_pages).unwrap();
+        if !vaddr.is_aligned(self.page_size) {
+            return Err(Error::MisalignedAddress);
+        }
+        let end_vaddr = self
+            .vaddr
+            .checked_add_pages_with_size(self.num_pages, self.page_size)
+            .unwrap();
         if vaddr < self.vaddr || vaddr >= end_vaddr {
             return Err(Error::OutOfMapRange);
         }
 
         let mut inner = self.owner.inner.lock();
-        let pte_fields = PteFieldBits::user_leaf_with_perms(PteLeafPerms::RWX);
+        let pte_fields = PteFieldBits::user_leaf_with_perms(perms);
         unsafe {
             // Safe since we uniquely own page_to_map.
-            inner.map_4k_leaf(vaddr, page_to_map.addr(), pte_fields)
+            inner.map_leaf(vaddr, page_to_map.addr(), self.page_size, pte_fields)
         }
     }
 }
@@ -1017,12 +1576,14 @@ impl<'a, T: PagingMode> GuestStageMapper<'a, T> {
 impl<'a, T: PagingMode> Drop for GuestStageMapper<'a, T> {
     fn drop(&mut self) {
         let mut inner = self.owner.inner.lock();
-        for a in self.vaddr.iter_from().take(self.num_pages as usize) {
+        // Unwrap ok since the mapper was created with a `page_size`-aligned address.
+        let addrs = self.vaddr.iter_from_with_size(self.page_size).unwrap();
+        for a in addrs.take(self.num_pages as usize) {
             // Ignore the return value since this is expected to fail if the PTE was successfully
             // mapped (which will unlock the PTE), but may succeed if the holder of the
             // GuestStageMapper bailed before having filled the entire range (e.g. because of
             // another failure).
-            let _ = inner.unlock_4k_leaf(a);
+            let _ = inner.unlock_leaf(a, self.page_size);
         }
     }
 }
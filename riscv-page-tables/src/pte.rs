@@ -56,6 +56,7 @@ impl PteFieldBit {
 
 /// Permissions for a leaf page entry.
 #[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum PteLeafPerms {
     /// Read only
     R = PteFieldBit::Read.mask() as isize,
@@ -74,6 +75,37 @@ const MASK_RWX: u64 = (1 << PteFieldBit::Read.shift())
     | (1 << PteFieldBit::Write.shift())
     | (1 << PteFieldBit::Execute.shift());
 
+impl PteLeafPerms {
+    /// Returns the permissions encoded in the R, W and X bits of `bits`, or `None` if they don't
+    /// form a valid combination of leaf permissions.
+    pub fn from_bits(bits: u64) -> Option<Self> {
+        use PteLeafPerms::*;
+        [R, RW, X, RX, RWX]
+            .into_iter()
+            .find(|p| *p as u64 == bits & MASK_RWX)
+    }
+
+    /// Returns if these permissions allow reads.
+    pub fn is_readable(&self) -> bool {
+        PteFieldBit::Read.is_set(*self as u64)
+    }
+
+    /// Returns if these permissions allow writes.
+    pub fn is_writable(&self) -> bool {
+        PteFieldBit::Write.is_set(*self as u64)
+    }
+
+    /// Returns if these permissions allow execution.
+    pub fn is_executable(&self) -> bool {
+        PteFieldBit::Execute.is_set(*self as u64)
+    }
+
+    /// Returns if every access allowed by `other` is also allowed by these permissions.
+    pub fn contains(&self, other: PteLeafPerms) -> bool {
+        (*self as u64) & (other as u64) == other as u64
+    }
+}
+
 /// Represents a PTE in memory. Never instantiated. Only used as a reference to entries in a page
 /// table.
 pub(crate) struct Pte(u64);
@@ -131,6 +163,16 @@ impl Pte {
         self.bits() & MASK_RWX != 0
     }
 
+    /// Returns the permissions of this entry if it's a leaf.
+    pub fn leaf_perms(&self) -> Option<PteLeafPerms> {
+        PteLeafPerms::from_bits(self.bits())
+    }
+
+    /// Replaces the permissions of this leaf entry with `perms`.
+    pub fn set_leaf_perms(&mut self, perms: PteLeafPerms) {
+        self.0 = (self.0 & !MASK_RWX) | perms as u64;
+    }
+
     /// Returns the pfn of this entry.
     pub fn pfn(&self) -> SupervisorPfn {
         Pfn::supervisor((self.bits() >> PFN_SHIFT) & PFN_MASK)
@@ -0,0 +1,90 @@
+// Copyright (c) 2022 by Rivos Inc.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use riscv_pages::*;
+
+use crate::page_table::*;
+
+/// The levels of the three-level Sv39x4 page table.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sv39x4Level {
+    /// Level 1 table - references 4k pages.
+    L1Table,
+    /// Level 2 table - references L1 tables or 2M pages.
+    L2Table,
+    /// Level 3 table - references L2 tables or 1G pages.
+    L3Table,
+}
+
+impl PageTableLevel for Sv39x4Level {
+    fn leaf_page_size(&self) -> PageSize {
+        match self {
+            Sv39x4Level::L1Table => PageSize::Size4k,
+            Sv39x4Level::L2Table => PageSize::Size2M,
+            Sv39x4Level::L3Table => PageSize::Size1G,
+        }
+    }
+
+    fn next(&self) -> Option<Self> {
+        match self {
+            Sv39x4Level::L1Table => None,
+            Sv39x4Level::L2Table => Some(Sv39x4Level::L1Table),
+            Sv39x4Level::L3Table => Some(Sv39x4Level::L2Table),
+        }
+    }
+
+    fn addr_shift(&self) -> u64 {
+        match self {
+            Sv39x4Level::L1Table => 12,
+            Sv39x4Level::L2Table => 21,
+            Sv39x4Level::L3Table => 30,
+        }
+    }
+
+    fn addr_width(&self) -> u64 {
+        match self {
+            Sv39x4Level::L1Table => 9,
+            Sv39x4Level::L2Table => 9,
+            Sv39x4Level::L3Table => 11,
+        }
+    }
+
+    fn table_pages(&self) -> usize {
+        match self {
+            Sv39x4Level::L1Table => 1,
+            Sv39x4Level::L2Table => 1,
+            Sv39x4Level::L3Table => 4,
+        }
+    }
+
+    fn is_leaf(&self) -> bool {
+        matches!(self, Sv39x4Level::L1Table)
+    }
+}
+
+/// The `Sv39x4` addressing mode for 2nd-stage translation tables.
+pub enum Sv39x4 {}
+
+impl GuestStagePagingMode for Sv39x4 {
+    const HGATP_VALUE: u64 = 8;
+}
+
+impl PagingMode for Sv39x4 {
+    type Level = Sv39x4Level;
+    type MappedAddressSpace = GuestPhys;
+
+    const TOP_LEVEL_ALIGN: u64 = 16 * 1024;
+
+    fn root_level() -> Self::Level {
+        Sv39x4Level::L3Table
+    }
+
+    fn max_pte_pages(num_pages: u64) -> u64 {
+        // Determine how much ram is needed for sv39x4 mappings; 512 8-byte ptes per page
+        let num_l1_pages = num_pages / ENTRIES_PER_PAGE + 1;
+        let num_l2_pages = num_l1_pages / ENTRIES_PER_PAGE + 1;
+        let num_l3_pages = 4;
+        num_l1_pages + num_l2_pages + num_l3_pages
+    }
+}
@@ -0,0 +1,106 @@
+// Copyright (c) 2022 by Rivos Inc.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use riscv_pages::*;
+
+use crate::page_table::*;
+
+/// The levels of the five-level Sv57x4 page table.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sv57x4Level {
+    /// Level 1 table - references 4k pages.
+    L1Table,
+    /// Level 2 table - references L1 tables or 2M pages.
+    L2Table,
+    /// Level 3 table - references L2 tables or 1G pages.
+    L3Table,
+    /// Level 4 table - references L3 tables or 512G pages.
+    L4Table,
+    /// Level 5 table - references L4 tables or 256T pages.
+    L5Table,
+}
+
+impl PageTableLevel for Sv57x4Level {
+    fn leaf_page_size(&self) -> PageSize {
+        match self {
+            Sv57x4Level::L1Table => PageSize::Size4k,
+            Sv57x4Level::L2Table => PageSize::Size2M,
+            Sv57x4Level::L3Table => PageSize::Size1G,
+            Sv57x4Level::L4Table => PageSize::Size512G,
+            Sv57x4Level::L5Table => PageSize::Size256T,
+        }
+    }
+
+    fn next(&self) -> Option<Self> {
+        match self {
+            Sv57x4Level::L1Table => None,
+            Sv57x4Level::L2Table => Some(Sv57x4Level::L1Table),
+            Sv57x4Level::L3Table => Some(Sv57x4Level::L2Table),
+            Sv57x4Level::L4Table => Some(Sv57x4Level::L3Table),
+            Sv57x4Level::L5Table => Some(Sv57x4Level::L4Table),
+        }
+    }
+
+    fn addr_shift(&self) -> u64 {
+        match self {
+            Sv57x4Level::L1Table => 12,
+            Sv57x4Level::L2Table => 21,
+            Sv57x4Level::L3Table => 30,
+            Sv57x4Level::L4Table => 39,
+            Sv57x4Level::L5Table => 48,
+        }
+    }
+
+    fn addr_width(&self) -> u64 {
+        match self {
+            Sv57x4Level::L1Table => 9,
+            Sv57x4Level::L2Table => 9,
+            Sv57x4Level::L3Table => 9,
+            Sv57x4Level::L4Table => 9,
+            Sv57x4Level::L5Table => 11,
+        }
+    }
+
+    fn table_pages(&self) -> usize {
+        match self {
+            Sv57x4Level::L1Table => 1,
+            Sv57x4Level::L2Table => 1,
+            Sv57x4Level::L3Table => 1,
+            Sv57x4Level::L4Table => 1,
+            Sv57x4Level::L5Table => 4,
+        }
+    }
+
+    fn is_leaf(&self) -> bool {
+        matches!(self, Sv57x4Level::L1Table)
+    }
+}
+
+/// The `Sv57x4` addressing mode for 2nd-stage translation tables.
+pub enum Sv57x4 {}
+
+impl GuestStagePagingMode for Sv57x4 {
+    const HGATP_VALUE: u64 = 10;
+}
+
+impl PagingMode for Sv57x4 {
+    type Level = Sv57x4Level;
+    type MappedAddressSpace = GuestPhys;
+
+    const TOP_LEVEL_ALIGN: u64 = 16 * 1024;
+
+    fn root_level() -> Self::Level {
+        Sv57x4Level::L5Table
+    }
+
+    fn max_pte_pages(num_pages: u64) -> u64 {
+        // Determine how much ram is needed for sv57x4 mappings; 512 8-byte ptes per page
+        let num_l1_pages = num_pages / ENTRIES_PER_PAGE + 1;
+        let num_l2_pages = num_l1_pages / ENTRIES_PER_PAGE + 1;
+        let num_l3_pages = num_l2_pages / ENTRIES_PER_PAGE + 1;
+        let num_l4_pages = num_l3_pages / ENTRIES_PER_PAGE + 1;
+        let num_l5_pages = 4;
+        num_l1_pages + num_l2_pages + num_l3_pages + num_l4_pages + num_l5_pages
+    }
+}
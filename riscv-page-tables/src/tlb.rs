@@ -60,8 +60,38 @@ pub fn hfence_gvma(gaddr: Option<u64>, vmid: Option<u64>) {
     }
 }
 
+/// Executes an HFENCE.VVMA instruction, invalidating VS-stage translations for the VMID currently
+/// programmed in HGATP.
+///
+/// If `vaddr` is not None only translations mapping the specified guest virtual address are
+/// invalidated, otherwise translations for all guest virtual addresses are invalidated.
+///
+/// If 'asid' is not None only translations using the specified ASID are invalidated, otherwise
+/// translations for all ASIDs are invalidated.
+#[cfg(all(target_arch = "riscv64", target_os = "none"))]
+pub fn hfence_vvma(vaddr: Option<u64>, asid: Option<u64>) {
+    match (vaddr, asid) {
+        // Safety: HFENCE.VVMA's behavior is well-defined and its only side effect is to invalidate
+        // address translation caches.
+        (Some(addr), Some(id)) => unsafe {
+            asm!("hfence.vvma {rs1}, {rs2}", rs1 = in(reg) addr, rs2 = in(reg) id);
+        },
+        (Some(addr), None) => unsafe {
+            asm!("hfence.vvma {rs1}, zero", rs1 = in(reg) addr);
+        },
+        (None, Some(id)) => unsafe {
+            asm!("hfence.vvma zero, {rs2}", rs2 = in(reg) id);
+        },
+        (None, None) => unsafe {
+            asm!("hfence.vvma");
+        },
+    }
+}
+
 // Make fence instructions a no-op for testing.
 #[cfg(not(any(target_arch = "riscv64", target_os = "none")))]
 pub fn sfence_vma(_vaddr: Option<u64>, _asid: Option<u64>) {}
 #[cfg(not(any(target_arch = "riscv64", target_os = "none")))]
 pub fn hfence_gvma(_gaddr: Option<u64>, _vmid: Option<u64>) {}
+#[cfg(not(any(target_arch = "riscv64", target_os = "none")))]
+pub fn hfence_vvma(_vaddr: Option<u64>, _asid: Option<u64>) {}
@@ -70,6 +70,7 @@ register_bitfields![u64,
         ssoft OFFSET(1) NUMBITS(1) [],
         stimer OFFSET(5) NUMBITS(1) [],
         sext OFFSET(9) NUMBITS(1) [],
+        sgext OFFSET(12) NUMBITS(1) [],
     ]
 ];
 
@@ -159,6 +160,13 @@ register_bitfields![u64,
     ]
 ];
 
+// Real-time counter register.
+register_bitfields![u64,
+    pub time [
+        value OFFSET(0) NUMBITS(64) [],
+    ]
+];
+
 // IMSIC indirect CSR address register.
 register_bitfields![u64,
     pub siselect [
@@ -73,6 +73,8 @@ pub struct CSR {
     pub vtype: ReadWriteRiscvCsr<vtype::Register, 0xC21>,
     pub vlenb: ReadWriteRiscvCsr<vlenb::Register, 0xC22>,
 
+    pub time: ReadWriteRiscvCsr<time::Register, CSR_TIME>,
+
     pub hpmcounter: [&'static dyn RiscvCsrInterface<R = hpmcounter::Register>; 32],
 }
 
@@ -130,6 +132,8 @@ pub const CSR: &CSR = &CSR {
     vtype: ReadWriteRiscvCsr::new(),
     vlenb: ReadWriteRiscvCsr::new(),
 
+    time: ReadWriteRiscvCsr::new(),
+
     // TODO: Use a procedural macro to generate these.
     hpmcounter: [
         &ReadWriteRiscvCsr::<hpmcounter::Register, 0xc00>::new(),
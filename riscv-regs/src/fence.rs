@@ -37,6 +37,12 @@ pub fn mmio_rmb() {
     unsafe { asm!("fence i,r") };
 }
 
+/// Synchronizes the instruction stream with preceeding stores to instruction memory.
+#[cfg(all(target_arch = "riscv64", target_os = "none"))]
+pub fn fence_i() {
+    unsafe { asm!("fence.i") };
+}
+
 /// Hint that the CPU's rate of instruction retirement should be temporarily paused or reduced.
 #[cfg(all(target_arch = "riscv64", target_os = "none"))]
 pub fn pause() {
@@ -54,4 +60,6 @@ pub fn mmio_wmb() {}
 #[cfg(not(any(target_arch = "riscv64", target_os = "none")))]
 pub fn mmio_rmb() {}
 #[cfg(not(any(target_arch = "riscv64", target_os = "none")))]
+pub fn fence_i() {}
+#[cfg(not(any(target_arch = "riscv64", target_os = "none")))]
 pub fn pause() {}
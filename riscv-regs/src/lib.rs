@@ -12,15 +12,18 @@
 //! csrs - (H)S-mode CSRs
 //! decode - basic RV64 instruction decoding
 //! fence - memory fence instructions
+//! mmio - decoding of load/store instructions used for MMIO emulation
 
 mod csrs;
 mod decode;
 mod fence;
 mod inst;
+mod mmio;
 mod regs;
 
 pub use csrs::*;
 pub use decode::*;
 pub use fence::*;
 pub use inst::*;
+pub use mmio::*;
 pub use regs::*;
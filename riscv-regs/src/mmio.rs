@@ -0,0 +1,223 @@
+// Copyright (c) 2022 by Rivos Inc.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{DecodedInstruction, GprIndex, Instruction};
+
+/// Possible MMIO instructions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MmioOpcode {
+    Load64,
+    Load32,
+    Load32U,
+    Load16,
+    Load16U,
+    Load8,
+    Load8U,
+    Store64,
+    Store32,
+    Store16,
+    Store8,
+    FpLoad64,
+    FpLoad32,
+    FpStore64,
+    FpStore32,
+}
+
+impl MmioOpcode {
+    /// Returns if the MMIO operation is a load.
+    pub fn is_load(&self) -> bool {
+        use MmioOpcode::*;
+        matches!(
+            self,
+            Load8 | Load8U | Load16 | Load16U | Load32 | Load32U | Load64 | FpLoad32 | FpLoad64
+        )
+    }
+
+    /// Returns the width of the MMIO access in bytes.
+    pub fn width(&self) -> u64 {
+        use MmioOpcode::*;
+        match self {
+            Load8 | Load8U | Store8 => 1,
+            Load16 | Load16U | Store16 => 2,
+            Load32 | Load32U | Store32 | FpLoad32 | FpStore32 => 4,
+            Load64 | Store64 | FpLoad64 | FpStore64 => 8,
+        }
+    }
+
+    /// Returns if the MMIO operation loads or stores a floating point register.
+    pub fn is_fp(&self) -> bool {
+        use MmioOpcode::*;
+        matches!(self, FpLoad32 | FpLoad64 | FpStore32 | FpStore64)
+    }
+
+    /// Returns the value written to the destination register when this load operation reads
+    /// `val`: sign- or zero-extended to 64 bits for integer loads, and NaN-boxed for
+    /// single-precision floating point loads. Returns `val` unmodified for stores.
+    pub fn extend_loaded_value(&self, val: u64) -> u64 {
+        use MmioOpcode::*;
+        match self {
+            Load8 => val as i8 as u64,
+            Load8U => val as u8 as u64,
+            Load16 => val as i16 as u64,
+            Load16U => val as u16 as u64,
+            Load32 => val as i32 as u64,
+            Load32U => val as u32 as u64,
+            FpLoad32 => val as u32 as u64 | 0xffff_ffff_0000_0000,
+            _ => val,
+        }
+    }
+}
+
+/// A decoded MMIO operation.
+#[derive(Clone, Copy, Debug)]
+pub struct MmioOperation {
+    opcode: MmioOpcode,
+    register: u32,
+    len: usize,
+}
+
+impl MmioOperation {
+    /// Creates an `MmioOperation` from `instruction` if the MMIO is supported using that instruction.
+    pub fn from_instruction(instruction: DecodedInstruction) -> Option<Self> {
+        use Instruction::*;
+        let (opcode, reg_index) = match instruction.instruction() {
+            Lb(i) => (MmioOpcode::Load8, i.rd()),
+            Lh(i) => (MmioOpcode::Load16, i.rd()),
+            Lw(i) => (MmioOpcode::Load32, i.rd()),
+            Lbu(i) => (MmioOpcode::Load8U, i.rd()),
+            Lhu(i) => (MmioOpcode::Load16U, i.rd()),
+            Lwu(i) => (MmioOpcode::Load32U, i.rd()),
+            Ld(i) => (MmioOpcode::Load64, i.rd()),
+            Sb(s) => (MmioOpcode::Store8, s.rs2()),
+            Sh(s) => (MmioOpcode::Store16, s.rs2()),
+            Sw(s) => (MmioOpcode::Store32, s.rs2()),
+            Sd(s) => (MmioOpcode::Store64, s.rs2()),
+            Flw(i) => (MmioOpcode::FpLoad32, i.rd()),
+            Fld(i) => (MmioOpcode::FpLoad64, i.rd()),
+            Fsw(s) => (MmioOpcode::FpStore32, s.rs2()),
+            Fsd(s) => (MmioOpcode::FpStore64, s.rs2()),
+            _ => {
+                return None;
+            }
+        };
+        Some(Self {
+            opcode,
+            register: reg_index,
+            len: instruction.len(),
+        })
+    }
+
+    /// Returns the operation as a `MmioOpcode`.
+    pub fn opcode(&self) -> MmioOpcode {
+        self.opcode
+    }
+
+    /// Returns the target register for the operation. Either 'rd' for load instructions, or 'rs2' for
+    /// store instructions. Only valid for integer operations.
+    pub fn register(&self) -> GprIndex {
+        // Unwrap ok since 'rd' and 'rs2' are always valid GPR indices.
+        GprIndex::from_raw(self.register).unwrap()
+    }
+
+    /// Returns the index of the target floating point register for the operation. Either 'rd' for
+    /// load instructions, or 'rs2' for store instructions. Only valid for floating point
+    /// operations.
+    pub fn fp_register(&self) -> usize {
+        self.register as usize
+    }
+
+    /// Returns the length of the raw instruction.
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_mmio(raw: u32) -> Option<MmioOperation> {
+        MmioOperation::from_instruction(DecodedInstruction::from_raw(raw).unwrap())
+    }
+
+    #[test]
+    fn fp_mmio_decoding() {
+        // flw f5, 8(a0)
+        let op = decode_mmio(0x0085_2287).unwrap();
+        assert_eq!(op.opcode(), MmioOpcode::FpLoad32);
+        assert_eq!(op.fp_register(), 5);
+        assert_eq!(op.len(), 4);
+        // fld f6, 16(a1)
+        let op = decode_mmio(0x0105_b307).unwrap();
+        assert_eq!(op.opcode(), MmioOpcode::FpLoad64);
+        assert_eq!(op.fp_register(), 6);
+        // fsw f7, 4(a2)
+        let op = decode_mmio(0x0076_2227).unwrap();
+        assert_eq!(op.opcode(), MmioOpcode::FpStore32);
+        assert_eq!(op.fp_register(), 7);
+        // fsd f8, 8(a3)
+        let op = decode_mmio(0x0086_b427).unwrap();
+        assert_eq!(op.opcode(), MmioOpcode::FpStore64);
+        assert_eq!(op.fp_register(), 8);
+
+        for opcode in [MmioOpcode::FpLoad32, MmioOpcode::FpStore32] {
+            assert!(opcode.is_fp());
+            assert_eq!(opcode.width(), 4);
+        }
+        for opcode in [MmioOpcode::FpLoad64, MmioOpcode::FpStore64] {
+            assert!(opcode.is_fp());
+            assert_eq!(opcode.width(), 8);
+        }
+        assert!(MmioOpcode::FpLoad32.is_load() && MmioOpcode::FpLoad64.is_load());
+        assert!(!MmioOpcode::FpStore32.is_load() && !MmioOpcode::FpStore64.is_load());
+    }
+
+    #[test]
+    fn integer_mmio_decoding() {
+        // lw a1, 0(a0)
+        let op = decode_mmio(0x0005_2583).unwrap();
+        assert_eq!(op.opcode(), MmioOpcode::Load32);
+        assert_eq!(op.register(), GprIndex::A1);
+        assert!(!op.opcode().is_fp());
+        // sd a2, 8(a0)
+        let op = decode_mmio(0x00c5_3423).unwrap();
+        assert_eq!(op.opcode(), MmioOpcode::Store64);
+        assert_eq!(op.register(), GprIndex::A2);
+        // add ra, sp, gp isn't a memory access.
+        assert!(decode_mmio(0x0031_00b3).is_none());
+    }
+
+    #[test]
+    fn loaded_values() {
+        // Single-precision values are NaN-boxed, regardless of what's in the upper 32 bits.
+        let one = 0x3f80_0000;
+        assert_eq!(
+            MmioOpcode::FpLoad32.extend_loaded_value(one),
+            0xffff_ffff_3f80_0000
+        );
+        assert_eq!(
+            MmioOpcode::FpLoad32.extend_loaded_value(0x1234_5678_0000_0000 | one),
+            0xffff_ffff_3f80_0000
+        );
+        assert_eq!(
+            MmioOpcode::FpLoad64.extend_loaded_value(0x3ff0_0000_0000_0000),
+            0x3ff0_0000_0000_0000
+        );
+
+        assert_eq!(
+            MmioOpcode::Load8.extend_loaded_value(0x80),
+            0xffff_ffff_ffff_ff80
+        );
+        assert_eq!(MmioOpcode::Load8U.extend_loaded_value(0x180), 0x80);
+        assert_eq!(
+            MmioOpcode::Load32.extend_loaded_value(0x8000_0000),
+            0xffff_ffff_8000_0000
+        );
+        assert_eq!(
+            MmioOpcode::Load32U.extend_loaded_value(0xffff_ffff_8000_0000),
+            0x8000_0000
+        );
+    }
+}
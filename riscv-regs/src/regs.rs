@@ -118,6 +118,18 @@ impl GeneralPurposeRegisters {
 #[repr(C)]
 pub struct FloatingPointRegisters([u64; 32]);
 
+impl FloatingPointRegisters {
+    /// Returns the value of the register at `index`.
+    pub fn reg(&self, index: usize) -> u64 {
+        self.0[index]
+    }
+
+    /// Sets the value of the register at `index`.
+    pub fn set_reg(&mut self, index: usize, val: u64) {
+        self.0[index] = val;
+    }
+}
+
 /// The vector register file. We don't expect to directly interact with a guest's vector state
 /// other than for saving/restoring the registers, so simply treat the register file as an array
 /// of 256b values. This actually depends on the vlenb csr, so if the register is greater than 256
@@ -134,3 +146,21 @@ pub struct VectorRegister([u64; U64S_IN_REGISTER]);
 #[derive(Default)]
 #[repr(C)]
 pub struct VectorRegisters([VectorRegister; 32]);
+
+impl VectorRegisters {
+    /// Returns the contents of the register at `index`.
+    pub fn reg(&self, index: usize) -> [u8; MAX_VECTOR_REGISTER_LEN] {
+        let mut bytes = [0; MAX_VECTOR_REGISTER_LEN];
+        for (chunk, val) in bytes.chunks_exact_mut(8).zip(self.0[index].0.iter()) {
+            chunk.copy_from_slice(&val.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Sets the contents of the register at `index`.
+    pub fn set_reg(&mut self, index: usize, bytes: &[u8; MAX_VECTOR_REGISTER_LEN]) {
+        for (val, chunk) in self.0[index].0.iter_mut().zip(bytes.chunks_exact(8)) {
+            *val = u64::from_le_bytes(chunk.try_into().unwrap());
+        }
+    }
+}
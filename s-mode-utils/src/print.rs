@@ -26,6 +26,13 @@ impl Console {
     pub fn set_writer(writer: &'static dyn ConsoleWriter) {
         CONSOLE.lock().writer = Some(writer);
     }
+
+    /// Writes the raw `bytes` to the system console.
+    pub fn write_bytes(bytes: &[u8]) {
+        if let Some(w) = CONSOLE.lock().writer {
+            w.write_bytes(bytes);
+        }
+    }
 }
 
 /// The `Console` singleton.
@@ -0,0 +1,37 @@
+// Copyright (c) 2022 by Rivos Inc.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::DebugConsoleFunction::*;
+use crate::{ecall_send, Result, SbiMessage};
+
+/// Writes `bytes` to the debug console, returning the number of bytes actually written. The
+/// caller must be running with `bytes` identity-mapped.
+pub fn console_write(bytes: &[u8]) -> Result<u64> {
+    let msg = SbiMessage::DebugConsole(ConsoleWrite {
+        num_bytes: bytes.len() as u64,
+        base_addr: bytes.as_ptr() as u64,
+    });
+    // Safety: ConsoleWrite only reads within the bounds of `bytes`.
+    unsafe { ecall_send(&msg) }
+}
+
+/// Reads any pending input from the debug console into `buf` without blocking, returning the
+/// number of bytes actually read. The caller must be running with `buf` identity-mapped.
+pub fn console_read(buf: &mut [u8]) -> Result<u64> {
+    let msg = SbiMessage::DebugConsole(ConsoleRead {
+        num_bytes: buf.len() as u64,
+        base_addr: buf.as_mut_ptr() as u64,
+    });
+    // Safety: ConsoleRead only writes bytes within the bounds of `buf`, which is a valid `u8`
+    // slice and thus can hold arbitrary values.
+    unsafe { ecall_send(&msg) }
+}
+
+/// Writes a single byte to the debug console.
+pub fn console_write_byte(byte: u8) -> Result<()> {
+    let msg = SbiMessage::DebugConsole(ConsoleWriteByte { byte });
+    // Safety: ConsoleWriteByte doesn't touch memory.
+    unsafe { ecall_send(&msg) }?;
+    Ok(())
+}
@@ -0,0 +1,14 @@
+// Copyright (c) 2022 by Rivos Inc.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::IpiFunction::*;
+use crate::{ecall_send, HartMask, Result, SbiMessage};
+
+/// Sends a supervisor software interrupt to the harts in `hart_mask`.
+pub fn send_ipi(hart_mask: HartMask) -> Result<()> {
+    let msg = SbiMessage::Ipi(SendIpi { hart_mask });
+    // Safety: SendIpi doesn't touch memory.
+    unsafe { ecall_send(&msg) }?;
+    Ok(())
+}
@@ -8,6 +8,24 @@ pub mod reset;
 /// Host interfaces for hart state management.
 pub mod state;
 
+/// Interfaces for sending inter-processor interrupts.
+pub mod ipi;
+
+/// Interfaces for issuing remote fences.
+pub mod rfence;
+
+/// Interfaces for programming the timer.
+pub mod time;
+
+/// Interfaces for the debug console.
+pub mod debug_console;
+
+/// Interfaces for steal-time accounting.
+pub mod steal_time;
+
+/// Interfaces for suspending the system.
+pub mod system_suspend;
+
 /// Host interfaces for confidential computing.
 pub mod tee_host;
 
@@ -82,3 +82,20 @@ pub fn read_firmware_counter(counter_index: u64) -> Result<u64> {
     // Safety: PmuFunction does not touch memory.
     unsafe { ecall_send(&msg) }
 }
+
+/// Sets the PMU snapshot shared memory area to the page at `shmem_addr`, or disables snapshots if
+/// `shmem_addr` is `None`.
+///
+/// # Safety
+///
+/// `shmem_addr` must be the 4kB aligned, identity-mapped address of a `PmuSnapshot` which remains
+/// valid, and is not otherwise accessed except with volatile semantics, until snapshots are
+/// disabled again.
+pub unsafe fn set_snapshot_shmem(shmem_addr: Option<u64>) -> Result<()> {
+    let msg = SbiMessage::Pmu(PmuFunction::SnapshotSetShmem {
+        shmem_addr,
+        flags: 0,
+    });
+    ecall_send(&msg)?;
+    Ok(())
+}
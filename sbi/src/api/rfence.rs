@@ -0,0 +1,46 @@
+// Copyright (c) 2022 by Rivos Inc.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::RfenceFunction::*;
+use crate::{ecall_send, HartMask, Result, SbiMessage};
+
+/// Instructs the harts in `hart_mask` to execute FENCE.I.
+pub fn remote_fence_i(hart_mask: HartMask) -> Result<()> {
+    let msg = SbiMessage::Rfence(RemoteFenceI { hart_mask });
+    // Safety: RemoteFenceI doesn't touch memory.
+    unsafe { ecall_send(&msg) }?;
+    Ok(())
+}
+
+/// Instructs the harts in `hart_mask` to invalidate their address translations for the `size`
+/// bytes at `start_addr`.
+pub fn remote_sfence_vma(hart_mask: HartMask, start_addr: u64, size: u64) -> Result<()> {
+    let msg = SbiMessage::Rfence(RemoteSFenceVma {
+        hart_mask,
+        start_addr,
+        size,
+    });
+    // Safety: RemoteSFenceVma doesn't touch memory.
+    unsafe { ecall_send(&msg) }?;
+    Ok(())
+}
+
+/// Instructs the harts in `hart_mask` to invalidate their address translations for the `size`
+/// bytes at `start_addr` in the address space identified by `asid`.
+pub fn remote_sfence_vma_asid(
+    hart_mask: HartMask,
+    start_addr: u64,
+    size: u64,
+    asid: u64,
+) -> Result<()> {
+    let msg = SbiMessage::Rfence(RemoteSFenceVmaAsid {
+        hart_mask,
+        start_addr,
+        size,
+        asid,
+    });
+    // Safety: RemoteSFenceVmaAsid doesn't touch memory.
+    unsafe { ecall_send(&msg) }?;
+    Ok(())
+}
@@ -22,3 +22,18 @@ pub unsafe fn hart_start(hart_id: u64, start_addr: u64, opaque: u64) -> Result<(
     ecall_send(&msg)?;
     Ok(())
 }
+
+/// Stops the calling cpu. Only returns if the request failed.
+pub fn hart_stop() -> Result<()> {
+    let msg = SbiMessage::HartState(HartStop);
+    // Safety: HartStop doesn't touch any memory.
+    unsafe { ecall_send(&msg) }?;
+    Ok(())
+}
+
+/// Returns the `HartState` of the given cpu as reported by HartStatus.
+pub fn hart_status(hart_id: u64) -> Result<u64> {
+    let msg = SbiMessage::HartState(HartStatus { hart_id });
+    // Safety: HartStatus doesn't touch any memory.
+    unsafe { ecall_send(&msg) }
+}
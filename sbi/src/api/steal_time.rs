@@ -0,0 +1,23 @@
+// Copyright (c) 2022 by Rivos Inc.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::StealTimeFunction::*;
+use crate::{ecall_send, Result, SbiMessage};
+
+/// Sets the steal-time record of the calling vCPU to the `StealTimeRecord` at `shmem_addr`, or
+/// stops reporting steal-time if `shmem_addr` is `None`.
+///
+/// # Safety
+///
+/// `shmem_addr` must be the 64-byte aligned, identity-mapped address of a `StealTimeRecord` which
+/// remains valid, and is not otherwise accessed except with volatile semantics, until steal-time
+/// reporting is disabled again.
+pub unsafe fn set_shmem(shmem_addr: Option<u64>) -> Result<()> {
+    let msg = SbiMessage::StealTime(SetShmem {
+        shmem_addr,
+        flags: 0,
+    });
+    ecall_send(&msg)?;
+    Ok(())
+}
@@ -0,0 +1,24 @@
+// Copyright (c) 2022 by Rivos Inc.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::SystemSuspendFunction::*;
+use crate::{ecall_send, Result, SbiMessage, SuspendSleepType};
+
+/// Suspends the system to the sleep state given by `sleep_type`. All other harts must have been
+/// stopped. Only returns if the suspend request failed; on resume the calling hart starts executing
+/// at `resume_addr` with its hart ID in a0 and `opaque` in a1.
+///
+/// # Safety
+///
+/// `resume_addr` must point to code that can be safely executed with translation disabled.
+/// `opaque`, if a pointer, must point to data that is safe to access from the resumed context.
+pub unsafe fn suspend(sleep_type: SuspendSleepType, resume_addr: u64, opaque: u64) -> Result<()> {
+    let msg = SbiMessage::SystemSuspend(Suspend {
+        sleep_type: sleep_type.raw(),
+        resume_addr,
+        opaque,
+    });
+    ecall_send(&msg)?;
+    Ok(())
+}
@@ -3,7 +3,7 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::TeeGuestFunction::*;
-use crate::{ecall_send, Result, SbiMessage, TeeMemoryRegion};
+use crate::{ecall_send, Result, SbiMessage, TeeMemoryRegion, TvmPagePerms};
 
 /// Registers an emulated MMIO region in a previously-unused range of guest physical address space.
 /// Future accesses in the specified address range will trap to the host, allowing it to emulate
@@ -36,3 +36,42 @@ pub fn add_shared_memory_region(addr: u64, len: u64) -> Result<()> {
     unsafe { ecall_send(&msg) }?;
     Ok(())
 }
+
+/// Converts the range of confidential memory at `addr` to shared memory. The contents of the range
+/// are discarded, and future accesses to the range will trap to the host, allowing it to insert
+/// pages of shared memory.
+///
+/// # Safety
+///
+/// The caller must not rely on the contents of the range being preserved, and must treat any
+/// memory within the range as accessible by the host once the call returns.
+pub unsafe fn share_memory(addr: u64, len: u64) -> Result<()> {
+    let msg = SbiMessage::TeeGuest(ShareMemory { addr, len });
+    // The caller must guarantee that it no longer relies on the contents of the range.
+    ecall_send(&msg)?;
+    Ok(())
+}
+
+/// Converts the range of shared memory at `addr` back to confidential memory. Future accesses to
+/// the range will trap to the host, allowing it to insert zero-filled confidential pages.
+///
+/// # Safety
+///
+/// The caller must not rely on the contents of the range being preserved.
+pub unsafe fn unshare_memory(addr: u64, len: u64) -> Result<()> {
+    let msg = SbiMessage::TeeGuest(UnshareMemory { addr, len });
+    // The caller must guarantee that it no longer relies on the contents of the range.
+    ecall_send(&msg)?;
+    Ok(())
+}
+
+/// Restricts the access permissions of the range of confidential memory at `addr` to `perms`.
+/// Permissions can only be removed, never added. Accesses that the new permissions don't allow
+/// will fault and be reported to the host rather than being handled by the caller.
+pub fn restrict_page_permissions(addr: u64, len: u64, perms: TvmPagePerms) -> Result<()> {
+    let msg = SbiMessage::TeeGuest(RestrictPagePermissions { addr, len, perms });
+    // Safety: RestrictPagePermissions does not directly access our memory. Accesses to the range
+    // that are no longer permitted fault rather than having undefined behavior.
+    unsafe { ecall_send(&msg) }?;
+    Ok(())
+}
@@ -4,7 +4,10 @@
 
 use crate::TeeHostFunction::*;
 use crate::{ecall_send, Error, Result, SbiMessage};
-use crate::{RegisterSetLocation, TeeMemoryRegion, TsmInfo, TsmPageType, TvmCreateParams};
+use crate::{
+    RegisterSetLocation, TeeMemoryRegion, TsmInfo, TsmPageType, TvmCreateParams, TvmPagePerms,
+    TvmPagingMode,
+};
 
 /// Initiates a TSM fence on this CPU.
 pub fn initiate_fence() -> Result<()> {
@@ -80,17 +83,22 @@ pub fn reclaim_pages(addr: u64, num_pages: u64) -> Result<()> {
 /// - tvm_vcpu_addr: The base physical address of the confidential memory region to be used to hold
 /// the TVM's vCPU state. Must be page-aligned and `TsmInfo::tvm_bytes_per_vcpu` * `tvm_num_vcpus`
 /// bytes in length, rounded up to the nearest multiple of 4kB.
+///
+/// - tvm_paging_mode: The guest-stage paging mode of the TVM, which determines the width of its
+/// guest physical address space. Must be supported by the hardware.
 pub fn tvm_create(
     tvm_page_directory_addr: u64,
     tvm_state_addr: u64,
     tvm_num_vcpus: u64,
     tvm_vcpu_addr: u64,
+    tvm_paging_mode: TvmPagingMode,
 ) -> Result<u64> {
     let tvm_create_params = TvmCreateParams {
         tvm_page_directory_addr,
         tvm_state_addr,
         tvm_num_vcpus,
         tvm_vcpu_addr,
+        tvm_paging_mode: tvm_paging_mode as u64,
     };
     let msg = SbiMessage::TeeHost(TvmCreate {
         params_addr: (&tvm_create_params as *const TvmCreateParams) as u64,
@@ -110,7 +118,8 @@ pub fn tvm_finalize(vmid: u64) -> Result<()> {
     Ok(())
 }
 
-/// Destroys a TVM created with `tvm_create`.
+/// Destroys a TVM created with `tvm_create`. The memory donated to the TVM is returned in the
+/// converted state and may then be reclaimed with `reclaim_pages`.
 pub fn tvm_destroy(vmid: u64) -> Result<()> {
     let msg = SbiMessage::TeeHost(TvmDestroy { guest_id: vmid });
     // Safety: destroying a VM doesn't write to memory that's accessible from the host.
@@ -118,11 +127,13 @@ pub fn tvm_destroy(vmid: u64) -> Result<()> {
     Ok(())
 }
 
-/// Runs the given vcpu of the specified TVM.
-pub fn tvm_run(vmid: u64, vcpu_id: u64) -> Result<u64> {
+/// Runs the given vcpu of the specified TVM for at most `time_slice` timebase ticks, or until it
+/// exits if `time_slice` is 0.
+pub fn tvm_run(vmid: u64, vcpu_id: u64, time_slice: u64) -> Result<u64> {
     let msg = SbiMessage::TeeHost(TvmCpuRun {
         guest_id: vmid,
         vcpu_id,
+        time_slice,
     });
     // Safety: running a VM will only write to the shared-memory area registered in add_vcpu().
     unsafe { ecall_send(&msg) }
@@ -141,6 +152,33 @@ pub fn add_page_table_pages(vmid: u64, page_addr: u64, num_pages: u64) -> Result
     Ok(())
 }
 
+/// Adds pages to the pool from which confidential page faults of the given vmid are resolved.
+pub fn add_zero_page_pool(vmid: u64, page_addr: u64, num_pages: u64) -> Result<()> {
+    let msg = SbiMessage::TeeHost(TvmAddZeroPagePool {
+        guest_id: vmid,
+        page_addr,
+        num_pages,
+    });
+    // Safety: `TvmAddZeroPagePool` only accesses pages that have been previously converted.
+    // Passing non-converted memory will result in a failure and not touch the memory.
+    unsafe { ecall_send(&msg) }?;
+    Ok(())
+}
+
+/// Writes information about the state of the TVM `vmid` to `buf`: a `TvmInfo` structure followed
+/// by as many of the TVM's `TvmRegionInfo` and `TvmCpuInfo` structures as fit. Returns the number
+/// of bytes needed to hold the complete information.
+pub fn get_tvm_info(vmid: u64, buf: &mut [u8]) -> Result<u64> {
+    let msg = SbiMessage::TeeHost(TvmGetInfo {
+        guest_id: vmid,
+        dest_addr: buf.as_mut_ptr() as u64,
+        len: buf.len() as u64,
+    });
+    // Safety: `buf` is uniquely owned so it's safe to modify in SBI, and at most `buf.len()` bytes
+    // are written.
+    unsafe { ecall_send(&msg) }
+}
+
 /// Returns the number of register sets in the vCPU shared-memory state area for vCPUs of `vmid`.
 pub fn num_vcpu_register_sets(vmid: u64) -> Result<u64> {
     let msg = SbiMessage::TeeHost(TvmCpuNumRegisterSets { guest_id: vmid });
@@ -193,12 +231,14 @@ pub fn add_confidential_memory_region(vmid: u64, guest_addr: u64, len: u64) -> R
 }
 
 /// Copies the data from the pages backing `src_data` to the guest and records their measurement for
-/// attestation.  src_data must be aligned to the given page size.
+/// attestation.  src_data must be aligned to the given page size. The pages are mapped into the
+/// guest with the access permissions in `perms`.
 pub fn add_measured_pages(
     vmid: u64,
     src_data: &[u8],
     dest_addr: u64,
     page_type: TsmPageType,
+    perms: TvmPagePerms,
     guest_addr: u64,
 ) -> Result<()> {
     if src_data
@@ -214,6 +254,7 @@ pub fn add_measured_pages(
         src_addr: src_data.as_ptr() as u64,
         dest_addr,
         page_type,
+        perms,
         num_pages: src_data.len() as u64 / page_type.size_bytes(),
         guest_addr,
     });
@@ -225,11 +266,13 @@ pub fn add_measured_pages(
 }
 
 /// Adds previously converted pages to the guest at the given address. The page will be left cleared
-/// and read zeros to the guest.
+/// and read zeros to the guest. The pages are mapped into the guest with the access permissions in
+/// `perms`.
 pub fn add_zero_pages(
     vmid: u64,
     page_addr: u64,
     page_type: TsmPageType,
+    perms: TvmPagePerms,
     num_pages: u64,
     guest_addr: u64,
 ) -> Result<()> {
@@ -237,6 +280,7 @@ pub fn add_zero_pages(
         guest_id: vmid,
         page_addr,
         page_type,
+        perms,
         num_pages,
         guest_addr,
     });
@@ -245,6 +289,25 @@ pub fn add_zero_pages(
     Ok(())
 }
 
+/// Removes the confidential pages mapped at `guest_addr` from the given TVM, returning them to the
+/// host as converted pages. Returns the number of pages removed, which is 0 if the removal must be
+/// retried once the TVM's vCPUs have exited.
+pub fn remove_pages(
+    vmid: u64,
+    guest_addr: u64,
+    page_type: TsmPageType,
+    num_pages: u64,
+) -> Result<u64> {
+    let msg = SbiMessage::TeeHost(TvmRemovePages {
+        guest_id: vmid,
+        guest_addr,
+        page_type,
+        num_pages,
+    });
+    // Safety: `TvmRemovePages` only touches pages that are confidential to the TVM.
+    unsafe { ecall_send(&msg) }
+}
+
 /// Adds pages shared between the host and the given TVM.
 ///
 /// # Safety
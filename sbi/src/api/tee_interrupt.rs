@@ -52,3 +52,16 @@ pub fn reclaim_imsic(imsic_addr: u64) -> Result<()> {
     unsafe { ecall_send(&msg) }?;
     Ok(())
 }
+
+/// Injects the virtual interrupt `interrupt_id` into the specified vCPU of the non-AIA TVM
+/// `tvm_id`.
+pub fn inject_interrupt(tvm_id: u64, vcpu_id: u64, interrupt_id: u64) -> Result<()> {
+    let msg = SbiMessage::TeeInterrupt(TvmCpuInjectInterrupt {
+        tvm_id,
+        vcpu_id,
+        interrupt_id,
+    });
+    // Safety: `TvmCpuInjectInterrupt` doesn't touch host memory in any way.
+    unsafe { ecall_send(&msg) }?;
+    Ok(())
+}
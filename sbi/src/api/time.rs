@@ -0,0 +1,14 @@
+// Copyright (c) 2022 by Rivos Inc.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::TimeFunction::*;
+use crate::{ecall_send, Result, SbiMessage};
+
+/// Programs the timer to fire at `stime_value`, clearing any pending timer interrupt.
+pub fn set_timer(stime_value: u64) -> Result<()> {
+    let msg = SbiMessage::Time(SetTimer { stime_value });
+    // Safety: SetTimer doesn't touch memory.
+    unsafe { ecall_send(&msg) }?;
+    Ok(())
+}
@@ -7,9 +7,15 @@
 // Extension constants
 pub const EXT_PUT_CHAR: u64 = 0x01;
 pub const EXT_BASE: u64 = 0x10;
+pub const EXT_TIME: u64 = 0x54494D45;
 pub const EXT_HART_STATE: u64 = 0x48534D;
+pub const EXT_IPI: u64 = 0x735049;
 pub const EXT_PMU: u64 = 0x504D55;
+pub const EXT_RFENCE: u64 = 0x52464E43;
 pub const EXT_RESET: u64 = 0x53525354;
+pub const EXT_DBCN: u64 = 0x4442434E; // DBCN
+pub const EXT_STA: u64 = 0x535441; // STA
+pub const EXT_SUSP: u64 = 0x53555350; // SUSP
 pub const EXT_ATTESTATION: u64 = 0x41545354; // ATST
 pub const EXT_TEE_HOST: u64 = 0x54454548; // TEEH
 pub const EXT_TEE_INTERRUPT: u64 = 0x54454549; // TEEI
@@ -0,0 +1,82 @@
+// Copyright (c) 2022 by Rivos Inc.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::error::*;
+use crate::function::*;
+
+/// Functions for the Debug Console (DBCN) extension. Buffers are specified by their physical
+/// address, which is split across two registers; only the low half is used on RV64.
+#[derive(Copy, Clone, Debug)]
+pub enum DebugConsoleFunction {
+    /// Writes up to `num_bytes` from the buffer at `base_addr` to the debug console. Returns the
+    /// number of bytes actually written.
+    ConsoleWrite {
+        /// a0 - The number of bytes in the buffer.
+        num_bytes: u64,
+        /// a1, a2 - The physical address of the buffer.
+        base_addr: u64,
+    },
+    /// Reads up to `num_bytes` from the debug console into the buffer at `base_addr` without
+    /// blocking. Returns the number of bytes actually read.
+    ConsoleRead {
+        /// a0 - The number of bytes in the buffer.
+        num_bytes: u64,
+        /// a1, a2 - The physical address of the buffer.
+        base_addr: u64,
+    },
+    /// Writes a single byte to the debug console, blocking until the byte has been written.
+    ConsoleWriteByte {
+        /// a0 - The byte to write.
+        byte: u8,
+    },
+}
+
+impl DebugConsoleFunction {
+    /// Attempts to parse `Self` from the passed in `a0-a7`.
+    pub(crate) fn from_regs(args: &[u64]) -> Result<Self> {
+        use DebugConsoleFunction::*;
+        match args[6] {
+            0 | 1 if args[2] != 0 => Err(Error::InvalidParam),
+            0 => Ok(ConsoleWrite {
+                num_bytes: args[0],
+                base_addr: args[1],
+            }),
+            1 => Ok(ConsoleRead {
+                num_bytes: args[0],
+                base_addr: args[1],
+            }),
+            2 => Ok(ConsoleWriteByte {
+                byte: args[0] as u8,
+            }),
+            _ => Err(Error::NotSupported),
+        }
+    }
+}
+
+impl SbiFunction for DebugConsoleFunction {
+    fn a6(&self) -> u64 {
+        use DebugConsoleFunction::*;
+        match self {
+            ConsoleWrite { .. } => 0,
+            ConsoleRead { .. } => 1,
+            ConsoleWriteByte { .. } => 2,
+        }
+    }
+
+    fn a0(&self) -> u64 {
+        use DebugConsoleFunction::*;
+        match self {
+            ConsoleWrite { num_bytes, .. } | ConsoleRead { num_bytes, .. } => *num_bytes,
+            ConsoleWriteByte { byte } => *byte as u64,
+        }
+    }
+
+    fn a1(&self) -> u64 {
+        use DebugConsoleFunction::*;
+        match self {
+            ConsoleWrite { base_addr, .. } | ConsoleRead { base_addr, .. } => *base_addr,
+            ConsoleWriteByte { .. } => 0,
+        }
+    }
+}
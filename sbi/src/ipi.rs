@@ -0,0 +1,119 @@
+// Copyright (c) 2022 by Rivos Inc.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::error::*;
+use crate::function::*;
+
+/// A set of harts, encoded as a bitmask relative to a base hart ID as in the SBI specification.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct HartMask {
+    mask: u64,
+    base: u64,
+}
+
+impl HartMask {
+    // A `hart_mask_base` of -1 indicates that all harts are selected.
+    const ALL_HARTS_BASE: u64 = u64::MAX;
+
+    /// Creates a `HartMask` from the raw `hart_mask` and `hart_mask_base` values.
+    pub fn new(mask: u64, base: u64) -> Self {
+        Self { mask, base }
+    }
+
+    /// Creates a `HartMask` that selects all harts in the system.
+    pub fn all() -> Self {
+        Self {
+            mask: 0,
+            base: Self::ALL_HARTS_BASE,
+        }
+    }
+
+    /// Returns if this `HartMask` selects all harts in the system.
+    pub fn is_all(&self) -> bool {
+        self.base == Self::ALL_HARTS_BASE
+    }
+
+    /// Returns the raw `hart_mask` value.
+    pub fn mask(&self) -> u64 {
+        self.mask
+    }
+
+    /// Returns the raw `hart_mask_base` value.
+    pub fn base(&self) -> u64 {
+        self.base
+    }
+
+    /// Returns if `hart_id` is selected by this `HartMask`.
+    pub fn contains(&self, hart_id: u64) -> bool {
+        if self.is_all() {
+            return true;
+        }
+        hart_id
+            .checked_sub(self.base)
+            .filter(|&bit| bit < u64::BITS as u64)
+            .map_or(false, |bit| self.mask & (1 << bit) != 0)
+    }
+
+    /// Returns an iterator over the IDs of the selected harts, given that `num_harts` harts exist.
+    /// Harts in the mask at or beyond `num_harts` are not returned.
+    pub fn iter(&self, num_harts: u64) -> impl Iterator<Item = u64> {
+        let hart_mask = *self;
+        (0..num_harts).filter(move |&hart_id| hart_mask.contains(hart_id))
+    }
+
+    /// Returns the number of harts selected by the mask, or `None` if all harts are selected.
+    pub fn count(&self) -> Option<u64> {
+        if self.is_all() {
+            None
+        } else {
+            Some(self.mask.count_ones() as u64)
+        }
+    }
+}
+
+/// Functions for the IPI extension.
+#[derive(Copy, Clone, Debug)]
+pub enum IpiFunction {
+    /// Sends a supervisor software interrupt to each of the harts in `hart_mask`.
+    SendIpi {
+        /// a0, a1 - The harts to interrupt.
+        hart_mask: HartMask,
+    },
+}
+
+impl IpiFunction {
+    /// Attempts to parse `Self` from the passed in `a0-a7`.
+    pub(crate) fn from_regs(args: &[u64]) -> Result<Self> {
+        use IpiFunction::*;
+        match args[6] {
+            0 => Ok(SendIpi {
+                hart_mask: HartMask::new(args[0], args[1]),
+            }),
+            _ => Err(Error::NotSupported),
+        }
+    }
+}
+
+impl SbiFunction for IpiFunction {
+    fn a6(&self) -> u64 {
+        use IpiFunction::*;
+        match self {
+            SendIpi { .. } => 0,
+        }
+    }
+
+    fn a0(&self) -> u64 {
+        use IpiFunction::*;
+        match self {
+            SendIpi { hart_mask } => hart_mask.mask(),
+        }
+    }
+
+    fn a1(&self) -> u64 {
+        use IpiFunction::*;
+        match self {
+            SendIpi { hart_mask } => hart_mask.base(),
+        }
+    }
+}
@@ -52,6 +52,45 @@ pub enum PmuFunction {
     },
     /// Returns the current value firmware counter specified by the inner value.
     ReadFirmwareCounter(u64),
+    /// Returns the upper 32 bits of the firmware counter specified by the inner value. Always 0
+    /// on RV64.
+    ReadFirmwareCounterHi(u64),
+    /// Sets the page of memory used to report counter values on `StopCounters` with the
+    /// take_snapshot flag, and to initialize counter values on `StartCounters` with the
+    /// init_snapshot flag. The memory layout is given by `PmuSnapshot`.
+    SnapshotSetShmem {
+        /// The 4kB aligned physical address of the snapshot page, or `None` to disable snapshots.
+        shmem_addr: Option<u64>,
+        /// Reserved, must be 0.
+        flags: u64,
+    },
+}
+
+/// The layout of the PMU snapshot shared memory area set with `PmuFunction::SnapshotSetShmem`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct PmuSnapshot {
+    /// Bitmap of counters which have overflowed, indexed by counter index.
+    pub counter_overflow_bitmap: u64,
+    /// The values of the counters, indexed by counter index.
+    pub counter_values: [u64; PMU_SNAPSHOT_MAX_COUNTERS],
+    reserved: [u64; 447],
+}
+
+/// The maximum number of counters that can be represented in `PmuSnapshot`.
+pub const PMU_SNAPSHOT_MAX_COUNTERS: usize = 64;
+
+/// The size of the PMU snapshot shared memory area.
+pub const PMU_SNAPSHOT_SIZE: usize = 4096;
+
+impl Default for PmuSnapshot {
+    fn default() -> Self {
+        Self {
+            counter_overflow_bitmap: 0,
+            counter_values: [0; PMU_SNAPSHOT_MAX_COUNTERS],
+            reserved: [0; 447],
+        }
+    }
 }
 
 /// This encapsulates the bit-fields for PMU config_flags parameter as described in the SBI documentation
@@ -190,11 +229,15 @@ impl PmuCounterConfigFlags {
 pub struct PmuCounterStartFlags(u64);
 
 impl PmuCounterStartFlags {
+    const INIT_VALUE: u64 = 1 << 0;
+    const INIT_SNAPSHOT: u64 = 1 << 1;
+
     /// Constructs a new PmuCounterStartFlags from a valid passed-in value.
     pub fn from_raw_value(value: u64) -> Result<Self> {
-        match value {
-            0 | 1 => Ok(PmuCounterStartFlags(value)),
-            _ => Err(Error::InvalidParam),
+        if value & !(Self::INIT_VALUE | Self::INIT_SNAPSHOT) == 0 {
+            Ok(PmuCounterStartFlags(value))
+        } else {
+            Err(Error::InvalidParam)
         }
     }
 
@@ -205,12 +248,27 @@ impl PmuCounterStartFlags {
 
     /// Sets the set_init_value bit-flag (set initial counter value).
     pub fn set_init_value(self) -> Self {
-        PmuCounterStartFlags(self.0 | 1)
+        PmuCounterStartFlags(self.0 | Self::INIT_VALUE)
     }
 
     /// Returns if set_init_value bit-flag is set.
     pub fn is_init_value(&self) -> bool {
-        self.0 & 1 != 0
+        self.0 & Self::INIT_VALUE != 0
+    }
+
+    /// Sets the init_snapshot bit-flag (set initial counter values from the snapshot area).
+    pub fn set_init_snapshot(self) -> Self {
+        PmuCounterStartFlags(self.0 | Self::INIT_SNAPSHOT)
+    }
+
+    /// Returns if the init_snapshot bit-flag is set.
+    pub fn is_init_snapshot(&self) -> bool {
+        self.0 & Self::INIT_SNAPSHOT != 0
+    }
+
+    /// Clears the init_snapshot bit-flag.
+    pub fn unset_init_snapshot(&self) -> Self {
+        PmuCounterStartFlags(self.0 & !Self::INIT_SNAPSHOT)
     }
 }
 
@@ -220,11 +278,15 @@ impl PmuCounterStartFlags {
 pub struct PmuCounterStopFlags(u64);
 
 impl PmuCounterStopFlags {
+    const RESET: u64 = 1 << 0;
+    const TAKE_SNAPSHOT: u64 = 1 << 1;
+
     /// Constructs a new PmuCounterStopFlags from a valid passed-in value.
     pub fn from_raw_value(value: u64) -> Result<Self> {
-        match value {
-            0 | 1 => Ok(PmuCounterStopFlags(value)),
-            _ => Err(Error::InvalidParam),
+        if value & !(Self::RESET | Self::TAKE_SNAPSHOT) == 0 {
+            Ok(PmuCounterStopFlags(value))
+        } else {
+            Err(Error::InvalidParam)
         }
     }
 
@@ -235,12 +297,27 @@ impl PmuCounterStopFlags {
 
     /// Sets the stop_reset bit-flag (resets the counter after stopping).
     pub fn set_reset_flag(self) -> Self {
-        PmuCounterStopFlags(self.0 | 1)
+        PmuCounterStopFlags(self.0 | Self::RESET)
     }
 
     /// Returns if the stop_reset bit flag is set.
     pub fn is_reset_flag(&self) -> bool {
-        self.0 & 1 != 0
+        self.0 & Self::RESET != 0
+    }
+
+    /// Sets the take_snapshot bit-flag (saves the counter values to the snapshot area).
+    pub fn set_take_snapshot(self) -> Self {
+        PmuCounterStopFlags(self.0 | Self::TAKE_SNAPSHOT)
+    }
+
+    /// Returns if the take_snapshot bit-flag is set.
+    pub fn is_take_snapshot(&self) -> bool {
+        self.0 & Self::TAKE_SNAPSHOT != 0
+    }
+
+    /// Clears the take_snapshot bit-flag.
+    pub fn unset_take_snapshot(&self) -> Self {
+        PmuCounterStopFlags(self.0 & !Self::TAKE_SNAPSHOT)
     }
 }
 
@@ -249,11 +326,18 @@ impl PmuCounterStopFlags {
 pub struct PmuCounterInfo(u64);
 
 impl PmuCounterInfo {
+    const FIRMWARE_COUNTER: u64 = 1 << 63;
+
     /// Constructs a PmuCounterInfo from the passed in value.
     pub fn new(value: u64) -> Self {
         PmuCounterInfo(value)
     }
 
+    /// Constructs a PmuCounterInfo describing a firmware counter.
+    pub fn new_firmware() -> Self {
+        PmuCounterInfo(Self::FIRMWARE_COUNTER)
+    }
+
     /// Returns the inner value.
     pub fn raw(&self) -> u64 {
         self.0
@@ -261,7 +345,7 @@ impl PmuCounterInfo {
 
     /// Returns if the counter is a hardware counter.
     pub fn is_hardware_counter(&self) -> bool {
-        self.0 & (1 << 63) == 0
+        self.0 & Self::FIRMWARE_COUNTER == 0
     }
 
     /// Returns if the counter is a firmware counter.
@@ -493,7 +577,7 @@ impl PmuHwCacheParams {
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 #[repr(u64)]
 /// Enumeration of the firmware event types.
 pub enum PmuFirmware {
@@ -541,6 +625,8 @@ pub enum PmuFirmware {
     HfenceVvmaAsidSent = 20,
     /// HFENCE.ASID request received event.
     HfenceVvmaAsidReceived = 21,
+    /// Platform-specific firmware event, selected by the event data. See `PmuPlatformFirmware`.
+    PlatformSpecific = 0xFFFF,
 }
 
 impl PmuFirmware {
@@ -569,6 +655,30 @@ impl PmuFirmware {
             19 => Ok(HfenceVvmaReceived),
             20 => Ok(HfenceVvmaAsidSent),
             21 => Ok(HfenceVvmaAsidReceived),
+            0xFFFF => Ok(PlatformSpecific),
+            _ => Err(Error::InvalidParam),
+        }
+    }
+}
+
+/// Enumeration of the Salus-specific firmware events, passed as the event data when configuring a
+/// counter for `PmuFirmware::PlatformSpecific`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(u64)]
+pub enum PmuPlatformFirmware {
+    /// SBI call (of any extension) event.
+    SbiCall = 0,
+    /// Guest page fault forwarded to the host event.
+    ForwardedPageFault = 1,
+}
+
+impl PmuPlatformFirmware {
+    /// Constructs PmuPlatformFirmware from a valid passed-in value.
+    pub fn from_raw_value(value: u64) -> Result<Self> {
+        use PmuPlatformFirmware::*;
+        match value {
+            0 => Ok(SbiCall),
+            1 => Ok(ForwardedPageFault),
             _ => Err(Error::InvalidParam),
         }
     }
@@ -600,6 +710,19 @@ impl PmuFunction {
                 stop_flags: PmuCounterStopFlags::from_raw_value(args[2])?,
             }),
             5 => Ok(ReadFirmwareCounter(args[0])),
+            6 => Ok(ReadFirmwareCounterHi(args[0])),
+            7 => {
+                // An address of all ones disables the snapshot area.
+                let shmem_addr = match (args[0], args[1]) {
+                    (u64::MAX, u64::MAX) => None,
+                    (lo, 0) => Some(lo),
+                    _ => return Err(Error::InvalidParam),
+                };
+                Ok(SnapshotSetShmem {
+                    shmem_addr,
+                    flags: args[2],
+                })
+            }
             _ => Err(Error::NotSupported),
         }
     }
@@ -630,6 +753,8 @@ impl SbiFunction for PmuFunction {
                 stop_flags: _,
             } => 4,
             ReadFirmwareCounter(_) => 5,
+            ReadFirmwareCounterHi(_) => 6,
+            SnapshotSetShmem { .. } => 7,
         }
     }
 
@@ -692,6 +817,10 @@ impl SbiFunction for PmuFunction {
                 counter_mask: _,
                 stop_flags,
             } => stop_flags.raw(),
+            SnapshotSetShmem {
+                shmem_addr: _,
+                flags,
+            } => *flags,
             _ => 0,
         }
     }
@@ -717,6 +846,10 @@ impl SbiFunction for PmuFunction {
                 counter_mask,
                 stop_flags: _,
             } => *counter_mask,
+            SnapshotSetShmem {
+                shmem_addr: None,
+                flags: _,
+            } => u64::MAX,
             _ => 0,
         }
     }
@@ -744,6 +877,11 @@ impl SbiFunction for PmuFunction {
                 stop_flags: _,
             } => *counter_index,
             ReadFirmwareCounter(counter_index) => *counter_index,
+            ReadFirmwareCounterHi(counter_index) => *counter_index,
+            SnapshotSetShmem {
+                shmem_addr,
+                flags: _,
+            } => shmem_addr.unwrap_or(u64::MAX),
             _ => 0,
         }
     }
@@ -60,7 +60,7 @@ impl ResetReason {
         use ResetReason::*;
         Ok(match a1 {
             0 => NoReason,
-            2 => SystemFailure,
+            1 => SystemFailure,
             _ => return Err(Error::InvalidParam),
         })
     }
@@ -93,18 +93,18 @@ impl SbiFunction for ResetFunction {
     fn a0(&self) -> u64 {
         match self {
             ResetFunction::Reset {
-                reset_type: _,
-                reason,
-            } => *reason as u64,
+                reset_type,
+                reason: _,
+            } => *reset_type as u64,
         }
     }
 
     fn a1(&self) -> u64 {
         match self {
             ResetFunction::Reset {
-                reset_type,
-                reason: _,
-            } => *reset_type as u64,
+                reset_type: _,
+                reason,
+            } => *reason as u64,
         }
     }
 }
@@ -0,0 +1,201 @@
+// Copyright (c) 2022 by Rivos Inc.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::error::*;
+use crate::function::*;
+use crate::HartMask;
+
+/// Functions for the RFENCE extension. `start_addr` and `size` specify the range of addresses to
+/// be fenced. A `size` of -1, or a `start_addr` and `size` both equal to 0, fence the entire
+/// address space.
+#[derive(Copy, Clone, Debug)]
+pub enum RfenceFunction {
+    /// Instructs the harts in `hart_mask` to execute FENCE.I.
+    RemoteFenceI {
+        /// a0, a1 - The harts to fence.
+        hart_mask: HartMask,
+    },
+    /// Instructs the harts in `hart_mask` to execute SFENCE.VMA covering the given range.
+    RemoteSFenceVma {
+        /// a0, a1 - The harts to fence.
+        hart_mask: HartMask,
+        /// a2 - The start of the virtual address range to fence.
+        start_addr: u64,
+        /// a3 - The size of the virtual address range to fence.
+        size: u64,
+    },
+    /// Instructs the harts in `hart_mask` to execute SFENCE.VMA covering the given range for
+    /// `asid` only.
+    RemoteSFenceVmaAsid {
+        /// a0, a1 - The harts to fence.
+        hart_mask: HartMask,
+        /// a2 - The start of the virtual address range to fence.
+        start_addr: u64,
+        /// a3 - The size of the virtual address range to fence.
+        size: u64,
+        /// a4 - The ASID to fence.
+        asid: u64,
+    },
+    /// Instructs the harts in `hart_mask` to execute HFENCE.GVMA covering the given range of
+    /// guest physical addresses for `vmid` only.
+    RemoteHFenceGvmaVmid {
+        /// a0, a1 - The harts to fence.
+        hart_mask: HartMask,
+        /// a2 - The start of the guest physical address range to fence.
+        start_addr: u64,
+        /// a3 - The size of the guest physical address range to fence.
+        size: u64,
+        /// a4 - The VMID to fence.
+        vmid: u64,
+    },
+    /// Instructs the harts in `hart_mask` to execute HFENCE.GVMA covering the given range of
+    /// guest physical addresses.
+    RemoteHFenceGvma {
+        /// a0, a1 - The harts to fence.
+        hart_mask: HartMask,
+        /// a2 - The start of the guest physical address range to fence.
+        start_addr: u64,
+        /// a3 - The size of the guest physical address range to fence.
+        size: u64,
+    },
+    /// Instructs the harts in `hart_mask` to execute HFENCE.VVMA covering the given range of
+    /// guest virtual addresses for `asid` only.
+    RemoteHFenceVvmaAsid {
+        /// a0, a1 - The harts to fence.
+        hart_mask: HartMask,
+        /// a2 - The start of the guest virtual address range to fence.
+        start_addr: u64,
+        /// a3 - The size of the guest virtual address range to fence.
+        size: u64,
+        /// a4 - The ASID to fence.
+        asid: u64,
+    },
+    /// Instructs the harts in `hart_mask` to execute HFENCE.VVMA covering the given range of
+    /// guest virtual addresses.
+    RemoteHFenceVvma {
+        /// a0, a1 - The harts to fence.
+        hart_mask: HartMask,
+        /// a2 - The start of the guest virtual address range to fence.
+        start_addr: u64,
+        /// a3 - The size of the guest virtual address range to fence.
+        size: u64,
+    },
+}
+
+impl RfenceFunction {
+    /// Attempts to parse `Self` from the passed in `a0-a7`.
+    pub(crate) fn from_regs(args: &[u64]) -> Result<Self> {
+        use RfenceFunction::*;
+        let hart_mask = HartMask::new(args[0], args[1]);
+        match args[6] {
+            0 => Ok(RemoteFenceI { hart_mask }),
+            1 => Ok(RemoteSFenceVma {
+                hart_mask,
+                start_addr: args[2],
+                size: args[3],
+            }),
+            2 => Ok(RemoteSFenceVmaAsid {
+                hart_mask,
+                start_addr: args[2],
+                size: args[3],
+                asid: args[4],
+            }),
+            3 => Ok(RemoteHFenceGvmaVmid {
+                hart_mask,
+                start_addr: args[2],
+                size: args[3],
+                vmid: args[4],
+            }),
+            4 => Ok(RemoteHFenceGvma {
+                hart_mask,
+                start_addr: args[2],
+                size: args[3],
+            }),
+            5 => Ok(RemoteHFenceVvmaAsid {
+                hart_mask,
+                start_addr: args[2],
+                size: args[3],
+                asid: args[4],
+            }),
+            6 => Ok(RemoteHFenceVvma {
+                hart_mask,
+                start_addr: args[2],
+                size: args[3],
+            }),
+            _ => Err(Error::NotSupported),
+        }
+    }
+
+    /// Returns the set of harts targeted by this function.
+    pub fn hart_mask(&self) -> HartMask {
+        use RfenceFunction::*;
+        match self {
+            RemoteFenceI { hart_mask }
+            | RemoteSFenceVma { hart_mask, .. }
+            | RemoteSFenceVmaAsid { hart_mask, .. }
+            | RemoteHFenceGvmaVmid { hart_mask, .. }
+            | RemoteHFenceGvma { hart_mask, .. }
+            | RemoteHFenceVvmaAsid { hart_mask, .. }
+            | RemoteHFenceVvma { hart_mask, .. } => *hart_mask,
+        }
+    }
+}
+
+impl SbiFunction for RfenceFunction {
+    fn a6(&self) -> u64 {
+        use RfenceFunction::*;
+        match self {
+            RemoteFenceI { .. } => 0,
+            RemoteSFenceVma { .. } => 1,
+            RemoteSFenceVmaAsid { .. } => 2,
+            RemoteHFenceGvmaVmid { .. } => 3,
+            RemoteHFenceGvma { .. } => 4,
+            RemoteHFenceVvmaAsid { .. } => 5,
+            RemoteHFenceVvma { .. } => 6,
+        }
+    }
+
+    fn a0(&self) -> u64 {
+        self.hart_mask().mask()
+    }
+
+    fn a1(&self) -> u64 {
+        self.hart_mask().base()
+    }
+
+    fn a2(&self) -> u64 {
+        use RfenceFunction::*;
+        match self {
+            RemoteFenceI { .. } => 0,
+            RemoteSFenceVma { start_addr, .. }
+            | RemoteSFenceVmaAsid { start_addr, .. }
+            | RemoteHFenceGvmaVmid { start_addr, .. }
+            | RemoteHFenceGvma { start_addr, .. }
+            | RemoteHFenceVvmaAsid { start_addr, .. }
+            | RemoteHFenceVvma { start_addr, .. } => *start_addr,
+        }
+    }
+
+    fn a3(&self) -> u64 {
+        use RfenceFunction::*;
+        match self {
+            RemoteFenceI { .. } => 0,
+            RemoteSFenceVma { size, .. }
+            | RemoteSFenceVmaAsid { size, .. }
+            | RemoteHFenceGvmaVmid { size, .. }
+            | RemoteHFenceGvma { size, .. }
+            | RemoteHFenceVvmaAsid { size, .. }
+            | RemoteHFenceVvma { size, .. } => *size,
+        }
+    }
+
+    fn a4(&self) -> u64 {
+        use RfenceFunction::*;
+        match self {
+            RemoteSFenceVmaAsid { asid, .. } | RemoteHFenceVvmaAsid { asid, .. } => *asid,
+            RemoteHFenceGvmaVmid { vmid, .. } => *vmid,
+            _ => 0,
+        }
+    }
+}
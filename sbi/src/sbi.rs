@@ -37,6 +37,24 @@ pub use tee_guest::*;
 // The PMU SBI extension
 mod pmu;
 pub use pmu::*;
+// The IPI SBI extension
+mod ipi;
+pub use ipi::*;
+// The RFENCE SBI extension
+mod rfence;
+pub use rfence::*;
+// The TIME SBI extension
+mod time;
+pub use time::*;
+// The Debug Console SBI extension
+mod debug_console;
+pub use debug_console::*;
+// The Steal-Time Accounting SBI extension
+mod steal_time;
+pub use steal_time::*;
+// The System Suspend SBI extension
+mod system_suspend;
+pub use system_suspend::*;
 
 /// Interfaces for invoking SBI functionality.
 pub mod api;
@@ -121,6 +139,18 @@ pub enum SbiMessage {
     Attestation(AttestationFunction),
     /// The extension for getting performance counter state.
     Pmu(PmuFunction),
+    /// The extension for sending inter-processor interrupts.
+    Ipi(IpiFunction),
+    /// The extension for issuing remote fences.
+    Rfence(RfenceFunction),
+    /// The extension for programming the timer.
+    Time(TimeFunction),
+    /// The extension for writing to and reading from the debug console.
+    DebugConsole(DebugConsoleFunction),
+    /// The extension for reporting steal-time to vCPUs.
+    StealTime(StealTimeFunction),
+    /// The extension for suspending the system.
+    SystemSuspend(SystemSuspendFunction),
 }
 
 impl SbiMessage {
@@ -140,6 +170,12 @@ impl SbiMessage {
             EXT_TEE_GUEST => TeeGuestFunction::from_regs(args).map(SbiMessage::TeeGuest),
             EXT_ATTESTATION => AttestationFunction::from_regs(args).map(SbiMessage::Attestation),
             EXT_PMU => PmuFunction::from_regs(args).map(SbiMessage::Pmu),
+            EXT_IPI => IpiFunction::from_regs(args).map(SbiMessage::Ipi),
+            EXT_RFENCE => RfenceFunction::from_regs(args).map(SbiMessage::Rfence),
+            EXT_TIME => TimeFunction::from_regs(args).map(SbiMessage::Time),
+            EXT_DBCN => DebugConsoleFunction::from_regs(args).map(SbiMessage::DebugConsole),
+            EXT_STA => StealTimeFunction::from_regs(args).map(SbiMessage::StealTime),
+            EXT_SUSP => SystemSuspendFunction::from_regs(args).map(SbiMessage::SystemSuspend),
             _ => Err(Error::NotSupported),
         }
     }
@@ -157,6 +193,12 @@ impl SbiMessage {
             TeeGuest(_) => EXT_TEE_GUEST,
             Attestation(_) => EXT_ATTESTATION,
             Pmu(_) => EXT_PMU,
+            Ipi(_) => EXT_IPI,
+            Rfence(_) => EXT_RFENCE,
+            Time(_) => EXT_TIME,
+            DebugConsole(_) => EXT_DBCN,
+            StealTime(_) => EXT_STA,
+            SystemSuspend(_) => EXT_SUSP,
         }
     }
 
@@ -175,6 +217,12 @@ impl SbiMessage {
             TeeGuest(f) => f.a6(),
             Attestation(f) => f.a6(),
             Pmu(f) => f.a6(),
+            Ipi(f) => f.a6(),
+            Rfence(f) => f.a6(),
+            Time(f) => f.a6(),
+            DebugConsole(f) => f.a6(),
+            StealTime(f) => f.a6(),
+            SystemSuspend(f) => f.a6(),
         }
     }
 
@@ -191,6 +239,12 @@ impl SbiMessage {
             TeeGuest(f) => f.a5(),
             Attestation(f) => f.a5(),
             Pmu(f) => f.a5(),
+            Ipi(f) => f.a5(),
+            Rfence(f) => f.a5(),
+            Time(f) => f.a5(),
+            DebugConsole(f) => f.a5(),
+            StealTime(f) => f.a5(),
+            SystemSuspend(f) => f.a5(),
         }
     }
 
@@ -207,6 +261,12 @@ impl SbiMessage {
             TeeGuest(f) => f.a4(),
             Attestation(f) => f.a4(),
             Pmu(f) => f.a4(),
+            Ipi(f) => f.a4(),
+            Rfence(f) => f.a4(),
+            Time(f) => f.a4(),
+            DebugConsole(f) => f.a4(),
+            StealTime(f) => f.a4(),
+            SystemSuspend(f) => f.a4(),
         }
     }
 
@@ -223,6 +283,12 @@ impl SbiMessage {
             TeeGuest(f) => f.a3(),
             Attestation(f) => f.a3(),
             Pmu(f) => f.a3(),
+            Ipi(f) => f.a3(),
+            Rfence(f) => f.a3(),
+            Time(f) => f.a3(),
+            DebugConsole(f) => f.a3(),
+            StealTime(f) => f.a3(),
+            SystemSuspend(f) => f.a3(),
         }
     }
 
@@ -239,6 +305,12 @@ impl SbiMessage {
             TeeGuest(f) => f.a2(),
             Attestation(f) => f.a2(),
             Pmu(f) => f.a2(),
+            Ipi(f) => f.a2(),
+            Rfence(f) => f.a2(),
+            Time(f) => f.a2(),
+            DebugConsole(f) => f.a2(),
+            StealTime(f) => f.a2(),
+            SystemSuspend(f) => f.a2(),
         }
     }
 
@@ -255,6 +327,12 @@ impl SbiMessage {
             TeeGuest(f) => f.a1(),
             Attestation(f) => f.a1(),
             Pmu(f) => f.a1(),
+            Ipi(f) => f.a1(),
+            Rfence(f) => f.a1(),
+            Time(f) => f.a1(),
+            DebugConsole(f) => f.a1(),
+            StealTime(f) => f.a1(),
+            SystemSuspend(f) => f.a1(),
         }
     }
 
@@ -271,6 +349,12 @@ impl SbiMessage {
             TeeGuest(f) => f.a0(),
             Attestation(f) => f.a0(),
             Pmu(f) => f.a0(),
+            Ipi(f) => f.a0(),
+            Rfence(f) => f.a0(),
+            Time(f) => f.a0(),
+            DebugConsole(f) => f.a0(),
+            StealTime(f) => f.a0(),
+            SystemSuspend(f) => f.a0(),
         }
     }
 
@@ -35,6 +35,37 @@ pub enum StateFunction {
     },
 }
 
+/// The types of suspend that may be requested with `HartSuspend`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HartSuspendType {
+    /// The default retentive suspend. Execution resumes after the `HartSuspend` call with all
+    /// hart state preserved.
+    DefaultRetentive,
+    /// The default non-retentive suspend. Execution resumes at `resume_addr` with A0 set to the
+    /// hart ID and A1 set to `opaque`.
+    DefaultNonRetentive,
+}
+
+impl HartSuspendType {
+    /// Attempts to parse `Self` from the raw `suspend_type` of a `HartSuspend` call. Reserved and
+    /// platform-specific suspend types are rejected with `InvalidParam`.
+    pub fn from_raw(suspend_type: u32) -> Result<Self> {
+        match suspend_type {
+            0x0000_0000 => Ok(HartSuspendType::DefaultRetentive),
+            0x8000_0000 => Ok(HartSuspendType::DefaultNonRetentive),
+            _ => Err(Error::InvalidParam),
+        }
+    }
+
+    /// Returns the raw `suspend_type` value for this suspend type.
+    pub fn raw(&self) -> u32 {
+        match self {
+            HartSuspendType::DefaultRetentive => 0x0000_0000,
+            HartSuspendType::DefaultNonRetentive => 0x8000_0000,
+        }
+    }
+}
+
 /// Return value for the HartStatus SBI call.
 #[repr(u64)]
 pub enum HartState {
@@ -0,0 +1,109 @@
+// Copyright (c) 2022 by Rivos Inc.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::error::*;
+use crate::function::*;
+
+/// Functions for the Steal-Time Accounting (STA) extension.
+#[derive(Copy, Clone, Debug)]
+pub enum StealTimeFunction {
+    /// Sets the shared memory used to report the steal-time of the calling vCPU. The memory holds
+    /// a `StealTimeRecord` and must be 64-byte aligned.
+    SetShmem {
+        /// a0, a1 - The physical address of the steal-time record, or `None` to stop reporting
+        /// steal-time.
+        shmem_addr: Option<u64>,
+        /// a2 - Reserved, must be 0.
+        flags: u64,
+    },
+}
+
+/// The steal-time record shared between a vCPU and the SBI implementation.
+///
+/// The SBI implementation makes `sequence` odd while it updates the record, so readers must
+/// retry if `sequence` is odd or changes while reading the other fields.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct StealTimeRecord {
+    /// Incremented before and after each update of the record.
+    pub sequence: u32,
+    /// Reserved, always 0.
+    pub flags: u32,
+    /// The total time, in units of the `time` CSR, for which the vCPU has been ready to run but
+    /// was not running.
+    pub steal: u64,
+    /// Non-zero if the vCPU is currently not running.
+    pub preempted: u8,
+    pad: [u8; 47],
+}
+
+impl Default for StealTimeRecord {
+    fn default() -> Self {
+        Self {
+            sequence: 0,
+            flags: 0,
+            steal: 0,
+            preempted: 0,
+            pad: [0; 47],
+        }
+    }
+}
+
+/// The required alignment of the steal-time record.
+pub const STEAL_TIME_RECORD_ALIGN: u64 = 64;
+
+impl StealTimeFunction {
+    /// Attempts to parse `Self` from the passed in `a0-a7`.
+    pub(crate) fn from_regs(args: &[u64]) -> Result<Self> {
+        use StealTimeFunction::*;
+        match args[6] {
+            0 => {
+                // An address of all ones disables steal-time reporting.
+                let shmem_addr = match (args[0], args[1]) {
+                    (u64::MAX, u64::MAX) => None,
+                    (lo, 0) => Some(lo),
+                    _ => return Err(Error::InvalidParam),
+                };
+                Ok(SetShmem {
+                    shmem_addr,
+                    flags: args[2],
+                })
+            }
+            _ => Err(Error::NotSupported),
+        }
+    }
+}
+
+impl SbiFunction for StealTimeFunction {
+    fn a6(&self) -> u64 {
+        use StealTimeFunction::*;
+        match self {
+            SetShmem { .. } => 0,
+        }
+    }
+
+    fn a0(&self) -> u64 {
+        use StealTimeFunction::*;
+        match self {
+            SetShmem { shmem_addr, .. } => shmem_addr.unwrap_or(u64::MAX),
+        }
+    }
+
+    fn a1(&self) -> u64 {
+        use StealTimeFunction::*;
+        match self {
+            SetShmem {
+                shmem_addr: None, ..
+            } => u64::MAX,
+            SetShmem { .. } => 0,
+        }
+    }
+
+    fn a2(&self) -> u64 {
+        use StealTimeFunction::*;
+        match self {
+            SetShmem { flags, .. } => *flags,
+        }
+    }
+}
@@ -0,0 +1,92 @@
+// Copyright (c) 2022 by Rivos Inc.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::error::*;
+use crate::function::*;
+
+/// Functions for the System Suspend (SUSP) extension.
+#[derive(Copy, Clone, Debug)]
+pub enum SystemSuspendFunction {
+    /// Requests that the system transition to the sleep state given by `sleep_type`. All harts
+    /// other than the caller must be stopped. Upon resume, the calling hart starts executing at
+    /// `resume_addr` as if it had been started with `HartStart`.
+    Suspend {
+        /// a0 - The type of sleep state to enter.
+        sleep_type: u32,
+        /// a1 - The address to jump to on resume.
+        resume_addr: u64,
+        /// a2 - An opaque value to load in a1 when resuming the hart.
+        opaque: u64,
+    },
+}
+
+/// The sleep states that may be requested with `Suspend`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SuspendSleepType {
+    /// Suspend-to-RAM. Memory contents are retained while all other system state may be lost.
+    SuspendToRam,
+}
+
+impl SuspendSleepType {
+    /// Attempts to parse `Self` from the raw `sleep_type` of a `Suspend` call. Reserved and
+    /// platform-specific sleep types are rejected with `InvalidParam`.
+    pub fn from_raw(sleep_type: u32) -> Result<Self> {
+        match sleep_type {
+            0 => Ok(SuspendSleepType::SuspendToRam),
+            _ => Err(Error::InvalidParam),
+        }
+    }
+
+    /// Returns the raw `sleep_type` value for this sleep type.
+    pub fn raw(&self) -> u32 {
+        match self {
+            SuspendSleepType::SuspendToRam => 0,
+        }
+    }
+}
+
+impl SystemSuspendFunction {
+    /// Attempts to parse `Self` from the passed in `a0-a7`.
+    pub(crate) fn from_regs(args: &[u64]) -> Result<Self> {
+        use SystemSuspendFunction::*;
+        match args[6] {
+            0 => Ok(Suspend {
+                sleep_type: args[0] as u32,
+                resume_addr: args[1],
+                opaque: args[2],
+            }),
+            _ => Err(Error::NotSupported),
+        }
+    }
+}
+
+impl SbiFunction for SystemSuspendFunction {
+    fn a6(&self) -> u64 {
+        use SystemSuspendFunction::*;
+        match self {
+            Suspend { .. } => 0,
+        }
+    }
+
+    fn a0(&self) -> u64 {
+        use SystemSuspendFunction::*;
+        match self {
+            Suspend { sleep_type, .. } => *sleep_type as u64,
+        }
+    }
+
+    fn a1(&self) -> u64 {
+        use SystemSuspendFunction::*;
+        match self {
+            Suspend { resume_addr, .. } => *resume_addr,
+        }
+    }
+
+    fn a2(&self) -> u64 {
+        use SystemSuspendFunction::*;
+        match self {
+            Suspend { opaque, .. } => *opaque,
+        }
+    }
+}
@@ -4,7 +4,7 @@
 
 use crate::error::*;
 use crate::function::*;
-use crate::TeeMemoryRegion;
+use crate::{TeeMemoryRegion, TvmPagePerms};
 
 /// Functions provided by the TEE Guest extension to TVM guests.
 #[derive(Copy, Clone, Debug)]
@@ -24,6 +24,59 @@ pub enum TeeGuestFunction {
         /// a2 = length of the region
         len: u64,
     },
+    /// Converts the specified range of confidential memory in the calling TVM to shared memory.
+    /// Both `addr` and `len` must be 4kB-aligned and the range must lie within a single
+    /// confidential memory region. Any pages populated in the range must be 4kB pages; unpopulated
+    /// pages simply become part of the shared memory region. The populated confidential pages are
+    /// blocked from access by the TVM, and the range becomes a shared memory region. The host is
+    /// notified so that it may remove the blocked pages with `TvmRemovePages` and insert shared
+    /// pages in the range.
+    ///
+    /// The confidential pages aren't zeroed by this call: they're zeroed when the host removes
+    /// them with `TvmRemovePages`, which is the first point at which the host can access them.
+    ///
+    /// a6 = 1
+    ShareMemory {
+        /// a0 = start of the range
+        addr: u64,
+        /// a1 = length of the range
+        len: u64,
+    },
+    /// Converts the specified range of shared memory in the calling TVM back to confidential
+    /// memory. Both `addr` and `len` must be 4kB-aligned and the range must lie within a single
+    /// shared memory region that is fully populated. The shared pages are unmapped from the TVM
+    /// and the range becomes a confidential memory region. The host is notified so that it may
+    /// insert zero pages in the range.
+    ///
+    /// a6 = 2
+    UnshareMemory {
+        /// a0 = start of the range
+        addr: u64,
+        /// a1 = length of the range
+        len: u64,
+    },
+    /// Restricts the access permissions of the confidential memory in the specified range of the
+    /// calling TVM's address space to `perms`. Both `addr` and `len` must be 4kB-aligned, and the
+    /// range must lie within a single confidential memory region that is fully populated with
+    /// pages that lie entirely within the range. Permissions can only be removed: the call fails,
+    /// leaving the range unchanged, if `perms` would grant any access to a page that the page's
+    /// current permissions don't allow. The new permissions are in effect on all of the TVM's vCPUs
+    /// by the time the call returns, after which the host is notified. If the TVM's other vCPUs
+    /// have yet to discard the translations invalidated by an earlier call, the host is notified
+    /// without the range being changed and the call is retried once the calling vCPU is resumed.
+    ///
+    /// Subsequent accesses that violate the new permissions cause a `PermissionFault` exit to the
+    /// host.
+    ///
+    /// a6 = 3
+    RestrictPagePermissions {
+        /// a0 = start of the range
+        addr: u64,
+        /// a1 = length of the range
+        len: u64,
+        /// a2 = new permissions for the range
+        perms: TvmPagePerms,
+    },
 }
 
 impl TeeGuestFunction {
@@ -36,6 +89,19 @@ impl TeeGuestFunction {
                 addr: args[1],
                 len: args[2],
             }),
+            1 => Ok(ShareMemory {
+                addr: args[0],
+                len: args[1],
+            }),
+            2 => Ok(UnshareMemory {
+                addr: args[0],
+                len: args[1],
+            }),
+            3 => Ok(RestrictPagePermissions {
+                addr: args[0],
+                len: args[1],
+                perms: TvmPagePerms::from_reg(args[2])?,
+            }),
             _ => Err(Error::NotSupported),
         }
     }
@@ -46,6 +112,9 @@ impl SbiFunction for TeeGuestFunction {
         use TeeGuestFunction::*;
         match self {
             AddMemoryRegion { .. } => 0,
+            ShareMemory { .. } => 1,
+            UnshareMemory { .. } => 2,
+            RestrictPagePermissions { .. } => 3,
         }
     }
 
@@ -57,6 +126,13 @@ impl SbiFunction for TeeGuestFunction {
                 addr: _,
                 len: _,
             } => *region_type as u64,
+            ShareMemory { addr, len: _ } => *addr,
+            UnshareMemory { addr, len: _ } => *addr,
+            RestrictPagePermissions {
+                addr,
+                len: _,
+                perms: _,
+            } => *addr,
         }
     }
 
@@ -68,6 +144,13 @@ impl SbiFunction for TeeGuestFunction {
                 addr,
                 len: _,
             } => *addr,
+            ShareMemory { addr: _, len } => *len,
+            UnshareMemory { addr: _, len } => *len,
+            RestrictPagePermissions {
+                addr: _,
+                len,
+                perms: _,
+            } => *len,
         }
     }
 
@@ -79,6 +162,12 @@ impl SbiFunction for TeeGuestFunction {
                 addr: _,
                 len,
             } => *len,
+            RestrictPagePermissions {
+                addr: _,
+                len: _,
+                perms,
+            } => *perms as u64,
+            _ => 0,
         }
     }
 }
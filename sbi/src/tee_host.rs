@@ -25,6 +25,12 @@ pub enum RegisterSetId {
     SupervisorCsrs = 1,
     /// HS-level CSRs.
     HypervisorCsrs = 2,
+    /// Floating point registers.
+    Fprs = 3,
+    /// Vector registers. Only present if the platform supports the vector extension.
+    Vprs = 4,
+    /// The reason for the most recent exit from `TvmCpuRun`.
+    ExitReason = 5,
 }
 
 impl RegisterSetId {
@@ -34,6 +40,9 @@ impl RegisterSetId {
             0 => Ok(RegisterSetId::Gprs),
             1 => Ok(RegisterSetId::SupervisorCsrs),
             2 => Ok(RegisterSetId::HypervisorCsrs),
+            3 => Ok(RegisterSetId::Fprs),
+            4 => Ok(RegisterSetId::Vprs),
+            5 => Ok(RegisterSetId::ExitReason),
             _ => Err(Error::InvalidParam),
         }
     }
@@ -44,6 +53,9 @@ impl RegisterSetId {
             RegisterSetId::Gprs => core::mem::size_of::<Gprs>(),
             RegisterSetId::SupervisorCsrs => core::mem::size_of::<SupervisorCsrs>(),
             RegisterSetId::HypervisorCsrs => core::mem::size_of::<HypervisorCsrs>(),
+            RegisterSetId::Fprs => core::mem::size_of::<Fprs>(),
+            RegisterSetId::Vprs => core::mem::size_of::<Vprs>(),
+            RegisterSetId::ExitReason => core::mem::size_of::<ExitReason>(),
         }
     }
 }
@@ -128,9 +140,128 @@ pub struct HypervisorCsrs {
     ///  - MMIO store page faults. The TSM will write the value to be stored by the vCPU to the
     ///    register in `gprs` corresponding to the 'rs2' register in the instruction upon return
     ///    from `TvmCpuRun`.
+    ///
+    /// For floating point loads and stores the value is instead passed in the register in `fprs`
+    /// corresponding to the 'rd' or 'rs2' register in the instruction.
     pub htinst: u64,
 }
 
+/// Floating point registers. Structure for register sets of type `RegisterSetId::Fprs`. Each
+/// register holds the 64-bit value of the corresponding 'f' register; single-precision values
+/// are NaN-boxed.
+///
+/// The TSM will write to these registers upon return from `TvmCpuRun` when:
+///  - The vCPU takes a floating point store guest page fault in an emulated MMIO region.
+///
+/// The TSM will read from these registers when:
+///  - The vCPU takes a floating point load guest page fault in an emulated MMIO region.
+#[repr(C)]
+#[derive(Default)]
+pub struct Fprs(pub [u64; 32]);
+
+/// The maximum length in bytes of a vector register in the `Vprs` register set.
+pub const VPRS_MAX_VLENB: usize = 32;
+
+/// Vector registers. Structure for register sets of type `RegisterSetId::Vprs`. Only the first
+/// `vlenb` bytes of each register are used.
+///
+/// The TSM will write the vCPU's vector registers to this register set upon return from
+/// `TvmCpuRun`, and will load the vCPU's vector registers from it before resuming the vCPU in
+/// `TvmCpuRun`.
+#[repr(C)]
+#[derive(Default)]
+pub struct Vprs(pub [[u8; VPRS_MAX_VLENB]; 32]);
+
+/// Identifies why a TVM vCPU stopped running and returned from `TvmCpuRun`. The payload for each
+/// reason is reported in the `ExitReason` register set.
+#[repr(u64)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum TvmExitReason {
+    /// The vCPU hasn't exited yet.
+    #[default]
+    NoExit = 0,
+    /// A load from an emulated MMIO region. `addr` holds the guest physical address and `info`
+    /// the width of the access in bytes. The host completes the load as described for `htinst`.
+    MmioLoad = 1,
+    /// A store to an emulated MMIO region. `addr` holds the guest physical address, `info` the
+    /// width of the access in bytes and `value` the value being stored.
+    MmioStore = 2,
+    /// A guest page fault in a confidential memory region. `addr` holds the guest physical
+    /// address of the faulting page and `info` the exception code of the fault.
+    ConfidentialPageFault = 3,
+    /// A guest page fault in a shared memory region. `addr` holds the guest physical address of
+    /// the faulting page and `info` the exception code of the fault.
+    SharedPageFault = 4,
+    /// The vCPU executed WFI.
+    Wfi = 5,
+    /// The vCPU stopped itself with `HartStop`. The vCPU can't be run again until it is restarted.
+    HartStop = 6,
+    /// The TVM requested a system reset. `info` holds the reset type and `value` the reset reason.
+    /// The TVM can't be run again.
+    SystemReset = 7,
+    /// The TVM made a TEE Guest request which the host must handle. `info` holds the function ID
+    /// of the request, and its arguments are held in `gprs`.
+    TeeGuest = 8,
+    /// The vCPU took a trap that the TSM couldn't handle. `info` holds the SCAUSE of the trap. The
+    /// vCPU can't be run again.
+    FatalTrap = 9,
+    /// The vCPU was interrupted so that the host may handle an interrupt. `info` holds the SCAUSE
+    /// of the interrupt.
+    Interrupted = 10,
+    /// The vCPU made an ECALL that is forwarded to the host. `info` holds the extension ID and
+    /// `value` the function ID, and the arguments are held in `gprs`.
+    Ecall = 11,
+    /// The vCPU was preempted, either because its time slice expired or so that the host may
+    /// handle an interrupt of its own. `info` holds the SCAUSE of the interrupt that caused the
+    /// preemption.
+    Preempted = 12,
+    /// A guest page fault caused by an access to a mapped page of confidential memory that the
+    /// page's permissions don't allow. `addr` holds the guest physical address of the faulting page
+    /// and `info` the exception code of the fault. The fault can't be resolved by the host.
+    PermissionFault = 13,
+}
+
+impl TvmExitReason {
+    /// Returns the `TvmExitReason` corresponding to `val`.
+    pub fn from_raw(val: u64) -> Result<Self> {
+        use TvmExitReason::*;
+        match val {
+            0 => Ok(NoExit),
+            1 => Ok(MmioLoad),
+            2 => Ok(MmioStore),
+            3 => Ok(ConfidentialPageFault),
+            4 => Ok(SharedPageFault),
+            5 => Ok(Wfi),
+            6 => Ok(HartStop),
+            7 => Ok(SystemReset),
+            8 => Ok(TeeGuest),
+            9 => Ok(FatalTrap),
+            10 => Ok(Interrupted),
+            11 => Ok(Ecall),
+            12 => Ok(Preempted),
+            13 => Ok(PermissionFault),
+            _ => Err(Error::InvalidParam),
+        }
+    }
+}
+
+/// Exit reason. Structure for register sets of type `RegisterSetId::ExitReason`.
+///
+/// The TSM will write to these registers upon every return from `TvmCpuRun`. Fields that aren't
+/// used by the reported reason are zeroed.
+#[repr(C)]
+#[derive(Default)]
+pub struct ExitReason {
+    /// The reason for the exit, as a `TvmExitReason`.
+    pub reason: u64,
+    /// The guest physical address associated with the exit.
+    pub addr: u64,
+    /// Additional information about the exit, depending on `reason`.
+    pub info: u64,
+    /// A value associated with the exit, depending on `reason`.
+    pub value: u64,
+}
+
 /// Provides the state of the confidential VM supervisor.
 #[repr(u32)]
 #[derive(Copy, Clone, PartialEq, Eq, Default)]
@@ -179,6 +310,39 @@ pub struct TvmCreateParams {
     /// vCPU state. Must be page-aligned and `TsmInfo::tvm_bytes_per_vcpu` * `tvm_num_vcpus` bytes
     /// in length, rounded up to the nearest multiple of 4kB.
     pub tvm_vcpu_addr: u64,
+    /// The guest-stage paging mode, and hence the guest physical address width, of the TVM. Must
+    /// be a `TvmPagingMode` value supported by the hardware, or 0 to select the default
+    /// (`TvmPagingMode::Sv48x4`). May be omitted from the structure passed to `TvmCreate`, in
+    /// which case the default is used.
+    pub tvm_paging_mode: u64,
+}
+
+/// Guest-stage paging modes with which a confidential VM may be created. The values match the
+/// encoding of the MODE field of the `hgatp` CSR.
+#[repr(u64)]
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+pub enum TvmPagingMode {
+    /// 41-bit guest physical address space, translated with a 3-level page table.
+    Sv39x4 = 8,
+    /// 50-bit guest physical address space, translated with a 4-level page table.
+    #[default]
+    Sv48x4 = 9,
+    /// 59-bit guest physical address space, translated with a 5-level page table.
+    Sv57x4 = 10,
+}
+
+impl TvmPagingMode {
+    /// Attempts to create a paging mode from the given u64 register value. Returns an error if the
+    /// value isn't a valid paging mode.
+    pub fn from_reg(reg: u64) -> Result<Self> {
+        use TvmPagingMode::*;
+        match reg {
+            8 => Ok(Sv39x4),
+            9 => Ok(Sv48x4),
+            10 => Ok(Sv57x4),
+            _ => Err(Error::InvalidParam),
+        }
+    }
 }
 
 /// Types of pages allowed to used for creating or managing confidential VMs.
@@ -221,6 +385,54 @@ impl TsmPageType {
     }
 }
 
+/// Access permissions for pages of confidential memory mapped into a TVM. Passed in bits 15:8 of
+/// the register holding the `TsmPageType` of the pages being mapped, so that a value of 0 maps
+/// pages with full permissions.
+#[repr(u64)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TvmPagePerms {
+    /// Readable, writable and executable.
+    #[default]
+    ReadWriteExecute = 0,
+    /// Readable and executable, but not writable.
+    ReadExecute = 1,
+    /// Readable and writable, but not executable.
+    ReadWrite = 2,
+    /// Readable only.
+    ReadOnly = 3,
+    /// Executable only.
+    ExecuteOnly = 4,
+}
+
+impl TvmPagePerms {
+    /// Attempts to create page permissions from the given u64 register value.
+    pub fn from_reg(reg: u64) -> Result<Self> {
+        use TvmPagePerms::*;
+        match reg {
+            0 => Ok(ReadWriteExecute),
+            1 => Ok(ReadExecute),
+            2 => Ok(ReadWrite),
+            3 => Ok(ReadOnly),
+            4 => Ok(ExecuteOnly),
+            _ => Err(Error::InvalidParam),
+        }
+    }
+}
+
+// Page permissions are passed in the bits above the page type in the same register.
+const PAGE_PERMS_SHIFT: u64 = 8;
+const PAGE_TYPE_MASK: u64 = (1 << PAGE_PERMS_SHIFT) - 1;
+
+fn page_type_and_perms_from_reg(reg: u64) -> Result<(TsmPageType, TvmPagePerms)> {
+    let page_type = TsmPageType::from_reg(reg & PAGE_TYPE_MASK)?;
+    let perms = TvmPagePerms::from_reg(reg >> PAGE_PERMS_SHIFT)?;
+    Ok((page_type, perms))
+}
+
+fn page_type_and_perms_to_reg(page_type: TsmPageType, perms: TvmPagePerms) -> u64 {
+    (page_type as u64) | ((perms as u64) << PAGE_PERMS_SHIFT)
+}
+
 /// Types of memory regions that can be created in a TVM.
 #[repr(u64)]
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -253,12 +465,112 @@ impl TeeMemoryRegion {
     }
 }
 
+/// The version of the `TvmInfo` structure written by `TvmGetInfo`.
+pub const TVM_INFO_VERSION: u32 = 1;
+
+/// The state of a TVM, as reported by `TvmGetInfo`.
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub enum TvmState {
+    /// The TVM is being constructed and has not yet been finalized.
+    #[default]
+    TvmInitializing = 0,
+    /// The TVM has been finalized and its vCPUs may be run.
+    TvmFinalized = 1,
+}
+
+/// Types of regions in a TVM's guest physical address space, as reported by `TvmGetInfo`. The
+/// values for regions that can be created with `TvmAddMemoryRegion` match `TeeMemoryRegion`.
+#[repr(u64)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub enum TvmRegionType {
+    /// Confidential memory.
+    #[default]
+    Confidential = 0,
+    /// Shared, non-confidential memory.
+    Shared = 1,
+    /// Emulated MMIO.
+    EmulatedMmio = 2,
+    /// The pages of the TVM's virtualized IMSICs.
+    Imsic = 3,
+    /// The BARs of PCI devices assigned to the TVM.
+    Pci = 4,
+}
+
+/// The status of a TVM vCPU, as reported by `TvmGetInfo`.
+#[repr(u64)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub enum TvmCpuStatus {
+    /// The vCPU has not been created with `TvmCpuCreate`.
+    #[default]
+    NotPresent = 0,
+    /// The vCPU has been created, but is not powered on.
+    PoweredOff = 1,
+    /// The vCPU is available to be run.
+    Runnable = 2,
+    /// The vCPU is currently running on a physical CPU.
+    Running = 3,
+    /// The vCPU has suspended itself and is waiting for an interrupt.
+    Suspended = 4,
+    /// The vCPU was suspended, but has received an interrupt and is waiting to be run.
+    ResumePending = 5,
+}
+
+/// Information returned from the system about the state of a TVM. Written by `TvmGetInfo`, and
+/// followed in memory by `num_regions` `TvmRegionInfo` structures and then `num_vcpus`
+/// `TvmCpuInfo` structures.
+#[repr(C)]
+#[derive(Default)]
+pub struct TvmInfo {
+    /// The version of this structure. Set to `TVM_INFO_VERSION`.
+    pub version: u32,
+    /// The current state of the TVM.
+    pub tvm_state: TvmState,
+    /// The number of 4kB pages currently mapped into the TVM's address space. Huge pages are
+    /// counted as the number of 4kB pages they span.
+    pub mapped_pages: u64,
+    /// The number of unused pages in the pool added with `TvmAddZeroPagePool`.
+    pub zero_page_pool_pages: u64,
+    /// The number of regions in the TVM's guest physical address space.
+    pub num_regions: u64,
+    /// The maximum number of vCPUs in the TVM, as specified in `TvmCreateParams`.
+    pub num_vcpus: u64,
+}
+
+/// Describes a region of a TVM's guest physical address space. Regions are reported in order of
+/// increasing address.
+#[repr(C)]
+#[derive(Default)]
+pub struct TvmRegionInfo {
+    /// The base guest physical address of the region.
+    pub guest_addr: u64,
+    /// The length of the region in bytes.
+    pub len: u64,
+    /// The type of the region.
+    pub region_type: TvmRegionType,
+}
+
+/// Describes a vCPU of a TVM. vCPUs are reported in order of vCPU ID.
+#[repr(C)]
+#[derive(Default)]
+pub struct TvmCpuInfo {
+    /// The status of the vCPU.
+    pub status: TvmCpuStatus,
+    /// The guest physical address of the vCPU's virtualized IMSIC as set with
+    /// `TvmCpuSetImsicAddr`, or 0 if none has been set. Not reported for running vCPUs.
+    pub imsic_addr: u64,
+    /// The guest interrupt file bound to the vCPU, numbered as in HGEIE, or 0 if the vCPU isn't
+    /// bound to one. Not reported for running vCPUs.
+    pub interrupt_file: u64,
+}
+
 /// Functions provided by the TEE Host extension.
 #[derive(Copy, Clone, Debug)]
 pub enum TeeHostFunction {
     /// Creates a TVM from the parameters in the `TvmCreateParams` structure at the non-confidential
     /// physical address `params_addr`. Returns a guest ID that can be used to refer to the TVM in
-    /// TVM management TEECALLs.
+    /// TVM management TEECALLs. `len` may exclude the trailing `tvm_paging_mode` field, in which
+    /// case the TVM uses the default paging mode.
     ///
     /// a6 = 0
     TvmCreate {
@@ -267,7 +579,9 @@ pub enum TeeHostFunction {
         /// a1 = length of the `TvmCreateParams` structure in bytes
         len: u64,
     },
-    /// Message to destroy a TVM created with `TvmCreate`.
+    /// Message to destroy a TVM created with `TvmCreate`. All of the TVM's confidential memory,
+    /// including its page-table pages and vCPU state, is wiped and returned to the host in the
+    /// converted state, from which it may be reclaimed with `TsmReclaimPages`.
     ///
     /// a6 = 1
     TvmDestroy {
@@ -287,9 +601,9 @@ pub enum TeeHostFunction {
         num_pages: u64,
     },
     /// Maps `num_pages` zero-filled pages of confidential memory starting at `page_addr` into the
-    /// specified guest's address space at `guest_addr`. The mapping must lie within a region of
-    /// confidential memory created with `TvmAddMemoryRegion`. Zero pages may only be added after
-    /// the TVM has been finalized.
+    /// specified guest's address space at `guest_addr` with the access permissions in `perms`.
+    /// The mapping must lie within a region of confidential memory created with
+    /// `TvmAddMemoryRegion`. Zero pages may only be added after the TVM has been finalized.
     ///
     /// a6 = 3
     TvmAddZeroPages {
@@ -297,8 +611,10 @@ pub enum TeeHostFunction {
         guest_id: u64,
         /// a1 = physical address of the pages to insert
         page_addr: u64,
-        /// a2 = page size
+        /// a2[7:0] = page size
         page_type: TsmPageType,
+        /// a2[15:8] = page permissions
+        perms: TvmPagePerms,
         /// a3 = number of pages
         num_pages: u64,
         /// a4 = guest physical address
@@ -316,6 +632,10 @@ pub enum TeeHostFunction {
     /// Returns 0 if the vCPU can be resumed via a subsequent call to `TvmCpuRun`, or a value other
     /// than 0 if the vCPU was terminated and is no longer runnable.
     ///
+    /// If `time_slice` is non-zero the vCPU is preempted, and `TvmCpuRun` returns, once it has run
+    /// for `time_slice` ticks of the platform timebase. The vCPU is also preempted if an interrupt
+    /// arrives for the calling vCPU while the TVM vCPU is running.
+    ///
     /// Returns an error if the specified TVM or vCPU does not exist, or if the vCPU exists but
     /// is not currently runnable.
     ///
@@ -325,6 +645,8 @@ pub enum TeeHostFunction {
         guest_id: u64,
         /// a1 = vCPU id
         vcpu_id: u64,
+        /// a2 = maximum run time in timebase ticks, or 0 for no limit
+        time_slice: u64,
     },
     /// Returns the number of register sets in the vCPU shared-memory state area for vCPUs of
     /// `guest_id`.
@@ -380,9 +702,13 @@ pub enum TeeHostFunction {
     },
     /// Copies `num_pages` pages from non-confidential memory at `src_addr` to confidential
     /// memory at `dest_addr`, then measures and maps the pages at `dest_addr` into the specified
-    /// guest's address space at `guest_addr`. The mapping must lie within a region of confidential
-    /// memory created with `TvmAddMemoryRegion`. Measured pages may only be added prior to TVM
-    /// finalization.
+    /// guest's address space at `guest_addr` with the access permissions in `perms`. The mapping
+    /// must lie within a region of confidential memory created with `TvmAddMemoryRegion`. Measured
+    /// pages may only be added prior to TVM finalization.
+    ///
+    /// Permissions other than `ReadWriteExecute` are included in the TVM's measurement, allowing
+    /// the TVM's owner to verify that, for example, its kernel text was mapped read-only and
+    /// executable from the moment the TVM was finalized.
     ///
     /// a6 = 11
     TvmAddMeasuredPages {
@@ -392,8 +718,10 @@ pub enum TeeHostFunction {
         src_addr: u64,
         /// a2 = physical address of the pages to insert
         dest_addr: u64,
-        /// a3 = page size
+        /// a3[7:0] = page size
         page_type: TsmPageType,
+        /// a3[15:8] = page permissions
+        perms: TvmPagePerms,
         /// a4 = number of pages
         num_pages: u64,
         /// a5 = guest physical address
@@ -413,7 +741,10 @@ pub enum TeeHostFunction {
         num_pages: u64,
     },
     /// Reclaims `num_pages` of confidential memory starting at `page_addr`. The pages must not
-    /// be currently assigned to an active TVM.
+    /// be currently assigned to an active TVM. Pages whose conversion with `TsmConvertPages` has
+    /// not yet been completed by a TLB invalidation sequence may also be reclaimed, cancelling
+    /// their conversion. Pages blocked for removal by `TvmRemovePages`, or by `ShareMemory`, can't
+    /// be reclaimed.
     ///
     /// a6 = 13
     TsmReclaimPages {
@@ -472,6 +803,69 @@ pub enum TeeHostFunction {
         /// a4 = guest physical address
         guest_addr: u64,
     },
+    /// Removes `num_pages` confidential pages mapped at `guest_addr` from the specified guest's
+    /// address space and returns them to the host. The pages are first blocked from access by the
+    /// TVM and a TLB invalidation of the TVM's address space is initiated. Once no vCPU of the TVM
+    /// may hold stale translations for the blocked pages they are scrubbed and returned to the host
+    /// as converted pages, from which they may be reclaimed with `TsmReclaimPages` or assigned to
+    /// a TVM.
+    ///
+    /// Returns the number of pages removed: either `num_pages`, or 0 if the pages are blocked but
+    /// vCPUs of the TVM are still running with stale translations, in which case the call should
+    /// be retried with the same range once those vCPUs have exited. Fails if any page in the range
+    /// isn't a confidential page of the TVM, in which case retrying can't succeed.
+    ///
+    /// Also used to complete the TVM's `ShareMemory` and `UnshareMemory` calls: the former leaves
+    /// the shared range blocked for removal, and for the latter the TVM's references to the
+    /// unshared 4kB pages are dropped instead of returning confidential pages.
+    ///
+    /// a6 = 23
+    TvmRemovePages {
+        /// a0 = guest id
+        guest_id: u64,
+        /// a1 = guest physical address of the pages to remove
+        guest_addr: u64,
+        /// a2 = page size
+        page_type: TsmPageType,
+        /// a3 = number of pages
+        num_pages: u64,
+    },
+    /// Adds `num_pages` 4kB pages of confidential memory starting at `page_addr` to the pool of
+    /// pages from which the specified guest's page faults in confidential memory regions are
+    /// resolved. When the TVM faults on an unmapped page in a confidential region, a page is taken
+    /// from the pool, zeroed and mapped at the faulting address without exiting to the host. The
+    /// host is only notified of such faults once the pool is empty.
+    ///
+    /// Pages remain in the pool until they're mapped or the TVM is destroyed, after which any
+    /// unused pages are returned to the host as converted pages.
+    ///
+    /// a6 = 24
+    TvmAddZeroPagePool {
+        /// a0 = guest id
+        guest_id: u64,
+        /// a1 = physical address of the first page
+        page_addr: u64,
+        /// a2 = number of pages
+        num_pages: u64,
+    },
+    /// Writes up to `len` bytes of information about the state of the specified TVM to the
+    /// non-confidential physical address `dest_addr`: a `TvmInfo` structure followed by a
+    /// `TvmRegionInfo` structure for each region of the TVM's address space and a `TvmCpuInfo`
+    /// structure for each of its vCPUs. `len` must be at least the size of `TvmInfo`; the
+    /// region and vCPU structures that don't fit are omitted.
+    ///
+    /// Returns the number of bytes needed to hold the complete information, which may be larger
+    /// than `len`.
+    ///
+    /// a6 = 25
+    TvmGetInfo {
+        /// a0 = guest id
+        guest_id: u64,
+        /// a1 = destination address of the `TvmInfo` structure
+        dest_addr: u64,
+        /// a2 = maximum number of bytes to be written
+        len: u64,
+    },
 }
 
 impl TeeHostFunction {
@@ -489,17 +883,22 @@ impl TeeHostFunction {
                 page_addr: args[1],
                 num_pages: args[2],
             }),
-            3 => Ok(TvmAddZeroPages {
-                guest_id: args[0],
-                page_addr: args[1],
-                page_type: TsmPageType::from_reg(args[2])?,
-                num_pages: args[3],
-                guest_addr: args[4],
-            }),
+            3 => {
+                let (page_type, perms) = page_type_and_perms_from_reg(args[2])?;
+                Ok(TvmAddZeroPages {
+                    guest_id: args[0],
+                    page_addr: args[1],
+                    page_type,
+                    perms,
+                    num_pages: args[3],
+                    guest_addr: args[4],
+                })
+            }
             4 => Ok(Finalize { guest_id: args[0] }),
             5 => Ok(TvmCpuRun {
                 guest_id: args[0],
                 vcpu_id: args[1],
+                time_slice: args[2],
             }),
             8 => Ok(TvmCpuCreate {
                 guest_id: args[0],
@@ -510,14 +909,18 @@ impl TeeHostFunction {
                 dest_addr: args[0],
                 len: args[1],
             }),
-            11 => Ok(TvmAddMeasuredPages {
-                guest_id: args[0],
-                src_addr: args[1],
-                dest_addr: args[2],
-                page_type: TsmPageType::from_reg(args[3])?,
-                num_pages: args[4],
-                guest_addr: args[5],
-            }),
+            11 => {
+                let (page_type, perms) = page_type_and_perms_from_reg(args[3])?;
+                Ok(TvmAddMeasuredPages {
+                    guest_id: args[0],
+                    src_addr: args[1],
+                    dest_addr: args[2],
+                    page_type,
+                    perms,
+                    num_pages: args[4],
+                    guest_addr: args[5],
+                })
+            }
             12 => Ok(TsmConvertPages {
                 page_addr: args[0],
                 page_type: TsmPageType::from_reg(args[1])?,
@@ -548,6 +951,22 @@ impl TeeHostFunction {
                 guest_id: args[0],
                 index: args[1],
             }),
+            23 => Ok(TvmRemovePages {
+                guest_id: args[0],
+                guest_addr: args[1],
+                page_type: TsmPageType::from_reg(args[2])?,
+                num_pages: args[3],
+            }),
+            24 => Ok(TvmAddZeroPagePool {
+                guest_id: args[0],
+                page_addr: args[1],
+                num_pages: args[2],
+            }),
+            25 => Ok(TvmGetInfo {
+                guest_id: args[0],
+                dest_addr: args[1],
+                len: args[2],
+            }),
             _ => Err(Error::NotSupported),
         }
     }
@@ -571,6 +990,7 @@ impl SbiFunction for TeeHostFunction {
                 guest_id: _,
                 page_addr: _,
                 page_type: _,
+                perms: _,
                 num_pages: _,
                 guest_addr: _,
             } => 3,
@@ -578,6 +998,7 @@ impl SbiFunction for TeeHostFunction {
             TvmCpuRun {
                 guest_id: _,
                 vcpu_id: _,
+                time_slice: _,
             } => 5,
             TvmCpuCreate {
                 guest_id: _,
@@ -593,6 +1014,7 @@ impl SbiFunction for TeeHostFunction {
                 src_addr: _,
                 dest_addr: _,
                 page_type: _,
+                perms: _,
                 num_pages: _,
                 guest_addr: _,
             } => 11,
@@ -626,6 +1048,22 @@ impl SbiFunction for TeeHostFunction {
                 guest_id: _,
                 index: _,
             } => 22,
+            TvmRemovePages {
+                guest_id: _,
+                guest_addr: _,
+                page_type: _,
+                num_pages: _,
+            } => 23,
+            TvmAddZeroPagePool {
+                guest_id: _,
+                page_addr: _,
+                num_pages: _,
+            } => 24,
+            TvmGetInfo {
+                guest_id: _,
+                dest_addr: _,
+                len: _,
+            } => 25,
         }
     }
 
@@ -646,6 +1084,7 @@ impl SbiFunction for TeeHostFunction {
                 guest_id,
                 page_addr: _,
                 page_type: _,
+                perms: _,
                 num_pages: _,
                 guest_addr: _,
             } => *guest_id,
@@ -653,6 +1092,7 @@ impl SbiFunction for TeeHostFunction {
             TvmCpuRun {
                 guest_id,
                 vcpu_id: _,
+                time_slice: _,
             } => *guest_id,
             TvmCpuCreate {
                 guest_id,
@@ -665,6 +1105,7 @@ impl SbiFunction for TeeHostFunction {
                 src_addr: _,
                 dest_addr: _,
                 page_type: _,
+                perms: _,
                 num_pages: _,
                 guest_addr: _,
             } => *guest_id,
@@ -693,6 +1134,22 @@ impl SbiFunction for TeeHostFunction {
             } => *guest_id,
             TvmCpuNumRegisterSets { guest_id } => *guest_id,
             TvmCpuGetRegisterSet { guest_id, index: _ } => *guest_id,
+            TvmRemovePages {
+                guest_id,
+                guest_addr: _,
+                page_type: _,
+                num_pages: _,
+            } => *guest_id,
+            TvmAddZeroPagePool {
+                guest_id,
+                page_addr: _,
+                num_pages: _,
+            } => *guest_id,
+            TvmGetInfo {
+                guest_id,
+                dest_addr: _,
+                len: _,
+            } => *guest_id,
             _ => 0,
         }
     }
@@ -713,12 +1170,14 @@ impl SbiFunction for TeeHostFunction {
                 guest_id: _,
                 page_addr,
                 page_type: _,
+                perms: _,
                 num_pages: _,
                 guest_addr: _,
             } => *page_addr,
             TvmCpuRun {
                 guest_id: _,
                 vcpu_id,
+                time_slice: _,
             } => *vcpu_id,
             TvmCpuCreate {
                 guest_id: _,
@@ -731,6 +1190,7 @@ impl SbiFunction for TeeHostFunction {
                 src_addr,
                 dest_addr: _,
                 page_type: _,
+                perms: _,
                 num_pages: _,
                 guest_addr: _,
             } => *src_addr,
@@ -758,6 +1218,22 @@ impl SbiFunction for TeeHostFunction {
                 guest_addr: _,
             } => *page_addr,
             TvmCpuGetRegisterSet { guest_id: _, index } => *index,
+            TvmRemovePages {
+                guest_id: _,
+                guest_addr,
+                page_type: _,
+                num_pages: _,
+            } => *guest_addr,
+            TvmAddZeroPagePool {
+                guest_id: _,
+                page_addr,
+                num_pages: _,
+            } => *page_addr,
+            TvmGetInfo {
+                guest_id: _,
+                dest_addr,
+                len: _,
+            } => *dest_addr,
             _ => 0,
         }
     }
@@ -774,9 +1250,15 @@ impl SbiFunction for TeeHostFunction {
                 guest_id: _,
                 page_addr: _,
                 page_type,
+                perms,
                 num_pages: _,
                 guest_addr: _,
-            } => *page_type as u64,
+            } => page_type_and_perms_to_reg(*page_type, *perms),
+            TvmCpuRun {
+                guest_id: _,
+                vcpu_id: _,
+                time_slice,
+            } => *time_slice,
             TvmCpuCreate {
                 guest_id: _,
                 vcpu_id: _,
@@ -787,6 +1269,7 @@ impl SbiFunction for TeeHostFunction {
                 src_addr: _,
                 dest_addr,
                 page_type: _,
+                perms: _,
                 num_pages: _,
                 guest_addr: _,
             } => *dest_addr,
@@ -813,6 +1296,22 @@ impl SbiFunction for TeeHostFunction {
                 num_pages: _,
                 guest_addr: _,
             } => *page_type as u64,
+            TvmRemovePages {
+                guest_id: _,
+                guest_addr: _,
+                page_type,
+                num_pages: _,
+            } => *page_type as u64,
+            TvmAddZeroPagePool {
+                guest_id: _,
+                page_addr: _,
+                num_pages,
+            } => *num_pages,
+            TvmGetInfo {
+                guest_id: _,
+                dest_addr: _,
+                len,
+            } => *len,
             _ => 0,
         }
     }
@@ -824,6 +1323,7 @@ impl SbiFunction for TeeHostFunction {
                 guest_id: _,
                 page_addr: _,
                 page_type: _,
+                perms: _,
                 num_pages,
                 guest_addr: _,
             } => *num_pages,
@@ -832,9 +1332,10 @@ impl SbiFunction for TeeHostFunction {
                 src_addr: _,
                 dest_addr: _,
                 page_type,
+                perms,
                 num_pages: _,
                 guest_addr: _,
-            } => *page_type as u64,
+            } => page_type_and_perms_to_reg(*page_type, *perms),
             TvmAddMemoryRegion {
                 guest_id: _,
                 region_type: _,
@@ -848,6 +1349,12 @@ impl SbiFunction for TeeHostFunction {
                 num_pages,
                 guest_addr: _,
             } => *num_pages,
+            TvmRemovePages {
+                guest_id: _,
+                guest_addr: _,
+                page_type: _,
+                num_pages,
+            } => *num_pages,
             _ => 0,
         }
     }
@@ -859,6 +1366,7 @@ impl SbiFunction for TeeHostFunction {
                 guest_id: _,
                 page_addr: _,
                 page_type: _,
+                perms: _,
                 num_pages: _,
                 guest_addr,
             } => *guest_addr,
@@ -867,6 +1375,7 @@ impl SbiFunction for TeeHostFunction {
                 src_addr: _,
                 dest_addr: _,
                 page_type: _,
+                perms: _,
                 num_pages,
                 guest_addr: _,
             } => *num_pages,
@@ -889,6 +1398,7 @@ impl SbiFunction for TeeHostFunction {
                 src_addr: _,
                 dest_addr: _,
                 page_type: _,
+                perms: _,
                 num_pages: _,
                 guest_addr,
             } => *guest_addr,
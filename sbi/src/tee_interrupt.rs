@@ -97,6 +97,23 @@ pub enum TeeInterruptFunction {
         /// a0 = physical address of interrupt file to be reclaimed
         imsic_addr: u64,
     },
+    /// Injects a virtual interrupt into the specified vCPU of a TVM that doesn't use AIA
+    /// virtualization. `interrupt_id` is the supervisor-level interrupt cause seen by the vCPU,
+    /// either 1 for a software interrupt or 9 for an external interrupt. The interrupt is taken
+    /// the next time the vCPU runs with the interrupt enabled; a running vCPU is kicked so that
+    /// it picks up the interrupt.
+    ///
+    /// Returns 0 on success.
+    ///
+    /// a6 = 4
+    TvmCpuInjectInterrupt {
+        /// a0 = TVM ID
+        tvm_id: u64,
+        /// a1 = vCPU ID
+        vcpu_id: u64,
+        /// a2 = interrupt ID
+        interrupt_id: u64,
+    },
 }
 
 impl TeeInterruptFunction {
@@ -120,6 +137,11 @@ impl TeeInterruptFunction {
             3 => Ok(TsmReclaimImsic {
                 imsic_addr: args[0],
             }),
+            4 => Ok(TvmCpuInjectInterrupt {
+                tvm_id: args[0],
+                vcpu_id: args[1],
+                interrupt_id: args[2],
+            }),
             _ => Err(Error::NotSupported),
         }
     }
@@ -133,6 +155,7 @@ impl SbiFunction for TeeInterruptFunction {
             TvmCpuSetImsicAddr { .. } => 1,
             TsmConvertImsic { .. } => 2,
             TsmReclaimImsic { .. } => 3,
+            TvmCpuInjectInterrupt { .. } => 4,
         }
     }
 
@@ -151,6 +174,11 @@ impl SbiFunction for TeeInterruptFunction {
             } => *tvm_id,
             TsmConvertImsic { imsic_addr } => *imsic_addr,
             TsmReclaimImsic { imsic_addr } => *imsic_addr,
+            TvmCpuInjectInterrupt {
+                tvm_id,
+                vcpu_id: _,
+                interrupt_id: _,
+            } => *tvm_id,
         }
     }
 
@@ -167,6 +195,11 @@ impl SbiFunction for TeeInterruptFunction {
                 vcpu_id,
                 imsic_addr: _,
             } => *vcpu_id,
+            TvmCpuInjectInterrupt {
+                tvm_id: _,
+                vcpu_id,
+                interrupt_id: _,
+            } => *vcpu_id,
             _ => 0,
         }
     }
@@ -184,6 +217,11 @@ impl SbiFunction for TeeInterruptFunction {
                 vcpu_id: _,
                 imsic_addr,
             } => *imsic_addr,
+            TvmCpuInjectInterrupt {
+                tvm_id: _,
+                vcpu_id: _,
+                interrupt_id,
+            } => *interrupt_id,
             _ => 0,
         }
     }
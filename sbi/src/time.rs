@@ -0,0 +1,47 @@
+// Copyright (c) 2022 by Rivos Inc.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::error::*;
+use crate::function::*;
+
+/// Functions for the TIME extension.
+#[derive(Copy, Clone, Debug)]
+pub enum TimeFunction {
+    /// Programs the clock for the next event after `stime_value` time. Any pending timer interrupt
+    /// is cleared. To clear the timer without scheduling a new event, `stime_value` may be set to
+    /// an infinitely distant time, e.g. `u64::MAX`.
+    SetTimer {
+        /// a0 - The absolute time, in units of the `time` CSR, at which the timer should fire.
+        stime_value: u64,
+    },
+}
+
+impl TimeFunction {
+    /// Attempts to parse `Self` from the passed in `a0-a7`.
+    pub(crate) fn from_regs(args: &[u64]) -> Result<Self> {
+        use TimeFunction::*;
+        match args[6] {
+            0 => Ok(SetTimer {
+                stime_value: args[0],
+            }),
+            _ => Err(Error::NotSupported),
+        }
+    }
+}
+
+impl SbiFunction for TimeFunction {
+    fn a6(&self) -> u64 {
+        use TimeFunction::*;
+        match self {
+            SetTimer { .. } => 0,
+        }
+    }
+
+    fn a0(&self) -> u64 {
+        use TimeFunction::*;
+        match self {
+            SetTimer { stime_value } => *stime_value,
+        }
+    }
+}
@@ -5,7 +5,7 @@
 use core::marker::PhantomData;
 use page_tracking::collections::{PageArc, PageVec};
 use page_tracking::PageTracker;
-use riscv_page_tables::GuestStagePagingMode;
+use riscv_page_tables::{GuestStagePagingMode, Sv39x4, Sv48x4, Sv57x4};
 use riscv_pages::{InternalClean, Page, PageOwnerId, SequentialPages};
 use spin::{Mutex, RwLock, RwLockReadGuard};
 
@@ -138,12 +138,68 @@ impl<T: GuestStagePagingMode> GuestVm<T> {
     }
 }
 
+/// A reference to a guest VM using any of the supported guest-stage paging modes.
+#[derive(Clone)]
+pub enum AnyGuestVm {
+    Sv39x4(GuestVm<Sv39x4>),
+    Sv48x4(GuestVm<Sv48x4>),
+    Sv57x4(GuestVm<Sv57x4>),
+}
+
+/// Evaluates `$body` with `$guest` bound to the `GuestVm` wrapped by the `AnyGuestVm` `$any`,
+/// regardless of the guest's paging mode. Invoked as `with_guest_vm!(any_guest, |guest| body)`.
+macro_rules! with_guest_vm {
+    ($any:expr, |$guest:ident| $body:expr) => {
+        match $any {
+            $crate::guest_tracking::AnyGuestVm::Sv39x4($guest) => $body,
+            $crate::guest_tracking::AnyGuestVm::Sv48x4($guest) => $body,
+            $crate::guest_tracking::AnyGuestVm::Sv57x4($guest) => $body,
+        }
+    };
+}
+pub(crate) use with_guest_vm;
+
+impl AnyGuestVm {
+    /// Returns the `PageOwnerId` for the wrapped VM.
+    pub fn page_owner_id(&self) -> PageOwnerId {
+        with_guest_vm!(self, |g| g.page_owner_id())
+    }
+
+    // Returns true if any vCPU of the wrapped VM is currently running.
+    fn has_running_vcpus(&self) -> bool {
+        with_guest_vm!(self, |g| g.inner.read().vm.has_running_vcpus())
+    }
+
+    // Returns the number of outstanding references to the wrapped VM.
+    fn ref_count(&self) -> usize {
+        with_guest_vm!(self, |g| PageArc::ref_count(&g.inner))
+    }
+}
+
+impl From<GuestVm<Sv39x4>> for AnyGuestVm {
+    fn from(guest: GuestVm<Sv39x4>) -> Self {
+        AnyGuestVm::Sv39x4(guest)
+    }
+}
+
+impl From<GuestVm<Sv48x4>> for AnyGuestVm {
+    fn from(guest: GuestVm<Sv48x4>) -> Self {
+        AnyGuestVm::Sv48x4(guest)
+    }
+}
+
+impl From<GuestVm<Sv57x4>> for AnyGuestVm {
+    fn from(guest: GuestVm<Sv57x4>) -> Self {
+        AnyGuestVm::Sv57x4(guest)
+    }
+}
+
 /// Tracks the guest VMs for a host VM.
-pub struct Guests<T: GuestStagePagingMode> {
-    guests: Mutex<PageVec<GuestVm<T>>>,
+pub struct Guests {
+    guests: Mutex<PageVec<AnyGuestVm>>,
 }
 
-impl<T: GuestStagePagingMode> Guests<T> {
+impl Guests {
     /// Creates a new `Guests` using `vec_pages` as storage.
     pub fn new(vec_pages: SequentialPages<InternalClean>, page_tracker: PageTracker) -> Self {
         Self {
@@ -152,7 +208,7 @@ impl<T: GuestStagePagingMode> Guests<T> {
     }
 
     /// Adds `guest` to this guest tracking table.
-    pub fn add(&self, guest: GuestVm<T>) -> Result<()> {
+    pub fn add(&self, guest: AnyGuestVm) -> Result<()> {
         let mut guests = self.guests.lock();
         guests
             .try_reserve(1)
@@ -162,11 +218,17 @@ impl<T: GuestStagePagingMode> Guests<T> {
     }
 
     /// Returns the guest with the given ID.
-    pub fn get(&self, id: PageOwnerId) -> Option<GuestVm<T>> {
+    pub fn get(&self, id: PageOwnerId) -> Option<AnyGuestVm> {
         let guests = self.guests.lock();
         guests.iter().find(|g| g.page_owner_id() == id).cloned()
     }
 
+    /// Returns true if any vCPU of any of the tracked guests is currently running.
+    pub fn any_vcpu_running(&self) -> bool {
+        let guests = self.guests.lock();
+        guests.iter().any(|g| g.has_running_vcpus())
+    }
+
     /// Removes the guest with the given ID if there are no outstanding references to it.
     pub fn remove(&self, id: PageOwnerId) -> Result<()> {
         // Pull the last reference to this guest out of the vector first so we don't do the final
@@ -180,7 +242,7 @@ impl<T: GuestStagePagingMode> Guests<T> {
                 .ok_or(Error::InvalidGuestId)?;
             // This use of ref_count() is sound since we hold the lock on self.guests and no new
             // references can be created if we hold the only reference.
-            if PageArc::ref_count(&guest.inner) != 1 {
+            if guest.ref_count() != 1 {
                 return Err(Error::GuestInUse);
             }
             let last = guest.clone();
@@ -28,6 +28,7 @@ mod asm;
 mod guest_tracking;
 mod host_vm_loader;
 mod smp;
+mod suspend;
 mod trap;
 mod vm;
 mod vm_cpu;
@@ -36,7 +37,10 @@ mod vm_pages;
 mod vm_pmu;
 
 use device_tree::{DeviceTree, Fdt};
-use drivers::{imsic::Imsic, iommu::Iommu, pci::PcieRoot, pmu::PmuInfo, uart::UartDriver, CpuInfo};
+use drivers::{
+    imsic::Imsic, iommu::Iommu, pci::PcieRoot, pmu::PmuInfo, reset::ResetDriver, uart::UartDriver,
+    CpuInfo,
+};
 use host_vm_loader::HostVmLoader;
 use hyp_alloc::HypAlloc;
 use page_tracking::*;
@@ -52,9 +56,11 @@ use riscv_regs::{
 use s_mode_utils::abort::abort;
 use s_mode_utils::print::*;
 use s_mode_utils::sbi_console::SbiConsole;
+use sbi::api::base;
+use sbi::{ResetReason, ResetType};
 use smp::PerCpu;
 use spin::Once;
-use vm::HostVm;
+use vm::{HostVm, TvmMachineIdPolicy};
 
 #[panic_handler]
 fn panic(info: &core::panic::PanicInfo) -> ! {
@@ -103,13 +109,21 @@ pub fn alloc_error(_layout: Layout) -> ! {
     abort()
 }
 
-// Powers off this machine.
-fn poweroff() -> ! {
-    println!("Shutting down");
-    // Safety: on this platform, a write of 0x5555 to 0x100000 will trigger the platform to
-    // poweroff, which is defined behavior.
-    unsafe {
-        core::ptr::write_volatile(0x10_0000 as *mut u32, 0x5555);
+// Resets or powers off this machine as requested by the host VM.
+fn reset(reset_type: ResetType) -> ! {
+    match reset_type {
+        ResetType::ColdReset | ResetType::WarmReset => println!("Rebooting"),
+        _ => println!("Shutting down"),
+    }
+    if let Some(driver) = ResetDriver::get() {
+        match reset_type {
+            ResetType::ColdReset | ResetType::WarmReset if driver.can_reboot() => driver.reboot(),
+            _ => driver.poweroff(),
+        }
+    }
+    // We couldn't reset the machine ourselves, so ask firmware to do it for us.
+    if let Err(e) = sbi::api::reset::reset(reset_type, ResetReason::NoReason) {
+        println!("Failed to reset through firmware: {:?}", e);
     }
     abort()
 }
@@ -304,9 +318,6 @@ fn setup_hyp_paging(mem_map: &mut HwMemMap) {
         hyp_map_region(&sv48, base, size, perms, &mut || pte_pages.next());
     }
 
-    // TODO - reset device is hard coded in vm.rs
-    map_fixed_device(0x10_0000, &sv48, &mut || pte_pages.next());
-
     // Install the page table in satp
     let mut satp = LocalRegisterCopy::<u64, satp::Register>::new(0);
     satp.set_from(&sv48, 0);
@@ -316,27 +327,6 @@ fn setup_hyp_paging(mem_map: &mut HwMemMap) {
     tlb::sfence_vma(None, None);
 }
 
-// Adds some hard-coded device location to the given sv48 page table so that the devices can be
-// accessed by the hypervisor. Identity maps a single page at base to base.
-fn map_fixed_device(
-    base: u64,
-    sv48: &FirstStagePageTable<Sv48>,
-    get_pte_page: &mut dyn FnMut() -> Option<Page<InternalClean>>,
-) {
-    let virt_base = PageAddr::new(RawAddr::supervisor_virt(base)).unwrap();
-    let phys_base = PageAddr::new(RawAddr::supervisor(base)).unwrap();
-    let pte_fields = PteFieldBits::leaf_with_perms(PteLeafPerms::RW);
-    let mapper = sv48
-        .map_range(virt_base, PageSize::Size4k, 1, get_pte_page)
-        .unwrap();
-    // Safe to map access to the device because this will be the only mapping it is used through.
-    unsafe {
-        mapper
-            .map_4k_addr(virt_base, phys_base, pte_fields)
-            .unwrap();
-    }
-}
-
 /// Creates a heap from the given `mem_map`, marking the region occupied by the heap as reserved.
 fn create_heap(mem_map: &mut HwMemMap) {
     const HEAP_SIZE: u64 = 16 * 1024 * 1024;
@@ -401,10 +391,13 @@ pub fn setup_csrs() {
     hie.modify(Interrupt::VirtualSupervisorSoft.to_hie_field().unwrap());
     hie.modify(Interrupt::VirtualSupervisorTimer.to_hie_field().unwrap());
     hie.modify(Interrupt::VirtualSupervisorExternal.to_hie_field().unwrap());
+    // Guest external interrupts are gated per interrupt file by HGEIE.
+    hie.modify(Interrupt::SupervisorGuestExternal.to_hie_field().unwrap());
     CSR.hie.set(hie.get());
 
-    // TODO: Handle virtualization of timer/htimedelta (see issue #46)
-    // Enable access to timer for now.
+    // VS-mode reads of `time` are offset by the per-vCPU HTIMEDELTA, and timer interrupts are
+    // virtualized using either VSTIMECMP (with Sstc) or the SBI TIME extension, so the guest is
+    // free to read `time` directly.
     CSR.hcounteren.set(1 << (CSR_TIME - CSR_CYCLE));
 
     // Make the basic counters available to any of our U-mode tasks.
@@ -445,7 +438,7 @@ extern "C" fn kernel_init(hart_id: u64, fdt_addr: u64) {
     setup_csrs();
 
     SbiConsole::set_as_console();
-    println!("Salus: Boot test VM");
+    println!("Salus {}: Boot test VM", env!("CARGO_PKG_VERSION"));
 
     // Will panic if register width too long. (currently 256 bits)
     #[cfg(target_feature = "v")]
@@ -477,6 +470,15 @@ extern "C" fn kernel_init(hart_id: u64, fdt_addr: u64) {
     // Find the UART and switch to it as the system console.
     UartDriver::probe_from(&hyp_dt, &mut mem_map).expect("Failed to probe UART");
 
+    // Find the system controller used to reboot or power off the platform.
+    // Without one, we rely on firmware to reset the platform for us.
+    if let Err(e) = ResetDriver::probe_from(&hyp_dt, &mut mem_map) {
+        if base::probe_sbi_extension(sbi::EXT_RESET).is_err() {
+            panic!("No reset device ({:?}) or firmware reset support", e);
+        }
+        println!("No reset device ({:?}), resetting through firmware", e);
+    }
+
     // Discover the CPU topology.
     CpuInfo::parse_from(&hyp_dt);
     let cpu_info = CpuInfo::get();
@@ -503,6 +505,14 @@ extern "C" fn kernel_init(hart_id: u64, fdt_addr: u64) {
             .raw(),
         hart_id
     );
+    println!(
+        "mvendorid: 0x{:x}, marchid: 0x{:x}, mimpid: 0x{:x}",
+        cpu_info.mvendorid(),
+        cpu_info.marchid(),
+        cpu_info.mimpid()
+    );
+    TvmMachineIdPolicy::init_from(&hyp_dt);
+    println!("TVM machine ID policy: {:?}", TvmMachineIdPolicy::get());
 
     // Probe for the IMSIC.
     Imsic::probe_from(&hyp_dt, &mut mem_map).expect("Failed to probe IMSIC");
@@ -589,8 +599,8 @@ extern "C" fn kernel_init(hart_id: u64, fdt_addr: u64) {
 
     HOST_VM.call_once(|| host);
     let cpu_id = PerCpu::this_cpu().cpu_id();
-    HOST_VM.get().unwrap().run(cpu_id.raw() as u64);
-    poweroff();
+    let reset_type = HOST_VM.get().unwrap().run(cpu_id.raw() as u64);
+    reset(reset_type);
 }
 
 #[no_mangle]
@@ -609,6 +619,6 @@ extern "C" fn secondary_init(_hart_id: u64) {
     let me = PerCpu::this_cpu();
     me.set_online();
 
-    HOST_VM.wait().run(me.cpu_id().raw() as u64);
-    poweroff();
+    let reset_type = HOST_VM.wait().run(me.cpu_id().raw() as u64);
+    reset(reset_type);
 }
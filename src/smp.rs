@@ -3,13 +3,15 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use core::arch::asm;
-use core::cell::{RefCell, RefMut};
+use core::cell::{Cell, RefCell, RefMut};
+use core::sync::atomic::{AtomicBool, Ordering};
 use drivers::{imsic::Imsic, CpuId, CpuInfo};
 use page_tracking::{HwMemMap, HwMemRegionType, HwReservedMemType};
 use riscv_pages::{PageSize, RawAddr, SupervisorPageAddr};
-use riscv_regs::{sstatus, ReadWriteable, CSR};
+use riscv_regs::{sstatus, ReadWriteable, Writeable, CSR};
 use s_mode_utils::print::*;
-use sbi::api::state;
+use sbi::api::{state, time};
+use sbi::HartState;
 use spin::Once;
 
 use crate::vm_id::VmIdTracker;
@@ -25,7 +27,9 @@ extern "C" {
 pub struct PerCpu {
     cpu_id: CpuId,
     vmid_tracker: RefCell<VmIdTracker>,
-    online: Once<bool>,
+    // The deadline the physical S-mode timer is currently programmed with, if any.
+    timer_deadline: Cell<Option<u64>>,
+    online: AtomicBool,
 }
 
 /// The number of pages we allocate per CPU: the CPU's stack + it's `PerCpu` structure.
@@ -34,6 +38,9 @@ const PER_CPU_PAGES: u64 = 8;
 /// The base address of the per-CPU memory region.
 static PER_CPU_BASE: Once<SupervisorPageAddr> = Once::new();
 
+/// Set while the secondary CPUs are being stopped by `stop_secondary_cpus()`.
+static STOP_REQUESTED: AtomicBool = AtomicBool::new(false);
+
 impl PerCpu {
     /// Initializes the `PerCpu` structures for each CPU, taking memory from `mem_map`. This (the
     /// boot CPU's) per-CPU area is initialized and loaded into TP as well.
@@ -63,7 +70,8 @@ impl PerCpu {
             let pcpu = PerCpu {
                 cpu_id,
                 vmid_tracker: RefCell::new(VmIdTracker::new()),
-                online: Once::new(),
+                timer_deadline: Cell::new(None),
+                online: AtomicBool::new(false),
             };
             // Safety: ptr is guaranteed to be properly aligned and point to valid memory owned by
             // PerCpu. No other CPUs are alive at this point, so it cannot be concurrently modified
@@ -117,13 +125,35 @@ impl PerCpu {
 
     /// Marks this CPU as online.
     pub fn set_online(&self) {
-        self.online.call_once(|| true);
+        self.online.store(true, Ordering::Release);
+    }
+
+    /// Forgets the deadline the S-mode timer was last programmed with. Used when the timer state
+    /// may have been lost, e.g. across a system suspend.
+    pub fn reset_timer(&self) {
+        self.timer_deadline.set(None);
     }
 
     /// Returns a mutable reference to this CPU's VMID tracker.
     pub fn vmid_tracker_mut(&self) -> RefMut<VmIdTracker> {
         self.vmid_tracker.borrow_mut()
     }
+
+    /// Programs this CPU's timer to raise a supervisor timer interrupt at `deadline`, or disarms
+    /// it if `deadline` is `None`. Any pending timer interrupt is cleared if the deadline changes.
+    pub fn set_timer(&self, deadline: Option<u64>) {
+        if self.timer_deadline.get() == deadline {
+            return;
+        }
+        self.timer_deadline.set(deadline);
+        let stime_value = deadline.unwrap_or(u64::MAX);
+        if CpuInfo::get().has_sstc() {
+            CSR.stimecmp.set(stime_value);
+        } else {
+            // Without Sstc the S-mode timer is owned by firmware.
+            time::set_timer(stime_value).expect("Failed to program timer");
+        }
+    }
 }
 
 /// Halts this CPU until an interrupt (for example, delivered via `kick_cpu()`) is received.
@@ -168,8 +198,48 @@ pub fn start_secondary_cpus() {
             // Safe since TP is set up to point to a valid PerCpu struct in init().
             pcpu.as_ref().unwrap()
         };
-        pcpu.online.wait();
+        while !pcpu.online.load(Ordering::Acquire) {
+            core::hint::spin_loop();
+        }
     }
 
     println!("Brought online {} CPU(s)", cpu_info.num_cpus());
 }
+
+/// Stops all secondary CPUs using the HSM SBI call. Secondary CPUs are expected to poll
+/// `stop_requested()` while idle and call `stop_this_cpu()` in response. Upon return, all other
+/// CPUs are stopped and may be brought back with `start_secondary_cpus()`.
+pub fn stop_secondary_cpus() {
+    let cpu_info = CpuInfo::get();
+    let me = PerCpu::this_cpu().cpu_id();
+    STOP_REQUESTED.store(true, Ordering::Release);
+    for i in 0..cpu_info.num_cpus() {
+        let cpu_id = CpuId::new(i);
+        if cpu_id == me {
+            continue;
+        }
+        send_ipi(cpu_id);
+        let hart_id = cpu_info.cpu_to_hart_id(cpu_id).unwrap() as u64;
+        // Wait until firmware reports that the CPU has actually stopped. TODO: Timeout?
+        while state::hart_status(hart_id).ok() != Some(HartState::Stopped as u64) {
+            core::hint::spin_loop();
+        }
+    }
+    STOP_REQUESTED.store(false, Ordering::Release);
+}
+
+/// Returns true if this CPU has been requested to stop by `stop_secondary_cpus()`.
+pub fn stop_requested() -> bool {
+    STOP_REQUESTED.load(Ordering::Acquire)
+}
+
+/// Marks this CPU as offline and stops it. The CPU is restarted from `_secondary_start`, with a
+/// fresh stack, by `start_secondary_cpus()`, so the caller must not be holding any locks.
+pub fn stop_this_cpu() -> ! {
+    let me = PerCpu::this_cpu();
+    me.online.store(false, Ordering::Release);
+    // Whatever the timer was programmed with won't survive the CPU being stopped.
+    me.reset_timer();
+    state::hart_stop().expect("Failed to stop CPU");
+    unreachable!();
+}
@@ -0,0 +1,95 @@
+// Copyright (c) 2022 by Rivos Inc.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use core::arch::global_asm;
+use core::mem::size_of;
+use drivers::{imsic::Imsic, iommu::Iommu};
+use memoffset::offset_of;
+use riscv_page_tables::tlb;
+use riscv_regs::{Readable, Writeable, CSR};
+use sbi::{Error as SbiError, SuspendSleepType, EXT_SUSP};
+
+use crate::smp::{self, PerCpu};
+
+/// The register state of the CPU that requested a system suspend, as saved by `_suspend_enter`
+/// and restored by `_suspend_resume`.
+#[repr(C)]
+#[derive(Default)]
+struct SuspendContext {
+    ra: u64,
+    sp: u64,
+    gp: u64,
+    tp: u64,
+    s: [u64; 12],
+}
+
+extern "C" {
+    // Saves the callee-saved registers to `ctx` and suspends the system. Returns the SBI error
+    // code if the request failed, or 0 upon resume.
+    fn _suspend_enter(ctx: *mut SuspendContext, sleep_type: u64) -> i64;
+}
+
+const fn s_offset(index: usize) -> usize {
+    offset_of!(SuspendContext, s) + index * size_of::<u64>()
+}
+
+global_asm!(
+    include_str!("suspend.S"),
+    sc_ra = const offset_of!(SuspendContext, ra),
+    sc_sp = const offset_of!(SuspendContext, sp),
+    sc_gp = const offset_of!(SuspendContext, gp),
+    sc_tp = const offset_of!(SuspendContext, tp),
+    sc_s0 = const s_offset(0),
+    sc_s1 = const s_offset(1),
+    sc_s2 = const s_offset(2),
+    sc_s3 = const s_offset(3),
+    sc_s4 = const s_offset(4),
+    sc_s5 = const s_offset(5),
+    sc_s6 = const s_offset(6),
+    sc_s7 = const s_offset(7),
+    sc_s8 = const s_offset(8),
+    sc_s9 = const s_offset(9),
+    sc_s10 = const s_offset(10),
+    sc_s11 = const s_offset(11),
+    sbi_ext_susp = const EXT_SUSP,
+);
+
+/// Suspends the system to `sleep_type`, returning once the system has resumed or if firmware
+/// refused the request. The secondary CPUs are stopped for the duration of the suspend, and the
+/// hypervisor state held in registers, which firmware doesn't preserve, is saved beforehand and
+/// restored afterwards. No vCPUs may be active on any CPU other than this one.
+pub fn suspend_system(sleep_type: SuspendSleepType) -> sbi::Result<()> {
+    smp::stop_secondary_cpus();
+
+    let satp = CSR.satp.get();
+    let henvcfg = CSR.henvcfg.get();
+    let imsic_state = Imsic::get().save_this_cpu();
+    let iommu_state = Iommu::get().map(|iommu| iommu.save_state());
+
+    let mut ctx = SuspendContext::default();
+    // Safety: `_suspend_enter` returns to us with our callee-saved registers intact, either
+    // directly if the suspend failed or via `_suspend_resume` from `ctx`, which lives on our stack
+    // for the duration of the suspend.
+    let ret = unsafe { _suspend_enter(&mut ctx, sleep_type.raw() as u64) };
+
+    // We may have resumed with translation off and the CSRs reset, so restore everything that we
+    // set up at boot.
+    crate::setup_csrs();
+    CSR.satp.set(satp);
+    tlb::sfence_vma(None, None);
+    CSR.henvcfg.set(henvcfg);
+    PerCpu::this_cpu().reset_timer();
+    Imsic::get().restore_this_cpu(&imsic_state);
+    if let Some(iommu) = Iommu::get() && let Some(state) = iommu_state {
+        iommu
+            .restore_state(&state)
+            .expect("Failed to restore IOMMU state");
+    }
+    smp::start_secondary_cpus();
+
+    match ret {
+        0 => Ok(()),
+        e => Err(SbiError::from_code(e)),
+    }
+}
@@ -12,6 +12,8 @@ use riscv_regs::{
 };
 use s_mode_utils::print::*;
 
+use crate::smp::PerCpu;
+
 /// Stores the trap context as pushed onto the stack by the trap handler.
 #[repr(C)]
 struct TrapFrame {
@@ -72,7 +74,8 @@ global_asm!(
 );
 
 /// Attempts to handle an interrupt, returning true if the interrupt was successfully handled.
-fn handle_interrupt(irq: Interrupt) -> bool {
+/// Called both from the trap handler and upon an exit from a vCPU due to an interrupt.
+pub fn handle_interrupt(irq: Interrupt) -> bool {
     match irq {
         Interrupt::SupervisorExternal => {
             let mut handled = false;
@@ -86,7 +89,21 @@ fn handle_interrupt(irq: Interrupt) -> bool {
             }
             handled
         }
-        // TODO: Handle supervisor guest external interrupts.
+        Interrupt::SupervisorTimer => {
+            // Timer deadlines belong to vCPUs and are re-armed the next time a vCPU is run, so all
+            // we need to do here is silence the timer.
+            PerCpu::this_cpu().set_timer(None);
+            true
+        }
+        Interrupt::SupervisorGuestExternal => {
+            // Guest external interrupts are only enabled in HGEIE while waiting for a suspended
+            // vCPU to resume, or while running a child vCPU on behalf of a vCPU with an interrupt
+            // file. Disable them again and leave it to the waiter, or the run loop, to notice.
+            //
+            // TODO: Route guest external interrupts to the host VM.
+            CSR.hgeie.set(0);
+            true
+        }
         _ => false,
     }
 }
@@ -232,6 +249,9 @@ extern "C" fn handle_trap(tf_ptr: *mut TrapFrame) {
 pub fn install_trap_handler() {
     CSR.stvec.set((_trap_entry as usize).try_into().unwrap());
 
-    // We only expect supervisor-level external interrupts for now.
-    CSR.sie.read_and_set_bits(1 << sie::sext.shift);
+    // We expect supervisor-level external interrupts (IPIs) and timer interrupts, the latter used
+    // to multiplex vCPU timers. Guest external interrupts are gated by HGEIE.
+    CSR.sie.read_and_set_bits(
+        (1 << sie::sext.shift) | (1 << sie::stimer.shift) | (1 << sie::sgext.shift),
+    );
 }
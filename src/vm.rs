@@ -9,28 +9,41 @@ use attestation::{
 };
 use core::{mem, ops::ControlFlow, slice};
 use der::Decode;
+use device_tree::DeviceTree;
 use drivers::{
-    imsic::*, iommu::*, pci::PciBarPage, pci::PciDevice, pci::PcieRoot, pmu::PmuInfo, CpuId,
-    CpuInfo, MAX_CPUS,
+    imsic::*, iommu::*, pci::PciBarPage, pci::PciDevice, pci::PcieRoot, pmu::PmuInfo,
+    uart::UartDriver, CpuId, CpuInfo, MAX_CPUS,
+};
+use memoffset::offset_of;
+use page_tracking::{HypPageAlloc, PageList, PageTracker, VmRegionList, VmRegionType};
+use riscv_page_tables::{
+    tlb, GuestStagePageTable, GuestStagePagingMode, PteLeafPerms, Sv39x4, Sv48x4, Sv57x4,
 };
-use page_tracking::{HypPageAlloc, PageList, PageTracker};
-use riscv_page_tables::{GuestStagePageTable, GuestStagePagingMode};
 use riscv_pages::*;
-use riscv_regs::{DecodedInstruction, Exception, GprIndex, Instruction, Trap};
+use riscv_regs::{
+    fence_i, pause, DecodedInstruction, Exception, GprIndex, Instruction, Interrupt, MmioOperation,
+    Trap,
+};
 use s_mode_utils::print::*;
 use sbi::{Error as SbiError, *};
+use spin::Once;
 
-use crate::guest_tracking::{GuestStateGuard, GuestVm, Guests, Result as GuestTrackingResult};
-use crate::smp;
+use crate::guest_tracking::{
+    with_guest_vm, AnyGuestVm, GuestStateGuard, GuestVm, Guests, Result as GuestTrackingResult,
+};
+use crate::smp::{self, PerCpu};
+use crate::suspend;
+use crate::trap;
 use crate::vm_cpu::{
-    ActiveVmCpu, VmCpuSharedArea, VmCpuSharedState, VmCpuSharedStateRef, VmCpuStatus, VmCpuTrap,
-    VmCpus, VM_CPU_BYTES, VM_CPU_SHARED_LAYOUT, VM_CPU_SHARED_PAGES,
+    vm_cpu_shared_layout, ActiveVmCpu, VmCpuFence, VmCpuSharedArea, VmCpuSharedState,
+    VmCpuSharedStateRef, VmCpuStatus, VmCpuTrap, VmCpus, VM_CPU_BYTES, VM_CPU_SHARED_PAGES,
 };
 use crate::vm_pages::Error as VmPagesError;
 use crate::vm_pages::{
     ActiveVmPages, AnyVmPages, InstructionFetchError, PageFaultType, VmPages, VmPagesRef,
-    VmRegionList, TVM_REGION_LIST_PAGES, TVM_STATE_PAGES,
+    TVM_REGION_LIST_PAGES, TVM_STATE_PAGES,
 };
+use crate::vm_pmu::VmPmuState;
 
 #[derive(Debug)]
 pub enum Error {
@@ -47,82 +60,138 @@ pub type Result<T> = core::result::Result<T, Error>;
 // confuses us with BBL/OpenSBI.
 const SBI_IMPL_ID_SALUS: u64 = 7;
 
-/// Possible MMIO instructions.
-#[derive(Clone, Copy, Debug)]
-pub enum MmioOpcode {
-    Load64,
-    Load32,
-    Load32U,
-    Load16,
-    Load16U,
-    Load8,
-    Load8U,
-    Store64,
-    Store32,
-    Store16,
-    Store8,
+// The version of the SBI specification we implement, as reported in sbi_get_spec_version().
+// Encoded as `(major << 24) | minor`. Guests only probe for extensions such as DBCN if we claim
+// v2.0 or later.
+const SBI_SPEC_VERSION: u64 = 2 << 24;
+
+// Parses a decimal component of the package version at compile time.
+const fn parse_version_component(s: &str) -> u32 {
+    let bytes = s.as_bytes();
+    let mut value = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        value = value * 10 + (bytes[i] - b'0') as u32;
+        i += 1;
+    }
+    value
 }
 
-impl MmioOpcode {
-    /// Returns if the MMIO operation is a load.
-    pub fn is_load(&self) -> bool {
-        use MmioOpcode::*;
-        matches!(
-            self,
-            Load8 | Load8U | Load16 | Load16U | Load32 | Load32U | Load64
-        )
+// The version of Salus, as reported in sbi_get_sbi_impl_version() and `TsmInfo::tsm_version`.
+// Encoded from the package version as `(major << 16) | (minor << 8) | patch`.
+const SALUS_VERSION: u32 = (parse_version_component(env!("CARGO_PKG_VERSION_MAJOR")) << 16)
+    | (parse_version_component(env!("CARGO_PKG_VERSION_MINOR")) << 8)
+    | parse_version_component(env!("CARGO_PKG_VERSION_PATCH"));
+
+/// Determines the machine IDs (mvendorid, marchid and mimpid) reported to TVMs by the Base
+/// extension. The host VM is always given the IDs of the underlying machine.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TvmMachineIdPolicy {
+    /// Report the IDs of the underlying machine, allowing TVMs to apply machine-specific errata.
+    #[default]
+    PassThrough,
+    /// Report 0 ("not implemented") for all IDs, hiding the identity of the underlying machine.
+    Hidden,
+}
+
+static TVM_MACHINE_ID_POLICY: Once<TvmMachineIdPolicy> = Once::new();
+
+impl TvmMachineIdPolicy {
+    /// Initializes the global policy from the "salus,tvm-machine-ids" property of the /chosen node
+    /// in `dt`, which may be "passthrough" or "hidden". Uses the default policy if the property is
+    /// absent or invalid.
+    pub fn init_from(dt: &DeviceTree) {
+        let policy = dt
+            .iter()
+            .find(|n| n.name() == "chosen")
+            .and_then(|n| n.props().find(|p| p.name() == "salus,tvm-machine-ids"))
+            .and_then(|p| match p.value_str() {
+                Some("passthrough") => Some(TvmMachineIdPolicy::PassThrough),
+                Some("hidden") => Some(TvmMachineIdPolicy::Hidden),
+                _ => None,
+            })
+            .unwrap_or_default();
+        TVM_MACHINE_ID_POLICY.call_once(|| policy);
+    }
+
+    /// Returns the global policy, or the default policy if `init_from()` hasn't been called.
+    pub fn get() -> Self {
+        TVM_MACHINE_ID_POLICY.get().copied().unwrap_or_default()
     }
 }
 
-/// A decoded MMIO operation.
-#[derive(Clone, Copy, Debug)]
-pub struct MmioOperation {
-    opcode: MmioOpcode,
-    register: GprIndex,
-    len: usize,
+// The maximum number of pages we'll invalidate individually for a ranged SFENCE.VMA request before
+// falling back to invalidating the entire address space.
+const MAX_RANGED_FENCE_PAGES: u64 = 64;
+
+// The maximum number of bytes we'll transfer to or from the debug console at a time on behalf of
+// the host.
+const DBCN_CHUNK_BYTES: usize = 256;
+
+// Returns the `PageSize` for `page_type` if it's a supported size for TVM memory.
+fn guest_page_size(page_type: TsmPageType) -> EcallResult<PageSize> {
+    match page_type {
+        TsmPageType::Page4k => Ok(PageSize::Size4k),
+        TsmPageType::Page2M => Ok(PageSize::Size2M),
+        TsmPageType::Page1G => Ok(PageSize::Size1G),
+        _ => Err(EcallError::Sbi(SbiError::InvalidParam)),
+    }
 }
 
-impl MmioOperation {
-    /// Creates an `MmioOperation` from `instruction` if the MMIO is supported using that instruction.
-    fn from_instruction(instruction: DecodedInstruction) -> Option<Self> {
-        use Instruction::*;
-        let (opcode, reg_index) = match instruction.instruction() {
-            Lb(i) => (MmioOpcode::Load8, i.rd()),
-            Lh(i) => (MmioOpcode::Load16, i.rd()),
-            Lw(i) => (MmioOpcode::Load32, i.rd()),
-            Lbu(i) => (MmioOpcode::Load8U, i.rd()),
-            Lhu(i) => (MmioOpcode::Load16U, i.rd()),
-            Lwu(i) => (MmioOpcode::Load32U, i.rd()),
-            Ld(i) => (MmioOpcode::Load64, i.rd()),
-            Sb(s) => (MmioOpcode::Store8, s.rs2()),
-            Sh(s) => (MmioOpcode::Store16, s.rs2()),
-            Sw(s) => (MmioOpcode::Store32, s.rs2()),
-            Sd(s) => (MmioOpcode::Store64, s.rs2()),
-            _ => {
-                return None;
-            }
-        };
-        Some(Self {
-            opcode,
-            register: GprIndex::from_raw(reg_index).unwrap(),
-            len: instruction.len(),
-        })
+// Returns the `PteLeafPerms` used to map TVM memory with the permissions in `perms`.
+fn guest_page_perms(perms: TvmPagePerms) -> PteLeafPerms {
+    match perms {
+        TvmPagePerms::ReadWriteExecute => PteLeafPerms::RWX,
+        TvmPagePerms::ReadExecute => PteLeafPerms::RX,
+        TvmPagePerms::ReadWrite => PteLeafPerms::RW,
+        TvmPagePerms::ReadOnly => PteLeafPerms::R,
+        TvmPagePerms::ExecuteOnly => PteLeafPerms::X,
     }
+}
 
-    /// Returns the operation as a `MmioOpcode`.
-    pub fn opcode(&self) -> MmioOpcode {
-        self.opcode
+// Returns the `TvmRegionType` reported to a VM's host for regions of type `region_type`.
+fn tvm_region_type(region_type: VmRegionType) -> TvmRegionType {
+    match region_type {
+        VmRegionType::Confidential => TvmRegionType::Confidential,
+        VmRegionType::Shared => TvmRegionType::Shared,
+        VmRegionType::Mmio => TvmRegionType::EmulatedMmio,
+        VmRegionType::Imsic => TvmRegionType::Imsic,
+        VmRegionType::Pci => TvmRegionType::Pci,
     }
+}
 
-    /// Returns the target register for the operation. Either 'rd' for load instructions, or 'rs2' for
-    /// store instructions.
-    pub fn register(&self) -> GprIndex {
-        self.register
+// Returns the `TvmCpuStatus` reported to a VM's host for vCPUs in state `status`.
+fn tvm_cpu_status(status: VmCpuStatus) -> TvmCpuStatus {
+    match status {
+        VmCpuStatus::NotPresent => TvmCpuStatus::NotPresent,
+        VmCpuStatus::PoweredOff => TvmCpuStatus::PoweredOff,
+        VmCpuStatus::Runnable => TvmCpuStatus::Runnable,
+        VmCpuStatus::Running => TvmCpuStatus::Running,
+        VmCpuStatus::Suspended => TvmCpuStatus::Suspended,
+        VmCpuStatus::ResumePending => TvmCpuStatus::ResumePending,
     }
+}
 
-    /// Returns the length of the raw instruction.
-    pub fn len(&self) -> usize {
-        self.len
+// Invalidates the VS-stage translations of the currently-active vCPU for the `size` bytes at
+// `start_addr`, optionally limited to `asid`. Follows the SBI RFENCE convention of `size` being -1,
+// or both `start_addr` and `size` being 0, to request a fence of the entire address space.
+fn hfence_vvma_range(start_addr: u64, size: u64, asid: Option<u64>) {
+    let page_size = PageSize::Size4k as u64;
+    if (start_addr == 0 && size == 0)
+        || size == u64::MAX
+        || size > MAX_RANGED_FENCE_PAGES * page_size
+    {
+        tlb::hfence_vvma(None, asid);
+        return;
+    }
+    let end = start_addr.saturating_add(size);
+    let mut addr = start_addr & !(page_size - 1);
+    while addr < end {
+        tlb::hfence_vvma(Some(addr), asid);
+        match addr.checked_add(page_size) {
+            Some(next) => addr = next,
+            None => break,
+        }
     }
 }
 
@@ -131,9 +200,14 @@ impl MmioOperation {
 pub enum VmExitCause {
     FatalEcall(SbiMessage),
     ResumableEcall(SbiMessage),
-    PageFault(Exception, GuestPageAddr),
+    PageFault(PageFaultType, Exception, GuestPageAddr),
     MmioFault(MmioOperation, GuestPhysAddr),
     Wfi(DecodedInstruction),
+    Interrupted(Interrupt),
+    Preempted(Interrupt),
+    DebugConsole(DebugConsoleFunction),
+    SystemReset(ResetType, ResetReason),
+    SystemSuspend(SystemSuspendFunction),
     UnhandledTrap(u64),
 }
 
@@ -141,7 +215,7 @@ impl VmExitCause {
     /// Returns if the exit cause is fatal.
     pub fn is_fatal(&self) -> bool {
         use VmExitCause::*;
-        matches!(self, FatalEcall(_) | UnhandledTrap(_))
+        matches!(self, FatalEcall(_) | SystemReset(..) | UnhandledTrap(_))
     }
 }
 
@@ -204,9 +278,11 @@ impl From<EcallResult<u64>> for EcallAction {
                     // Unhandleable page faults or page faults in MMIO space just result in an
                     // error to the caller.
                     Unmapped | Mmio => Continue(SbiReturn::from(SbiError::InvalidAddress)),
+                    // As do accesses to memory that the caller isn't permitted to make.
+                    Permission => Continue(SbiReturn::from(SbiError::Denied)),
                     Confidential | Shared => {
                         let addr = PageAddr::with_round_down(addr, PageSize::Size4k);
-                        Retry(VmExitCause::PageFault(e, addr))
+                        Retry(VmExitCause::PageFault(pf, e, addr))
                     }
                 }
             }
@@ -220,7 +296,7 @@ type AttestationSha384 = AttestationManager<sha2::Sha384>;
 pub struct Vm<T: GuestStagePagingMode> {
     vcpus: VmCpus,
     vm_pages: VmPages<T>,
-    guests: Option<Guests<T>>,
+    guests: Option<Guests>,
     attestation_mgr: AttestationSha384,
 }
 
@@ -248,7 +324,7 @@ impl<T: GuestStagePagingMode> Vm<T> {
     pub fn with_guest_tracking(
         vm_pages: VmPages<T>,
         vcpus: VmCpus,
-        guests: Guests<T>,
+        guests: Guests,
     ) -> Result<Self> {
         let mut this = Self::new(vm_pages, vcpus)?;
         this.guests = Some(guests);
@@ -260,6 +336,11 @@ impl<T: GuestStagePagingMode> Vm<T> {
         self.vm_pages.page_owner_id()
     }
 
+    /// Returns true if any of this VM's vCPUs is currently running.
+    pub fn has_running_vcpus(&self) -> bool {
+        self.vcpus.any_running()
+    }
+
     /// Returns the `PageTracker` singleton.
     pub fn page_tracker(&self) -> PageTracker {
         self.vm_pages.page_tracker()
@@ -463,6 +544,8 @@ impl<'a, T: GuestStagePagingMode> FinalizedVm<'a, T> {
         let status = match vcpu_status {
             VmCpuStatus::Runnable | VmCpuStatus::Running => HartState::Started,
             VmCpuStatus::PoweredOff => HartState::Stopped,
+            VmCpuStatus::Suspended => HartState::Suspended,
+            VmCpuStatus::ResumePending => HartState::ResumePending,
             VmCpuStatus::NotPresent => {
                 return Err(EcallError::Sbi(SbiError::InvalidParam));
             }
@@ -470,6 +553,95 @@ impl<'a, T: GuestStagePagingMode> FinalizedVm<'a, T> {
         Ok(status as u64)
     }
 
+    /// Waits on this physical CPU for the specified vCPU to resume if it is suspended.
+    fn wait_for_vcpu_resume(&self, vcpu_id: u64) -> EcallResult<()> {
+        self.vm()
+            .vcpus
+            .wait_for_resume(vcpu_id)
+            .map_err(|_| EcallError::Sbi(SbiError::InvalidParam))
+    }
+
+    /// Returns an iterator over the IDs of the vCPUs selected by `hart_mask`. Returns an error if
+    /// any of the harts explicitly selected by `hart_mask` don't refer to a vCPU in this VM.
+    fn hart_mask_to_vcpus(
+        &self,
+        hart_mask: HartMask,
+    ) -> EcallResult<impl Iterator<Item = u64> + '_> {
+        let vcpus = &self.vm().vcpus;
+        let num_vcpus = vcpus.num_vcpus() as u64;
+        let is_present = move |vcpu_id| {
+            matches!(vcpus.get_vcpu_status(vcpu_id), Ok(s) if s != VmCpuStatus::NotPresent)
+        };
+        if let Some(count) = hart_mask.count() {
+            let present = hart_mask.iter(num_vcpus).filter(|&id| is_present(id)).count();
+            if present as u64 != count {
+                return Err(EcallError::Sbi(SbiError::InvalidParam));
+            }
+        }
+        Ok(hart_mask.iter(num_vcpus).filter(move |&id| is_present(id)))
+    }
+
+    /// Posts the virtual interrupt `irq` to the vCPU with `vcpu_id`, kicking it if it's running, or
+    /// waiting to resume, on another physical CPU so that it picks up the interrupt.
+    fn post_interrupt(&self, vcpu_id: u64, irq: Interrupt) -> EcallResult<()> {
+        let running_cpu = self
+            .vm()
+            .vcpus
+            .post_interrupt(vcpu_id, irq)
+            .map_err(|_| EcallError::Sbi(SbiError::InvalidParam))?;
+        if let Some(cpu) = running_cpu && cpu != PerCpu::this_cpu().cpu_id() {
+            smp::send_ipi(cpu);
+        }
+        Ok(())
+    }
+
+    /// Posts a virtual supervisor software interrupt to each of the vCPUs in `hart_mask`, kicking
+    /// any that are running, or waiting to resume, on another physical CPU so that they pick up the
+    /// interrupt.
+    fn send_ipis(&self, hart_mask: HartMask) -> EcallResult<u64> {
+        for vcpu_id in self.hart_mask_to_vcpus(hart_mask)? {
+            self.post_interrupt(vcpu_id, Interrupt::VirtualSupervisorSoft)?;
+        }
+        Ok(0)
+    }
+
+    /// Requests `fence` of each of the vCPUs other than `active_vcpu` in `hart_mask`, waiting for
+    /// any that are active on other physical CPUs to complete the fence.
+    fn send_remote_fences(
+        &self,
+        hart_mask: HartMask,
+        fence: VmCpuFence,
+        active_vcpu: &mut ActiveVmCpu<T>,
+    ) -> EcallResult<()> {
+        let vcpus = &self.vm().vcpus;
+        let this_vcpu = active_vcpu.vcpu_id();
+        let this_cpu = PerCpu::this_cpu().cpu_id();
+        for vcpu_id in self
+            .hart_mask_to_vcpus(hart_mask)?
+            .filter(|&id| id != this_vcpu)
+        {
+            let active_cpu = vcpus
+                .post_fence(vcpu_id, fence)
+                .map_err(|_| EcallError::Sbi(SbiError::InvalidParam))?;
+            if let Some(cpu) = active_cpu && cpu != this_cpu {
+                smp::send_ipi(cpu);
+            }
+        }
+
+        for vcpu_id in self
+            .hart_mask_to_vcpus(hart_mask)?
+            .filter(|&id| id != this_vcpu)
+        {
+            // Complete any fences requested of us in the meantime so that we don't deadlock with
+            // another vCPU that's waiting on us.
+            while !vcpus.fence_completed(vcpu_id) {
+                active_vcpu.complete_pending_fences();
+                pause();
+            }
+        }
+        Ok(())
+    }
+
     fn process_decoded_instruction(
         active_vcpu: &mut ActiveVmCpu<T>,
         inst: DecodedInstruction,
@@ -502,14 +674,21 @@ impl<'a, T: GuestStagePagingMode> FinalizedVm<'a, T> {
         }
     }
 
-    /// Run this guest until an unhandled exit is encountered.
-    fn run_vcpu(&self, vcpu_id: u64, parent_vcpu: Option<&mut ActiveVmCpu<T>>) -> EcallResult<u64> {
+    /// Run this guest until an unhandled exit is encountered, or until it has run for `time_slice`
+    /// ticks of the host's time base if `time_slice` is non-zero.
+    fn run_vcpu<P: GuestStagePagingMode>(
+        &self,
+        vcpu_id: u64,
+        time_slice: u64,
+        parent_vcpu: Option<&mut ActiveVmCpu<P>>,
+    ) -> EcallResult<u64> {
         // Take the vCPU out of self.vcpus, giving us exclusive ownership.
         let mut active_vcpu = self
             .vm()
             .vcpus
             .activate_vcpu(vcpu_id, self.vm_pages(), parent_vcpu)
             .map_err(|_| EcallError::Sbi(SbiError::InvalidParam))?;
+        active_vcpu.set_time_slice(time_slice);
         // Run until there's an exit we can't handle.
         let cause = loop {
             let exit = active_vcpu.run();
@@ -552,8 +731,19 @@ impl<'a, T: GuestStagePagingMode> FinalizedVm<'a, T> {
                         .get_page_fault_cause(exception, fault_addr);
                     use PageFaultType::*;
                     match pf {
-                        Confidential | Shared => {
+                        Confidential
+                            if active_vcpu
+                                .active_pages()
+                                .map_demand_zero_page(fault_addr)
+                                .is_ok() =>
+                        {
+                            // The page was populated from the guest's pool of zero pages; let the
+                            // guest retry the access.
+                            continue;
+                        }
+                        Confidential | Shared | Permission => {
                             break VmExitCause::PageFault(
+                                pf,
                                 exception,
                                 PageAddr::with_round_down(fault_addr, PageSize::Size4k),
                             );
@@ -632,6 +822,30 @@ impl<'a, T: GuestStagePagingMode> FinalizedVm<'a, T> {
                 VmCpuTrap::DelegatedException { exception, stval } => {
                     active_vcpu.inject_exception(exception, stval);
                 }
+                VmCpuTrap::HypervisorInterrupt(Interrupt::SupervisorTimer) => {
+                    // The timer is shared with the vCPUs we were activated from. If one of their
+                    // timers expired, return to our host so that it can take the interrupt.
+                    if active_vcpu.handle_timer_interrupt() {
+                        break VmExitCause::Interrupted(Interrupt::SupervisorTimer);
+                    }
+                    if active_vcpu.time_slice_expired() {
+                        break VmExitCause::Preempted(Interrupt::SupervisorTimer);
+                    }
+                }
+                VmCpuTrap::HypervisorInterrupt(irq) => {
+                    // Most likely an IPI sent to kick us out of the guest in order to pick up a
+                    // newly-posted virtual interrupt, which happens the next time we enter the
+                    // guest.
+                    if !trap::handle_interrupt(irq) {
+                        println!("Unhandled hypervisor interrupt {:?}", irq);
+                        break VmExitCause::UnhandledTrap(Trap::Interrupt(irq).to_scause());
+                    }
+                    // The interrupt may instead have been meant for one of the vCPUs we were
+                    // activated from, in which case we return to our host so that it can take it.
+                    if active_vcpu.parent_interrupt_pending() {
+                        break VmExitCause::Preempted(irq);
+                    }
+                }
                 VmCpuTrap::Other(ref trap_csrs) => {
                     println!("Unhandled guest exit, SCAUSE = 0x{:08x}", trap_csrs.scause);
                     break VmExitCause::UnhandledTrap(trap_csrs.scause);
@@ -646,26 +860,305 @@ impl<'a, T: GuestStagePagingMode> FinalizedVm<'a, T> {
 
     /// Handles ecalls from the guest.
     fn handle_ecall(&self, msg: SbiMessage, active_vcpu: &mut ActiveVmCpu<T>) -> EcallAction {
+        active_vcpu
+            .pmu()
+            .record_firmware_event(PmuPlatformFirmware::SbiCall);
         match msg {
             SbiMessage::PutChar(c) => {
                 // put char - legacy command
                 print!("{}", c as u8 as char);
                 EcallAction::LegacyOk
             }
-            SbiMessage::Reset(ResetFunction::Reset { .. }) => {
-                EcallAction::Break(VmExitCause::FatalEcall(msg), SbiReturn::success(0))
-            }
+            SbiMessage::Reset(ResetFunction::Reset { reset_type, reason }) => EcallAction::Break(
+                VmExitCause::SystemReset(reset_type, reason),
+                SbiReturn::success(0),
+            ),
             SbiMessage::Base(base_func) => EcallAction::Continue(self.handle_base_msg(base_func)),
-            SbiMessage::HartState(hsm_func) => self.handle_hart_state_msg(hsm_func),
+            SbiMessage::HartState(hsm_func) => self.handle_hart_state_msg(hsm_func, active_vcpu),
             SbiMessage::TeeHost(host_func) => self.handle_tee_host_msg(host_func, active_vcpu),
             SbiMessage::TeeInterrupt(interrupt_func) => {
                 self.handle_tee_interrupt_msg(interrupt_func, active_vcpu.active_pages())
             }
-            SbiMessage::TeeGuest(guest_func) => self.handle_tee_guest_msg(guest_func),
+            SbiMessage::TeeGuest(guest_func) => {
+                self.handle_tee_guest_msg(guest_func, active_vcpu)
+            }
             SbiMessage::Attestation(attestation_func) => {
                 self.handle_attestation_msg(attestation_func, active_vcpu.active_pages())
             }
             SbiMessage::Pmu(pmu_func) => self.handle_pmu_msg(pmu_func, active_vcpu).into(),
+            SbiMessage::DebugConsole(dbcn_func) => {
+                self.handle_debug_console_msg(dbcn_func, active_vcpu.active_pages())
+            }
+            SbiMessage::Ipi(ipi_func) => self.handle_ipi_msg(ipi_func, active_vcpu).into(),
+            SbiMessage::Rfence(rfence_func) => {
+                self.handle_rfence_msg(rfence_func, active_vcpu).into()
+            }
+            SbiMessage::Time(time_func) => self.handle_time_msg(time_func, active_vcpu).into(),
+            SbiMessage::StealTime(sta_func) => {
+                self.handle_steal_time_msg(sta_func, active_vcpu).into()
+            }
+            SbiMessage::SystemSuspend(susp_func) => {
+                self.handle_system_suspend_msg(susp_func, active_vcpu.vcpu_id())
+            }
+        }
+    }
+
+    fn handle_system_suspend_msg(
+        &self,
+        susp_func: SystemSuspendFunction,
+        vcpu_id: u64,
+    ) -> EcallAction {
+        use SystemSuspendFunction::*;
+        match susp_func {
+            Suspend { sleep_type, .. } => {
+                // TVMs don't get to suspend the system out from under the host.
+                if !self.page_owner_id().is_host() {
+                    return EcallAction::Continue(SbiError::NotSupported.into());
+                }
+                if let Err(e) = SuspendSleepType::from_raw(sleep_type) {
+                    return EcallAction::Continue(e.into());
+                }
+                if !self.can_suspend_system(vcpu_id) {
+                    return EcallAction::Continue(SbiError::Denied.into());
+                }
+                // The suspend itself is carried out once this vCPU has exited. The ECALL is left
+                // pending until the vCPU is next run, when it either resumes at `resume_addr` or
+                // returns the error that prevented the suspend.
+                EcallAction::Retry(VmExitCause::SystemSuspend(susp_func))
+            }
+        }
+    }
+
+    /// Returns true if the system may be suspended at the request of `vcpu_id`, which requires
+    /// that all other vCPUs are stopped and that no TVM vCPUs are running.
+    fn can_suspend_system(&self, vcpu_id: u64) -> bool {
+        let vcpus = &self.vm().vcpus;
+        let others_stopped = (0..vcpus.num_vcpus() as u64)
+            .filter(|&id| id != vcpu_id)
+            .all(|id| {
+                matches!(
+                    vcpus.get_vcpu_status(id),
+                    Ok(VmCpuStatus::PoweredOff | VmCpuStatus::NotPresent)
+                )
+            });
+        others_stopped && !self.guests().map_or(false, |g| g.any_vcpu_running())
+    }
+
+    fn handle_time_msg(
+        &self,
+        time_func: TimeFunction,
+        active_vcpu: &mut ActiveVmCpu<T>,
+    ) -> EcallResult<u64> {
+        use TimeFunction::*;
+        match time_func {
+            SetTimer { stime_value } => {
+                active_vcpu
+                    .pmu()
+                    .record_firmware_event(PmuFirmware::SetTimer);
+                active_vcpu.set_timer(stime_value);
+                Ok(0)
+            }
+        }
+    }
+
+    fn handle_steal_time_msg(
+        &self,
+        sta_func: StealTimeFunction,
+        active_vcpu: &mut ActiveVmCpu<T>,
+    ) -> EcallResult<u64> {
+        use StealTimeFunction::*;
+        match sta_func {
+            SetShmem { shmem_addr, flags } => {
+                if flags != 0 {
+                    return Err(EcallError::Sbi(SbiError::InvalidParam));
+                }
+                let addr = match shmem_addr {
+                    Some(addr) => addr,
+                    None => {
+                        active_vcpu.set_steal_time_addr(None);
+                        return Ok(0);
+                    }
+                };
+                if addr % STEAL_TIME_RECORD_ALIGN != 0 {
+                    return Err(EcallError::Sbi(SbiError::InvalidParam));
+                }
+                let addr = RawAddr::guest(addr, self.page_owner_id());
+                let record = StealTimeRecord::default();
+                let len = mem::size_of::<StealTimeRecord>();
+                // Steal-time reflects how the untrusted host schedules the TVM's vCPUs, so require
+                // TVMs to keep the record in shared memory rather than among confidential state.
+                if !self.page_owner_id().is_host()
+                    && !active_vcpu.active_pages().is_shared_range(addr, len as u64)
+                {
+                    return Err(EcallError::Sbi(SbiError::InvalidAddress));
+                }
+                // Safety: &record points to len bytes of initialized memory.
+                let record_bytes: &[u8] = unsafe {
+                    slice::from_raw_parts((&record as *const StealTimeRecord).cast(), len)
+                };
+                active_vcpu
+                    .active_pages()
+                    .copy_to_guest(addr, record_bytes)?;
+                active_vcpu.set_steal_time_addr(Some(addr));
+                Ok(0)
+            }
+        }
+    }
+
+    fn handle_debug_console_msg(
+        &self,
+        dbcn_func: DebugConsoleFunction,
+        active_pages: &ActiveVmPages<T>,
+    ) -> EcallAction {
+        use DebugConsoleFunction::*;
+        if !self.page_owner_id().is_host() {
+            // The memory of a TVM is confidential, so forward the request to the host, which can
+            // only service it if the buffer lies in shared memory. The host completes the ECALL
+            // by writing the result to A0/A1 in the shared-memory state area.
+            let buffer_ok = match dbcn_func {
+                ConsoleWrite {
+                    num_bytes,
+                    base_addr,
+                }
+                | ConsoleRead {
+                    num_bytes,
+                    base_addr,
+                } => active_pages.is_shared_range(
+                    RawAddr::guest(base_addr, self.page_owner_id()),
+                    num_bytes,
+                ),
+                ConsoleWriteByte { .. } => true,
+            };
+            if !buffer_ok {
+                return EcallAction::Continue(SbiReturn::from(SbiError::InvalidParam));
+            }
+            return EcallAction::Retry(VmExitCause::DebugConsole(dbcn_func));
+        }
+
+        match dbcn_func {
+            ConsoleWrite {
+                num_bytes,
+                base_addr,
+            } => self
+                .debug_console_write(num_bytes, base_addr, active_pages)
+                .into(),
+            ConsoleRead {
+                num_bytes,
+                base_addr,
+            } => self
+                .debug_console_read(num_bytes, base_addr, active_pages)
+                .into(),
+            ConsoleWriteByte { byte } => {
+                Console::write_bytes(&[byte]);
+                EcallAction::Continue(SbiReturn::success(0))
+            }
+        }
+    }
+
+    fn debug_console_write(
+        &self,
+        num_bytes: u64,
+        base_addr: u64,
+        active_pages: &ActiveVmPages<T>,
+    ) -> EcallResult<u64> {
+        let mut buf = [0u8; DBCN_CHUNK_BYTES];
+        let mut written = 0;
+        while written < num_bytes {
+            let len = core::cmp::min(num_bytes - written, DBCN_CHUNK_BYTES as u64) as usize;
+            let gpa = RawAddr::guest(base_addr, self.page_owner_id())
+                .checked_increment(written)
+                .ok_or(EcallError::Sbi(SbiError::InvalidParam))?;
+            if let Err(e) = active_pages.copy_from_guest(&mut buf[..len], gpa) {
+                // Report a partial write if we managed to get anything out.
+                if written != 0 {
+                    break;
+                }
+                return Err(e.into());
+            }
+            Console::write_bytes(&buf[..len]);
+            written += len as u64;
+        }
+        Ok(written)
+    }
+
+    fn debug_console_read(
+        &self,
+        num_bytes: u64,
+        base_addr: u64,
+        active_pages: &ActiveVmPages<T>,
+    ) -> EcallResult<u64> {
+        let mut buf = [0u8; DBCN_CHUNK_BYTES];
+        let len = core::cmp::min(num_bytes, DBCN_CHUNK_BYTES as u64) as usize;
+        let count = UartDriver::get().map_or(0, |uart| uart.read_bytes(&mut buf[..len]));
+        if count != 0 {
+            let gpa = RawAddr::guest(base_addr, self.page_owner_id());
+            active_pages
+                .copy_to_guest(gpa, &buf[..count])
+                .map_err(EcallError::from)?;
+        }
+        Ok(count as u64)
+    }
+
+    fn handle_rfence_msg(
+        &self,
+        rfence_func: RfenceFunction,
+        active_vcpu: &mut ActiveVmCpu<T>,
+    ) -> EcallResult<u64> {
+        use RfenceFunction::*;
+        // Remote vCPUs just invalidate their entire address space rather than the requested range.
+        let fence = match rfence_func {
+            RemoteFenceI { .. } => VmCpuFence::Instruction,
+            RemoteSFenceVma { .. } | RemoteSFenceVmaAsid { .. } => VmCpuFence::AddressTranslation,
+            // We don't virtualize the hypervisor extension, so there are no G-stage or nested
+            // VS-stage translations for the VM to fence.
+            RemoteHFenceGvmaVmid { .. }
+            | RemoteHFenceGvma { .. }
+            | RemoteHFenceVvmaAsid { .. }
+            | RemoteHFenceVvma { .. } => {
+                return Err(EcallError::Sbi(SbiError::NotSupported));
+            }
+        };
+        let hart_mask = rfence_func.hart_mask();
+        self.send_remote_fences(hart_mask, fence, active_vcpu)?;
+        let event = match rfence_func {
+            RemoteFenceI { .. } => PmuFirmware::FenceISent,
+            RemoteSFenceVmaAsid { .. } => PmuFirmware::SfenceAsidSent,
+            _ => PmuFirmware::SfenceVmaSent,
+        };
+        active_vcpu.pmu().record_firmware_event(event);
+
+        if hart_mask.contains(active_vcpu.vcpu_id()) {
+            match rfence_func {
+                RemoteFenceI { .. } => fence_i(),
+                RemoteSFenceVma {
+                    start_addr, size, ..
+                } => hfence_vvma_range(start_addr, size, None),
+                RemoteSFenceVmaAsid {
+                    start_addr,
+                    size,
+                    asid,
+                    ..
+                } => hfence_vvma_range(start_addr, size, Some(asid)),
+                _ => (),
+            }
+        }
+        Ok(0)
+    }
+
+    fn handle_ipi_msg(
+        &self,
+        ipi_func: IpiFunction,
+        active_vcpu: &mut ActiveVmCpu<T>,
+    ) -> EcallResult<u64> {
+        use IpiFunction::*;
+        match ipi_func {
+            SendIpi { hart_mask } => {
+                self.send_ipis(hart_mask)?;
+                active_vcpu
+                    .pmu()
+                    .record_firmware_event(PmuFirmware::IpiSent);
+                Ok(0)
+            }
         }
     }
 
@@ -675,18 +1168,42 @@ impl<'a, T: GuestStagePagingMode> FinalizedVm<'a, T> {
         active_vcpu: &mut ActiveVmCpu<T>,
     ) -> EcallResult<u64> {
         use PmuFunction::*;
-        fn get_num_counters() -> EcallResult<u64> {
-            let pmu_info = PmuInfo::get()?;
-            Ok(pmu_info.get_num_counters())
+        fn get_counter_info(counter_index: u64) -> EcallResult<u64> {
+            if counter_index < VmPmuState::num_hardware_counters() {
+                let pmu_info = PmuInfo::get()?;
+                let info = pmu_info.get_counter_info(counter_index)?;
+                Ok(info.raw())
+            } else {
+                VmPmuState::firmware_counter_index(counter_index)?;
+                Ok(PmuCounterInfo::new_firmware().raw())
+            }
         }
 
-        fn get_counter_info(counter_index: u64) -> EcallResult<u64> {
-            let pmu_info = PmuInfo::get()?;
-            let info = pmu_info.get_counter_info(counter_index)?;
-            Ok(info.raw())
+        // Returns the guest physical address of the value of `counter_index` in the snapshot
+        // shared memory area.
+        fn snapshot_value_addr<T: GuestStagePagingMode>(
+            counter_index: u64,
+            active_vcpu: &mut ActiveVmCpu<T>,
+        ) -> EcallResult<GuestPhysAddr> {
+            let base = active_vcpu
+                .pmu()
+                .snapshot_addr()
+                .ok_or(EcallError::Sbi(SbiError::InvalidParam))?;
+            let offset = offset_of!(PmuSnapshot, counter_values) as u64
+                + counter_index * mem::size_of::<u64>() as u64;
+            base.checked_increment(offset)
+                .ok_or(EcallError::Sbi(SbiError::InvalidAddress))
         }
 
-        fn start_counters<T: GuestStagePagingMode>(
+        // Returns an iterator over the (absolute) counter indexes selected by `counter_index` and
+        // `counter_mask`.
+        fn counter_indexes(counter_index: u64, counter_mask: u64) -> impl Iterator<Item = u64> {
+            (0..u64::BITS as u64)
+                .filter(move |i| counter_mask & (1 << i) != 0)
+                .map(move |i| counter_index + i)
+        }
+
+        fn start_hw_counters<T: GuestStagePagingMode>(
             counter_index: u64,
             counter_mask: u64,
             start_flags: PmuCounterStartFlags,
@@ -712,7 +1229,67 @@ impl<'a, T: GuestStagePagingMode> FinalizedVm<'a, T> {
             result.map(|_| 0).map_err(EcallError::from)
         }
 
-        fn stop_counters<T: GuestStagePagingMode>(
+        fn start_counters<T: GuestStagePagingMode>(
+            counter_index: u64,
+            counter_mask: u64,
+            start_flags: PmuCounterStartFlags,
+            initial_value: u64,
+            active_vcpu: &mut ActiveVmCpu<T>,
+        ) -> EcallResult<u64> {
+            let (hw_mask, fw_mask) = VmPmuState::split_counter_range(counter_index, counter_mask)?;
+            // We don't share a snapshot area with the platform firmware, so read the initial
+            // values ourselves before starting anything.
+            let init_snapshot = start_flags.is_init_snapshot();
+            let mut snapshot_values = [0; PMU_SNAPSHOT_MAX_COUNTERS];
+            if init_snapshot {
+                for i in counter_indexes(counter_index, counter_mask) {
+                    let mut bytes = [0u8; mem::size_of::<u64>()];
+                    let addr = snapshot_value_addr(i, active_vcpu)?;
+                    active_vcpu
+                        .active_pages()
+                        .copy_from_guest(&mut bytes, addr)?;
+                    snapshot_values[i as usize] = u64::from_le_bytes(bytes);
+                }
+            }
+            let start_flags = start_flags.unset_init_snapshot();
+
+            if hw_mask != 0 {
+                if init_snapshot {
+                    // Each counter may have a different initial value, so start them one at a time.
+                    let pmu_info = PmuInfo::get()?;
+                    let hw_mask = pmu_info.filter_counter_mask(counter_index, hw_mask)?;
+                    for i in counter_indexes(counter_index, hw_mask) {
+                        start_hw_counters(
+                            i,
+                            0x1,
+                            start_flags.set_init_value(),
+                            snapshot_values[i as usize],
+                            active_vcpu,
+                        )?;
+                    }
+                } else {
+                    start_hw_counters(
+                        counter_index,
+                        hw_mask,
+                        start_flags,
+                        initial_value,
+                        active_vcpu,
+                    )?;
+                }
+            }
+            if fw_mask != 0 {
+                active_vcpu.pmu().start_firmware_counters(fw_mask, |i| {
+                    if init_snapshot {
+                        Some(snapshot_values[i as usize])
+                    } else {
+                        start_flags.is_init_value().then_some(initial_value)
+                    }
+                })?;
+            }
+            Ok(0)
+        }
+
+        fn stop_hw_counters<T: GuestStagePagingMode>(
             counter_index: u64,
             counter_mask: u64,
             stop_flags: PmuCounterStopFlags,
@@ -733,6 +1310,38 @@ impl<'a, T: GuestStagePagingMode> FinalizedVm<'a, T> {
             result.map(|_| 0).map_err(EcallError::from)
         }
 
+        fn stop_counters<T: GuestStagePagingMode>(
+            counter_index: u64,
+            counter_mask: u64,
+            stop_flags: PmuCounterStopFlags,
+            active_vcpu: &mut ActiveVmCpu<T>,
+        ) -> EcallResult<u64> {
+            let (hw_mask, fw_mask) = VmPmuState::split_counter_range(counter_index, counter_mask)?;
+            // Write the snapshot before stopping anything so that a fault on the snapshot area
+            // can be resolved and the ECALL retried. The counters keep running in the meantime,
+            // but only for as long as it takes to write out their values.
+            if stop_flags.is_take_snapshot() {
+                for i in counter_indexes(counter_index, counter_mask) {
+                    let value = active_vcpu.pmu().snapshot_counter_value(i);
+                    let addr = snapshot_value_addr(i, active_vcpu)?;
+                    active_vcpu
+                        .active_pages()
+                        .copy_to_guest(addr, &value.to_le_bytes())?;
+                }
+            }
+            let stop_flags = stop_flags.unset_take_snapshot();
+
+            if hw_mask != 0 {
+                stop_hw_counters(counter_index, hw_mask, stop_flags, active_vcpu)?;
+            }
+            if fw_mask != 0 {
+                active_vcpu
+                    .pmu()
+                    .stop_firmware_counters(fw_mask, stop_flags)?;
+            }
+            Ok(0)
+        }
+
         fn configure_counters<T: GuestStagePagingMode>(
             counter_index: u64,
             counter_mask: u64,
@@ -741,10 +1350,24 @@ impl<'a, T: GuestStagePagingMode> FinalizedVm<'a, T> {
             event_data: u64,
             active_vcpu: &mut ActiveVmCpu<T>,
         ) -> EcallResult<u64> {
+            let (hw_mask, fw_mask) = VmPmuState::split_counter_range(counter_index, counter_mask)?;
+            if let PmuEventType::Firmware(event) = event_type {
+                let counter_index = active_vcpu.pmu().configure_firmware_counter(
+                    fw_mask,
+                    config_flags,
+                    event,
+                    event_data,
+                )?;
+                return Ok(counter_index);
+            }
+            if hw_mask == 0 {
+                return Err(EcallError::Sbi(SbiError::NotSupported));
+            }
+
             let config_flags = config_flags.set_sinh().set_minh();
             let counter_mask = active_vcpu.pmu().get_configurable_counter_range(
                 counter_index,
-                counter_mask,
+                hw_mask,
                 config_flags,
             )?;
             let platform_counter_index = sbi::api::pmu::configure_matching_counters(
@@ -763,8 +1386,42 @@ impl<'a, T: GuestStagePagingMode> FinalizedVm<'a, T> {
             Ok(platform_counter_index)
         }
 
+        fn set_snapshot_shmem<T: GuestStagePagingMode>(
+            shmem_addr: Option<u64>,
+            flags: u64,
+            owner: PageOwnerId,
+            active_vcpu: &mut ActiveVmCpu<T>,
+        ) -> EcallResult<u64> {
+            if flags != 0 {
+                return Err(EcallError::Sbi(SbiError::InvalidParam));
+            }
+            let addr = match shmem_addr {
+                Some(addr) => addr,
+                None => {
+                    active_vcpu.pmu().set_snapshot_addr(None);
+                    return Ok(0);
+                }
+            };
+            if addr % (PMU_SNAPSHOT_SIZE as u64) != 0 {
+                return Err(EcallError::Sbi(SbiError::InvalidParam));
+            }
+            // Clear the area, which also verifies that it's writable by this vCPU.
+            let zeros = [0u8; 512];
+            let base = RawAddr::guest(addr, owner);
+            for offset in (0..PMU_SNAPSHOT_SIZE).step_by(zeros.len()) {
+                let chunk_addr = base
+                    .checked_increment(offset as u64)
+                    .ok_or(EcallError::Sbi(SbiError::InvalidAddress))?;
+                active_vcpu
+                    .active_pages()
+                    .copy_to_guest(chunk_addr, &zeros)?;
+            }
+            active_vcpu.pmu().set_snapshot_addr(Some(base));
+            Ok(0)
+        }
+
         match pmu_func {
-            GetNumCounters => get_num_counters(),
+            GetNumCounters => Ok(VmPmuState::num_counters()),
             GetCounterInfo(counter_index) => get_counter_info(counter_index),
             StartCounters {
                 counter_index,
@@ -797,36 +1454,64 @@ impl<'a, T: GuestStagePagingMode> FinalizedVm<'a, T> {
                 event_data,
                 active_vcpu,
             ),
-            ReadFirmwareCounter(_) => Err(EcallError::Sbi(SbiError::NotSupported)),
+            ReadFirmwareCounter(counter_index) => active_vcpu
+                .pmu()
+                .read_firmware_counter(counter_index)
+                .map_err(EcallError::from),
+            ReadFirmwareCounterHi(counter_index) => {
+                // Counters are 64 bits wide, so there's nothing in the upper half on RV64.
+                VmPmuState::firmware_counter_index(counter_index)?;
+                Ok(0)
+            }
+            SnapshotSetShmem { shmem_addr, flags } => {
+                set_snapshot_shmem(shmem_addr, flags, self.page_owner_id(), active_vcpu)
+            }
         }
     }
 
     fn handle_base_msg(&self, base_func: BaseFunction) -> SbiReturn {
         use BaseFunction::*;
         let ret = match base_func {
-            GetSpecificationVersion => 3,
+            GetSpecificationVersion => SBI_SPEC_VERSION,
             GetImplementationID => SBI_IMPL_ID_SALUS,
-            GetImplementationVersion => 0,
+            GetImplementationVersion => SALUS_VERSION as u64,
             ProbeSbiExtension(ext) => match ext {
                 sbi::EXT_PUT_CHAR
                 | sbi::EXT_BASE
+                | sbi::EXT_TIME
                 | sbi::EXT_HART_STATE
+                | sbi::EXT_IPI
+                | sbi::EXT_RFENCE
                 | sbi::EXT_RESET
+                | sbi::EXT_DBCN
+                | sbi::EXT_PMU
+                | sbi::EXT_STA
                 | sbi::EXT_TEE_HOST
                 | sbi::EXT_TEE_INTERRUPT
                 | sbi::EXT_TEE_GUEST
                 | sbi::EXT_ATTESTATION => 1,
-                sbi::EXT_PMU if PmuInfo::get().is_ok() => 1,
+                // Only the host may suspend the system.
+                sbi::EXT_SUSP => self.page_owner_id().is_host() as u64,
                 _ => 0,
             },
-            // TODO: 0 is valid result for the GetMachine* SBI calls but we should probably
-            // report real values here.
-            _ => 0,
+            GetMachineVendorID | GetMachineArchitectureID | GetMachineImplementationID
+                if !self.page_owner_id().is_host()
+                    && TvmMachineIdPolicy::get() == TvmMachineIdPolicy::Hidden =>
+            {
+                0
+            }
+            GetMachineVendorID => CpuInfo::get().mvendorid(),
+            GetMachineArchitectureID => CpuInfo::get().marchid(),
+            GetMachineImplementationID => CpuInfo::get().mimpid(),
         };
         SbiReturn::success(ret)
     }
 
-    fn handle_hart_state_msg(&self, hsm_func: StateFunction) -> EcallAction {
+    fn handle_hart_state_msg(
+        &self,
+        hsm_func: StateFunction,
+        active_vcpu: &mut ActiveVmCpu<T>,
+    ) -> EcallAction {
         use StateFunction::*;
         match hsm_func {
             HartStart {
@@ -850,7 +1535,33 @@ impl<'a, T: GuestStagePagingMode> FinalizedVm<'a, T> {
                 SbiReturn::success(0),
             ),
             HartStatus { hart_id } => self.get_vcpu_status(hart_id).into(),
-            _ => EcallAction::Unhandled,
+            HartSuspend {
+                suspend_type,
+                resume_addr,
+                opaque,
+            } => {
+                let suspend_type = match HartSuspendType::from_raw(suspend_type) {
+                    Ok(t) => t,
+                    Err(e) => return EcallAction::Continue(e.into()),
+                };
+                active_vcpu.suspend(suspend_type, resume_addr, opaque);
+                // Let our host know that the vCPU is suspended, masking the resume state.
+                let msg = SbiMessage::HartState(StateFunction::HartSuspend {
+                    suspend_type: suspend_type.raw(),
+                    resume_addr: 0,
+                    opaque: 0,
+                });
+                match suspend_type {
+                    // The SBI call returns successfully upon resume.
+                    HartSuspendType::DefaultRetentive => {
+                        EcallAction::Break(VmExitCause::ResumableEcall(msg), SbiReturn::success(0))
+                    }
+                    // The vCPU resumes at `resume_addr` instead of returning from the SBI call.
+                    HartSuspendType::DefaultNonRetentive => {
+                        EcallAction::Retry(VmExitCause::ResumableEcall(msg))
+                    }
+                }
+            }
         }
     }
 
@@ -899,16 +1610,18 @@ impl<'a, T: GuestStagePagingMode> FinalizedVm<'a, T> {
                 guest_id,
                 page_addr,
                 page_type,
+                perms,
                 num_pages,
                 guest_addr,
             } => self
-                .guest_add_zero_pages(guest_id, page_addr, page_type, num_pages, guest_addr)
+                .guest_add_zero_pages(guest_id, page_addr, page_type, perms, num_pages, guest_addr)
                 .into(),
             TvmAddMeasuredPages {
                 guest_id,
                 src_addr,
                 dest_addr,
                 page_type,
+                perms,
                 num_pages,
                 guest_addr,
             } => self
@@ -917,15 +1630,20 @@ impl<'a, T: GuestStagePagingMode> FinalizedVm<'a, T> {
                     src_addr,
                     dest_addr,
                     page_type,
+                    perms,
                     num_pages,
                     guest_addr,
                     active_vcpu.active_pages(),
                 )
                 .into(),
             Finalize { guest_id } => self.guest_finalize(guest_id).into(),
-            TvmCpuRun { guest_id, vcpu_id } => {
-                self.guest_run_vcpu(guest_id, vcpu_id, active_vcpu).into()
-            }
+            TvmCpuRun {
+                guest_id,
+                vcpu_id,
+                time_slice,
+            } => self
+                .guest_run_vcpu(guest_id, vcpu_id, time_slice, active_vcpu)
+                .into(),
             TvmCpuNumRegisterSets { guest_id } => {
                 self.guest_num_vcpu_register_sets(guest_id).into()
             }
@@ -948,6 +1666,28 @@ impl<'a, T: GuestStagePagingMode> FinalizedVm<'a, T> {
             } => self
                 .guest_add_shared_pages(guest_id, page_addr, page_type, num_pages, guest_addr)
                 .into(),
+            TvmRemovePages {
+                guest_id,
+                guest_addr,
+                page_type,
+                num_pages,
+            } => self
+                .guest_remove_pages(guest_id, guest_addr, page_type, num_pages)
+                .into(),
+            TvmAddZeroPagePool {
+                guest_id,
+                page_addr,
+                num_pages,
+            } => self
+                .guest_add_zero_page_pool(guest_id, page_addr, num_pages)
+                .into(),
+            TvmGetInfo {
+                guest_id,
+                dest_addr,
+                len,
+            } => self
+                .guest_get_info(guest_id, dest_addr, len, active_vcpu.active_pages())
+                .into(),
         }
     }
 
@@ -1034,6 +1774,13 @@ impl<'a, T: GuestStagePagingMode> FinalizedVm<'a, T> {
                 .into(),
             TsmConvertImsic { imsic_addr } => self.convert_imsic(imsic_addr).into(),
             TsmReclaimImsic { imsic_addr } => self.reclaim_imsic(imsic_addr).into(),
+            TvmCpuInjectInterrupt {
+                tvm_id,
+                vcpu_id,
+                interrupt_id,
+            } => self
+                .guest_inject_interrupt(tvm_id, vcpu_id, interrupt_id)
+                .into(),
         }
     }
 
@@ -1051,7 +1798,7 @@ impl<'a, T: GuestStagePagingMode> FinalizedVm<'a, T> {
         // Since we're the hypervisor we're ready from boot.
         let tsm_info = sbi::TsmInfo {
             tsm_state: sbi::TsmState::TsmReady,
-            tsm_version: 0,
+            tsm_version: SALUS_VERSION,
             tvm_state_pages: TVM_STATE_PAGES,
             tvm_max_vcpus: MAX_CPUS as u64,
             tvm_bytes_per_vcpu: VM_CPU_BYTES,
@@ -1072,13 +1819,10 @@ impl<'a, T: GuestStagePagingMode> FinalizedVm<'a, T> {
         page_type: sbi::TsmPageType,
         num_pages: u64,
     ) -> EcallResult<u64> {
-        if page_type != sbi::TsmPageType::Page4k {
-            // TODO: Support converting hugepages.
-            return Err(EcallError::Sbi(SbiError::InvalidParam));
-        }
+        let page_size = guest_page_size(page_type)?;
         let page_addr = self.guest_addr_from_raw(page_addr)?;
         self.vm_pages()
-            .convert_pages(page_addr, num_pages)
+            .convert_pages(page_addr, page_size, num_pages)
             .map_err(EcallError::from)?;
         Ok(num_pages)
     }
@@ -1090,13 +1834,10 @@ impl<'a, T: GuestStagePagingMode> FinalizedVm<'a, T> {
         page_type: sbi::TsmPageType,
         num_pages: u64,
     ) -> EcallResult<u64> {
-        if page_type != sbi::TsmPageType::Page4k {
-            // TODO: Support converting hugepages.
-            return Err(EcallError::Sbi(SbiError::InvalidParam));
-        }
+        let page_size = guest_page_size(page_type)?;
         let page_addr = self.guest_addr_from_raw(page_addr)?;
         self.vm_pages()
-            .reclaim_pages(page_addr, num_pages)
+            .reclaim_pages(page_addr, page_size, num_pages)
             .map_err(EcallError::from)?;
         Ok(num_pages)
     }
@@ -1113,7 +1854,7 @@ impl<'a, T: GuestStagePagingMode> FinalizedVm<'a, T> {
         Ok(0)
     }
 
-    fn guests(&self) -> Option<&Guests<T>> {
+    fn guests(&self) -> Option<&Guests> {
         self.vm().guests.as_ref()
     }
 
@@ -1127,14 +1868,17 @@ impl<'a, T: GuestStagePagingMode> FinalizedVm<'a, T> {
             return Err(EcallError::Sbi(SbiError::InvalidParam));
         }
 
-        // Read the params from the VM's address space.
+        // Read the params from the VM's address space. `tvm_paging_mode` was added to the end of
+        // `TvmCreateParams` later, so accept the original, shorter, structure too.
         let params_addr = RawAddr::guest(params_addr, self.page_owner_id());
-        if len < mem::size_of::<sbi::TvmCreateParams>() as u64 {
+        let params_size = mem::size_of::<sbi::TvmCreateParams>();
+        if len < offset_of!(sbi::TvmCreateParams, tvm_paging_mode) as u64 {
             return Err(EcallError::Sbi(SbiError::InvalidParam));
         }
         let mut param_bytes = [0u8; mem::size_of::<sbi::TvmCreateParams>()];
+        let copy_len = core::cmp::min(len, params_size as u64) as usize;
         active_pages
-            .copy_from_guest(param_bytes.as_mut_slice(), params_addr)
+            .copy_from_guest(&mut param_bytes[..copy_len], params_addr)
             .map_err(EcallError::from)?;
 
         // Safety: `param_bytes` points to `size_of::<TvmCreateParams>()` contiguous, initialized
@@ -1142,18 +1886,48 @@ impl<'a, T: GuestStagePagingMode> FinalizedVm<'a, T> {
         let params: sbi::TvmCreateParams =
             unsafe { core::ptr::read_unaligned(param_bytes.as_slice().as_ptr().cast()) };
 
+        // A missing or 0 paging mode selects the default.
+        let paging_mode = match params.tvm_paging_mode {
+            0 => TvmPagingMode::default(),
+            mode => TvmPagingMode::from_reg(mode)?,
+        };
+        let cpu_info = CpuInfo::get();
+        let supported = match paging_mode {
+            TvmPagingMode::Sv39x4 => cpu_info.has_guest_paging_mode::<Sv39x4>(),
+            TvmPagingMode::Sv48x4 => cpu_info.has_guest_paging_mode::<Sv48x4>(),
+            TvmPagingMode::Sv57x4 => cpu_info.has_guest_paging_mode::<Sv57x4>(),
+        };
+        if !supported {
+            return Err(EcallError::Sbi(SbiError::NotSupported));
+        }
+
         // Now create the VM, claiming the pages that the host donated to us.
         let page_root_addr = self.guest_addr_from_raw(params.tvm_page_directory_addr)?;
         let state_addr = self.guest_addr_from_raw(params.tvm_state_addr)?;
         let vcpu_addr = self.guest_addr_from_raw(params.tvm_vcpu_addr)?;
         let num_vcpu_pages = PageSize::num_4k_pages(params.tvm_num_vcpus * VM_CPU_BYTES);
-        let (guest_vm, state_page) = self
-            .vm_pages()
-            .create_guest_vm(page_root_addr, state_addr, vcpu_addr, num_vcpu_pages)
-            .map_err(EcallError::from)?;
-        let id = guest_vm.page_owner_id();
+        let guest = match paging_mode {
+            TvmPagingMode::Sv39x4 => AnyGuestVm::from(self.create_guest::<Sv39x4>(
+                page_root_addr,
+                state_addr,
+                vcpu_addr,
+                num_vcpu_pages,
+            )?),
+            TvmPagingMode::Sv48x4 => AnyGuestVm::from(self.create_guest::<Sv48x4>(
+                page_root_addr,
+                state_addr,
+                vcpu_addr,
+                num_vcpu_pages,
+            )?),
+            TvmPagingMode::Sv57x4 => AnyGuestVm::from(self.create_guest::<Sv57x4>(
+                page_root_addr,
+                state_addr,
+                vcpu_addr,
+                num_vcpu_pages,
+            )?),
+        };
+        let id = guest.page_owner_id();
 
-        let guest = GuestVm::new(guest_vm, state_page);
         self.guests()
             .and_then(|g| g.add(guest).ok())
             .ok_or(EcallError::Sbi(SbiError::Failed))?;
@@ -1161,6 +1935,23 @@ impl<'a, T: GuestStagePagingMode> FinalizedVm<'a, T> {
         Ok(id.raw())
     }
 
+    // Creates a guest VM using the paging mode `U` from the pages that the host donated to us.
+    fn create_guest<U: GuestStagePagingMode>(
+        &self,
+        page_root_addr: GuestPageAddr,
+        state_addr: GuestPageAddr,
+        vcpu_addr: GuestPageAddr,
+        num_vcpu_pages: u64,
+    ) -> EcallResult<GuestVm<U>> {
+        let (guest_vm, state_page) = self
+            .vm_pages()
+            .create_guest_vm::<U>(page_root_addr, state_addr, vcpu_addr, num_vcpu_pages)
+            .map_err(EcallError::from)?;
+        Ok(GuestVm::new(guest_vm, state_page))
+    }
+
+    // Destroys the guest with the ID `guest_id`. Dropping the last reference to the guest tears it
+    // down, wiping its confidential memory and returning it to us as converted pages.
     fn destroy_guest(&self, guest_id: u64) -> EcallResult<u64> {
         let guest_id = PageOwnerId::new(guest_id).ok_or(EcallError::Sbi(SbiError::InvalidParam))?;
         self.guests()
@@ -1170,7 +1961,7 @@ impl<'a, T: GuestStagePagingMode> FinalizedVm<'a, T> {
     }
 
     /// Retrieves the guest VM with the ID `guest_id`.
-    fn guest_by_id(&self, guest_id: u64) -> EcallResult<GuestVm<T>> {
+    fn guest_by_id(&self, guest_id: u64) -> EcallResult<AnyGuestVm> {
         let guest_id = PageOwnerId::new(guest_id).ok_or(EcallError::Sbi(SbiError::InvalidParam))?;
         let guest = self
             .guests()
@@ -1181,11 +1972,12 @@ impl<'a, T: GuestStagePagingMode> FinalizedVm<'a, T> {
 
     // converts the given guest from init to running
     fn guest_finalize(&self, guest_id: u64) -> EcallResult<u64> {
-        let guest = self.guest_by_id(guest_id)?;
-        guest
-            .finalize()
-            .map_err(|_| EcallError::Sbi(SbiError::InvalidParam))?;
-        Ok(0)
+        with_guest_vm!(self.guest_by_id(guest_id)?, |guest| {
+            guest
+                .finalize()
+                .map_err(|_| EcallError::Sbi(SbiError::InvalidParam))?;
+            Ok(0)
+        })
     }
 
     // Returns the number of register sets in the vCPU shared-memory state area for `guest_id`.
@@ -1193,7 +1985,7 @@ impl<'a, T: GuestStagePagingMode> FinalizedVm<'a, T> {
         // All guests have the same layout since we don't support customization of virtualized
         // features currently, but make sure that the specified guest_id is at least valid.
         self.guest_by_id(guest_id)?;
-        Ok(VM_CPU_SHARED_LAYOUT.len() as u64)
+        Ok(vm_cpu_shared_layout().len() as u64)
     }
 
     // Get the location of the register set at `index` in the vCPU shared-memory state area for
@@ -1201,7 +1993,7 @@ impl<'a, T: GuestStagePagingMode> FinalizedVm<'a, T> {
     fn guest_get_vcpu_register_set(&self, guest_id: u64, index: u64) -> EcallResult<u64> {
         // As above, make sure the `guest_id` is valid even though the layout is uniform (for now).
         self.guest_by_id(guest_id)?;
-        let regset = VM_CPU_SHARED_LAYOUT
+        let regset = vm_cpu_shared_layout()
             .get(index as usize)
             .ok_or(EcallError::Sbi(SbiError::InvalidParam))?;
         Ok(u32::from(*regset) as u64)
@@ -1224,12 +2016,13 @@ impl<'a, T: GuestStagePagingMode> FinalizedVm<'a, T> {
         let shared_area = VmCpuSharedArea::from_pinned_pages(pin)
             .map_err(|_| EcallError::Sbi(SbiError::InvalidAddress))?;
 
-        let guest = self.guest_by_id(guest_id)?;
-        let guest_vm = guest
-            .as_initializing_vm()
-            .ok_or(EcallError::Sbi(SbiError::InvalidParam))?;
-        guest_vm.add_vcpu(vcpu_id, shared_area)?;
-        Ok(0)
+        with_guest_vm!(self.guest_by_id(guest_id)?, |guest| {
+            let guest_vm = guest
+                .as_initializing_vm()
+                .ok_or(EcallError::Sbi(SbiError::InvalidParam))?;
+            guest_vm.add_vcpu(vcpu_id, shared_area)?;
+            Ok(0)
+        })
     }
 
     /// Runs a guest VM's vCPU.
@@ -1237,13 +2030,42 @@ impl<'a, T: GuestStagePagingMode> FinalizedVm<'a, T> {
         &self,
         guest_id: u64,
         vcpu_id: u64,
+        time_slice: u64,
         active_vcpu: &mut ActiveVmCpu<T>,
     ) -> EcallResult<u64> {
-        let guest = self.guest_by_id(guest_id)?;
-        let guest_vm = guest
-            .as_finalized_vm()
-            .ok_or(EcallError::Sbi(SbiError::InvalidParam))?;
-        guest_vm.run_vcpu(vcpu_id, Some(active_vcpu))
+        with_guest_vm!(self.guest_by_id(guest_id)?, |guest| {
+            let guest_vm = guest
+                .as_finalized_vm()
+                .ok_or(EcallError::Sbi(SbiError::InvalidParam))?;
+            guest_vm.run_vcpu(vcpu_id, time_slice, Some(active_vcpu))
+        })
+    }
+
+    /// Injects the virtual interrupt `interrupt_id` into a vCPU of a guest VM without AIA.
+    fn guest_inject_interrupt(
+        &self,
+        guest_id: u64,
+        vcpu_id: u64,
+        interrupt_id: u64,
+    ) -> EcallResult<u64> {
+        // The IDs are the interrupt causes seen by the guest, which are injected as VS-level
+        // interrupts.
+        let irq = match Interrupt::from_scause_reason(interrupt_id) {
+            Ok(Interrupt::SupervisorSoft) => Interrupt::VirtualSupervisorSoft,
+            Ok(Interrupt::SupervisorExternal) => Interrupt::VirtualSupervisorExternal,
+            _ => return Err(EcallError::Sbi(SbiError::InvalidParam)),
+        };
+        with_guest_vm!(self.guest_by_id(guest_id)?, |guest| {
+            let guest_vm = guest
+                .as_finalized_vm()
+                .ok_or(EcallError::Sbi(SbiError::InvalidParam))?;
+            if guest_vm.vm_pages().imsic_geometry().is_some() {
+                // Interrupts are delivered to AIA-enabled guests through their interrupt files.
+                return Err(EcallError::Sbi(SbiError::NotSupported));
+            }
+            guest_vm.post_interrupt(vcpu_id, irq)?;
+            Ok(0)
+        })
     }
 
     fn guest_add_page_table_pages(
@@ -1253,12 +2075,130 @@ impl<'a, T: GuestStagePagingMode> FinalizedVm<'a, T> {
         num_pages: u64,
     ) -> EcallResult<u64> {
         let from_page_addr = self.guest_addr_from_raw(from_addr)?;
-        let guest = self.guest_by_id(guest_id)?;
-        self.vm_pages()
-            .add_pte_pages_to(from_page_addr, num_pages, guest.as_any_vm().vm_pages())
-            .map_err(EcallError::from)?;
+        with_guest_vm!(self.guest_by_id(guest_id)?, |guest| {
+            self.vm_pages()
+                .add_pte_pages_to(from_page_addr, num_pages, guest.as_any_vm().vm_pages())
+                .map_err(EcallError::from)?;
 
-        Ok(0)
+            Ok(0)
+        })
+    }
+
+    fn guest_add_zero_page_pool(
+        &self,
+        guest_id: u64,
+        from_addr: u64,
+        num_pages: u64,
+    ) -> EcallResult<u64> {
+        let from_page_addr = self.guest_addr_from_raw(from_addr)?;
+        with_guest_vm!(self.guest_by_id(guest_id)?, |guest| {
+            self.vm_pages()
+                .add_zero_page_pool_to(from_page_addr, num_pages, guest.as_any_vm().vm_pages())
+                .map_err(EcallError::from)?;
+
+            Ok(0)
+        })
+    }
+
+    /// Writes a `TvmInfo` structure describing the guest `guest_id` to `dest_addr`, followed by as
+    /// many of the guest's `TvmRegionInfo` and `TvmCpuInfo` structures as fit in `len` bytes.
+    /// Returns the number of bytes needed to describe the guest in full.
+    fn guest_get_info(
+        &self,
+        guest_id: u64,
+        dest_addr: u64,
+        len: u64,
+        active_pages: &ActiveVmPages<T>,
+    ) -> EcallResult<u64> {
+        let info_len = mem::size_of::<sbi::TvmInfo>() as u64;
+        if len < info_len {
+            return Err(EcallError::Sbi(SbiError::InvalidParam));
+        }
+        with_guest_vm!(self.guest_by_id(guest_id)?, |guest| {
+            let guest_vm = guest.as_any_vm();
+            // The guest can't be finalized while we hold a reference to it.
+            let tvm_state = if guest.as_finalized_vm().is_some() {
+                TvmState::TvmFinalized
+            } else {
+                TvmState::TvmInitializing
+            };
+            let vm_pages = guest_vm.vm_pages();
+
+            // The header is written last, once the number of regions is known.
+            let mut offset = info_len;
+            let mut num_regions = 0;
+            vm_pages.try_for_each_region(
+                |guest_addr, region_len, region_type| -> EcallResult<()> {
+                    let region_info = sbi::TvmRegionInfo {
+                        guest_addr: guest_addr.bits(),
+                        len: region_len,
+                        region_type: tvm_region_type(region_type),
+                    };
+                    num_regions += 1;
+                    offset =
+                        self.copy_info_struct(&region_info, dest_addr, offset, len, active_pages)?;
+                    Ok(())
+                },
+            )?;
+
+            let vcpus = &guest_vm.vm().vcpus;
+            let imsic_geometry = vm_pages.imsic_geometry();
+            for vcpu_id in 0..vcpus.num_vcpus() as u64 {
+                // Unwrap ok: `vcpu_id` is within the range of the guest's vCPUs.
+                let status = vcpus.get_vcpu_status(vcpu_id).unwrap();
+                let mut cpu_info = sbi::TvmCpuInfo {
+                    status: tvm_cpu_status(status),
+                    ..Default::default()
+                };
+                // Running vCPUs hold their own lock, so their IMSIC state isn't reported.
+                if let Ok(vcpu) = vcpus.get_vcpu(vcpu_id) {
+                    cpu_info.imsic_addr = vcpu
+                        .get_imsic_location()
+                        .and_then(|loc| imsic_geometry.as_ref()?.location_to_addr(loc))
+                        .map_or(0, |addr| addr.bits());
+                    cpu_info.interrupt_file =
+                        vcpu.get_interrupt_file().map_or(0, |f| f.bits() as u64);
+                }
+                offset = self.copy_info_struct(&cpu_info, dest_addr, offset, len, active_pages)?;
+            }
+
+            let tvm_info = sbi::TvmInfo {
+                version: TVM_INFO_VERSION,
+                tvm_state,
+                mapped_pages: vm_pages.num_mapped_pages(),
+                zero_page_pool_pages: vm_pages.zero_page_pool_len(),
+                num_regions,
+                num_vcpus: vcpus.num_vcpus() as u64,
+            };
+            self.copy_info_struct(&tvm_info, dest_addr, 0, len, active_pages)?;
+            Ok(offset)
+        })
+    }
+
+    // Copies the `#[repr(C)]` structure `info` to `offset` bytes past `dest_addr` if it fits within
+    // the `len` bytes there. Returns the offset just past the structure, whether or not it fit.
+    fn copy_info_struct<S>(
+        &self,
+        info: &S,
+        dest_addr: u64,
+        offset: u64,
+        len: u64,
+        active_pages: &ActiveVmPages<T>,
+    ) -> EcallResult<u64> {
+        let info_len = mem::size_of::<S>();
+        let end = offset + info_len as u64;
+        if end <= len {
+            let addr = dest_addr
+                .checked_add(offset)
+                .ok_or(EcallError::Sbi(SbiError::InvalidAddress))?;
+            // Safety: `info` points to `info_len` bytes of initialized memory.
+            let info_bytes: &[u8] =
+                unsafe { slice::from_raw_parts((info as *const S).cast(), info_len) };
+            active_pages
+                .copy_to_guest(RawAddr::guest(addr, self.page_owner_id()), info_bytes)
+                .map_err(EcallError::from)?;
+        }
+        Ok(end)
     }
 
     fn guest_add_memory_region(
@@ -1268,20 +2208,21 @@ impl<'a, T: GuestStagePagingMode> FinalizedVm<'a, T> {
         guest_addr: u64,
         len: u64,
     ) -> EcallResult<u64> {
-        let guest = self.guest_by_id(guest_id)?;
-        let guest_vm = guest
-            .as_initializing_vm()
-            .ok_or(EcallError::Sbi(SbiError::InvalidParam))?;
-        let guest_addr = guest_vm.guest_addr_from_raw(guest_addr)?;
-        use TeeMemoryRegion::*;
-        match region_type {
-            Confidential => guest_vm
-                .vm_pages()
-                .add_confidential_memory_region(guest_addr, len)
-                .map_err(EcallError::from),
-            _ => Err(EcallError::Sbi(SbiError::InvalidParam)),
-        }?;
-        Ok(0)
+        with_guest_vm!(self.guest_by_id(guest_id)?, |guest| {
+            let guest_vm = guest
+                .as_initializing_vm()
+                .ok_or(EcallError::Sbi(SbiError::InvalidParam))?;
+            let guest_addr = guest_vm.guest_addr_from_raw(guest_addr)?;
+            use TeeMemoryRegion::*;
+            match region_type {
+                Confidential => guest_vm
+                    .vm_pages()
+                    .add_confidential_memory_region(guest_addr, len)
+                    .map_err(EcallError::from),
+                _ => Err(EcallError::Sbi(SbiError::InvalidParam)),
+            }?;
+            Ok(0)
+        })
     }
 
     fn guest_add_zero_pages(
@@ -1289,26 +2230,53 @@ impl<'a, T: GuestStagePagingMode> FinalizedVm<'a, T> {
         guest_id: u64,
         page_addr: u64,
         page_type: sbi::TsmPageType,
+        perms: TvmPagePerms,
         num_pages: u64,
         guest_addr: u64,
     ) -> EcallResult<u64> {
-        if page_type != sbi::TsmPageType::Page4k {
-            // TODO - support huge pages.
-            // TODO - need to break up mappings if given address that's part of a huge page.
-            return Err(EcallError::Sbi(SbiError::InvalidParam));
-        }
-
+        // TODO - need to break up mappings if given address that's part of a huge page.
+        let page_size = guest_page_size(page_type)?;
         let from_page_addr = self.guest_addr_from_raw(page_addr)?;
-        let guest = self.guest_by_id(guest_id)?;
-        let guest_vm = guest
-            .as_finalized_vm()
-            .ok_or(EcallError::Sbi(SbiError::InvalidParam))?;
-        let to_page_addr = guest_vm.guest_addr_from_raw(guest_addr)?;
-        self.vm_pages()
-            .add_zero_pages_to(from_page_addr, num_pages, guest_vm.vm_pages(), to_page_addr)
-            .map_err(EcallError::from)?;
+        with_guest_vm!(self.guest_by_id(guest_id)?, |guest| {
+            let guest_vm = guest
+                .as_finalized_vm()
+                .ok_or(EcallError::Sbi(SbiError::InvalidParam))?;
+            let to_page_addr = guest_vm.guest_addr_from_raw(guest_addr)?;
+            self.vm_pages()
+                .add_zero_pages_to(
+                    from_page_addr,
+                    page_size,
+                    num_pages,
+                    guest_vm.vm_pages(),
+                    to_page_addr,
+                    guest_page_perms(perms),
+                )
+                .map_err(EcallError::from)?;
 
-        Ok(num_pages)
+            Ok(num_pages)
+        })
+    }
+
+    fn guest_remove_pages(
+        &self,
+        guest_id: u64,
+        guest_addr: u64,
+        page_type: sbi::TsmPageType,
+        num_pages: u64,
+    ) -> EcallResult<u64> {
+        let page_size = guest_page_size(page_type)?;
+        with_guest_vm!(self.guest_by_id(guest_id)?, |guest| {
+            let guest_vm = guest
+                .as_finalized_vm()
+                .ok_or(EcallError::Sbi(SbiError::InvalidParam))?;
+            let from_page_addr = guest_vm.guest_addr_from_raw(guest_addr)?;
+            let removed = self
+                .vm_pages()
+                .remove_pages_from(guest_vm.vm_pages(), from_page_addr, page_size, num_pages)
+                .map_err(EcallError::from)?;
+
+            Ok(removed)
+        })
     }
 
     #[allow(clippy::too_many_arguments)]
@@ -1318,35 +2286,35 @@ impl<'a, T: GuestStagePagingMode> FinalizedVm<'a, T> {
         src_addr: u64,
         dest_addr: u64,
         page_type: sbi::TsmPageType,
+        perms: TvmPagePerms,
         num_pages: u64,
         guest_addr: u64,
         active_pages: &ActiveVmPages<T>,
     ) -> EcallResult<u64> {
-        if page_type != sbi::TsmPageType::Page4k {
-            // TODO - support huge pages.
-            // TODO - need to break up mappings if given address that's part of a huge page.
-            return Err(EcallError::Sbi(SbiError::InvalidParam));
-        }
-
+        // TODO - need to break up mappings if given address that's part of a huge page.
+        let page_size = guest_page_size(page_type)?;
         let src_page_addr = self.guest_addr_from_raw(src_addr)?;
         let from_page_addr = self.guest_addr_from_raw(dest_addr)?;
-        let guest = self.guest_by_id(guest_id)?;
-        let guest_vm = guest
-            .as_initializing_vm()
-            .ok_or(EcallError::Sbi(SbiError::InvalidParam))?;
-        let to_page_addr = guest_vm.guest_addr_from_raw(guest_addr)?;
-        active_pages
-            .copy_and_add_data_pages_builder(
-                src_page_addr,
-                from_page_addr,
-                num_pages,
-                guest_vm.vm_pages(),
-                to_page_addr,
-                guest_vm.attestation_mgr(),
-            )
-            .map_err(EcallError::from)?;
+        with_guest_vm!(self.guest_by_id(guest_id)?, |guest| {
+            let guest_vm = guest
+                .as_initializing_vm()
+                .ok_or(EcallError::Sbi(SbiError::InvalidParam))?;
+            let to_page_addr = guest_vm.guest_addr_from_raw(guest_addr)?;
+            active_pages
+                .copy_and_add_data_pages_builder(
+                    src_page_addr,
+                    from_page_addr,
+                    page_size,
+                    num_pages,
+                    guest_vm.vm_pages(),
+                    to_page_addr,
+                    guest_page_perms(perms),
+                    guest_vm.attestation_mgr(),
+                )
+                .map_err(EcallError::from)?;
 
-        Ok(num_pages)
+            Ok(num_pages)
+        })
     }
 
     fn get_attestation_capabilities(
@@ -1527,25 +2495,26 @@ impl<'a, T: GuestStagePagingMode> FinalizedVm<'a, T> {
         if params.guests_per_hart != 0 {
             return Err(EcallError::Sbi(SbiError::NotSupported));
         }
-        let guest = self.guest_by_id(guest_id)?;
-        let guest_vm = guest
-            .as_initializing_vm()
-            .ok_or(EcallError::Sbi(SbiError::InvalidParam))?;
-        let base_addr = guest_vm.guest_addr_from_raw(params.imsic_base_addr)?;
-        let geometry = ImsicGeometry::new(
-            base_addr,
-            params.group_index_bits,
-            params.group_index_shift,
-            params.hart_index_bits,
-            params.guest_index_bits,
-            0,
-        )
-        .map_err(|_| EcallError::Sbi(SbiError::InvalidParam))?;
-        guest_vm
-            .vm_pages()
-            .set_imsic_geometry(geometry)
-            .map_err(EcallError::from)?;
-        Ok(0)
+        with_guest_vm!(self.guest_by_id(guest_id)?, |guest| {
+            let guest_vm = guest
+                .as_initializing_vm()
+                .ok_or(EcallError::Sbi(SbiError::InvalidParam))?;
+            let base_addr = guest_vm.guest_addr_from_raw(params.imsic_base_addr)?;
+            let geometry = ImsicGeometry::new(
+                base_addr,
+                params.group_index_bits,
+                params.group_index_shift,
+                params.hart_index_bits,
+                params.guest_index_bits,
+                0,
+            )
+            .map_err(|_| EcallError::Sbi(SbiError::InvalidParam))?;
+            guest_vm
+                .vm_pages()
+                .set_imsic_geometry(geometry)
+                .map_err(EcallError::from)?;
+            Ok(0)
+        })
     }
 
     fn guest_set_vcpu_imsic_addr(
@@ -1554,21 +2523,22 @@ impl<'a, T: GuestStagePagingMode> FinalizedVm<'a, T> {
         vcpu_id: u64,
         imsic_addr: u64,
     ) -> EcallResult<u64> {
-        let guest = self.guest_by_id(guest_id)?;
-        let guest_vm = guest
-            .as_initializing_vm()
-            .ok_or(EcallError::Sbi(SbiError::InvalidParam))?;
-        let imsic_addr = guest_vm.guest_addr_from_raw(imsic_addr)?;
-        let geometry = guest_vm
-            .vm_pages()
-            .imsic_geometry()
-            .ok_or(EcallError::Sbi(SbiError::NotSupported))?;
-        let location = geometry
-            .addr_to_location(imsic_addr)
-            .ok_or(EcallError::Sbi(SbiError::InvalidParam))?;
-        // We'll verify that there's no aliasing between locations during finalize().
-        guest_vm.set_vcpu_imsic_location(vcpu_id, location)?;
-        Ok(0)
+        with_guest_vm!(self.guest_by_id(guest_id)?, |guest| {
+            let guest_vm = guest
+                .as_initializing_vm()
+                .ok_or(EcallError::Sbi(SbiError::InvalidParam))?;
+            let imsic_addr = guest_vm.guest_addr_from_raw(imsic_addr)?;
+            let geometry = guest_vm
+                .vm_pages()
+                .imsic_geometry()
+                .ok_or(EcallError::Sbi(SbiError::NotSupported))?;
+            let location = geometry
+                .addr_to_location(imsic_addr)
+                .ok_or(EcallError::Sbi(SbiError::InvalidParam))?;
+            // We'll verify that there's no aliasing between locations during finalize().
+            guest_vm.set_vcpu_imsic_location(vcpu_id, location)?;
+            Ok(0)
+        })
     }
 
     fn convert_imsic(&self, imsic_addr: u64) -> EcallResult<u64> {
@@ -1599,39 +2569,60 @@ impl<'a, T: GuestStagePagingMode> FinalizedVm<'a, T> {
             return Err(EcallError::Sbi(SbiError::InvalidParam));
         }
         let page_addr = self.guest_addr_from_raw(page_addr)?;
-        let guest = self.guest_by_id(guest_id)?;
-        let guest_vm = guest
-            .as_finalized_vm()
-            .ok_or(EcallError::Sbi(SbiError::InvalidParam))?;
-        let guest_addr = guest_vm.guest_addr_from_raw(guest_addr)?;
-        self.vm_pages()
-            .add_shared_pages_to(page_addr, num_pages, guest_vm.vm_pages(), guest_addr)
-            .map_err(EcallError::from)?;
-
-        Ok(num_pages)
+        with_guest_vm!(self.guest_by_id(guest_id)?, |guest| {
+            let guest_vm = guest
+                .as_finalized_vm()
+                .ok_or(EcallError::Sbi(SbiError::InvalidParam))?;
+            let guest_addr = guest_vm.guest_addr_from_raw(guest_addr)?;
+            self.vm_pages()
+                .add_shared_pages_to(page_addr, num_pages, guest_vm.vm_pages(), guest_addr)
+                .map_err(EcallError::from)?;
+
+            Ok(num_pages)
+        })
     }
 
-    fn handle_tee_guest_msg(&self, guest_func: TeeGuestFunction) -> EcallAction {
+    fn handle_tee_guest_msg(
+        &self,
+        guest_func: TeeGuestFunction,
+        active_vcpu: &mut ActiveVmCpu<T>,
+    ) -> EcallAction {
         use TeeGuestFunction::*;
         match guest_func {
             AddMemoryRegion {
                 region_type,
                 addr,
                 len,
-            } => {
-                let result = self.add_memory_region(region_type, addr, len);
-                // Notify the host if the call succeeded.
-                match result {
-                    Ok(r) => EcallAction::Break(
-                        VmExitCause::ResumableEcall(SbiMessage::TeeGuest(guest_func)),
-                        SbiReturn::success(r),
-                    ),
-                    Err(_) => result.into(),
-                }
+            } => self.notify_host_on_success(guest_func, || {
+                self.add_memory_region(region_type, addr, len)
+            }),
+            ShareMemory { addr, len } => {
+                self.notify_host_on_success(guest_func, || self.share_memory(addr, len))
+            }
+            UnshareMemory { addr, len } => {
+                self.notify_host_on_success(guest_func, || self.unshare_memory(addr, len))
+            }
+            RestrictPagePermissions { addr, len, perms } => {
+                self.restrict_page_perms(guest_func, addr, len, perms, active_vcpu)
             }
         }
     }
 
+    // Runs `f` and, if it succeeds, notifies the host of the completed `guest_func` call.
+    fn notify_host_on_success<F>(&self, guest_func: TeeGuestFunction, f: F) -> EcallAction
+    where
+        F: FnOnce() -> EcallResult<u64>,
+    {
+        let result = f();
+        match result {
+            Ok(r) => EcallAction::Break(
+                VmExitCause::ResumableEcall(SbiMessage::TeeGuest(guest_func)),
+                SbiReturn::success(r),
+            ),
+            Err(_) => result.into(),
+        }
+    }
+
     fn add_memory_region(
         &self,
         region_type: TeeMemoryRegion,
@@ -1653,6 +2644,53 @@ impl<'a, T: GuestStagePagingMode> FinalizedVm<'a, T> {
         }?;
         Ok(0)
     }
+
+    fn share_memory(&self, addr: u64, len: u64) -> EcallResult<u64> {
+        let addr = self.guest_addr_from_raw(addr)?;
+        self.vm_pages().share_memory(addr, len)?;
+        Ok(0)
+    }
+
+    fn unshare_memory(&self, addr: u64, len: u64) -> EcallResult<u64> {
+        let addr = self.guest_addr_from_raw(addr)?;
+        self.vm_pages().unshare_memory(addr, len)?;
+        Ok(0)
+    }
+
+    fn restrict_page_perms(
+        &self,
+        guest_func: TeeGuestFunction,
+        addr: u64,
+        len: u64,
+        perms: TvmPagePerms,
+        active_vcpu: &mut ActiveVmCpu<T>,
+    ) -> EcallAction {
+        let addr = match self.guest_addr_from_raw(addr) {
+            Ok(addr) => addr,
+            Err(e) => return EcallResult::<u64>::Err(e).into(),
+        };
+        let result = active_vcpu
+            .active_pages()
+            .restrict_page_perms(addr, len, guest_page_perms(perms));
+        match result {
+            // A fence initiated earlier is still waiting for vCPUs of this VM to exit. Exit to the
+            // host and retry the call once this vCPU is resumed.
+            Err(VmPagesError::TlbFenceInProgress) => EcallAction::Retry(
+                VmExitCause::ResumableEcall(SbiMessage::TeeGuest(guest_func)),
+            ),
+            result => self.notify_host_on_success(guest_func, || {
+                result?;
+                // The other vCPUs may be running with the old permissions cached in their TLBs, so
+                // make them fence their G-stage translations before reporting success.
+                self.send_remote_fences(
+                    HartMask::all(),
+                    VmCpuFence::GuestPhysTranslation,
+                    active_vcpu,
+                )?;
+                Ok(0)
+            }),
+        }
+    }
 }
 
 /// Errors encountered during MMIO emulation.
@@ -1790,7 +2828,7 @@ impl<T: GuestStagePagingMode> HostVm<T> {
         // Unwrap ok since we've donate sufficient PT pages to map the entire address space up front.
         let mapper = vm
             .vm_pages()
-            .map_measured_pages(to_addr, pages.len() as u64)
+            .map_measured_pages(to_addr, PageSize::Size4k, pages.len() as u64)
             .unwrap();
         for (page, vm_addr) in pages.zip(to_addr.iter_from()) {
             assert_eq!(page.size(), PageSize::Size4k);
@@ -1892,7 +2930,7 @@ impl<T: GuestStagePagingMode> HostVm<T> {
         // Unwrap ok since we've donate sufficient PT pages to map the entire address space up front.
         let mapper = vm
             .vm_pages()
-            .map_zero_pages(to_addr, pages.len() as u64)
+            .map_zero_pages(to_addr, PageSize::Size4k, pages.len() as u64)
             .unwrap();
         for (page, vm_addr) in pages.zip(to_addr.iter_from()) {
             assert_eq!(page.size(), PageSize::Size4k);
@@ -1913,12 +2951,18 @@ impl<T: GuestStagePagingMode> HostVm<T> {
         vm.vm_pages().add_mmio_region(addr, len).unwrap();
     }
 
-    /// Run the host VM's vCPU with ID `vcpu_id`. Does not return.
-    pub fn run(&self, vcpu_id: u64) {
+    /// Run the host VM's vCPU with ID `vcpu_id`. Returns the type of system reset to perform once
+    /// the host VM requests a reset or encounters an unrecoverable error.
+    pub fn run(&self, vcpu_id: u64) -> ResetType {
         let vm = self.inner.as_finalized_vm().unwrap();
         loop {
             // Wait until this vCPU is ready to run.
             while !self.vcpu_is_runnable(vcpu_id) {
+                if smp::stop_requested() {
+                    // Another CPU is suspending the system. We'll be restarted from scratch on
+                    // resume.
+                    smp::stop_this_cpu();
+                }
                 smp::wfi();
             }
 
@@ -1926,7 +2970,7 @@ impl<T: GuestStagePagingMode> HostVm<T> {
             let vcpu = self.vcpu_state(vcpu_id).unwrap();
             // Run until we shut down, or this vCPU stops.
             loop {
-                vm.run_vcpu(vcpu_id, None).unwrap();
+                vm.run_vcpu::<T>(vcpu_id, 0, None).unwrap();
                 let scause = vcpu.scause();
                 if let Ok(Trap::Exception(e)) = Trap::from_scause(scause) {
                     use Exception::*;
@@ -1942,9 +2986,12 @@ impl<T: GuestStagePagingMode> HostVm<T> {
                             }
                             use SbiMessage::*;
                             match SbiMessage::from_regs(&a_regs) {
-                                Ok(Reset(_)) => {
-                                    println!("Host VM requested shutdown");
-                                    return;
+                                Ok(Reset(ResetFunction::Reset { reset_type, reason })) => {
+                                    println!(
+                                        "Host VM requested {:?} (reason: {:?})",
+                                        reset_type, reason
+                                    );
+                                    return reset_type;
                                 }
                                 Ok(HartState(StateFunction::HartStart { hart_id, .. })) => {
                                     smp::send_ipi(CpuId::new(hart_id as usize));
@@ -1952,26 +2999,49 @@ impl<T: GuestStagePagingMode> HostVm<T> {
                                 Ok(HartState(StateFunction::HartStop)) => {
                                     break;
                                 }
+                                Ok(HartState(StateFunction::HartSuspend { .. })) => {
+                                    // Idle this CPU until the vCPU has an interrupt to take.
+                                    vm.wait_for_vcpu_resume(vcpu_id).unwrap();
+                                }
+                                Ok(SystemSuspend(SystemSuspendFunction::Suspend {
+                                    sleep_type,
+                                    ..
+                                })) => {
+                                    // Unwrap ok: the sleep type was checked when the vCPU made
+                                    // the ECALL.
+                                    let sleep_type =
+                                        SuspendSleepType::from_raw(sleep_type).unwrap();
+                                    println!("Host VM requested {:?}", sleep_type);
+                                    let ret = match suspend::suspend_system(sleep_type) {
+                                        Ok(()) => SbiReturn::success(0),
+                                        Err(e) => {
+                                            println!("System suspend failed: {:?}", e);
+                                            e.into()
+                                        }
+                                    };
+                                    vcpu.set_gpr(GprIndex::A0, ret.error_code as u64);
+                                    vcpu.set_gpr(GprIndex::A1, ret.return_value);
+                                }
                                 _ => {
                                     println!("Unhandled ECALL from host");
-                                    return;
+                                    return ResetType::Shutdown;
                                 }
                             }
                         }
                         GuestLoadPageFault | GuestStorePageFault => {
                             if let Err(err) = self.handle_page_fault(vcpu_id) {
                                 println!("Unhandled page fault: {:?}", err);
-                                return;
+                                return ResetType::Shutdown;
                             }
                         }
                         _ => {
                             println!("Unhandled host VM exception {:?}", e);
-                            return;
+                            return ResetType::Shutdown;
                         }
                     }
                 } else {
                     println!("Unexpected host VM trap (SCAUSE = 0x{:x})", scause);
-                    return;
+                    return ResetType::Shutdown;
                 }
             }
         }
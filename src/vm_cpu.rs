@@ -3,23 +3,27 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use core::arch::global_asm;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use core::{marker::PhantomData, mem::size_of, ptr, ptr::NonNull};
 use drivers::{imsic::ImsicFileId, imsic::ImsicLocation, CpuId, CpuInfo};
 use memoffset::offset_of;
 use page_tracking::collections::PageVec;
 use page_tracking::{PageTracker, TlbVersion};
-use riscv_page_tables::GuestStagePagingMode;
+use riscv_page_tables::{tlb, GuestStagePagingMode};
 use riscv_pages::{
     GuestPhysAddr, GuestVirtAddr, InternalClean, PageOwnerId, PageSize, RawAddr, SequentialPages,
 };
 use riscv_regs::*;
-use sbi::{self, SbiMessage, SbiReturnType};
+use sbi::{
+    self, HartSuspendType, PmuFirmware, PmuPlatformFirmware, ResetFunction, SbiMessage, SbiReturn,
+    SbiReturnType, StateFunction, StealTimeRecord, SystemSuspendFunction, TvmExitReason,
+};
 use spin::{Mutex, MutexGuard, Once, RwLock, RwLockReadGuard};
 
-use crate::smp::PerCpu;
-use crate::vm::{MmioOpcode, MmioOperation, VmExitCause};
+use crate::smp::{self, PerCpu};
+use crate::vm::VmExitCause;
 use crate::vm_id::VmId;
-use crate::vm_pages::{ActiveVmPages, FinalizedVmPages, PinnedPages};
+use crate::vm_pages::{ActiveVmPages, FinalizedVmPages, PageFaultType, PinnedPages};
 use crate::vm_pmu::VmPmuState;
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
@@ -34,6 +38,7 @@ pub enum Error {
     WrongAddressSpace,
     InvalidSharedStatePtr,
     InsufficientSharedStatePages,
+    InvalidInterrupt,
 }
 
 pub type Result<T> = core::result::Result<T, Error>;
@@ -79,6 +84,7 @@ struct GuestCpuState {
 #[repr(C)]
 struct GuestVCpuState {
     htimedelta: u64,
+    hvip: u64,
     vsstatus: u64,
     vsie: u64,
     vstvec: u64,
@@ -311,10 +317,14 @@ pub struct VmCpuSharedState {
     gprs: sbi::Gprs,
     s_csrs: sbi::SupervisorCsrs,
     hs_csrs: sbi::HypervisorCsrs,
+    fprs: sbi::Fprs,
+    exit_reason: sbi::ExitReason,
+    vprs: sbi::Vprs,
 }
 
-/// Defines the layout of `VmCpuSharedState` in terms of `RegisterSetLocation`s.
-pub const VM_CPU_SHARED_LAYOUT: &[sbi::RegisterSetLocation] = &[
+/// Defines the layout of `VmCpuSharedState` in terms of `RegisterSetLocation`s. The vector
+/// register set must remain last as it is omitted if the CPU doesn't support vectors.
+const VM_CPU_SHARED_LAYOUT: &[sbi::RegisterSetLocation] = &[
     sbi::RegisterSetLocation {
         id: sbi::RegisterSetId::Gprs as u16,
         offset: offset_of!(VmCpuSharedState, gprs) as u16,
@@ -327,8 +337,30 @@ pub const VM_CPU_SHARED_LAYOUT: &[sbi::RegisterSetLocation] = &[
         id: sbi::RegisterSetId::HypervisorCsrs as u16,
         offset: offset_of!(VmCpuSharedState, hs_csrs) as u16,
     },
+    sbi::RegisterSetLocation {
+        id: sbi::RegisterSetId::Fprs as u16,
+        offset: offset_of!(VmCpuSharedState, fprs) as u16,
+    },
+    sbi::RegisterSetLocation {
+        id: sbi::RegisterSetId::ExitReason as u16,
+        offset: offset_of!(VmCpuSharedState, exit_reason) as u16,
+    },
+    sbi::RegisterSetLocation {
+        id: sbi::RegisterSetId::Vprs as u16,
+        offset: offset_of!(VmCpuSharedState, vprs) as u16,
+    },
 ];
 
+/// Returns the layout of `VmCpuSharedState` exposed on this system in terms of
+/// `RegisterSetLocation`s.
+pub fn vm_cpu_shared_layout() -> &'static [sbi::RegisterSetLocation] {
+    if CpuInfo::get().has_vector() {
+        VM_CPU_SHARED_LAYOUT
+    } else {
+        &VM_CPU_SHARED_LAYOUT[..VM_CPU_SHARED_LAYOUT.len() - 1]
+    }
+}
+
 /// The number of pages required for `VmCpuSharedState`.
 pub const VM_CPU_SHARED_PAGES: u64 = PageSize::num_4k_pages(size_of::<VmCpuSharedState>() as u64);
 
@@ -378,6 +410,10 @@ impl<'a> VmCpuSharedStateRef<'a> {
     define_accessors! {s_csrs, stval, stval, set_stval}
     define_accessors! {hs_csrs, htval, htval, set_htval}
     define_accessors! {hs_csrs, htinst, htinst, set_htinst}
+    define_accessors! {exit_reason, reason, exit_reason, set_exit_reason}
+    define_accessors! {exit_reason, addr, exit_addr, set_exit_addr}
+    define_accessors! {exit_reason, info, exit_info, set_exit_info}
+    define_accessors! {exit_reason, value, exit_value, set_exit_value}
 
     /// Reads the general purpose register at `index`.
     pub fn gpr(&self, index: GprIndex) -> u64 {
@@ -392,6 +428,34 @@ impl<'a> VmCpuSharedStateRef<'a> {
         // construction that `ptr` points to a valid `VmCpuSharedState`.
         unsafe { ptr::addr_of_mut!((*self.ptr).gprs.0[index as usize]).write_volatile(val) };
     }
+
+    /// Reads the floating point register at `index`.
+    pub fn fpr(&self, index: usize) -> u64 {
+        // Safety: The caller guaranteed at construction that `ptr` points to a valid
+        // `VmCpuSharedState`, and indexing out of bounds will panic.
+        unsafe { ptr::addr_of!((*self.ptr).fprs.0[index]).read_volatile() }
+    }
+
+    /// Writes the floating point register at `index`.
+    pub fn set_fpr(&self, index: usize, val: u64) {
+        // Safety: The caller guaranteed at construction that `ptr` points to a valid
+        // `VmCpuSharedState`, and indexing out of bounds will panic.
+        unsafe { ptr::addr_of_mut!((*self.ptr).fprs.0[index]).write_volatile(val) };
+    }
+
+    /// Reads the vector register at `index`.
+    pub fn vpr(&self, index: usize) -> [u8; sbi::VPRS_MAX_VLENB] {
+        // Safety: The caller guaranteed at construction that `ptr` points to a valid
+        // `VmCpuSharedState`, and indexing out of bounds will panic.
+        unsafe { ptr::addr_of!((*self.ptr).vprs.0[index]).read_volatile() }
+    }
+
+    /// Writes the vector register at `index`.
+    pub fn set_vpr(&self, index: usize, val: &[u8; sbi::VPRS_MAX_VLENB]) {
+        // Safety: The caller guaranteed at construction that `ptr` points to a valid
+        // `VmCpuSharedState`, and indexing out of bounds will panic.
+        unsafe { ptr::addr_of_mut!((*self.ptr).vprs.0[index]).write_volatile(*val) };
+    }
 }
 
 /// Wrapper for the shared-memory state area used to communicate vCPU state with the host.
@@ -430,6 +494,15 @@ impl VmCpuSharedArea {
         })
     }
 
+    // Records `reason` and its payload in the exit reason register set.
+    fn update_exit_reason(&self, reason: TvmExitReason, addr: u64, info: u64, value: u64) {
+        let shared = self.as_ref();
+        shared.set_exit_reason(reason as u64);
+        shared.set_exit_addr(addr);
+        shared.set_exit_info(info);
+        shared.set_exit_value(value);
+    }
+
     // Updates the shared state buffer for an ECALL exit from the given SBI message.
     fn update_with_ecall_exit(&self, msg: SbiMessage) {
         let shared = self.as_ref();
@@ -503,11 +576,29 @@ pub enum VmCpuTrap {
     },
     /// An exception which we expected to handle directly at VS, but trapped to HS instead.
     DelegatedException { exception: Exception, stval: u64 },
+    /// An interrupt destined for the hypervisor that was taken while the vCPU was running.
+    HypervisorInterrupt(Interrupt),
     /// Everything else that we currently don't or can't handle.
     Other(VmCpuTrapState),
     // TODO: Add other exit causes as needed.
 }
 
+// Converts the timer value `stime_value`, in the time base of a vCPU with the given `htimedelta`, to a
+// deadline in the host's time base. Returns `None` if `stime_value` indicates a disarmed timer.
+fn host_timer_deadline(stime_value: u64, htimedelta: u64) -> Option<u64> {
+    if stime_value == u64::MAX {
+        return None;
+    }
+    // Guest time is host time plus HTIMEDELTA. Saturate rather than wrap so that we don't turn a
+    // deadline in the far future into one in the past, or vice versa.
+    let deadline = if (htimedelta as i64) < 0 {
+        stime_value.saturating_add(htimedelta.wrapping_neg())
+    } else {
+        stime_value.saturating_sub(htimedelta)
+    };
+    Some(deadline)
+}
+
 /// Used to store any per-physical-CPU state for a virtual CPU of a VM.
 struct CurrentCpu {
     cpu: CpuId,
@@ -527,14 +618,34 @@ pub struct VmCpu {
     // at present.
     interrupt_file: Option<ImsicFileId>,
     pending_mmio_op: Option<MmioOperation>,
+    // True if the host is expected to supply the result of an ECALL forwarded to it.
+    pending_ecall: bool,
+    // The resume address and opaque value of a system suspend that has yet to be completed.
+    pending_system_resume: Option<(u64, u64)>,
+    // True if the vector registers are to be reloaded from the shared-memory state area, which the
+    // host may have modified since the vCPU last exited.
+    pending_vprs_load: bool,
+    // The time, in the host's time base, at which the vCPU's timer fires. Only tracked while the
+    // vCPU is active if the timer isn't implemented in hardware by VSTIMECMP.
+    timer_deadline: Option<u64>,
+    // The steal-time record registered by the vCPU, if any.
+    steal_time_addr: Option<GuestPhysAddr>,
+    // The total steal-time and the sequence number last written to the steal-time record.
+    steal_time: u64,
+    steal_time_seq: u32,
+    // The time at which the vCPU exited while still runnable, if it hasn't run since.
+    preempted_at: Option<u64>,
     guest_id: PageOwnerId,
     vcpu_id: u64,
 }
 
 impl VmCpu {
-    // Creates a new vCPU.
-    fn new(vcpu_id: u64, guest_id: PageOwnerId) -> Self {
+    // Creates a new vCPU with a time base offset from the host's by `htimedelta`.
+    fn new(vcpu_id: u64, guest_id: PageOwnerId, htimedelta: u64) -> Self {
         let mut state = VmCpuState::default();
+        state.guest_vcpu_csrs.htimedelta = htimedelta;
+        // The timer starts off disarmed.
+        state.guest_vcpu_csrs.vstimecmp = u64::MAX;
 
         // A0 holds the hart ID on entry.
         state.guest_regs.gprs.set_reg(GprIndex::A0, vcpu_id);
@@ -569,12 +680,28 @@ impl VmCpu {
             pmu_state: VmPmuState::default(),
             current_cpu: None,
             pending_mmio_op: None,
+            pending_ecall: false,
+            pending_system_resume: None,
+            pending_vprs_load: false,
             interrupt_file: None,
+            timer_deadline: None,
+            steal_time_addr: None,
+            steal_time: 0,
+            steal_time_seq: 0,
+            preempted_at: None,
             guest_id,
             vcpu_id,
         }
     }
 
+    // Returns the HGEIP/HGEIE bit of this vCPU's interrupt file, or 0 if it has none.
+    fn interrupt_file_bit(&self) -> u64 {
+        // Guest interrupt files are numbered from 1 in HGEIP/HGEIE.
+        self.interrupt_file
+            .filter(|f| f.bits() != 0)
+            .map_or(0, |f| 1 << f.bits())
+    }
+
     // Returns a reference to the shared-memory state area for this vCPU.
     fn shared_area(&self) -> &VmCpuSharedArea {
         // Unwrap ok: shared_area must've been initialized for this vCPU to have been activated.
@@ -593,16 +720,20 @@ pub struct ActiveVmCpu<'vcpu, 'pages, 'prev, T: GuestStagePagingMode> {
     parent_vcpu: Option<&'prev mut dyn VmCpuSaveState>,
     // True if this vCPU should be powered-off on drop().
     power_off: bool,
+    // True if this vCPU should be suspended on drop().
+    suspend: bool,
+    // The time, in the host's time base, at which this vCPU is to be preempted, if any.
+    preempt_deadline: Option<u64>,
 }
 
 impl<'vcpu, 'pages, 'prev, T: GuestStagePagingMode> ActiveVmCpu<'vcpu, 'pages, 'prev, T> {
     // Restores and activates the vCPU state from `vcpu`, with the VM address space represented by
     // `vm_pages`.
-    fn restore_from(
+    fn restore_from<P: GuestStagePagingMode>(
         container: &'vcpu VmCpus,
         mut vcpu: MutexGuard<'vcpu, VmCpu>,
         vm_pages: FinalizedVmPages<'pages, T>,
-        parent_vcpu: Option<&'prev mut ActiveVmCpu<T>>,
+        parent_vcpu: Option<&'prev mut ActiveVmCpu<P>>,
     ) -> Self {
         let this_cpu = PerCpu::this_cpu();
         if let Some(ref c) = vcpu.current_cpu && c.cpu != this_cpu.cpu_id() {
@@ -623,6 +754,8 @@ impl<'vcpu, 'pages, 'prev, T: GuestStagePagingMode> ActiveVmCpu<'vcpu, 'pages, '
             active_pages: None,
             parent_vcpu,
             power_off: false,
+            suspend: false,
+            preempt_deadline: None,
         };
         active_vcpu.restore();
         active_vcpu
@@ -631,17 +764,36 @@ impl<'vcpu, 'pages, 'prev, T: GuestStagePagingMode> ActiveVmCpu<'vcpu, 'pages, '
     /// Runs this vCPU until it traps.
     pub fn run(&mut self) -> VmCpuTrap {
         self.complete_pending_mmio_op();
+        self.complete_pending_ecall();
+        self.complete_pending_vprs_load();
+        self.update_steal_time();
 
         // TODO, HGEIE programinng:
         //  - Track which guests the host wants interrupts from (by trapping HGEIE accesses from
         //    VS level) and update HGEIE[2:] appropriately.
-        //  - If this is the host: clear HGEIE[1] on entry; inject SGEI into host VM if we receive
-        //    any SGEI at HS level.
-        //  - If this is a guest: set HGEIE[1] on entry; switch to the host VM for any SGEI that
-        //    occur, injecting an SEI for the host interrupts and SGEI for guest VM interrupts.
+        //  - If this is the host: inject SGEI into host VM if we receive any SGEI at HS level.
 
         // TODO: Enforce that the vCPU has an assigned interrupt file before running.
 
+        // Perform any fences and inject any virtual interrupts that were posted since we last ran.
+        self.complete_pending_fences();
+        let pending = self.container.take_pending_interrupts(self.vcpu.vcpu_id);
+        if pending != 0 {
+            CSR.hvip.read_and_set_bits(pending);
+            if LocalRegisterCopy::<u64, hvip::Register>::new(pending).is_set(hvip::vssoft) {
+                self.pmu().record_firmware_event(PmuFirmware::IpiReceived);
+            }
+        }
+        self.update_timer();
+
+        // Take an SGEI if an external interrupt arrives for any of the vCPUs we were activated
+        // from so that we can exit and let them handle it.
+        let parent_files = self
+            .parent_vcpu
+            .as_ref()
+            .map_or(0, |p| p.guest_external_interrupts());
+        CSR.hgeie.set(parent_files);
+
         let has_vector = CpuInfo::get().has_vector();
         let guest_id = self.vcpu.guest_id;
         let vcpu_state = &mut self.vcpu.state;
@@ -728,7 +880,7 @@ impl<'vcpu, 'pages, 'prev, T: GuestStagePagingMode> ActiveVmCpu<'vcpu, 'pages, '
                     VmCpuTrap::Other(vcpu_state.trap_csrs.clone())
                 }
             }
-            _ => VmCpuTrap::Other(vcpu_state.trap_csrs.clone()),
+            Trap::Interrupt(i) => VmCpuTrap::HypervisorInterrupt(i),
         }
     }
 
@@ -749,12 +901,21 @@ impl<'vcpu, 'pages, 'prev, T: GuestStagePagingMode> ActiveVmCpu<'vcpu, 'pages, '
             Load16U => MATCH_LHU,
             Load32U => MATCH_LWU,
             Load64 => MATCH_LD,
+            FpStore32 => MATCH_FSW,
+            FpStore64 => MATCH_FSD,
+            FpLoad32 => MATCH_FLW,
+            FpLoad64 => MATCH_FLD,
+        };
+        // Set rd (for loads) or rs2 (for stores) to A0, or F0 for floating point operations.
+        let reg = if mmio_op.opcode().is_fp() {
+            0
+        } else {
+            GprIndex::A0 as u32
         };
-        // Set rd (for loads) or rs2 (for stores) to A0.
         let htinst = if mmio_op.opcode().is_load() {
-            htinst_base | ((GprIndex::A0 as u32) << 20)
+            htinst_base | (reg << 20)
         } else {
-            htinst_base | ((GprIndex::A0 as u32) << 7)
+            htinst_base | (reg << 7)
         };
         htinst as u64
     }
@@ -763,14 +924,69 @@ impl<'vcpu, 'pages, 'prev, T: GuestStagePagingMode> ActiveVmCpu<'vcpu, 'pages, '
     /// deactivates this vCPU. The vCPU is either returned to the `Available` or `PoweredOff`
     /// state, depending on if the exit cause is resumable.
     pub fn exit(mut self, cause: VmExitCause) {
+        // Time spent waiting for an interrupt or suspended isn't stolen from the vCPU.
+        let preempted =
+            !matches!(cause, VmExitCause::Wfi(_) | VmExitCause::SystemSuspend(_)) && !self.suspend;
         let shared = self.vcpu.shared_area();
         use VmExitCause::*;
         match cause {
             ResumableEcall(msg) | FatalEcall(msg) => {
                 shared.update_with_ecall_exit(msg);
+                match msg {
+                    SbiMessage::HartState(StateFunction::HartStop) => {
+                        shared.update_exit_reason(TvmExitReason::HartStop, 0, 0, 0);
+                    }
+                    SbiMessage::TeeGuest(_) => {
+                        shared.update_exit_reason(TvmExitReason::TeeGuest, 0, msg.a6(), 0);
+                    }
+                    _ => {
+                        shared.update_exit_reason(TvmExitReason::Ecall, 0, msg.a7(), msg.a6());
+                    }
+                }
+            }
+            DebugConsole(dbcn_func) => {
+                let msg = SbiMessage::DebugConsole(dbcn_func);
+                shared.update_with_ecall_exit(msg);
+                shared.update_exit_reason(TvmExitReason::Ecall, 0, msg.a7(), msg.a6());
+                // We'll complete the ECALL with the result the host provides the next time this
+                // vCPU is run.
+                self.vcpu.pending_ecall = true;
+            }
+            SystemSuspend(susp_func) => {
+                let msg = SbiMessage::SystemSuspend(susp_func);
+                shared.update_with_ecall_exit(msg);
+                shared.update_exit_reason(TvmExitReason::Ecall, 0, msg.a7(), msg.a6());
+                // The host completes the ECALL once the system has resumed, or failed to suspend.
+                let SystemSuspendFunction::Suspend {
+                    resume_addr,
+                    opaque,
+                    ..
+                } = susp_func;
+                self.vcpu.pending_ecall = true;
+                self.vcpu.pending_system_resume = Some((resume_addr, opaque));
             }
-            PageFault(exception, page_addr) => {
+            SystemReset(reset_type, reason) => {
+                // Report the reset as an SRST ECALL so that the host can tell a shutdown apart
+                // from a reboot request and tear down or rebuild the VM accordingly.
+                let msg = SbiMessage::Reset(ResetFunction::Reset { reset_type, reason });
+                shared.update_with_ecall_exit(msg);
+                shared.update_exit_reason(
+                    TvmExitReason::SystemReset,
+                    0,
+                    reset_type as u64,
+                    reason as u64,
+                );
+            }
+            PageFault(pf, exception, page_addr) => {
                 shared.update_with_pf_exit(exception, page_addr.into());
+                let reason = match pf {
+                    PageFaultType::Shared => TvmExitReason::SharedPageFault,
+                    PageFaultType::Permission => TvmExitReason::PermissionFault,
+                    _ => TvmExitReason::ConfidentialPageFault,
+                };
+                shared.update_exit_reason(reason, page_addr.bits(), exception as u64, 0);
+                self.pmu()
+                    .record_firmware_event(PmuPlatformFirmware::ForwardedPageFault);
             }
             MmioFault(mmio_op, addr) => {
                 let exception = if mmio_op.opcode().is_load() {
@@ -780,31 +996,71 @@ impl<'vcpu, 'pages, 'prev, T: GuestStagePagingMode> ActiveVmCpu<'vcpu, 'pages, '
                 };
                 shared.update_with_pf_exit(exception, addr);
 
-                // The MMIO instruction is transformed as an ordinary load/store to/from A0, so
-                // update A0 with the value the vCPU wants to store.
+                // The MMIO instruction is transformed as an ordinary load/store to/from A0 (or F0
+                // for floating point operations), so update A0 or F0 with the value the vCPU wants
+                // to store.
                 use MmioOpcode::*;
                 let val = match mmio_op.opcode() {
                     Store8 => self.get_gpr(mmio_op.register()) as u8 as u64,
                     Store16 => self.get_gpr(mmio_op.register()) as u16 as u64,
                     Store32 => self.get_gpr(mmio_op.register()) as u32 as u64,
                     Store64 => self.get_gpr(mmio_op.register()),
+                    FpStore32 => self.get_fpr(mmio_op.fp_register()) as u32 as u64,
+                    FpStore64 => self.get_fpr(mmio_op.fp_register()),
                     _ => 0,
                 };
                 shared.as_ref().set_htinst(Self::mmio_op_to_htinst(mmio_op));
-                shared.as_ref().set_gpr(GprIndex::A0, val);
+                if mmio_op.opcode().is_fp() {
+                    shared.as_ref().set_fpr(0, val);
+                } else {
+                    shared.as_ref().set_gpr(GprIndex::A0, val);
+                }
+                let reason = if mmio_op.opcode().is_load() {
+                    TvmExitReason::MmioLoad
+                } else {
+                    TvmExitReason::MmioStore
+                };
+                shared.update_exit_reason(reason, addr.bits(), mmio_op.opcode().width(), val);
 
                 // We'll complete a load instruction the next time this vCPU is run.
                 self.vcpu.pending_mmio_op = Some(mmio_op);
             }
             Wfi(inst) => {
                 shared.update_with_vi_exit(inst.raw() as u64);
+                shared.update_exit_reason(TvmExitReason::Wfi, 0, 0, 0);
+            }
+            Interrupted(irq) => {
+                // Report the interrupt as the trap cause; the host is expected to handle it and
+                // then resume the vCPU.
+                let scause = Trap::Interrupt(irq).to_scause();
+                shared.update_with_unhandled_exit(scause);
+                shared.update_exit_reason(TvmExitReason::Interrupted, 0, scause, 0);
+            }
+            Preempted(irq) => {
+                // Reported like any other interrupt; the vCPU may be resumed right away.
+                let scause = Trap::Interrupt(irq).to_scause();
+                shared.update_with_unhandled_exit(scause);
+                shared.update_exit_reason(TvmExitReason::Preempted, 0, scause, 0);
             }
             UnhandledTrap(scause) => {
                 shared.update_with_unhandled_exit(scause);
+                shared.update_exit_reason(TvmExitReason::FatalTrap, 0, scause, 0);
             }
         };
 
+        if CpuInfo::get().has_vector() {
+            let shared = self.vcpu.shared_area().as_ref();
+            for i in 0..32 {
+                shared.set_vpr(i, &self.vcpu.state.guest_regs.vprs.reg(i));
+            }
+            self.vcpu.pending_vprs_load = true;
+        }
+
         self.power_off = cause.is_fatal();
+        if preempted && !self.power_off {
+            self.vcpu.preempted_at = Some(CSR.time.get());
+            self.write_steal_time_record(offset_of!(StealTimeRecord, preempted), &[1]);
+        }
     }
 
     /// Delivers the given exception to the vCPU, setting up its register state to handle the trap
@@ -839,6 +1095,23 @@ impl<'vcpu, 'pages, 'prev, T: GuestStagePagingMode> ActiveVmCpu<'vcpu, 'pages, '
         self.vcpu.state.guest_regs.gprs.set_reg(gpr, value);
     }
 
+    /// Returns one of the vCPU's floating point registers.
+    pub fn get_fpr(&self, index: usize) -> u64 {
+        self.vcpu.state.guest_regs.fprs.reg(index)
+    }
+
+    /// Sets one of the vCPU's floating point registers, marking the floating point state as dirty.
+    pub fn set_fpr(&mut self, index: usize, value: u64) {
+        self.vcpu.state.guest_regs.fprs.set_reg(index, value);
+        let mut sstatus = LocalRegisterCopy::<u64, sstatus::Register>::new(
+            self.vcpu.state.guest_regs.sstatus,
+        );
+        sstatus.modify(sstatus::fs::Dirty);
+        self.vcpu.state.guest_regs.sstatus = sstatus.get();
+        // The guest must also observe that its floating point state has been modified.
+        CSR.vsstatus.modify(sstatus::fs::Dirty);
+    }
+
     /// Increments the current `sepc` CSR value by `value`.
     pub fn inc_sepc(&mut self, value: u64) {
         self.vcpu.state.guest_regs.sepc += value;
@@ -879,51 +1152,234 @@ impl<'vcpu, 'pages, 'prev, T: GuestStagePagingMode> ActiveVmCpu<'vcpu, 'pages, '
         &mut self.vcpu.pmu_state
     }
 
+    /// Returns the ID of this vCPU.
+    pub fn vcpu_id(&self) -> u64 {
+        self.vcpu.vcpu_id
+    }
+
+    /// Suspends this vCPU once it exits. For a non-retentive suspend, the vCPU is set up to resume
+    /// execution at `resume_addr` with `opaque` in A1; for a retentive suspend it will resume after
+    /// the ECALL, which the caller is expected to complete.
+    pub fn suspend(&mut self, suspend_type: HartSuspendType, resume_addr: u64, opaque: u64) {
+        if suspend_type == HartSuspendType::DefaultNonRetentive {
+            self.set_resume_state(resume_addr, opaque);
+        }
+        self.suspend = true;
+    }
+
+    // Sets up the vCPU to resume at `resume_addr` with `opaque` in A1 in VS-mode with translation
+    // and interrupts disabled, as if the vCPU had been started with HartStart.
+    fn set_resume_state(&mut self, resume_addr: u64, opaque: u64) {
+        let vcpu_id = self.vcpu.vcpu_id;
+        self.vcpu.state.guest_regs.sepc = resume_addr;
+        self.set_gpr(GprIndex::A0, vcpu_id);
+        self.set_gpr(GprIndex::A1, opaque);
+        let mut sstatus =
+            LocalRegisterCopy::<u64, sstatus::Register>::new(self.vcpu.state.guest_regs.sstatus);
+        sstatus.modify(sstatus::spp::Supervisor);
+        self.vcpu.state.guest_regs.sstatus = sstatus.get();
+        CSR.vsstatus.read_and_clear_field(sstatus::sie);
+        CSR.vsatp.set(0);
+    }
+
+    /// Sets the steal-time record of this vCPU to the `StealTimeRecord` at `addr`, or stops
+    /// reporting steal-time if `addr` is `None`. The caller must have initialized the record.
+    pub fn set_steal_time_addr(&mut self, addr: Option<GuestPhysAddr>) {
+        self.vcpu.steal_time_addr = addr;
+        self.vcpu.steal_time = 0;
+        self.vcpu.steal_time_seq = 0;
+    }
+
+    /// Programs this vCPU's timer to fire at `stime_value`, in the vCPU's time base, clearing any
+    /// pending virtual timer interrupt.
+    pub fn set_timer(&mut self, stime_value: u64) {
+        CSR.hvip.read_and_clear_field(hvip::vstimer);
+        if CpuInfo::get().has_sstc() {
+            // VSTIMECMP is already in the vCPU's time base and raises VSTIP without our involvement.
+            CSR.vstimecmp.set(stime_value);
+        } else {
+            let htimedelta = self.vcpu.state.guest_vcpu_csrs.htimedelta;
+            self.vcpu.timer_deadline = host_timer_deadline(stime_value, htimedelta);
+        }
+    }
+
+    /// Handles a supervisor timer interrupt taken while this vCPU was running. Returns true if the
+    /// timer of one of the vCPUs this vCPU was activated from has expired, in which case this vCPU
+    /// must exit so that the expired timer can be delivered. This vCPU's own timer is delivered
+    /// the next time it is run.
+    pub fn handle_timer_interrupt(&mut self) -> bool {
+        let now = CSR.time.get();
+        self.parent_vcpu
+            .as_ref()
+            .and_then(|p| p.next_timer_deadline())
+            .map_or(false, |deadline| deadline <= now)
+    }
+
+    /// Sets the amount of time, in ticks of the host's time base, that this vCPU may run before it
+    /// is preempted. The vCPU may run indefinitely if `time_slice` is 0.
+    pub fn set_time_slice(&mut self, time_slice: u64) {
+        self.preempt_deadline =
+            (time_slice != 0).then(|| CSR.time.get().saturating_add(time_slice));
+    }
+
+    /// Returns true if this vCPU's time slice has expired.
+    pub fn time_slice_expired(&self) -> bool {
+        self.preempt_deadline
+            .map_or(false, |deadline| deadline <= CSR.time.get())
+    }
+
+    /// Returns true if an interrupt has arrived for one of the vCPUs this vCPU was activated from,
+    /// in which case this vCPU must exit so that the interrupt can be handled.
+    pub fn parent_interrupt_pending(&self) -> bool {
+        self.parent_vcpu
+            .as_ref()
+            .map_or(false, |p| p.has_pending_interrupts())
+    }
+
+    /// Performs any fences that were requested of this vCPU by other vCPUs in the VM.
+    pub fn complete_pending_fences(&mut self) {
+        let fences = self.container.take_pending_fences(self.vcpu.vcpu_id);
+        if fences & VmCpuFence::AddressTranslation as u64 != 0 {
+            // HGATP holds this vCPU's VMID, so only its VS-stage translations are invalidated.
+            tlb::hfence_vvma(None, None);
+            self.pmu()
+                .record_firmware_event(PmuFirmware::SfenceVmaReceived);
+        }
+        if fences & VmCpuFence::GuestPhysTranslation as u64 != 0 {
+            // Use the VMID this VM was assigned on this CPU.
+            let vmid = self.vcpu.current_cpu.as_ref().map(|c| c.vmid.vmid());
+            tlb::hfence_gvma(None, vmid);
+        }
+        if fences & VmCpuFence::Instruction as u64 != 0 {
+            fence_i();
+            self.pmu()
+                .record_firmware_event(PmuFirmware::FenceIReceived);
+        }
+    }
+
+    // Injects a virtual timer interrupt if this vCPU's timer has expired, and then programs this
+    // CPU's timer with the earliest deadline of this vCPU and the vCPUs it was activated from.
+    fn update_timer(&mut self) {
+        if let Some(deadline) = self.vcpu.timer_deadline && deadline <= CSR.time.get() {
+            CSR.hvip.read_and_set_field(hvip::vstimer);
+            self.vcpu.timer_deadline = None;
+        }
+        PerCpu::this_cpu().set_timer(self.next_timer_deadline());
+    }
+
+    // Adds the time since this vCPU was preempted, if it was, to the steal-time reported in its
+    // steal-time record.
+    fn update_steal_time(&mut self) {
+        let preempted_at = match self.vcpu.preempted_at.take() {
+            Some(t) => t,
+            None => return,
+        };
+        if self.vcpu.steal_time_addr.is_none() {
+            return;
+        }
+        let steal = CSR.time.get().saturating_sub(preempted_at);
+        self.vcpu.steal_time = self.vcpu.steal_time.wrapping_add(steal);
+
+        // Make the sequence number odd while we update the record so that the vCPU knows to retry
+        // if it reads the record concurrently, e.g. from another vCPU.
+        let seq = self.vcpu.steal_time_seq.wrapping_add(1);
+        self.write_steal_time_record(offset_of!(StealTimeRecord, sequence), &seq.to_le_bytes());
+        let steal_time = self.vcpu.steal_time;
+        self.write_steal_time_record(
+            offset_of!(StealTimeRecord, steal),
+            &steal_time.to_le_bytes(),
+        );
+        self.write_steal_time_record(offset_of!(StealTimeRecord, preempted), &[0]);
+        let seq = seq.wrapping_add(1);
+        self.write_steal_time_record(offset_of!(StealTimeRecord, sequence), &seq.to_le_bytes());
+        self.vcpu.steal_time_seq = seq;
+    }
+
+    // Writes `bytes` at `offset` in this vCPU's steal-time record, if it has one. Failures are
+    // ignored as there's no one to report them to; it's up to the vCPU to keep its record mapped.
+    fn write_steal_time_record(&self, offset: usize, bytes: &[u8]) {
+        let addr = self
+            .vcpu
+            .steal_time_addr
+            .and_then(|addr| addr.checked_increment(offset as u64));
+        if let Some(addr) = addr {
+            let _ = self.active_pages().copy_to_guest(addr, bytes);
+        }
+    }
+
     // Completes any pending MMIO operation for this CPU.
     fn complete_pending_mmio_op(&mut self) {
         // Complete any pending load operations. The host is expected to have written the value
-        // to complete the load to A0.
+        // to complete the load to A0, or F0 for floating point loads.
         if let Some(mmio_op) = self.vcpu.pending_mmio_op {
-            let val = self.vcpu.shared_area().as_ref().gpr(GprIndex::A0);
-            use MmioOpcode::*;
+            let shared = self.vcpu.shared_area().as_ref();
+            let val = if mmio_op.opcode().is_fp() {
+                shared.fpr(0)
+            } else {
+                shared.gpr(GprIndex::A0)
+            };
             // Write the value to the actual destination register.
-            match mmio_op.opcode() {
-                Load8 => {
-                    self.set_gpr(mmio_op.register(), val as i8 as u64);
-                }
-                Load8U => {
-                    self.set_gpr(mmio_op.register(), val as u8 as u64);
-                }
-                Load16 => {
-                    self.set_gpr(mmio_op.register(), val as i16 as u64);
-                }
-                Load16U => {
-                    self.set_gpr(mmio_op.register(), val as u16 as u64);
-                }
-                Load32 => {
-                    self.set_gpr(mmio_op.register(), val as i32 as u64);
-                }
-                Load32U => {
-                    self.set_gpr(mmio_op.register(), val as u32 as u64);
-                }
-                Load64 => {
+            let opcode = mmio_op.opcode();
+            if opcode.is_load() {
+                let val = opcode.extend_loaded_value(val);
+                if opcode.is_fp() {
+                    self.set_fpr(mmio_op.fp_register(), val);
+                } else {
                     self.set_gpr(mmio_op.register(), val);
                 }
-                _ => (),
-            };
+            }
 
             self.vcpu.pending_mmio_op = None;
-            self.vcpu.shared_area().as_ref().set_gpr(GprIndex::A0, 0);
+            if mmio_op.opcode().is_fp() {
+                self.vcpu.shared_area().as_ref().set_fpr(0, 0);
+            } else {
+                self.vcpu.shared_area().as_ref().set_gpr(GprIndex::A0, 0);
+            }
 
             // Advance SEPC past the faulting instruction.
             self.inc_sepc(mmio_op.len() as u64);
         }
     }
 
+    // Completes any ECALL that was forwarded to the host for this CPU.
+    fn complete_pending_ecall(&mut self) {
+        // The host is expected to have written the SBI return value to A0 and A1.
+        if self.vcpu.pending_ecall {
+            let shared = self.vcpu.shared_area().as_ref();
+            let ret = SbiReturn {
+                error_code: shared.gpr(GprIndex::A0) as i64,
+                return_value: shared.gpr(GprIndex::A1),
+            };
+            // A successful system suspend doesn't return; the vCPU resumes at the address it
+            // passed in instead.
+            match self.vcpu.pending_system_resume.take() {
+                Some((resume_addr, opaque)) if ret.error_code == sbi::SBI_SUCCESS => {
+                    self.set_resume_state(resume_addr, opaque);
+                }
+                _ => self.set_ecall_result(SbiReturnType::Standard(ret)),
+            }
+            self.vcpu.pending_ecall = false;
+        }
+    }
+
+    // Reloads the vector registers from the shared-memory state area if the vCPU exited to the host
+    // since it was last run. They're restored to the CPU along with the rest of the vector state on
+    // entry to the vCPU.
+    fn complete_pending_vprs_load(&mut self) {
+        if self.vcpu.pending_vprs_load {
+            for i in 0..32 {
+                let val = self.vcpu.shared_area().as_ref().vpr(i);
+                self.vcpu.state.guest_regs.vprs.set_reg(i, &val);
+            }
+            self.vcpu.pending_vprs_load = false;
+        }
+    }
+
     // Saves the VS-level CSRs.
     fn save_vcpu_csrs(&mut self) {
         let vcpu_csrs = &mut self.vcpu.state.guest_vcpu_csrs;
         vcpu_csrs.htimedelta = CSR.htimedelta.get();
+        vcpu_csrs.hvip = CSR.hvip.get();
         vcpu_csrs.vsstatus = CSR.vsstatus.get();
         vcpu_csrs.vsie = CSR.vsie.get();
         vcpu_csrs.vstvec = CSR.vstvec.get();
@@ -942,6 +1398,7 @@ impl<'vcpu, 'pages, 'prev, T: GuestStagePagingMode> ActiveVmCpu<'vcpu, 'pages, '
         let vcpu_csrs = &self.vcpu.state.guest_vcpu_csrs;
         // Safe as these don't take effect until V=1.
         CSR.htimedelta.set(vcpu_csrs.htimedelta);
+        CSR.hvip.set(vcpu_csrs.hvip);
         CSR.vsstatus.set(vcpu_csrs.vsstatus);
         CSR.vsie.set(vcpu_csrs.vsie);
         CSR.vstvec.set(vcpu_csrs.vstvec);
@@ -996,19 +1453,74 @@ trait VmCpuSaveState {
 
     // Re-activates this `ActiveVmCpu` by restoring the state that was saved with `save()`.
     fn restore(&mut self);
+
+    // Returns the earliest timer deadline, in the host's time base, of this `ActiveVmCpu` and the
+    // vCPUs it was activated from.
+    fn next_timer_deadline(&self) -> Option<u64>;
+
+    // Returns the guest external interrupts, as a mask of HGEIE bits, destined for the interrupt
+    // files of this `ActiveVmCpu` and the vCPUs it was activated from.
+    fn guest_external_interrupts(&self) -> u64;
+
+    // Returns true if virtual interrupts have been posted to, or external interrupts are pending
+    // in the interrupt files of, this `ActiveVmCpu` or the vCPUs it was activated from.
+    fn has_pending_interrupts(&self) -> bool;
 }
 
 impl<T: GuestStagePagingMode> VmCpuSaveState for ActiveVmCpu<'_, '_, '_, T> {
     fn save(&mut self) {
+        self.container.set_active_cpu(self.vcpu.vcpu_id, None);
         self.active_pages = None;
         self.save_vcpu_csrs();
         self.pmu().save_counters();
+        if CpuInfo::get().has_sstc() {
+            // VSTIMECMP isn't in effect while we're saved, so track the deadline in software so
+            // that it can be multiplexed with the timers of other vCPUs on this CPU.
+            let vcpu_csrs = &self.vcpu.state.guest_vcpu_csrs;
+            self.vcpu.timer_deadline =
+                host_timer_deadline(vcpu_csrs.vstimecmp, vcpu_csrs.htimedelta);
+        }
     }
 
     fn restore(&mut self) {
         self.restore_vcpu_csrs();
         self.restore_vm_pages();
         self.pmu().restore_counters();
+        if CpuInfo::get().has_sstc() {
+            // VSTIMECMP takes over again.
+            self.vcpu.timer_deadline = None;
+        }
+        self.container
+            .set_active_cpu(self.vcpu.vcpu_id, Some(PerCpu::this_cpu().cpu_id()));
+    }
+
+    fn next_timer_deadline(&self) -> Option<u64> {
+        let parent_deadline = self
+            .parent_vcpu
+            .as_ref()
+            .and_then(|p| p.next_timer_deadline());
+        [self.vcpu.timer_deadline, self.preempt_deadline, parent_deadline]
+            .into_iter()
+            .flatten()
+            .min()
+    }
+
+    fn guest_external_interrupts(&self) -> u64 {
+        let parent_files = self
+            .parent_vcpu
+            .as_ref()
+            .map_or(0, |p| p.guest_external_interrupts());
+        self.vcpu.interrupt_file_bit() | parent_files
+    }
+
+    fn has_pending_interrupts(&self) -> bool {
+        let parent_pending = self
+            .parent_vcpu
+            .as_ref()
+            .map_or(false, |p| p.has_pending_interrupts());
+        self.container.has_pending_interrupts(self.vcpu.vcpu_id)
+            || CSR.hgeip.get() & self.vcpu.interrupt_file_bit() != 0
+            || parent_pending
     }
 }
 
@@ -1028,8 +1540,16 @@ impl<T: GuestStagePagingMode> Drop for ActiveVmCpu<'_, '_, '_, T> {
             .unwrap();
         let mut status = entry.status.write();
         assert_eq!(*status, VmCpuStatus::Running);
+        entry.running_cpu.store(VCPU_NOT_ACTIVE, Ordering::SeqCst);
         *status = if self.power_off {
             VmCpuStatus::PoweredOff
+        } else if self.suspend {
+            // Interrupts may have been posted while we were on our way out.
+            if entry.pending_interrupts.load(Ordering::SeqCst) != 0 {
+                VmCpuStatus::ResumePending
+            } else {
+                VmCpuStatus::Suspended
+            }
         } else {
             VmCpuStatus::Runnable
         };
@@ -1074,6 +1594,11 @@ impl<'vcpu> IdleVmCpu<'vcpu> {
         self.vcpu.imsic_location
     }
 
+    /// Returns the interrupt file this vCPU is bound to, if any.
+    pub fn get_interrupt_file(&self) -> Option<ImsicFileId> {
+        self.vcpu.interrupt_file
+    }
+
     /// Sets the interrupt file for this vCPU.
     pub fn set_interrupt_file(&mut self, interrupt_file: ImsicFileId) {
         self.vcpu.interrupt_file = Some(interrupt_file);
@@ -1086,6 +1611,19 @@ impl<'vcpu> IdleVmCpu<'vcpu> {
     }
 }
 
+/// Fences that may be requested of a vCPU by other vCPUs in the same VM. Requested fences are
+/// performed before the vCPU next executes guest code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u64)]
+pub enum VmCpuFence {
+    /// Synchronize the vCPU's instruction stream with prior stores (FENCE.I).
+    Instruction = 1 << 0,
+    /// Invalidate all of the vCPU's VS-stage address translations.
+    AddressTranslation = 1 << 1,
+    /// Invalidate all of the VM's G-stage address translations on the vCPU's CPU.
+    GuestPhysTranslation = 1 << 2,
+}
+
 /// Represents the state of a vCPU in a VM.
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum VmCpuStatus {
@@ -1097,14 +1635,40 @@ pub enum VmCpuStatus {
     Runnable,
     /// The vCPU has been claimed exclusively for running on a (physical) CPU.
     Running,
+    /// The vCPU has suspended itself and is waiting for an interrupt.
+    Suspended,
+    /// The vCPU was suspended, but has received an interrupt and is waiting to be run.
+    ResumePending,
+}
+
+impl VmCpuStatus {
+    // Returns true if the vCPU is present and powered on, but not running.
+    fn is_idle(&self) -> bool {
+        use VmCpuStatus::*;
+        matches!(self, Runnable | Suspended | ResumePending)
+    }
 }
 
 struct VmCpusInner {
     // Locking: status must be locked before vcpu.
     status: RwLock<VmCpuStatus>,
     vcpu: Mutex<VmCpu>,
+    // Virtual interrupts, as a mask of HVIP bits, to be injected the next time the vCPU is run.
+    // Kept outside of `vcpu` so that interrupts can be posted while the vCPU is running.
+    pending_interrupts: AtomicU64,
+    // Mask of `VmCpuFence`s to be performed the next time the vCPU is run.
+    pending_fences: AtomicU64,
+    // The physical CPU the vCPU's state is currently loaded on, or `VCPU_NOT_ACTIVE`. Note that
+    // a `Running` vCPU is not active while it is running a child vCPU.
+    active_cpu: AtomicUsize,
+    // The physical CPU a `Running` vCPU is running on, or `VCPU_NOT_ACTIVE`. Unlike `active_cpu`
+    // this remains set while the vCPU is running a child vCPU.
+    running_cpu: AtomicUsize,
 }
 
+// Value of `VmCpusInner::active_cpu` for a vCPU that isn't loaded on any physical CPU.
+const VCPU_NOT_ACTIVE: usize = usize::MAX;
+
 /// The set of vCPUs in a VM.
 pub struct VmCpus {
     inner: PageVec<VmCpusInner>,
@@ -1121,11 +1685,21 @@ impl VmCpus {
         if num_vcpus == 0 {
             return Err(Error::InsufficientVmCpuStorage);
         }
+        // Guest VMs get their own time base which starts from zero when the VM is created.
+        let htimedelta = if guest_id.is_host() {
+            0
+        } else {
+            CSR.time.get().wrapping_neg()
+        };
         let mut inner = PageVec::new(pages, page_tracker);
         for i in 0..num_vcpus {
             let entry = VmCpusInner {
                 status: RwLock::new(VmCpuStatus::NotPresent),
-                vcpu: Mutex::new(VmCpu::new(i, guest_id)),
+                vcpu: Mutex::new(VmCpu::new(i, guest_id, htimedelta)),
+                pending_interrupts: AtomicU64::new(0),
+                pending_fences: AtomicU64::new(0),
+                active_cpu: AtomicUsize::new(VCPU_NOT_ACTIVE),
+                running_cpu: AtomicUsize::new(VCPU_NOT_ACTIVE),
             };
             inner.push(entry);
         }
@@ -1156,12 +1730,12 @@ impl VmCpus {
         let entry = self.inner.get(vcpu_id as usize).ok_or(Error::BadCpuId)?;
         let status = entry.status.read();
         match *status {
-            VmCpuStatus::PoweredOff | VmCpuStatus::Runnable => Ok(IdleVmCpu {
+            s if s == VmCpuStatus::PoweredOff || s.is_idle() => Ok(IdleVmCpu {
                 _status: status,
                 vcpu: entry.vcpu.lock(),
             }),
-            VmCpuStatus::Running => Err(Error::VmCpuRunning),
             VmCpuStatus::NotPresent => Err(Error::VmCpuNotFound),
+            _ => Err(Error::VmCpuRunning),
         }
     }
 
@@ -1178,29 +1752,37 @@ impl VmCpus {
                     vcpu: entry.vcpu.lock(),
                 })
             }
-            VmCpuStatus::Running | VmCpuStatus::Runnable => Err(Error::VmCpuAlreadyPowered),
             VmCpuStatus::NotPresent => Err(Error::VmCpuNotFound),
+            _ => Err(Error::VmCpuAlreadyPowered),
         }
     }
 
     /// Takes exclusive ownership of and activates the vCPU with `vcpu_id`, returning it as an
     /// `ActiveVmCpu`. If `parent_vcpu` is not `None`, its state is saved before this vCPU is
     /// activated and is restored when the returned `ActiveVmCpu` is dropped.
-    pub fn activate_vcpu<'vcpu, 'pages, 'prev: 'vcpu + 'pages, T: GuestStagePagingMode>(
+    pub fn activate_vcpu<'vcpu, 'pages, 'prev, T, P>(
         &'vcpu self,
         vcpu_id: u64,
         vm_pages: FinalizedVmPages<'pages, T>,
-        mut parent_vcpu: Option<&'prev mut ActiveVmCpu<T>>,
-    ) -> Result<ActiveVmCpu<'vcpu, 'pages, 'prev, T>> {
+        mut parent_vcpu: Option<&'prev mut ActiveVmCpu<P>>,
+    ) -> Result<ActiveVmCpu<'vcpu, 'pages, 'prev, T>>
+    where
+        'prev: 'vcpu + 'pages,
+        T: GuestStagePagingMode,
+        P: GuestStagePagingMode,
+    {
         let entry = self.inner.get(vcpu_id as usize).ok_or(Error::BadCpuId)?;
         let mut status = entry.status.write();
         match *status {
-            VmCpuStatus::Runnable => {
+            s if s.is_idle() => {
                 let vcpu = entry.vcpu.lock();
                 if vcpu.guest_id != vm_pages.page_owner_id() {
                     return Err(Error::WrongAddressSpace);
                 }
                 *status = VmCpuStatus::Running;
+                entry
+                    .running_cpu
+                    .store(PerCpu::this_cpu().cpu_id().raw(), Ordering::SeqCst);
 
                 // Context-switch to the vCPU.
                 if let Some(ref mut p) = parent_vcpu {
@@ -1208,9 +1790,9 @@ impl VmCpus {
                 }
                 Ok(ActiveVmCpu::restore_from(self, vcpu, vm_pages, parent_vcpu))
             }
-            VmCpuStatus::Running => Err(Error::VmCpuRunning),
             VmCpuStatus::PoweredOff => Err(Error::VmCpuOff),
             VmCpuStatus::NotPresent => Err(Error::VmCpuNotFound),
+            _ => Err(Error::VmCpuRunning),
         }
     }
 
@@ -1219,6 +1801,136 @@ impl VmCpus {
         let entry = self.inner.get(vcpu_id as usize).ok_or(Error::BadCpuId)?;
         Ok(*entry.status.read())
     }
+
+    /// Returns true if any of the vCPUs is currently running on a physical CPU.
+    pub fn any_running(&self) -> bool {
+        self.inner
+            .iter()
+            .any(|e| *e.status.read() == VmCpuStatus::Running)
+    }
+
+    /// Posts the virtual interrupt `irq` to the vCPU with `vcpu_id`. The interrupt is injected the
+    /// next time the vCPU enters the guest, and resumes the vCPU if it is suspended. If the vCPU is
+    /// currently running, returns the physical CPU it is running on so that the caller may kick it
+    /// out of the guest, or out of the child vCPU it is running. If the vCPU was suspended, returns
+    /// the physical CPU it suspended on so that the caller may wake it with `wait_for_resume()`.
+    pub fn post_interrupt(&self, vcpu_id: u64, irq: Interrupt) -> Result<Option<CpuId>> {
+        let entry = self.inner.get(vcpu_id as usize).ok_or(Error::BadCpuId)?;
+        let hvip_bits = irq
+            .to_hvip_field()
+            .map_err(|_| Error::InvalidInterrupt)?
+            .value;
+        let mut status = entry.status.write();
+        if *status == VmCpuStatus::NotPresent {
+            return Err(Error::VmCpuNotFound);
+        }
+        entry
+            .pending_interrupts
+            .fetch_or(hvip_bits, Ordering::SeqCst);
+        if *status == VmCpuStatus::Suspended {
+            *status = VmCpuStatus::ResumePending;
+            // Nothing else can hold the vCPU lock while we hold the status lock for writing.
+            let cpu = entry.vcpu.lock().current_cpu.as_ref().map(|c| c.cpu);
+            return Ok(cpu);
+        }
+        let cpu = entry.running_cpu.load(Ordering::SeqCst);
+        Ok((cpu != VCPU_NOT_ACTIVE).then(|| CpuId::new(cpu)))
+    }
+
+    /// Waits on this physical CPU until the suspended vCPU with `vcpu_id` has a reason to resume,
+    /// marking it as `ResumePending`. The vCPU resumes if it has a pending virtual interrupt, if
+    /// its timer has expired, or if an external interrupt is pending in its interrupt file.
+    /// Returns immediately if the vCPU isn't suspended.
+    pub fn wait_for_resume(&self, vcpu_id: u64) -> Result<()> {
+        let entry = self.inner.get(vcpu_id as usize).ok_or(Error::BadCpuId)?;
+        loop {
+            {
+                let mut status = entry.status.write();
+                if *status != VmCpuStatus::Suspended {
+                    return Ok(());
+                }
+                let vcpu = entry.vcpu.lock();
+                let file_bit = vcpu.interrupt_file_bit();
+                let timer_expired = vcpu
+                    .timer_deadline
+                    .map_or(false, |deadline| deadline <= CSR.time.get());
+                if vcpu.state.guest_vcpu_csrs.hvip != 0
+                    || entry.pending_interrupts.load(Ordering::SeqCst) != 0
+                    || CSR.hgeip.get() & file_bit != 0
+                    || timer_expired
+                {
+                    *status = VmCpuStatus::ResumePending;
+                    return Ok(());
+                }
+
+                // Make sure we're woken up by the vCPU's timer and its external interrupts.
+                PerCpu::this_cpu().set_timer(vcpu.timer_deadline);
+                CSR.hgeie.set(file_bit);
+            }
+            smp::wfi();
+            CSR.hgeie.set(0);
+        }
+    }
+
+    /// Requests that the vCPU with `vcpu_id` perform `fence` before it next executes guest code.
+    /// If the vCPU is currently active, returns the physical CPU it is active on so that the caller
+    /// may kick it out of the guest and wait for it with `fence_completed()`.
+    pub fn post_fence(&self, vcpu_id: u64, fence: VmCpuFence) -> Result<Option<CpuId>> {
+        let entry = self.inner.get(vcpu_id as usize).ok_or(Error::BadCpuId)?;
+        let status = entry.status.read();
+        if *status == VmCpuStatus::NotPresent {
+            return Err(Error::VmCpuNotFound);
+        }
+        entry
+            .pending_fences
+            .fetch_or(fence as u64, Ordering::SeqCst);
+        Ok(Self::active_cpu_of(entry))
+    }
+
+    /// Returns true if the vCPU with `vcpu_id` has performed all the fences requested of it with
+    /// `post_fence()`, or is not active and thus not executing guest code. An inactive vCPU will
+    /// perform any pending fences before it next runs.
+    pub fn fence_completed(&self, vcpu_id: u64) -> bool {
+        self.inner.get(vcpu_id as usize).map_or(true, |entry| {
+            entry.pending_fences.load(Ordering::SeqCst) == 0
+                || Self::active_cpu_of(entry).is_none()
+        })
+    }
+
+    // Returns the physical CPU the vCPU in `entry` is active on, if any.
+    fn active_cpu_of(entry: &VmCpusInner) -> Option<CpuId> {
+        let cpu = entry.active_cpu.load(Ordering::SeqCst);
+        (cpu != VCPU_NOT_ACTIVE).then(|| CpuId::new(cpu))
+    }
+
+    // Marks the vCPU with `vcpu_id` as being active on `cpu`, or not active if `cpu` is `None`.
+    fn set_active_cpu(&self, vcpu_id: u64, cpu: Option<CpuId>) {
+        // Unwrap ok: vcpu_id must be valid for a vCPU which is running.
+        let entry = self.inner.get(vcpu_id as usize).unwrap();
+        let raw = cpu.map_or(VCPU_NOT_ACTIVE, |c| c.raw());
+        entry.active_cpu.store(raw, Ordering::SeqCst);
+    }
+
+    // Returns true if virtual interrupts are pending for the vCPU with `vcpu_id`.
+    fn has_pending_interrupts(&self, vcpu_id: u64) -> bool {
+        // Unwrap ok: vcpu_id must be valid for a vCPU which is running.
+        let entry = self.inner.get(vcpu_id as usize).unwrap();
+        entry.pending_interrupts.load(Ordering::SeqCst) != 0
+    }
+
+    // Claims the virtual interrupts pending for the vCPU with `vcpu_id`.
+    fn take_pending_interrupts(&self, vcpu_id: u64) -> u64 {
+        // Unwrap ok: vcpu_id must be valid for a vCPU which is running.
+        let entry = self.inner.get(vcpu_id as usize).unwrap();
+        entry.pending_interrupts.swap(0, Ordering::SeqCst)
+    }
+
+    // Claims the fences pending for the vCPU with `vcpu_id`.
+    fn take_pending_fences(&self, vcpu_id: u64) -> u64 {
+        // Unwrap ok: vcpu_id must be valid for a vCPU which is running.
+        let entry = self.inner.get(vcpu_id as usize).unwrap();
+        entry.pending_fences.swap(0, Ordering::SeqCst)
+    }
 }
 
 // Safety: Each VmCpu is wrapped with a Mutex to provide safe concurrent access to VmCpu and its
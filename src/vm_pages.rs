@@ -6,12 +6,12 @@ use attestation::measurement::AttestationManager;
 use core::arch::global_asm;
 use core::marker::PhantomData;
 use drivers::{imsic::*, iommu::*, pci::PciBarPage, pci::PciDevice, pci::PcieRoot};
-use page_tracking::collections::PageVec;
 use page_tracking::{
-    LockedPageList, PageList, PageTracker, PageTrackingError, TlbVersion, MAX_PAGE_OWNERS,
+    InvalidatedRanges, LockedPageList, PageList, PageTracker, PageTrackingError, TlbVersion,
+    VmRegionError, VmRegionList, VmRegionType, MAX_PAGE_OWNERS, MAX_RANGED_FENCE_PAGES,
 };
 use riscv_page_tables::{
-    tlb, GuestStageMapper, GuestStagePageTable, GuestStagePagingMode, PageTableError,
+    tlb, GuestStageMapper, GuestStagePageTable, GuestStagePagingMode, PageTableError, PteLeafPerms,
 };
 use riscv_pages::*;
 use riscv_regs::{
@@ -20,6 +20,7 @@ use riscv_regs::{
 };
 use spin::{Mutex, Once};
 
+use crate::guest_tracking::Guests;
 use crate::vm::{Vm, VmStateAny, VmStateFinalized, VmStateInitializing};
 use crate::vm_cpu::VmCpus;
 use crate::vm_id::VmId;
@@ -40,6 +41,8 @@ pub enum Error {
     OverlappingVmRegion,
     InsufficientVmRegionSpace,
     InvalidMapRegion,
+    EmptyZeroPagePool,
+    AddressOutOfRange,
     SharedPageNotMapped,
     EmptyPageRange,
     Measurement(attestation::Error),
@@ -57,6 +60,16 @@ pub enum Error {
 
 pub type Result<T> = core::result::Result<T, Error>;
 
+impl From<VmRegionError> for Error {
+    fn from(error: VmRegionError) -> Error {
+        match error {
+            VmRegionError::OverlappingRegion => Error::OverlappingVmRegion,
+            VmRegionError::InsufficientSpace => Error::InsufficientVmRegionSpace,
+            VmRegionError::InvalidRegion => Error::InvalidMapRegion,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum InstructionFetchError {
     FailedDecode(u32),
@@ -68,9 +81,13 @@ pub type InstructionFetchResult = core::result::Result<DecodedInstruction, Instr
 /// The number of pages for the `VmRegionList` vector.
 pub const TVM_REGION_LIST_PAGES: u64 = 1;
 
+/// The number of pages for the vector tracking a VM's own guest VMs.
+pub const TVM_GUEST_LIST_PAGES: u64 = 1;
+
 /// The base number of state pages required to be donated for creating a new VM. For now, we just need
-/// one page to hold the VM state itself and whatever is required to hold the `VmRegionList`.
-pub const TVM_STATE_PAGES: u64 = 1 + TVM_REGION_LIST_PAGES;
+/// one page to hold the VM state itself and whatever is required to hold the `VmRegionList` and
+/// the list of the VM's own guests.
+pub const TVM_STATE_PAGES: u64 = 1 + TVM_REGION_LIST_PAGES + TVM_GUEST_LIST_PAGES;
 
 global_asm!(include_str!("guest_mem.S"));
 
@@ -119,6 +136,23 @@ impl RefCountedTlbVersion {
 struct TlbTrackerInner {
     current: RefCountedTlbVersion,
     prev: Option<RefCountedTlbVersion>,
+    invalidated: InvalidatedRanges,
+}
+
+impl TlbTrackerInner {
+    // Returns if there are outstanding references to the previous TLB version, in which case the
+    // fence that retired it has yet to complete and the TLB version can't be incremented again.
+    fn fence_in_progress(&self) -> bool {
+        self.prev.as_ref().filter(|v| v.count() != 0).is_some()
+    }
+
+    // Increments the current TLB version, returning the previous one.
+    fn increment(&mut self) -> TlbVersion {
+        let prev = self.current.version();
+        self.prev = Some(self.current.clone());
+        self.current = RefCountedTlbVersion::new(prev.increment());
+        prev
+    }
 }
 
 /// Tracker for TLB versioning. Used to track which TLB versions are active in an `ActiveVmPages`
@@ -133,6 +167,7 @@ impl TlbTracker {
         let inner = TlbTrackerInner {
             current: RefCountedTlbVersion::default(),
             prev: None,
+            invalidated: InvalidatedRanges::new(),
         };
         Self {
             inner: Mutex::new(inner),
@@ -144,20 +179,85 @@ impl TlbTracker {
         self.inner.lock().current.version
     }
 
+    /// Returns the oldest TLB version that may still be referenced by an `ActiveVmPages`.
+    fn min_version(&self) -> TlbVersion {
+        let inner = self.inner.lock();
+        inner
+            .prev
+            .as_ref()
+            .filter(|v| v.count() != 0)
+            .unwrap_or(&inner.current)
+            .version()
+    }
+
     /// Attempts to increment the current TLB version. The TLB version can only be incremented if
-    /// there are no outstanding references to versions other than the current version.
-    fn increment(&self) -> Result<()> {
+    /// there are no outstanding references to versions other than the current version. Returns
+    /// the previous TLB version on success.
+    fn increment(&self) -> Result<TlbVersion> {
         let mut inner = self.inner.lock();
-        if inner.prev.as_ref().filter(|v| v.count() != 0).is_none() {
-            // We're only ok to proceed with an increment if there's no references to the previous
-            // TLB version.
-            let next = inner.current.version().increment();
-            inner.prev = Some(inner.current.clone());
-            inner.current = RefCountedTlbVersion::new(next);
-            Ok(())
-        } else {
-            Err(Error::TlbFenceInProgress)
+        // We're only ok to proceed with an increment if there's no references to the previous TLB
+        // version.
+        if inner.fence_in_progress() {
+            return Err(Error::TlbFenceInProgress);
         }
+        Ok(inner.increment())
+    }
+
+    /// Records that the mappings for `num_pages` of size `page_size` starting at `addr` have been
+    /// invalidated, returning the current TLB version at which the invalidation took place.
+    fn record_invalidation(
+        &self,
+        addr: GuestPageAddr,
+        page_size: PageSize,
+        num_pages: u64,
+    ) -> TlbVersion {
+        let mut inner = self.inner.lock();
+        let version = inner.current.version();
+        inner
+            .invalidated
+            .record(version, addr, page_size, num_pages);
+        version
+    }
+
+    /// Calls `invalidate` to invalidate the mappings for `num_pages` of size `page_size` starting at
+    /// `addr`, records the invalidation at the current TLB version and then increments the TLB
+    /// version, all without letting another increment intervene. Fails with `TlbFenceInProgress`,
+    /// without calling `invalidate`, if the TLB version can't be incremented. Returns the previous
+    /// TLB version on success.
+    fn invalidate_and_increment<F>(
+        &self,
+        addr: GuestPageAddr,
+        page_size: PageSize,
+        num_pages: u64,
+        invalidate: F,
+    ) -> Result<TlbVersion>
+    where
+        F: FnOnce() -> Result<()>,
+    {
+        let mut inner = self.inner.lock();
+        if inner.fence_in_progress() {
+            return Err(Error::TlbFenceInProgress);
+        }
+        invalidate()?;
+        let version = inner.current.version();
+        inner
+            .invalidated
+            .record(version, addr, page_size, num_pages);
+        Ok(inner.increment())
+    }
+
+    /// Calls `fence_page` with the address of each page invalidated at TLB versions from `from` up
+    /// to, but not including, `to`. Returns false without calling `fence_page` if those pages are
+    /// no longer all tracked, or are too numerous to be fenced individually, in which case the
+    /// caller must fence the entire address space instead.
+    fn fence_invalidated_pages<F>(&self, from: TlbVersion, to: TlbVersion, fence_page: F) -> bool
+    where
+        F: FnMut(GuestPageAddr),
+    {
+        self.inner
+            .lock()
+            .invalidated
+            .fence_pages(from, to, fence_page)
     }
 
     /// Acquires a reference to the current TLB version.
@@ -216,126 +316,12 @@ impl Drop for PinnedPages {
             // Unwrap ok: the caller guaranteed at construction that the range of pages is shared
             // and owned by `self.owner`.
             self.page_tracker
-                .release_page_by_addr(addr, self.owner)
+                .release_page_by_addr(addr, self.range.page_size(), self.owner)
                 .unwrap();
         }
     }
 }
 
-/// Types of regions in a VM's guest physical address space.
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
-pub enum VmRegionType {
-    /// Memory that is private to this VM.
-    Confidential,
-    /// Memory that is shared with the parent
-    Shared,
-    /// Emulated MMIO region; accesses always cause a fault that is forwarded to the VM's host.
-    Mmio,
-    /// IMSIC interrupt file pages.
-    Imsic,
-    /// PCI BAR pages.
-    Pci,
-}
-
-/// A contiguous region of guest physical address space.
-struct VmRegion {
-    start: GuestPageAddr,
-    end: GuestPageAddr,
-    region_type: VmRegionType,
-}
-
-/// The regions of guest physical address space for a VM. Used to track which parts of the address
-/// space are designated for a particular purpose. The region list is created during VM initialization
-/// and remains static after the VM is finalized. Pages may only be inserted into a VM's address space
-/// if the mapping falls within a region of the proper type.
-pub struct VmRegionList {
-    regions: Mutex<PageVec<VmRegion>>,
-}
-
-impl VmRegionList {
-    /// Creates a new `VmRegionList` using `pages` as the backing store.
-    pub fn new(pages: SequentialPages<InternalClean>, page_tracker: PageTracker) -> Self {
-        Self {
-            regions: Mutex::new(PageVec::new(pages, page_tracker)),
-        }
-    }
-
-    /// Inserts a region at [`start`, `end`) of type `region_type`.
-    fn add(
-        &self,
-        mut start: GuestPageAddr,
-        end: GuestPageAddr,
-        region_type: VmRegionType,
-    ) -> Result<()> {
-        let mut regions = self.regions.lock();
-        // Keep the list sorted, inserting the region in the requested spot as long as it doesn't
-        // overlap with anything else.
-        let mut index = 0;
-        for other in regions.iter() {
-            if other.start > start {
-                if other.start < end {
-                    return Err(Error::OverlappingVmRegion);
-                }
-                break;
-            } else if other.end > start {
-                return Err(Error::OverlappingVmRegion);
-            }
-            index += 1;
-        }
-        let region = VmRegion {
-            start,
-            end,
-            region_type,
-        };
-        regions
-            .try_reserve(1)
-            .map_err(|_| Error::InsufficientVmRegionSpace)?;
-        regions.insert(index, region);
-
-        // Coalesce with same-typed regions before and after this one, if possible.
-        // Avoid potential underflows and overflows on index.
-        if let Some(ref mut before) = index.checked_sub(1).and_then(|i| regions.get_mut(i)) {
-            if before.end == start && before.region_type == region_type {
-                before.end = end;
-                start = before.start;
-                regions.remove(index);
-                index -= 1;
-            }
-        }
-
-        if let Some(ref mut after) = index.checked_add(1).and_then(|i| regions.get_mut(i)) {
-            if after.start == end && after.region_type == region_type {
-                after.start = start;
-                regions.remove(index);
-            }
-        }
-
-        Ok(())
-    }
-
-    /// Returns if the range [`start`, `end`) is fully contained within a region of type `region_type`.
-    fn contains(
-        &self,
-        start: GuestPageAddr,
-        end: GuestPageAddr,
-        region_type: VmRegionType,
-    ) -> bool {
-        let regions = self.regions.lock();
-        regions
-            .iter()
-            .any(|r| r.start <= start && r.end >= end && r.region_type == region_type)
-    }
-
-    /// Returns the type of the region that `addr` resides in, or `None` if it's not in any region.
-    fn find(&self, addr: GuestPhysAddr) -> Option<VmRegionType> {
-        let regions = self.regions.lock();
-        regions
-            .iter()
-            .find(|r| r.start.bits() <= addr.bits() && r.end.bits() > addr.bits())
-            .map(|r| r.region_type)
-    }
-}
-
 /// Wrapper for a `GuestStageMapper` created from the page table of `VmPages`. Measures pages as
 /// they are inserted, if necessary.
 pub struct VmPagesMapper<'a, T: GuestStagePagingMode, M> {
@@ -345,23 +331,27 @@ pub struct VmPagesMapper<'a, T: GuestStagePagingMode, M> {
 }
 
 impl<'a, T: GuestStagePagingMode, M> VmPagesMapper<'a, T, M> {
-    // Creates a new `VmPagesMapper` for `num_pages` starting at `page_addr`, which must lie within
-    // a region of type `region_type`.
+    // Creates a new `VmPagesMapper` for `num_pages` of size `page_size` starting at `page_addr`,
+    // which must lie within a region of type `region_type`.
     fn new_in_region(
         vm_pages: &'a VmPages<T>,
         page_addr: GuestPageAddr,
+        page_size: PageSize,
         num_pages: u64,
         region_type: VmRegionType,
     ) -> Result<Self> {
+        if !page_addr.is_aligned(page_size) {
+            return Err(Error::UnalignedAddress);
+        }
         let end = page_addr
-            .checked_add_pages(num_pages)
+            .checked_add_pages_with_size(num_pages, page_size)
             .ok_or(Error::AddressOverflow)?;
         if !vm_pages.regions.contains(page_addr, end, region_type) {
             return Err(Error::InvalidMapRegion);
         }
         let mapper = vm_pages
             .root
-            .map_range(page_addr, PageSize::Size4k, num_pages, &mut || {
+            .map_range(page_addr, page_size, num_pages, &mut || {
                 vm_pages.pte_pages.pop()
             })
             .map_err(Error::Paging)?;
@@ -372,13 +362,15 @@ impl<'a, T: GuestStagePagingMode, M> VmPagesMapper<'a, T, M> {
         })
     }
 
-    // Maps `page` at `to_addr`.
-    fn do_map_page<P, MR>(&self, to_addr: GuestPageAddr, page: P) -> Result<()>
+    // Maps `page` at `to_addr` with the permissions in `perms`.
+    fn do_map_page<P, MR>(&self, to_addr: GuestPageAddr, page: P, perms: PteLeafPerms) -> Result<()>
     where
         P: MappablePhysPage<MR>,
         MR: MeasureRequirement,
     {
-        self.mapper.map_page(to_addr, page).map_err(Error::Paging)
+        self.mapper
+            .map_page_with_perms(to_addr, page, perms)
+            .map_err(Error::Paging)
     }
 }
 
@@ -389,7 +381,17 @@ pub type ZeroPagesMapper<'a, T> = VmPagesMapper<'a, T, ZeroPages>;
 impl<'a, T: GuestStagePagingMode> ZeroPagesMapper<'a, T> {
     /// Maps a zero page into the guest's address space.
     pub fn map_page(&self, to_addr: GuestPageAddr, page: Page<MappableClean>) -> Result<()> {
-        self.do_map_page(to_addr, page)
+        self.do_map_page(to_addr, page, PteLeafPerms::RWX)
+    }
+
+    /// Same as `map_page()`, but maps the page with the permissions in `perms`.
+    pub fn map_page_with_perms(
+        &self,
+        to_addr: GuestPageAddr,
+        page: Page<MappableClean>,
+        perms: PteLeafPerms,
+    ) -> Result<()> {
+        self.do_map_page(to_addr, page, perms)
     }
 }
 
@@ -405,6 +407,24 @@ impl<'a, T: GuestStagePagingMode> MeasuredPagesMapper<'a, T> {
         page: Page<S>,
         measurement: &AttestationManager<D, H>,
     ) -> Result<()>
+    where
+        S: Mappable<M>,
+        M: MeasureRequirement,
+        D: digest::Digest,
+        H: hkdf::HmacImpl<D>,
+    {
+        self.map_page_with_perms(to_addr, page, PteLeafPerms::RWX, measurement)
+    }
+
+    /// Same as `map_page()`, but maps the page with the permissions in `perms`. Permissions other
+    /// than RWX are measured along with the page's contents.
+    pub fn map_page_with_perms<S, M, D, H>(
+        &self,
+        to_addr: GuestPageAddr,
+        page: Page<S>,
+        perms: PteLeafPerms,
+        measurement: &AttestationManager<D, H>,
+    ) -> Result<()>
     where
         S: Mappable<M>,
         M: MeasureRequirement,
@@ -414,7 +434,14 @@ impl<'a, T: GuestStagePagingMode> MeasuredPagesMapper<'a, T> {
         measurement
             .extend_tvm_page(page.as_bytes(), to_addr.bits())
             .map_err(Error::Measurement)?;
-        self.do_map_page(to_addr, page)
+        // Pages with full permissions are measured as they always have been so that existing
+        // measurements remain valid.
+        if perms != PteLeafPerms::RWX {
+            measurement
+                .extend_tvm_page_perms(perms as u64, to_addr.bits())
+                .map_err(Error::Measurement)?;
+        }
+        self.do_map_page(to_addr, page, perms)
     }
 }
 
@@ -425,7 +452,7 @@ pub type SharedPagesMapper<'a, T> = VmPagesMapper<'a, T, SharedPages>;
 impl<'a, T: GuestStagePagingMode> SharedPagesMapper<'a, T> {
     /// Maps a shared page into the guest's address space.
     pub fn map_page(&self, to_addr: GuestPageAddr, page: Page<MappableShared>) -> Result<()> {
-        self.do_map_page(to_addr, page)
+        self.do_map_page(to_addr, page, PteLeafPerms::RWX)
     }
 }
 
@@ -442,7 +469,7 @@ impl<'a, T: GuestStagePagingMode> ImsicPagesMapper<'a, T> {
         page: ImsicGuestPage<MappableClean>,
     ) -> Result<()> {
         let dest_location = page.location();
-        self.do_map_page(to_addr, page)?;
+        self.do_map_page(to_addr, page, PteLeafPerms::RWX)?;
         if let Some(geometry) = self.vm_pages.imsic_geometry.get() &&
             let Some(iommu_context) = self.vm_pages.iommu_context.get()
         {
@@ -464,7 +491,7 @@ pub type PciPagesMapper<'a, T> = VmPagesMapper<'a, T, PciPages>;
 impl<'a, T: GuestStagePagingMode> PciPagesMapper<'a, T> {
     /// Maps a PCI BAR memory page into the guest's address space.
     pub fn map_page(&self, to_addr: GuestPageAddr, page: PciBarPage<MappableClean>) -> Result<()> {
-        self.do_map_page(to_addr, page)
+        self.do_map_page(to_addr, page, PteLeafPerms::RWX)
     }
 }
 
@@ -482,12 +509,16 @@ pub enum PageFaultType {
     /// A page fault taken when accessing memory outside of any valid region of guest physical
     /// address space. These faults are not resolvable.
     Unmapped,
+    /// A page fault taken when accessing a mapped page of confidential memory in a way that the
+    /// page's permissions don't allow. These faults are not resolvable.
+    Permission,
 }
 
 /// Represents the active VM address space. Holds a reference to the TLB version of the address space
 /// at the time the address space was activated. Used to directly access a guest's memory.
 pub struct ActiveVmPages<'a, T: GuestStagePagingMode> {
     tlb_version: TlbVersion,
+    vmid: VmId,
     vm_pages: FinalizedVmPages<'a, T>,
 }
 
@@ -516,21 +547,24 @@ impl<'a, T: GuestStagePagingMode> ActiveVmPages<'a, T> {
         CSR.hgatp.set(hgatp.get());
 
         let tlb_version = vm_pages.inner.tlb_tracker.get_version();
-        // Fence if this VMID was previously running on this CPU with an old TLB version.
-        if let Some(v) = prev_tlb_version && v < tlb_version {
-            // We flush all translations for this VMID since we don't have an efficient way to
-            // track which pages need fencing. Even if we let the user provide the range to be
-            // fenced it would require reverse-mapping the address so we could update that it was
-            // fenced in PageTracker, which is almost certainly more expensive than just flushing
-            // everything to begin with.
-            //
-            // TODO: Keep a list of pages in VmPages that are pending conversion so that we can
-            // do more fine-grained invalidation.
-            tlb::hfence_gvma(None, Some(vmid.vmid()));
+        // Fence if this VMID was previously running on this CPU with an old TLB version. The
+        // previous version can't be newer than the current one, so any other version is stale no
+        // matter how far the counter has advanced (or wrapped) since. Only the pages invalidated
+        // since then need fencing, unless there are too many of them to be worth fencing
+        // individually, in which case we flush all translations for this VMID.
+        if let Some(v) = prev_tlb_version && v != tlb_version {
+            let tlb_tracker = &vm_pages.inner.tlb_tracker;
+            let fenced = tlb_tracker.fence_invalidated_pages(v, tlb_version, |addr| {
+                tlb::hfence_gvma(Some(addr.bits()), Some(vmid.vmid()))
+            });
+            if !fenced {
+                tlb::hfence_gvma(None, Some(vmid.vmid()));
+            }
         }
 
         Self {
             tlb_version,
+            vmid,
             vm_pages,
         }
     }
@@ -588,24 +622,38 @@ impl<'a, T: GuestStagePagingMode> ActiveVmPages<'a, T> {
         }
     }
 
-    /// Copies `count` pages from `src_addr` in the current guest to the converted pages starting at
-    /// `from_addr`. The pages are then mapped into the child's address space at `to_addr`.
-    pub fn copy_and_add_data_pages_builder<D: digest::Digest, H: hkdf::HmacImpl<D>>(
+    /// Copies `count` pages of size `page_size` from `src_addr` in the current guest to the
+    /// converted pages starting at `from_addr`. The pages are then mapped into the child's address
+    /// space at `to_addr` with the permissions in `perms`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn copy_and_add_data_pages_builder<
+        U: GuestStagePagingMode,
+        D: digest::Digest,
+        H: hkdf::HmacImpl<D>,
+    >(
         &self,
         src_addr: GuestPageAddr,
         from_addr: GuestPageAddr,
+        page_size: PageSize,
         count: u64,
-        to: InitializingVmPages<T>,
+        to: InitializingVmPages<U>,
         to_addr: GuestPageAddr,
+        perms: PteLeafPerms,
         measurement: &AttestationManager<D, H>,
     ) -> Result<u64> {
-        let converted_pages = self.vm_pages.get_converted_pages(from_addr, count)?;
-        let mapper = to.map_measured_pages(to_addr, count)?;
+        let converted_pages = self
+            .vm_pages
+            .get_converted_pages(from_addr, page_size, count)?;
+        let mapper = to.map_measured_pages(to_addr, page_size, count)?;
 
-        // Make sure we can initialize the full set of pages before mapping them.
+        // Make sure we can initialize the full set of pages before mapping them. The source
+        // buffer need only be 4kB-aligned.
         let page_tracker = self.vm_pages.page_tracker();
         let mut initialized_pages = LockedPageList::new(page_tracker.clone());
-        for (dirty, src_addr) in converted_pages.zip(src_addr.iter_from()) {
+        let src_addrs = src_addr
+            .iter_from()
+            .step_by((page_size as u64 / PageSize::Size4k as u64) as usize);
+        for (dirty, src_addr) in converted_pages.zip(src_addrs) {
             match dirty.try_initialize(|bytes| self.copy_from_guest(bytes, src_addr.into())) {
                 Ok(p) => initialized_pages.push(p).unwrap(),
                 Err((e, p)) => {
@@ -618,13 +666,17 @@ impl<'a, T: GuestStagePagingMode> ActiveVmPages<'a, T> {
 
         // Now map & measure the pages.
         let new_owner = to.page_owner_id();
-        for (initialized, to_addr) in initialized_pages.zip(to_addr.iter_from()) {
+        // Unwrap ok since the mapper checked the alignment of `to_addr`.
+        let to_addrs = to_addr.iter_from_with_size(page_size).unwrap();
+        for (initialized, to_addr) in initialized_pages.zip(to_addrs) {
             // Unwrap ok since we've guaranteed there's space for another owner.
             let mappable = page_tracker
                 .assign_page_for_mapping(initialized, new_owner)
                 .unwrap();
             // Unwrap ok since the address is in range and we haven't mapped it yet.
-            mapper.map_page(to_addr, mappable, measurement).unwrap();
+            mapper
+                .map_page_with_perms(to_addr, mappable, perms, measurement)
+                .unwrap();
         }
 
         Ok(count)
@@ -655,6 +707,18 @@ impl<'a, T: GuestStagePagingMode> ActiveVmPages<'a, T> {
             .map_err(|_| InstructionFetchError::FailedDecode(raw_inst))
     }
 
+    /// Returns if the `len` bytes at `addr` lie entirely within a shared memory region of this VM.
+    pub fn is_shared_range(&self, addr: GuestPhysAddr, len: u64) -> bool {
+        let start = PageAddr::with_round_down(addr, PageSize::Size4k);
+        addr.checked_increment(len).map_or(false, |end| {
+            let end = PageAddr::with_round_up(end, PageSize::Size4k);
+            self.vm_pages
+                .inner
+                .regions
+                .contains(start, end, VmRegionType::Shared)
+        })
+    }
+
     /// Returns the cause of a guest page fault of type `exception` taken at `fault_addr` from this VM.
     pub fn get_page_fault_cause(
         &self,
@@ -663,6 +727,9 @@ impl<'a, T: GuestStagePagingMode> ActiveVmPages<'a, T> {
     ) -> PageFaultType {
         use PageFaultType::*;
         match self.vm_pages.inner.regions.find(fault_addr) {
+            Some(VmRegionType::Confidential) if self.is_permission_fault(exception, fault_addr) => {
+                Permission
+            }
             Some(VmRegionType::Confidential) => Confidential,
             Some(VmRegionType::Shared) => Shared,
             Some(VmRegionType::Mmio) => match exception {
@@ -675,6 +742,115 @@ impl<'a, T: GuestStagePagingMode> ActiveVmPages<'a, T> {
             _ => Unmapped,
         }
     }
+
+    // Returns if a guest page fault of type `exception` at `fault_addr` was caused by an access to
+    // a mapped page that its permissions don't allow.
+    fn is_permission_fault(&self, exception: Exception, fault_addr: GuestPhysAddr) -> bool {
+        let access = match exception {
+            Exception::GuestLoadPageFault => PteLeafPerms::R,
+            Exception::GuestStorePageFault => PteLeafPerms::RW,
+            Exception::GuestInstructionPageFault => PteLeafPerms::X,
+            _ => return false,
+        };
+        self.vm_pages
+            .inner
+            .root
+            .is_permission_fault(fault_addr, access)
+    }
+
+    /// Restricts the permissions of the confidential pages mapped in the `len` bytes starting at
+    /// `page_addr` to `perms`, which must not grant any access that the pages don't already allow.
+    /// The range must lie within a confidential memory region and be fully populated. The stale
+    /// translations for the range are fenced on this CPU and a TLB fence is initiated so that no
+    /// other vCPU retains the previous permissions once it next enters this VM. The caller must
+    /// fence any of this VM's vCPUs that are currently active on other CPUs. Fails with
+    /// `TlbFenceInProgress`, leaving the range unchanged, if a previously initiated fence has yet
+    /// to complete.
+    pub fn restrict_page_perms(
+        &self,
+        page_addr: GuestPageAddr,
+        len: u64,
+        perms: PteLeafPerms,
+    ) -> Result<()> {
+        let inner = self.vm_pages.inner;
+        let end = FinalizedVmPages::<T>::region_end(page_addr, len)?;
+        let num_pages = len / PageSize::Size4k as u64;
+        if num_pages == 0 {
+            return Err(Error::EmptyPageRange);
+        }
+        if !inner
+            .regions
+            .contains(page_addr, end, VmRegionType::Confidential)
+        {
+            return Err(Error::InvalidMapRegion);
+        }
+        let prev_version = inner.tlb_tracker.invalidate_and_increment(
+            page_addr,
+            PageSize::Size4k,
+            num_pages,
+            || {
+                inner
+                    .root
+                    .restrict_range_perms(page_addr, num_pages, perms)
+                    .map_err(Error::Paging)
+            },
+        )?;
+        // This vCPU keeps running with the TLB version it entered at, so it won't be fenced by the
+        // increment above until it next exits. Fence the range here so that the new permissions
+        // take effect as soon as we return to the guest. Large ranges aren't worth fencing page by
+        // page, so flush all translations for this VMID instead.
+        if num_pages > MAX_RANGED_FENCE_PAGES {
+            tlb::hfence_gvma(None, Some(self.vmid.vmid()));
+        } else {
+            for addr in page_addr.iter_from().take(num_pages as usize) {
+                tlb::hfence_gvma(Some(addr.bits()), Some(self.vmid.vmid()));
+            }
+        }
+        self.vm_pages.fence_iommu(prev_version);
+        Ok(())
+    }
+
+    /// Resolves a guest page fault at `fault_addr` in a confidential region by zeroing a page from
+    /// the pool of pages donated by the parent VM and mapping it at the faulting page. Fails if
+    /// the pool is empty or if the faulting page is already mapped or is being removed.
+    pub fn map_demand_zero_page(&self, fault_addr: GuestPhysAddr) -> Result<()> {
+        let inner = self.vm_pages.inner;
+        if inner.zero_pages.lock().is_empty() {
+            return Err(Error::EmptyZeroPagePool);
+        }
+        let page_addr = PageAddr::with_round_down(fault_addr, PageSize::Size4k);
+        let end = page_addr
+            .checked_add_pages(1)
+            .ok_or(Error::AddressOverflow)?;
+        if !inner
+            .regions
+            .contains(page_addr, end, VmRegionType::Confidential)
+        {
+            return Err(Error::InvalidMapRegion);
+        }
+        // Lock the PTE before taking a page from the pool so that we don't race with another
+        // vCPU faulting on the same page.
+        let mapper = inner
+            .root
+            .map_unused_range(page_addr, PageSize::Size4k, 1, &mut || {
+                inner.pte_pages.pop()
+            })
+            .map_err(Error::Paging)?;
+        let page = inner
+            .zero_pages
+            .lock()
+            .pop()
+            .ok_or(Error::EmptyZeroPagePool)?;
+        // Unwrap ok since the parent VM was able to convert the page, so there's space for another
+        // owner.
+        let mappable = inner
+            .page_tracker
+            .assign_page_for_mapping(page.clean(), inner.page_owner_id)
+            .unwrap();
+        // Unwrap ok since the address is in range and we haven't mapped it yet.
+        mapper.map_page(page_addr, mappable).unwrap();
+        Ok(())
+    }
 }
 
 /// A pool of page-table pages for a VM. Left over pages are released when the pool is dropped.
@@ -732,14 +908,11 @@ impl VmIommuContext {
             gscid,
         })
     }
-}
 
-impl Drop for VmIommuContext {
-    fn drop(&mut self) {
+    // Detaches any devices owned by this VM from the IOMMU.
+    fn detach_devices(&self) {
         // Unwrap ok: presence of an IOMMU is checked at creation time
         let iommu = Iommu::get().unwrap();
-
-        // Detach any devices we own from the IOMMU.
         let owner = self.msi_page_table.owner();
         let pci = PcieRoot::get();
         for dev in pci.devices() {
@@ -753,10 +926,14 @@ impl Drop for VmIommuContext {
                 iommu.detach_pci_device(&mut *dev, self.gscid).unwrap();
             }
         }
+    }
+}
 
-        // Unwrap ok: `self.gscid` must be valid and freeable since we've detached all devices
-        // using it.
-        iommu.free_gscid(self.gscid).unwrap();
+impl Drop for VmIommuContext {
+    fn drop(&mut self) {
+        // Unwrap ok: `self.gscid` must be valid and freeable since the owning `VmPages` detaches
+        // all devices using it before dropping us.
+        Iommu::get().unwrap().free_gscid(self.gscid).unwrap();
     }
 }
 
@@ -771,11 +948,16 @@ pub struct VmPages<T: GuestStagePagingMode> {
     page_owner_id: PageOwnerId,
     page_tracker: PageTracker,
     tlb_tracker: TlbTracker,
+    // The TLB version at which shared pages were most recently unshared by this VM.
+    unshare_version: Mutex<TlbVersion>,
     regions: VmRegionList,
     // How many nested TVMs deep this VM is, with 0 being the host.
     nesting: usize,
     root: GuestStagePageTable<T>,
     pte_pages: PtePagePool,
+    // Converted pages donated by the parent VM for mapping on demand in confidential regions. The
+    // pages remain locked, and thus can't be reclaimed, while in the pool.
+    zero_pages: Mutex<LockedPageList<Page<ConvertedDirty>>>,
     imsic_geometry: Once<GuestImsicGeometry>,
     iommu_context: Once<VmIommuContext>,
 }
@@ -788,10 +970,12 @@ impl<T: GuestStagePagingMode> VmPages<T> {
             page_owner_id: root.page_owner_id(),
             page_tracker: page_tracker.clone(),
             tlb_tracker: TlbTracker::new(),
+            unshare_version: Mutex::new(TlbVersion::default()),
             regions,
             nesting,
             root,
-            pte_pages: PtePagePool::new(page_tracker),
+            pte_pages: PtePagePool::new(page_tracker.clone()),
+            zero_pages: Mutex::new(LockedPageList::new(page_tracker)),
             imsic_geometry: Once::new(),
             iommu_context: Once::new(),
         }
@@ -814,6 +998,17 @@ impl<T: GuestStagePagingMode> VmPages<T> {
     }
 }
 
+impl<T: GuestStagePagingMode> Drop for VmPages<T> {
+    fn drop(&mut self) {
+        // Detach any assigned devices before the page table is torn down so that they can't DMA to
+        // pages that are being wiped and returned to our parent. Done explicitly here rather than
+        // relying on struct field ordering for proper drop() ordering.
+        if let Some(iommu_context) = self.iommu_context.get() {
+            iommu_context.detach_devices();
+        }
+    }
+}
+
 /// A reference to a `VmPages` in a particular state `S` that exposes the appropriate functionality
 /// for a VM in that state.
 pub struct VmPagesRef<'a, T: GuestStagePagingMode, S> {
@@ -851,6 +1046,28 @@ impl<'a, T: GuestStagePagingMode, S> VmPagesRef<'a, T, S> {
         self.inner.imsic_geometry.get().cloned()
     }
 
+    /// Returns the number of 4kB pages currently mapped into this VM's address space.
+    pub fn num_mapped_pages(&self) -> u64 {
+        self.inner.root.num_mapped_4k_pages()
+    }
+
+    /// Returns the number of unused pages in the pool from which confidential guest page faults
+    /// are resolved.
+    pub fn zero_page_pool_len(&self) -> u64 {
+        self.inner.zero_pages.lock().len() as u64
+    }
+
+    /// Calls `f` with the base address, length in bytes and type of each region of this VM's
+    /// address space, in order of increasing address, stopping at the first error.
+    pub fn try_for_each_region<E, F>(&self, mut f: F) -> core::result::Result<(), E>
+    where
+        F: FnMut(GuestPageAddr, u64, VmRegionType) -> core::result::Result<(), E>,
+    {
+        self.inner.regions.try_for_each(|start, end, region_type| {
+            f(start, end.bits() - start.bits(), region_type)
+        })
+    }
+
     /// Add a page to be used for building the guest's page tables.
     /// Currently only supports 4k pages.
     pub fn add_pte_page(&self, page: Page<InternalClean>) -> Result<()> {
@@ -861,13 +1078,25 @@ impl<'a, T: GuestStagePagingMode, S> VmPagesRef<'a, T, S> {
         Ok(())
     }
 
+    /// Adds a converted page to the pool of pages from which confidential guest page faults are
+    /// resolved. Currently only supports 4k pages.
+    pub fn add_zero_page_to_pool(&self, page: Page<ConvertedDirty>) -> Result<()> {
+        if page.size() != PageSize::Size4k {
+            return Err(Error::UnsupportedPageSize(page.size()));
+        }
+        // Unwrap ok, we must uniquely own the page and it isn't on another list.
+        self.inner.zero_pages.lock().push(page).unwrap();
+        Ok(())
+    }
+
     fn do_map_pages<M>(
         &self,
         page_addr: GuestPageAddr,
+        page_size: PageSize,
         count: u64,
         region_type: VmRegionType,
     ) -> Result<VmPagesMapper<'a, T, M>> {
-        VmPagesMapper::new_in_region(self.inner, page_addr, count, region_type)
+        VmPagesMapper::new_in_region(self.inner, page_addr, page_size, count, region_type)
     }
 
     /// Same as `map_zero_pages()`, but for IMSIC guest interrupt file pages.
@@ -876,7 +1105,7 @@ impl<'a, T: GuestStagePagingMode, S> VmPagesRef<'a, T, S> {
         page_addr: GuestPageAddr,
         count: u64,
     ) -> Result<ImsicPagesMapper<'a, T>> {
-        self.do_map_pages(page_addr, count, VmRegionType::Imsic)
+        self.do_map_pages(page_addr, PageSize::Size4k, count, VmRegionType::Imsic)
     }
 
     /// Same as `map_zero_pages()`, but for PCI BAR memory pages.
@@ -885,7 +1114,7 @@ impl<'a, T: GuestStagePagingMode, S> VmPagesRef<'a, T, S> {
         page_addr: GuestPageAddr,
         count: u64,
     ) -> Result<PciPagesMapper<'a, T>> {
-        self.do_map_pages(page_addr, count, VmRegionType::Pci)
+        self.do_map_pages(page_addr, PageSize::Size4k, count, VmRegionType::Pci)
     }
 
     // Adds a region of type `region_type`.
@@ -895,13 +1124,29 @@ impl<'a, T: GuestStagePagingMode, S> VmPagesRef<'a, T, S> {
         len: u64,
         region_type: VmRegionType,
     ) -> Result<()> {
-        let end = PageAddr::new(
+        let end = Self::region_end(page_addr, len)?;
+        Self::check_region_end(end)?;
+        self.inner.regions.add(page_addr, end, region_type)?;
+        Ok(())
+    }
+
+    // Returns the end of the region of `len` bytes starting at `page_addr`.
+    fn region_end(page_addr: GuestPageAddr, len: u64) -> Result<GuestPageAddr> {
+        PageAddr::new(
             RawAddr::from(page_addr)
                 .checked_increment(len)
                 .ok_or(Error::AddressOverflow)?,
         )
-        .ok_or(Error::UnalignedAddress)?;
-        self.inner.regions.add(page_addr, end, region_type)
+        .ok_or(Error::UnalignedAddress)
+    }
+
+    // Checks that a region ending at `end` lies within the guest physical address space that can be
+    // translated by this VM's paging mode.
+    fn check_region_end(end: GuestPageAddr) -> Result<()> {
+        if end.bits() > 1 << T::addr_bits() {
+            return Err(Error::AddressOutOfRange);
+        }
+        Ok(())
     }
 }
 
@@ -932,47 +1177,127 @@ impl<'a, T: GuestStagePagingMode> FinalizedVmPages<'a, T> {
         self.do_add_region(page_addr, len, VmRegionType::Mmio)
     }
 
-    /// Locks `count` 4kB pages starting at `page_addr` for mapping of zero-filled pages in a
-    /// region of confidential memory, returning a `VmPagesMapper` that can be used to insert
-    /// the pages.
+    /// Converts the `len` bytes of confidential memory starting at `page_addr` to shared memory.
+    /// The confidential pages populated in the range are blocked from access by this VM and a TLB
+    /// fence is initiated; unpopulated pages simply become part of the shared region. The blocked
+    /// pages are zeroed and returned to the parent VM once it removes them with
+    /// `remove_pages_from()`, after which the parent may insert shared pages in the range.
+    pub fn share_memory(&self, page_addr: GuestPageAddr, len: u64) -> Result<()> {
+        let end = Self::region_end(page_addr, len)?;
+        let num_pages = len / PageSize::Size4k as u64;
+        if num_pages == 0 {
+            return Err(Error::EmptyPageRange);
+        }
+        self.inner.regions.change_type(
+            page_addr,
+            end,
+            VmRegionType::Confidential,
+            VmRegionType::Shared,
+            || self.block_populated_pages(page_addr, num_pages),
+        )?;
+        self.try_initiate_fence();
+        Ok(())
+    }
+
+    /// Converts the `len` bytes of shared memory starting at `page_addr` back to confidential
+    /// memory. The shared pages in the range are unmapped from this VM and a TLB fence is
+    /// initiated. The parent VM drops the shared references to the pages once it removes them
+    /// with `remove_pages_from()`, after which the parent may insert zero pages in the range.
+    pub fn unshare_memory(&self, page_addr: GuestPageAddr, len: u64) -> Result<()> {
+        let end = Self::region_end(page_addr, len)?;
+        let num_pages = len / PageSize::Size4k as u64;
+        if num_pages == 0 {
+            return Err(Error::EmptyPageRange);
+        }
+        self.inner.regions.change_type(
+            page_addr,
+            end,
+            VmRegionType::Shared,
+            VmRegionType::Confidential,
+            || {
+                self.inner
+                    .root
+                    .invalidate_shared_range(page_addr, num_pages)
+                    .map_err(Error::Paging)?;
+                *self.inner.unshare_version.lock() = self.inner.tlb_tracker.record_invalidation(
+                    page_addr,
+                    PageSize::Size4k,
+                    num_pages,
+                );
+                Ok(())
+            },
+        )?;
+        self.try_initiate_fence();
+        Ok(())
+    }
+
+    // Removes the `num_pages` shared pages starting at `page_addr` that were unshared by this VM.
+    // Returns the number of pages removed, which is 0 if this VM may still hold stale translations
+    // for the pages.
+    fn remove_unshared_pages(&self, page_addr: GuestPageAddr, num_pages: u64) -> Result<u64> {
+        if !self
+            .inner
+            .root
+            .is_invalidated_shared_range(page_addr, num_pages)
+        {
+            return Err(Error::Paging(PageTableError::PageNotShared));
+        }
+        let unshare_version = *self.inner.unshare_version.lock();
+        if !unshare_version.is_older_than(self.inner.tlb_tracker.min_version()) {
+            self.try_initiate_fence();
+            return Ok(0);
+        }
+        self.inner
+            .root
+            .remove_invalidated_shared_range(page_addr, num_pages)
+            .map_err(Error::Paging)?;
+        Ok(num_pages)
+    }
+
+    /// Locks `count` pages of size `page_size` starting at `page_addr` for mapping of zero-filled
+    /// pages in a region of confidential memory, returning a `VmPagesMapper` that can be used to
+    /// insert the pages.
     pub fn map_zero_pages(
         &self,
         page_addr: GuestPageAddr,
+        page_size: PageSize,
         count: u64,
     ) -> Result<ZeroPagesMapper<'a, T>> {
-        self.do_map_pages(page_addr, count, VmRegionType::Confidential)
+        self.do_map_pages(page_addr, page_size, count, VmRegionType::Confidential)
     }
 
-    /// Same as `map_zero_pages()`, but for pages in shared (non-confidential) regions.
+    /// Same as `map_zero_pages()`, but for 4kB pages in shared (non-confidential) regions.
     pub fn map_shared_pages(
         &self,
         page_addr: GuestPageAddr,
         count: u64,
     ) -> Result<SharedPagesMapper<'a, T>> {
-        self.do_map_pages(page_addr, count, VmRegionType::Shared)
+        self.do_map_pages(page_addr, PageSize::Size4k, count, VmRegionType::Shared)
     }
 
-    // Returns a list of converted and locked pages created from `num_pages` starting at
-    // `page_addr`.
+    // Returns a list of converted and locked pages created from `num_pages` of size `page_size`
+    // starting at `page_addr`.
     fn get_converted_pages(
         &self,
         page_addr: GuestPageAddr,
+        page_size: PageSize,
         num_pages: u64,
     ) -> Result<LockedPageList<Page<ConvertedDirty>>> {
         let version = self.inner.tlb_tracker.current();
         self.inner
             .root
-            .get_converted_range::<Page<ConvertedDirty>>(
-                page_addr,
-                PageSize::Size4k,
-                num_pages,
-                version,
-            )
+            .get_converted_range::<Page<ConvertedDirty>>(page_addr, page_size, num_pages, version)
             .map_err(Error::Paging)
     }
 
-    /// Converts `num_pages` starting at guest physical address `page_addr` to confidential memory.
-    pub fn convert_pages(&self, page_addr: GuestPageAddr, num_pages: u64) -> Result<()> {
+    /// Converts `num_pages` of size `page_size` starting at guest physical address `page_addr` to
+    /// confidential memory.
+    pub fn convert_pages(
+        &self,
+        page_addr: GuestPageAddr,
+        page_size: PageSize,
+        num_pages: u64,
+    ) -> Result<()> {
         if self.inner.nesting >= MAX_PAGE_OWNERS - 1 {
             // We shouldn't bother converting pages if we won't be able to assign them.
             return Err(Error::NestingTooDeep);
@@ -981,9 +1306,12 @@ impl<'a, T: GuestStagePagingMode> FinalizedVmPages<'a, T> {
         let invalidated_pages = self
             .inner
             .root
-            .invalidate_range::<Page<Invalidated>>(page_addr, PageSize::Size4k, num_pages)
+            .invalidate_range::<Page<Invalidated>>(page_addr, page_size, num_pages)
             .map_err(Error::Paging)?;
-        let version = self.inner.tlb_tracker.current();
+        let version = self
+            .inner
+            .tlb_tracker
+            .record_invalidation(page_addr, page_size, num_pages);
         for page in invalidated_pages {
             // Unwrap ok since the page was just invalidated.
             self.inner.page_tracker.convert_page(page, version).unwrap();
@@ -991,15 +1319,83 @@ impl<'a, T: GuestStagePagingMode> FinalizedVmPages<'a, T> {
         Ok(())
     }
 
-    /// Reclaims `num_pages` of confidential memory starting at guest physical address `page_addr`.
-    pub fn reclaim_pages(&self, page_addr: GuestPageAddr, num_pages: u64) -> Result<()> {
-        // TODO: Support reclaim of converted pages that haven't yet been fenced.
-        let converted_pages = self.get_converted_pages(page_addr, num_pages)?;
+    // Invalidates the mappings for `num_pages` of size `page_size` starting at guest physical
+    // address `page_addr`, marking the pages as blocked at the current TLB version so that they
+    // can be removed by the parent VM. Unlike converted pages, blocked pages can't be reclaimed by
+    // this VM.
+    fn block_pages(
+        &self,
+        page_addr: GuestPageAddr,
+        page_size: PageSize,
+        num_pages: u64,
+    ) -> Result<()> {
+        let invalidated_pages = self
+            .inner
+            .root
+            .invalidate_range::<Page<Invalidated>>(page_addr, page_size, num_pages)
+            .map_err(Error::Paging)?;
+        self.block_invalidated_pages(invalidated_pages, page_addr, page_size, num_pages);
+        Ok(())
+    }
+
+    // Same as `block_pages()` for `num_pages` 4kB pages starting at `page_addr`, except that
+    // unpopulated pages in the range are skipped.
+    fn block_populated_pages(&self, page_addr: GuestPageAddr, num_pages: u64) -> Result<()> {
+        let invalidated_pages = self
+            .inner
+            .root
+            .invalidate_populated_range::<Page<Invalidated>>(page_addr, num_pages)
+            .map_err(Error::Paging)?;
+        if !invalidated_pages.is_empty() {
+            self.block_invalidated_pages(invalidated_pages, page_addr, PageSize::Size4k, num_pages);
+        }
+        Ok(())
+    }
+
+    // Marks the pages in `invalidated_pages`, which were invalidated from the range of `num_pages`
+    // pages of size `page_size` starting at `page_addr`, as blocked at the current TLB version.
+    fn block_invalidated_pages(
+        &self,
+        invalidated_pages: PageList<Page<Invalidated>>,
+        page_addr: GuestPageAddr,
+        page_size: PageSize,
+        num_pages: u64,
+    ) {
+        let version = self
+            .inner
+            .tlb_tracker
+            .record_invalidation(page_addr, page_size, num_pages);
+        for page in invalidated_pages {
+            // Unwrap ok since the page was just invalidated.
+            self.inner.page_tracker.block_page(page, version).unwrap();
+        }
+    }
+
+    /// Reclaims `num_pages` of size `page_size` of confidential memory starting at guest physical
+    /// address `page_addr`.
+    pub fn reclaim_pages(
+        &self,
+        page_addr: GuestPageAddr,
+        page_size: PageSize,
+        num_pages: u64,
+    ) -> Result<()> {
+        // Pages that are still converting may be reclaimed as well; they can't have been assigned
+        // to a child yet, so there's no need to wait for the conversion to be fenced. Pages that
+        // were blocked for removal by our parent, or for sharing, can't be reclaimed.
+        let converted_pages = self
+            .inner
+            .root
+            .get_reclaimable_range::<Page<ConvertedDirty>>(page_addr, page_size, num_pages)
+            .map_err(Error::Paging)?;
         // Unwrap ok since the PTE for the page must have previously been invalid and all of
         // the intermediate page-tables must already have been populatd.
-        let mapper = self.map_zero_pages(page_addr, num_pages).unwrap();
-        for (page, addr) in converted_pages.zip(page_addr.iter_from()) {
-            // Unwrap ok since we know that it's a converted page.
+        let mapper = self
+            .map_zero_pages(page_addr, page_size, num_pages)
+            .unwrap();
+        // Unwrap ok since the converted range must have been `page_size`-aligned.
+        let addrs = page_addr.iter_from_with_size(page_size).unwrap();
+        for (page, addr) in converted_pages.zip(addrs) {
+            // Unwrap ok since we know that it's a converted or converting page.
             let mappable = self.inner.page_tracker.reclaim_page(page.clean()).unwrap();
             mapper.map_page(addr, mappable).unwrap();
         }
@@ -1031,15 +1427,14 @@ impl<'a, T: GuestStagePagingMode> FinalizedVmPages<'a, T> {
             .root
             .invalidate_range::<ImsicGuestPage<Invalidated>>(imsic_addr, PageSize::Size4k, 1)
             .map_err(Error::Paging)?;
+        let version = self
+            .inner
+            .tlb_tracker
+            .record_invalidation(imsic_addr, PageSize::Size4k, 1);
         // Unwrap ok since the page was just invalidated.
         invalidated
             .next()
-            .and_then(|p| {
-                self.inner
-                    .page_tracker
-                    .convert_page(p, self.inner.tlb_tracker.current())
-                    .ok()
-            })
+            .and_then(|p| self.inner.page_tracker.convert_page(p, version).ok())
             .unwrap();
 
         // Unmap it from our MSI page table as well, if we have one.
@@ -1079,14 +1474,37 @@ impl<'a, T: GuestStagePagingMode> FinalizedVmPages<'a, T> {
 
     /// Initiates a page conversion fence for this `VmPages` by incrementing the TLB version.
     pub fn initiate_fence(&self) -> Result<()> {
-        self.inner.tlb_tracker.increment()?;
+        let prev_version = self.inner.tlb_tracker.increment()?;
+        self.fence_iommu(prev_version);
+        Ok(())
+    }
+
+    // Fences the pages invalidated at `prev_version` from the IOMMU after the TLB version has been
+    // incremented past it.
+    fn fence_iommu(&self, prev_version: TlbVersion) {
         // If we have an IOMMU context then we need to issue a fence there as well as our page
-        // tables may be used for DMA translation.
+        // tables may be used for DMA translation. Pages invalidated at earlier TLB versions were
+        // fenced when those versions were retired, so only those invalidated at the version we
+        // just retired need fencing.
         if let Some(iommu_context) = self.inner.iommu_context.get() {
             // Unwrap ok since we must have an IOMMU to have a `VmIommuContext`.
-            Iommu::get().unwrap().fence(iommu_context.gscid, None);
+            let iommu = Iommu::get().unwrap();
+            let fenced = self.inner.tlb_tracker.fence_invalidated_pages(
+                prev_version,
+                prev_version.increment(),
+                |addr| iommu.fence(iommu_context.gscid, Some(addr)),
+            );
+            if !fenced {
+                iommu.fence(iommu_context.gscid, None);
+            }
         }
-        Ok(())
+    }
+
+    // Initiates a page conversion fence unless one is already in progress. Used where pages are
+    // invalidated for later removal: a removal attempt that finds the pages still unfenced calls
+    // this again and asks to be retried, so a fence that can't be started now isn't an error.
+    fn try_initiate_fence(&self) {
+        let _ = self.initiate_fence();
     }
 
     // Assigns the converted pages in `pages` to `new_owner` as state pages.
@@ -1109,29 +1527,31 @@ impl<'a, T: GuestStagePagingMode> FinalizedVmPages<'a, T> {
         assigned_pages
     }
 
-    /// Creates a new `Vm` using pages donated by `self`. The returned `Vm` is in the initializing
-    /// state, ready for its address space to be constructed.
-    pub fn create_guest_vm(
+    /// Creates a new `Vm` using pages donated by `self`, with its address space translated using
+    /// paging mode `U`. The returned `Vm` is in the initializing state, ready for its address space
+    /// to be constructed.
+    pub fn create_guest_vm<U: GuestStagePagingMode>(
         &self,
         page_root_addr: GuestPageAddr,
         state_addr: GuestPageAddr,
         vcpus_addr: GuestPageAddr,
         num_vcpu_pages: u64,
-    ) -> Result<(Vm<T>, Page<InternalClean>)> {
-        if (page_root_addr.bits() as *const u64).align_offset(T::TOP_LEVEL_ALIGN as usize) != 0 {
+    ) -> Result<(Vm<U>, Page<InternalClean>)> {
+        if (page_root_addr.bits() as *const u64).align_offset(U::TOP_LEVEL_ALIGN as usize) != 0 {
             return Err(Error::UnalignedAddress);
         }
 
         // Make sure we can grab the pages first before we start wiping and assigning them.
-        let guest_root_pages = self.get_converted_pages(page_root_addr, 4)?;
+        let guest_root_pages = self.get_converted_pages(page_root_addr, PageSize::Size4k, 4)?;
         if !guest_root_pages.is_contiguous() {
             return Err(Error::NonContiguousPages);
         }
-        let state_pages = self.get_converted_pages(state_addr, TVM_STATE_PAGES)?;
+        let state_pages =
+            self.get_converted_pages(state_addr, PageSize::Size4k, TVM_STATE_PAGES)?;
         if !state_pages.is_contiguous() {
             return Err(Error::NonContiguousPages);
         }
-        let vcpu_pages = self.get_converted_pages(vcpus_addr, num_vcpu_pages)?;
+        let vcpu_pages = self.get_converted_pages(vcpus_addr, PageSize::Size4k, num_vcpu_pages)?;
         if !vcpu_pages.is_contiguous() {
             return Err(Error::NonContiguousPages);
         }
@@ -1149,16 +1569,21 @@ impl<'a, T: GuestStagePagingMode> FinalizedVmPages<'a, T> {
 
         let mut state_pages = self.assign_state_pages_for(state_pages, id);
         let box_page = state_pages.next().unwrap();
-        let region_vec_pages = SequentialPages::from_pages(state_pages).unwrap();
+        let region_list_pages = state_pages.by_ref().take(TVM_REGION_LIST_PAGES as usize);
+        let region_vec_pages = SequentialPages::from_pages(region_list_pages).unwrap();
         let region_vec = VmRegionList::new(region_vec_pages, self.inner.page_tracker.clone());
+        // The guest may in turn act as a host for its own guests.
+        let guest_list_pages = SequentialPages::from_pages(state_pages).unwrap();
+        let guests = Guests::new(guest_list_pages, self.inner.page_tracker.clone());
 
         let vcpu_pages =
             SequentialPages::from_pages(self.assign_state_pages_for(vcpu_pages, id)).unwrap();
 
         Ok((
-            Vm::new(
+            Vm::with_guest_tracking(
                 VmPages::new(guest_root, region_vec, self.inner.nesting + 1),
                 VmCpus::new(id, vcpu_pages, self.inner.page_tracker.clone()).unwrap(),
+                guests,
             )
             .map_err(Error::VmCreationFailed)?,
             box_page,
@@ -1166,13 +1591,13 @@ impl<'a, T: GuestStagePagingMode> FinalizedVmPages<'a, T> {
     }
 
     /// Adds pages to be used for building page table entries to a guest of this VM.
-    pub fn add_pte_pages_to(
+    pub fn add_pte_pages_to<U: GuestStagePagingMode>(
         &self,
         from_addr: GuestPageAddr,
         count: u64,
-        to: AnyVmPages<T>,
+        to: AnyVmPages<U>,
     ) -> Result<()> {
-        let converted_pages = self.get_converted_pages(from_addr, count)?;
+        let converted_pages = self.get_converted_pages(from_addr, PageSize::Size4k, count)?;
         let new_owner = to.page_owner_id();
         for page in converted_pages {
             // Unwrap ok since we've guaranteed the page is assignable.
@@ -1187,18 +1612,39 @@ impl<'a, T: GuestStagePagingMode> FinalizedVmPages<'a, T> {
         Ok(())
     }
 
-    /// Adds zero-filled pages to the given guest.
-    pub fn add_zero_pages_to(
+    /// Adds `count` 4kB pages to the pool of pages from which the given guest's confidential page
+    /// faults are resolved. The pages are zeroed when they're mapped into the guest.
+    pub fn add_zero_page_pool_to<U: GuestStagePagingMode>(
         &self,
         from_addr: GuestPageAddr,
         count: u64,
-        to: FinalizedVmPages<T>,
+        to: AnyVmPages<U>,
+    ) -> Result<()> {
+        let converted_pages = self.get_converted_pages(from_addr, PageSize::Size4k, count)?;
+        for page in converted_pages {
+            // Unwrap ok, pages must be 4kB.
+            to.add_zero_page_to_pool(page).unwrap();
+        }
+        Ok(())
+    }
+
+    /// Adds `count` zero-filled pages of size `page_size` to the given guest, mapped with the
+    /// permissions in `perms`.
+    pub fn add_zero_pages_to<U: GuestStagePagingMode>(
+        &self,
+        from_addr: GuestPageAddr,
+        page_size: PageSize,
+        count: u64,
+        to: FinalizedVmPages<U>,
         to_addr: GuestPageAddr,
+        perms: PteLeafPerms,
     ) -> Result<u64> {
-        let converted_pages = self.get_converted_pages(from_addr, count)?;
-        let mapper = to.map_zero_pages(to_addr, count)?;
+        let converted_pages = self.get_converted_pages(from_addr, page_size, count)?;
+        let mapper = to.map_zero_pages(to_addr, page_size, count)?;
         let new_owner = to.page_owner_id();
-        for (page, guest_addr) in converted_pages.zip(to_addr.iter_from()) {
+        // Unwrap ok since the mapper checked the alignment of `to_addr`.
+        let to_addrs = to_addr.iter_from_with_size(page_size).unwrap();
+        for (page, guest_addr) in converted_pages.zip(to_addrs) {
             // Unwrap ok since we've guaranteed there's space for another owner.
             let mappable = self
                 .inner
@@ -1206,18 +1652,80 @@ impl<'a, T: GuestStagePagingMode> FinalizedVmPages<'a, T> {
                 .assign_page_for_mapping(page.clean(), new_owner)
                 .unwrap();
             // Unwrap ok since the address is in range and we haven't mapped it yet.
-            mapper.map_page(guest_addr, mappable).unwrap();
+            mapper
+                .map_page_with_perms(guest_addr, mappable, perms)
+                .unwrap();
+        }
+        Ok(count)
+    }
+
+    /// Removes `count` confidential pages of size `page_size` mapped at `guest_addr` in the given
+    /// guest, scrubbing them and returning them to this VM as converted pages. The pages are first
+    /// blocked from access by the guest and a TLB fence of the guest's address space is initiated.
+    /// Returns the number of pages removed, which is 0 if the guest may still hold stale
+    /// translations for the blocked pages, in which case the removal should be retried once the
+    /// guest's vCPUs have exited. Ranges that can't be blocked, e.g. because they aren't fully
+    /// populated, are rejected with an error rather than reported as needing a retry.
+    ///
+    /// If the range instead holds 4kB shared pages that the guest has unshared, the guest's
+    /// references to the shared pages are dropped once any stale translations have been fenced.
+    pub fn remove_pages_from<U: GuestStagePagingMode>(
+        &self,
+        from: FinalizedVmPages<U>,
+        guest_addr: GuestPageAddr,
+        page_size: PageSize,
+        count: u64,
+    ) -> Result<u64> {
+        // The range may hold shared pages that the guest has unshared.
+        if page_size == PageSize::Size4k {
+            match from.remove_unshared_pages(guest_addr, count) {
+                Err(Error::Paging(PageTableError::PageNotShared)) => (),
+                result => return result,
+            }
+        }
+
+        // The range may have already been blocked by a previous attempt that couldn't complete.
+        match from.block_pages(guest_addr, page_size, count) {
+            Ok(()) => from.try_initiate_fence(),
+            Err(Error::Paging(PageTableError::PageNotUnmappable)) => (),
+            Err(e) => return Err(e),
+        }
+
+        // The pages can only be removed once no vCPU of the guest can be running with a TLB
+        // version at which the pages were still mapped.
+        let version = from.inner.tlb_tracker.min_version();
+        let pages = match from
+            .inner
+            .root
+            .remove_converted_range::<Page<ConvertedDirty>>(guest_addr, page_size, count, version)
+        {
+            Ok(pages) => pages,
+            Err(PageTableError::PageNotUnmappable) => {
+                // Only ask for a retry if the pages are blocked but haven't been fenced yet,
+                // otherwise retrying can never succeed.
+                let root = &from.inner.root;
+                if !root.is_converting_range::<Page<ConvertedDirty>>(guest_addr, page_size, count) {
+                    return Err(Error::Paging(PageTableError::PageNotUnmappable));
+                }
+                from.try_initiate_fence();
+                return Ok(0);
+            }
+            Err(e) => return Err(Error::Paging(e)),
+        };
+        for page in pages {
+            // Unwrap ok since the page was owned by the guest and is no longer mapped by it.
+            self.inner.page_tracker.release_page(page.clean()).unwrap();
         }
         Ok(count)
     }
 
     /// Maps num_pages of shared 4Kb pages starting at `from_addr` to the specified guest. The
     /// range must fit in a range declared by a call to `add_shared_memory_region`.
-    pub fn add_shared_pages_to(
+    pub fn add_shared_pages_to<U: GuestStagePagingMode>(
         &self,
         from_addr: GuestPageAddr,
         count: u64,
-        to: FinalizedVmPages<T>,
+        to: FinalizedVmPages<U>,
         to_addr: GuestPageAddr,
     ) -> Result<()> {
         let shared_list = self
@@ -1311,6 +1819,7 @@ impl<'a, T: GuestStagePagingMode> InitializingVmPages<'a, T> {
                         .base()
                         .checked_add_pages(range.num_pages())
                         .ok_or(Error::AddressOverflow)?;
+                    Self::check_region_end(end)?;
                     self.inner
                         .regions
                         .add(range.base(), end, VmRegionType::Imsic)?;
@@ -1371,9 +1880,10 @@ impl<'a, T: GuestStagePagingMode> InitializingVmPages<'a, T> {
     pub fn map_measured_pages(
         &self,
         page_addr: GuestPageAddr,
+        page_size: PageSize,
         count: u64,
     ) -> Result<MeasuredPagesMapper<'a, T>> {
-        self.do_map_pages(page_addr, count, VmRegionType::Confidential)
+        self.do_map_pages(page_addr, page_size, count, VmRegionType::Confidential)
     }
 
     /// Attaches the given PCI device to this VM by enabling DMA translation via the IOMMU using
@@ -3,13 +3,62 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use drivers::pmu;
+use riscv_pages::GuestPhysAddr;
 use riscv_regs::{RiscvCsrInterface, CSR, CSR_CYCLE};
 use s_mode_utils::print::*;
 use sbi::{
     api::pmu::*, Error as SbiError, PmuCounterConfigFlags, PmuCounterStartFlags,
-    PmuCounterStopFlags, PmuEventType, Result as SbiResult,
+    PmuCounterStopFlags, PmuEventType, PmuFirmware, PmuPlatformFirmware, Result as SbiResult,
 };
 
+/// The number of firmware counters exposed to each vCPU. Firmware counters are indexed after the
+/// hardware counters.
+pub const NUM_FIRMWARE_COUNTERS: usize = 16;
+
+/// A firmware event counted by the hypervisor on behalf of a vCPU.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FirmwareEvent {
+    /// One of the firmware events defined by the SBI specification.
+    Standard(PmuFirmware),
+    /// One of the Salus-specific firmware events.
+    Platform(PmuPlatformFirmware),
+}
+
+impl FirmwareEvent {
+    // Returns the firmware event selected by `event` and `event_data` if it can be counted.
+    fn from_config(event: PmuFirmware, event_data: u64) -> SbiResult<Self> {
+        use PmuFirmware::*;
+        match event {
+            PlatformSpecific => Ok(FirmwareEvent::Platform(
+                PmuPlatformFirmware::from_raw_value(event_data)?,
+            )),
+            // Misaligned accesses are delegated directly to the guest, so we never emulate them
+            // and these counters will always read 0. They're still supported so that a guest which
+            // expects to find them doesn't have to special-case us.
+            MisalignedLoad | MisalignedStore | SetTimer | IpiSent | IpiReceived | FenceISent
+            | FenceIReceived | SfenceVmaSent | SfenceVmaReceived | SfenceAsidSent => {
+                Ok(FirmwareEvent::Standard(event))
+            }
+            // Remote vCPUs perform a full VS-stage invalidation for any SFENCE.VMA request, which
+            // is counted as SfenceVmaReceived. We don't virtualize the hypervisor extension, so
+            // there are no HFENCE requests to count.
+            _ => Err(SbiError::NotSupported),
+        }
+    }
+}
+
+impl From<PmuFirmware> for FirmwareEvent {
+    fn from(event: PmuFirmware) -> Self {
+        FirmwareEvent::Standard(event)
+    }
+}
+
+impl From<PmuPlatformFirmware> for FirmwareEvent {
+    fn from(event: PmuPlatformFirmware) -> Self {
+        FirmwareEvent::Platform(event)
+    }
+}
+
 #[derive(Default, Copy, Clone)]
 struct CounterMaskIter {
     counter_index: u64,
@@ -69,20 +118,213 @@ impl Default for PmuCounterState {
     }
 }
 
+#[derive(Copy, Clone)]
+struct FirmwareCounterState {
+    event: FirmwareEvent,
+    value: u64,
+    started: bool,
+}
+
 pub struct VmPmuState {
     // Stores information about the current state of PMU counters.
     counter_state: [PmuCounterState; drivers::pmu::MAX_HARDWARE_COUNTERS],
+    // Firmware counters, which are maintained entirely in software.
+    firmware_counters: [Option<FirmwareCounterState>; NUM_FIRMWARE_COUNTERS],
+    // The address of the guest's snapshot shared memory area, if set.
+    snapshot_addr: Option<GuestPhysAddr>,
 }
 
 impl Default for VmPmuState {
     fn default() -> Self {
         Self {
             counter_state: [PmuCounterState::default(); drivers::pmu::MAX_HARDWARE_COUNTERS],
+            firmware_counters: [None; NUM_FIRMWARE_COUNTERS],
+            snapshot_addr: None,
         }
     }
 }
 
 impl VmPmuState {
+    /// Returns the number of hardware counters exposed to a vCPU, which is 0 if the platform
+    /// doesn't support the PMU extension.
+    pub fn num_hardware_counters() -> u64 {
+        pmu::PmuInfo::get().map_or(0, |pmu_info| pmu_info.get_num_counters())
+    }
+
+    /// Returns the total number of hardware and firmware counters exposed to a vCPU.
+    pub fn num_counters() -> u64 {
+        Self::num_hardware_counters() + NUM_FIRMWARE_COUNTERS as u64
+    }
+
+    /// Splits the counter range given by `counter_index` and `counter_mask` into a mask of hardware
+    /// counters relative to `counter_index` and a mask of firmware counters relative to the first
+    /// firmware counter.
+    pub fn split_counter_range(counter_index: u64, counter_mask: u64) -> SbiResult<(u64, u64)> {
+        let num_hw_counters = Self::num_hardware_counters();
+        let last_index = (u64::BITS - counter_mask.leading_zeros()) as u64;
+        if counter_index
+            .checked_add(last_index)
+            .map_or(true, |end| end > Self::num_counters())
+        {
+            return Err(SbiError::InvalidParam);
+        }
+        let mut hw_mask = 0;
+        let mut fw_mask = 0;
+        for i in CounterMaskIter::new(counter_index, counter_mask) {
+            let i = i as u64;
+            if i < num_hw_counters {
+                hw_mask |= 1 << (i - counter_index);
+            } else {
+                fw_mask |= 1 << (i - num_hw_counters);
+            }
+        }
+        Ok((hw_mask, fw_mask))
+    }
+
+    /// Returns the index of the firmware counter with the (absolute) index `counter_index`.
+    pub fn firmware_counter_index(counter_index: u64) -> SbiResult<usize> {
+        counter_index
+            .checked_sub(Self::num_hardware_counters())
+            .filter(|&i| i < NUM_FIRMWARE_COUNTERS as u64)
+            .map(|i| i as usize)
+            .ok_or(SbiError::InvalidParam)
+    }
+
+    /// Configures a firmware counter from `fw_mask` to count `event`, returning the (absolute)
+    /// index of the counter.
+    pub fn configure_firmware_counter(
+        &mut self,
+        fw_mask: u64,
+        config_flags: PmuCounterConfigFlags,
+        event: PmuFirmware,
+        event_data: u64,
+    ) -> SbiResult<u64> {
+        let event = FirmwareEvent::from_config(event, event_data)?;
+        let mut bitmask_iter = CounterMaskIter::new(0, fw_mask);
+        let index = if config_flags.is_skip_match() {
+            // If skip_match is set, the first counter in the mask must already be configured.
+            bitmask_iter
+                .next()
+                .filter(|&i| self.firmware_counters[i].is_some())
+                .ok_or(SbiError::InvalidParam)?
+        } else {
+            bitmask_iter
+                .find(|&i| self.firmware_counters[i].is_none())
+                .ok_or(SbiError::NotSupported)?
+        };
+        let counter = self.firmware_counters[index].get_or_insert(FirmwareCounterState {
+            event,
+            value: 0,
+            started: false,
+        });
+        counter.event = event;
+        if config_flags.is_clear_value() {
+            counter.value = 0;
+        }
+        if config_flags.is_auto_start() {
+            counter.started = true;
+        }
+        Ok(Self::num_hardware_counters() + index as u64)
+    }
+
+    /// Starts the firmware counters in `fw_mask`, setting their value to that returned by
+    /// `initial_value` for the (absolute) counter index if it returns `Some`.
+    pub fn start_firmware_counters(
+        &mut self,
+        fw_mask: u64,
+        initial_value: impl Fn(u64) -> Option<u64>,
+    ) -> SbiResult<()> {
+        let bitmask_iter = CounterMaskIter::new(0, fw_mask);
+        for i in bitmask_iter {
+            match self.firmware_counters[i] {
+                None => return Err(SbiError::InvalidParam),
+                Some(c) if c.started => return Err(SbiError::AlreadyStarted),
+                _ => {}
+            }
+        }
+        let num_hw_counters = Self::num_hardware_counters();
+        for i in bitmask_iter {
+            // Unwrap ok: We've verified above that the counter is configured.
+            let counter = self.firmware_counters[i].as_mut().unwrap();
+            if let Some(value) = initial_value(num_hw_counters + i as u64) {
+                counter.value = value;
+            }
+            counter.started = true;
+        }
+        Ok(())
+    }
+
+    /// Stops the firmware counters in `fw_mask`, unconfiguring them if `stop_flags` requests a
+    /// reset.
+    pub fn stop_firmware_counters(
+        &mut self,
+        fw_mask: u64,
+        stop_flags: PmuCounterStopFlags,
+    ) -> SbiResult<()> {
+        let bitmask_iter = CounterMaskIter::new(0, fw_mask);
+        for i in bitmask_iter {
+            match self.firmware_counters[i] {
+                None => return Err(SbiError::InvalidParam),
+                // Resetting a stopped counter is permitted, as for hardware counters.
+                Some(c) if !c.started && !stop_flags.is_reset_flag() => {
+                    return Err(SbiError::AlreadyStopped)
+                }
+                _ => {}
+            }
+        }
+        for i in bitmask_iter {
+            if stop_flags.is_reset_flag() {
+                self.firmware_counters[i] = None;
+            } else if let Some(c) = self.firmware_counters[i].as_mut() {
+                c.started = false;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the current value of the firmware counter with the (absolute) index `counter_index`.
+    pub fn read_firmware_counter(&self, counter_index: u64) -> SbiResult<u64> {
+        let index = Self::firmware_counter_index(counter_index)?;
+        self.firmware_counters[index]
+            .map(|c| c.value)
+            .ok_or(SbiError::InvalidParam)
+    }
+
+    /// Counts an occurrence of `event` in each started firmware counter that is configured for it.
+    pub fn record_firmware_event<E: Into<FirmwareEvent>>(&mut self, event: E) {
+        let event = event.into();
+        for c in self.firmware_counters.iter_mut().flatten() {
+            if c.started && c.event == event {
+                c.value = c.value.wrapping_add(1);
+            }
+        }
+    }
+
+    /// Returns the value of the counter with the (absolute) index `counter_index` for reporting in
+    /// the snapshot shared memory area, or 0 if the counter isn't configured.
+    pub fn snapshot_counter_value(&self, counter_index: u64) -> u64 {
+        use PmuCounterState::*;
+        if let Ok(index) = Self::firmware_counter_index(counter_index) {
+            return self.firmware_counters[index].map_or(0, |c| c.value);
+        }
+        match self.counter_state.get(counter_index as usize) {
+            Some(Started(_)) => VmPmuState::read_counter_csr(counter_index),
+            Some(Configured(c)) | Some(Poisoned(c)) => c.value,
+            _ => 0,
+        }
+    }
+
+    /// Returns the address of the snapshot shared memory area, if one was set.
+    pub fn snapshot_addr(&self) -> Option<GuestPhysAddr> {
+        self.snapshot_addr
+    }
+
+    /// Sets the address of the snapshot shared memory area. The caller must have validated that
+    /// it is writable by this vCPU.
+    pub fn set_snapshot_addr(&mut self, addr: Option<GuestPhysAddr>) {
+        self.snapshot_addr = addr;
+    }
+
     // Sets the bit to enable access to the CSR for counter_index
     fn set_hcounteren_bit(counter_index: u64) {
         // Unwrap ok: Guaranteed to succeed since we have already tested the condition in the call
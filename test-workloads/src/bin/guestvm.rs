@@ -25,7 +25,7 @@ use ::attestation::{
 use consts::*;
 use s_mode_utils::abort::abort;
 use s_mode_utils::{print::*, sbi_console::SbiConsole};
-use sbi::api::{attestation, base, reset, tee_guest};
+use sbi::api::{attestation, base, debug_console, reset, steal_time, tee_guest};
 
 // Dummy global allocator - panic if anything tries to do an allocation.
 struct GeneralGlobalAlloc;
@@ -269,6 +269,38 @@ fn test_attestation() {
     );
 }
 
+// Restricts the last zero page, which must already have been faulted in, to read-only and then
+// writes to it. Salus reports the write to the host as a permission fault, which the host can't
+// resolve, so this doesn't return unless the write is wrongly allowed.
+fn test_page_permissions(shared_page_addr: u64) {
+    let page_addr = GUEST_ZERO_PAGES_END_ADDRESS + 1 - PAGE_SIZE_4K;
+    // Only confidential memory has permissions for us to restrict.
+    assert!(tee_guest::restrict_page_permissions(
+        shared_page_addr,
+        PAGE_SIZE_4K,
+        sbi::TvmPagePerms::ReadOnly
+    )
+    .is_err());
+    tee_guest::restrict_page_permissions(page_addr, PAGE_SIZE_4K, sbi::TvmPagePerms::ReadOnly)
+        .expect("GuestVm -- RestrictPagePermissions failed");
+    // Permissions can't be extended again once removed.
+    assert!(tee_guest::restrict_page_permissions(
+        page_addr,
+        PAGE_SIZE_4K,
+        sbi::TvmPagePerms::ReadWrite
+    )
+    .is_err());
+
+    let ptr = (page_addr + 2 * core::mem::size_of::<u64>() as u64) as *mut u64;
+    // Safety: ptr is properly aligned and the page is still readable.
+    let val = unsafe { core::ptr::read_volatile(ptr) };
+    assert_eq!(val, 0xdeadbeef);
+    println!("Writing to read-only page at 0x{page_addr:x}");
+    // Safety: ptr is properly aligned, and the write faults rather than modifying the page.
+    unsafe { core::ptr::write_volatile(ptr, 0) };
+    println!("GuestVm -- Write to read-only page wasn't stopped");
+}
+
 #[no_mangle]
 #[allow(clippy::zero_ptr)]
 extern "C" fn kernel_init(_hart_id: u64, shared_page_addr: u64) {
@@ -278,6 +310,11 @@ extern "C" fn kernel_init(_hart_id: u64, shared_page_addr: u64) {
     println!("Hello world from Tellus guest            ");
 
     base::probe_sbi_extension(sbi::EXT_TEE_GUEST).expect("TEE-Guest extension not present");
+    println!(
+        "TSM version: 0x{:x}, mvendorid: 0x{:x}",
+        base::get_implementation_version().unwrap_or(0),
+        base::get_machine_vendor_id().unwrap_or(0)
+    );
 
     let mut next_page = USABLE_RAM_START_ADDRESS + NUM_GUEST_DATA_PAGES * PAGE_SIZE_4K;
     test_attestation();
@@ -309,6 +346,23 @@ extern "C" fn kernel_init(_hart_id: u64, shared_page_addr: u64) {
         }
     }
 
+    // Write a message to the console through a buffer in the shared page.
+    let msg = b"Hello from the debug console\n";
+    let buf_addr = shared_page_addr + core::mem::size_of::<u64>() as u64;
+    for (i, &b) in msg.iter().enumerate() {
+        // Safety: The shared page is mapped and the message fits within it.
+        unsafe { core::ptr::write_volatile((buf_addr + i as u64) as *mut u8, b) };
+    }
+    // Safety: The buffer was initialized above and is only read by the host.
+    let buf = unsafe { core::slice::from_raw_parts(buf_addr as *const u8, msg.len()) };
+    debug_console::console_write(buf).expect("GuestVm -- DBCN console_write failed");
+
+    // Register a steal-time record in the shared page, after the debug console message.
+    let sta_addr = shared_page_addr + 64;
+    // Safety: sta_addr is 64-byte aligned and the record fits within the shared page, which is
+    // only read with volatile semantics below.
+    unsafe { steal_time::set_shmem(Some(sta_addr)) }.expect("GuestVm -- STA set_shmem failed");
+
     #[cfg(target_feature = "v")]
     test_vector();
 
@@ -325,6 +379,13 @@ extern "C" fn kernel_init(_hart_id: u64, shared_page_addr: u64) {
     let val = unsafe { core::ptr::read_volatile(read_ptr) };
     println!("Host says: 0x{:x}", val);
 
+    // The MMIO accesses above exited to the host, so we should have accumulated some steal-time.
+    // The steal field follows the 32-bit sequence and flags fields.
+    let steal_ptr = (sta_addr + 8) as *const u64;
+    // Safety: steal_ptr is properly aligned and within the steal-time record registered above.
+    let steal = unsafe { core::ptr::read_volatile(steal_ptr) };
+    println!("Steal-time: {steal}");
+
     // Make sure we return from WFI.
     //
     // Safety: WFI behavior is well-defined.
@@ -333,6 +394,9 @@ extern "C" fn kernel_init(_hart_id: u64, shared_page_addr: u64) {
     println!("Exiting guest");
     println!("*****************************************");
 
+    // Finish by making an access that our page permissions don't allow, which stops us for good.
+    test_page_permissions(shared_page_addr);
+
     reset::reset(sbi::ResetType::Shutdown, sbi::ResetReason::NoReason)
         .expect("Guest shutdown failed");
     unreachable!();
@@ -20,7 +20,6 @@ mod consts;
 
 use arrayvec::ArrayVec;
 use consts::*;
-#[cfg(target_feature = "v")]
 use core::arch::asm;
 use core::{ops::Range, ptr};
 use device_tree::Fdt;
@@ -30,8 +29,9 @@ use s_mode_utils::ecall::ecall_send;
 use s_mode_utils::{print::*, sbi_console::SbiConsole};
 use sbi::api::{base, pmu, reset, tee_host, tee_interrupt};
 use sbi::{
-    PmuCounterConfigFlags, PmuCounterStartFlags, PmuCounterStopFlags, PmuEventType, PmuFirmware,
-    PmuHardware, SbiMessage, TeeMemoryRegion, EXT_PMU, EXT_TEE_HOST, EXT_TEE_INTERRUPT,
+    Error as SbiError, PmuCounterConfigFlags, PmuCounterStartFlags, PmuCounterStopFlags,
+    PmuEventType, PmuFirmware, PmuHardware, SbiMessage, SbiReturn, TeeMemoryRegion, TvmExitReason,
+    TvmPagePerms, TvmPagingMode, EXT_PMU, EXT_SUSP, EXT_TEE_HOST, EXT_TEE_INTERRUPT,
 };
 
 // Dummy global allocator - panic if anything tries to do an allocation.
@@ -130,6 +130,13 @@ impl TvmCpuSharedMem {
     define_accessors! {hs_csrs, htval, htval, set_htval}
     define_accessors! {hs_csrs, htinst, htinst, set_htinst}
 
+    fn exit_reason_regs(&self) -> *mut sbi::ExitReason {
+        self.register_set_addr(sbi::RegisterSetId::ExitReason) as *mut _
+    }
+
+    define_accessors! {exit_reason_regs, reason, exit_reason, set_exit_reason}
+    define_accessors! {exit_reason_regs, addr, exit_addr, set_exit_addr}
+
     fn gprs(&self) -> *mut sbi::Gprs {
         self.register_set_addr(sbi::RegisterSetId::Gprs) as *mut _
     }
@@ -150,6 +157,26 @@ impl TvmCpuSharedMem {
         unsafe { ptr::addr_of_mut!((*self.gprs()).0[index as usize]).write_volatile(val) };
     }
 
+    fn fprs(&self) -> *mut sbi::Fprs {
+        self.register_set_addr(sbi::RegisterSetId::Fprs) as *mut _
+    }
+
+    // Gets the floating point register at `index`.
+    fn fpr(&self, index: usize) -> u64 {
+        // Safety: The caller guaranteed at construction that `self.addr` points to a valid
+        // shared-memory state area for the layout provided by the TSM, and indexing out of bounds
+        // will panic.
+        unsafe { ptr::addr_of!((*self.fprs()).0[index]).read_volatile() }
+    }
+
+    // Sets the floating point register at `index`.
+    fn set_fpr(&self, index: usize, val: u64) {
+        // Safety: The caller guaranteed at construction that `self.addr` points to a valid
+        // shared-memory state area for the layout provided by the TSM, and indexing out of bounds
+        // will panic.
+        unsafe { ptr::addr_of_mut!((*self.fprs()).0[index]).write_volatile(val) };
+    }
+
     // Returns the number of pages required for a shared-memory state area with the given layout.
     fn required_pages(layout: &[sbi::RegisterSetLocation]) -> u64 {
         let bytes = layout.iter().fold(0, |acc, e| {
@@ -233,6 +260,47 @@ fn exercise_pmu_functionality() {
     .expect_err("Successfully configured FW counter");
 }
 
+// Entered if the platform accepted our request to suspend the system. Salus leaves `sp` untouched
+// across the suspend, so we're still on our boot stack.
+extern "C" fn system_resume(_hart_id: u64, _opaque: u64) -> ! {
+    println!("Tellus - Resumed from system suspend");
+    println!("Tellus - All OK");
+    poweroff();
+}
+
+fn exercise_system_suspend() {
+    if base::probe_sbi_extension(EXT_SUSP).is_err() {
+        println!("Platform doesn't support system suspend");
+        return;
+    }
+
+    // Issue the ECALL by hand so that we can tell whether the instruction that follows it was
+    // skipped when the ECALL returns.
+    let mut ret: i64 = 0;
+    let mut marker: u64 = 0;
+    unsafe {
+        // Safe since we either return here with an error or resume in `system_resume`.
+        asm!(
+            "ecall",
+            "li {marker}, 1",
+            marker = inout(reg) marker,
+            inout("a0") ret,
+            inout("a1") system_resume as u64 => _,
+            in("a2") 0u64,
+            in("a6") 0u64,
+            in("a7") EXT_SUSP,
+            options(nostack),
+        )
+    }
+    if marker != 1 {
+        panic!("Tellus - Skipped the instruction following a failed system suspend");
+    }
+    if ret == 0 {
+        panic!("Tellus - System suspend returned success without resuming");
+    }
+    println!("System suspend refused: {:?}", SbiError::from_code(ret));
+}
+
 #[cfg(target_feature = "v")]
 fn store_into_vectors() {
     let vec_len: u64 = 8;
@@ -392,6 +460,7 @@ extern "C" fn kernel_init(hart_id: u64, fdt_addr: u64) {
         tvm_state_addr,
         NUM_VCPUS,
         tvm_vcpu_addr,
+        TvmPagingMode::Sv48x4,
     )
     .expect("Tellus - TvmCreate returned error");
     println!("Tellus - TvmCreate Success vmid: {vmid:x}");
@@ -403,7 +472,8 @@ extern "C" fn kernel_init(hart_id: u64, fdt_addr: u64) {
     unsafe {
         convert_pages(next_page, NUM_TEE_PTE_PAGES);
     }
-    tee_host::add_page_table_pages(vmid, next_page, NUM_TEE_PTE_PAGES)
+    let pte_pages_base = next_page;
+    tee_host::add_page_table_pages(vmid, pte_pages_base, NUM_TEE_PTE_PAGES)
         .expect("Tellus - AddPageTablePages returned error");
     next_page += PAGE_SIZE_4K * NUM_TEE_PTE_PAGES;
 
@@ -417,6 +487,15 @@ extern "C" fn kernel_init(hart_id: u64, fdt_addr: u64) {
             tee_host::get_vcpu_register_set(vmid, i).expect("Tellus - TvmCpuGetRegisterSet");
         vcpu_mem_layout.push(regset);
     }
+    // The vector registers are only exposed if the platform supports vectors, which it must if
+    // we're using them ourselves.
+    let has_vprs = vcpu_mem_layout
+        .iter()
+        .any(|e| e.id == sbi::RegisterSetId::Vprs as u16);
+    if cfg!(target_feature = "v") && !has_vprs {
+        panic!("Tellus - Vector registers missing from the vCPU shared-memory layout");
+    }
+    println!("vCPU shared-memory layout has {num_regsets} register sets, vector: {has_vprs}");
     let num_vcpu_shared_pages = TvmCpuSharedMem::required_pages(&vcpu_mem_layout);
 
     // Add vCPU0.
@@ -487,6 +566,7 @@ extern "C" fn kernel_init(hart_id: u64, fdt_addr: u64) {
         guest_image,
         next_page,
         sbi::TsmPageType::Page4k,
+        TvmPagePerms::ReadWriteExecute,
         USABLE_RAM_START_ADDRESS,
     )
     .expect("Tellus - TvmAddMeasuredPages returned error");
@@ -516,6 +596,7 @@ extern "C" fn kernel_init(hart_id: u64, fdt_addr: u64) {
         vmid,
         zero_pages_base,
         sbi::TsmPageType::Page4k,
+        TvmPagePerms::ReadWriteExecute,
         PRE_FAULTED_ZERO_PAGES,
         GUEST_ZERO_PAGES_START_ADDRESS,
     )
@@ -526,11 +607,15 @@ extern "C" fn kernel_init(hart_id: u64, fdt_addr: u64) {
     store_into_vectors();
 
     let mut shared_mem_region: Option<Range<u64>> = None;
+    // The guest physical address at which the shared pages were inserted, if they have been.
+    let mut shared_pages_gpa: Option<u64> = None;
     let mut mmio_region: Option<Range<u64>> = None;
+    // The range of memory whose permissions the guest last restricted.
+    let mut restricted_region: Option<Range<u64>> = None;
     loop {
         // Safety: running a VM will only write the `TvmCpuSharedState` struct that was registered
         // with `add_vcpu()`.
-        tee_host::tvm_run(vmid, 0).expect("Could not run guest VM");
+        tee_host::tvm_run(vmid, 0, 0).expect("Could not run guest VM");
         let scause = vcpu.scause();
         if let Ok(Trap::Exception(e)) = Trap::from_scause(scause) {
             use Exception::*;
@@ -545,10 +630,57 @@ extern "C" fn kernel_init(hart_id: u64, fdt_addr: u64) {
                     }
                     use SbiMessage::*;
                     match SbiMessage::from_regs(&a_regs) {
-                        Ok(Reset(_)) => {
-                            println!("Guest VM requested shutdown");
+                        Ok(Reset(sbi::ResetFunction::Reset { reset_type, .. })) => {
+                            // A reboot request would have us rebuild the guest; we just tear it
+                            // down either way.
+                            println!("Guest VM requested {:?}", reset_type);
                             break;
                         }
+                        Ok(HartState(sbi::StateFunction::HartSuspend { .. })) => {
+                            // We have nothing else to run, so resume the vCPU right away.
+                            continue;
+                        }
+                        Ok(DebugConsole(dbcn_func)) => {
+                            use sbi::DebugConsoleFunction::*;
+                            let ret = match dbcn_func {
+                                ConsoleWriteByte { byte } => {
+                                    print!("{}", byte as char);
+                                    SbiReturn::success(0)
+                                }
+                                ConsoleWrite {
+                                    num_bytes,
+                                    base_addr,
+                                } => {
+                                    // Salus has already checked that the buffer is in the shared
+                                    // region, but we may not have populated it yet.
+                                    let shared_pages_size = NUM_GUEST_SHARED_PAGES * PAGE_SIZE_4K;
+                                    match shared_pages_gpa.filter(|&gpa| {
+                                        base_addr >= gpa
+                                            && base_addr + num_bytes <= gpa + shared_pages_size
+                                    }) {
+                                        Some(gpa) => {
+                                            let buf = shared_page_base + (base_addr - gpa);
+                                            for i in 0..num_bytes {
+                                                // Safety: We've checked that the buffer is within
+                                                // the shared pages we own. Shared pages must be
+                                                // accessed with volatile semantics.
+                                                let byte = unsafe {
+                                                    ptr::read_volatile((buf + i) as *const u8)
+                                                };
+                                                print!("{}", byte as char);
+                                            }
+                                            SbiReturn::success(num_bytes)
+                                        }
+                                        None => SbiReturn::from(SbiError::InvalidParam),
+                                    }
+                                }
+                                // We don't have any console input to give to the guest.
+                                ConsoleRead { .. } => SbiReturn::success(0),
+                            };
+                            // Complete the guest's ECALL.
+                            vcpu.set_gpr(GprIndex::A0, ret.error_code as u64);
+                            vcpu.set_gpr(GprIndex::A1, ret.return_value);
+                        }
                         Ok(TeeGuest(guest_func)) => {
                             use sbi::TeeGuestFunction::*;
                             match guest_func {
@@ -580,6 +712,19 @@ extern "C" fn kernel_init(hart_id: u64, fdt_addr: u64) {
                                         }
                                     }
                                 }
+                                ShareMemory { .. } | UnshareMemory { .. } => {
+                                    println!("Unexpected memory conversion from guest");
+                                    break;
+                                }
+                                // The new permissions have already been applied, and we don't
+                                // run the guest on more than one vCPU, so there are no other
+                                // vCPUs to kick. Just remember the range to check faults against.
+                                RestrictPagePermissions { addr, len, .. } => {
+                                    restricted_region = Some(Range {
+                                        start: addr,
+                                        end: addr + len,
+                                    });
+                                }
                             }
                         }
                         _ => {
@@ -589,7 +734,21 @@ extern "C" fn kernel_init(hart_id: u64, fdt_addr: u64) {
                     }
                 }
                 GuestLoadPageFault | GuestStorePageFault => {
-                    let fault_addr = (vcpu.htval() << 2) | (vcpu.stval() & 0x3);
+                    let fault_addr = vcpu.exit_addr();
+                    if vcpu.exit_reason() == TvmExitReason::PermissionFault as u64 {
+                        // We can't grant the guest the access it was denied, so the guest only
+                        // faults this way to end the test once it has restricted its memory.
+                        if restricted_region
+                            .as_ref()
+                            .filter(|r| r.contains(&fault_addr))
+                            .is_some()
+                        {
+                            println!("Guest VM stopped by permission fault at 0x{:x}", fault_addr);
+                        } else {
+                            println!("Unexpected guest permission fault at 0x{:x}", fault_addr);
+                        }
+                        break;
+                    }
                     match fault_addr {
                         GUEST_ZERO_PAGES_START_ADDRESS..=GUEST_ZERO_PAGES_END_ADDRESS => {
                             // Fault in the page.
@@ -600,6 +759,7 @@ extern "C" fn kernel_init(hart_id: u64, fdt_addr: u64) {
                                 vmid,
                                 zero_pages_base + zero_pages_added * PAGE_SIZE_4K,
                                 sbi::TsmPageType::Page4k,
+                                TvmPagePerms::ReadWriteExecute,
                                 1,
                                 fault_addr & !(PAGE_SIZE_4K - 1),
                             )
@@ -623,6 +783,7 @@ extern "C" fn kernel_init(hart_id: u64, fdt_addr: u64) {
                                 )
                                 .expect("Tellus -- TvmAddSharedPages failed");
                             }
+                            shared_pages_gpa = Some(addr & !(PAGE_SIZE_4K - 1));
 
                             // Safety: We own the page, and are writing a value expected by the
                             // guest. Note that any access to shared pages must use volatile memory
@@ -638,7 +799,8 @@ extern "C" fn kernel_init(hart_id: u64, fdt_addr: u64) {
                             let inst = DecodedInstruction::from_raw(vcpu.htinst() as u32)
                                 .expect("Failed to decode faulting MMIO instruction")
                                 .instruction();
-                            // Handle the load or store; the source/dest register is always A0.
+                            // Handle the load or store; the source/dest register is always A0, or
+                            // F0 for floating point accesses.
                             use Instruction::*;
                             match inst {
                                 Lb(_) | Lbu(_) | Lh(_) | Lhu(_) | Lw(_) | Lwu(_) | Ld(_) => {
@@ -648,6 +810,13 @@ extern "C" fn kernel_init(hart_id: u64, fdt_addr: u64) {
                                     let val = vcpu.gpr(GprIndex::A0);
                                     println!("Guest says: 0x{:x} at 0x{:x}", val, fault_addr);
                                 }
+                                Flw(_) | Fld(_) => {
+                                    vcpu.set_fpr(0, 0x42);
+                                }
+                                Fsw(_) | Fsd(_) => {
+                                    let val = vcpu.fpr(0);
+                                    println!("Guest says: 0x{:x} at 0x{:x}", val, fault_addr);
+                                }
                                 _ => {
                                     println!("Unexpected guest MMIO instruction: {:?}", inst);
                                     return;
@@ -679,6 +848,9 @@ extern "C" fn kernel_init(hart_id: u64, fdt_addr: u64) {
                     break;
                 }
             }
+        } else if let Ok(Trap::Interrupt(_)) = Trap::from_scause(scause) {
+            // The guest was interrupted so that we could handle an interrupt of our own; resume it.
+            continue;
         } else {
             println!("Guest VM terminated with unexpected cause 0x{:x}", scause);
         }
@@ -702,10 +874,12 @@ extern "C" fn kernel_init(hart_id: u64, fdt_addr: u64) {
         NUM_GUEST_DATA_PAGES + NUM_GUEST_ZERO_PAGES,
     );
     reclaim_pages(state_pages_base, tvm_create_pages);
+    reclaim_pages(pte_pages_base, NUM_TEE_PTE_PAGES);
     if has_aia {
         tee_interrupt::reclaim_imsic(imsic_file_addr).expect("Tellus - TsmReclaimImsic failed");
     }
     exercise_pmu_functionality();
+    exercise_system_suspend();
     println!("Tellus - All OK");
     poweroff();
 }